@@ -1,30 +1,78 @@
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
-    path::{Path, PathBuf},
+    path::PathBuf,
     rc::Rc,
+    sync::Arc,
 };
 
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
-use sqlx::SqlitePool;
 use tui::widgets::{ListState, TableState};
 
 use crate::{
-    collection_tree::CollectionTree,
-    data_structures::{Collection, Document, RcCollection, RcDoc, StatefulList},
-    ui::{RcUIBlock, UIBlock, UIBlockType},
+    backend::{LibraryBackend, MemoryBackend},
+    bib_sync::DbWatcher,
+    citation_server::CitationServer,
+    citeproc::{render_documents, Bibliography, OutputFormat, Style, StyleInfo},
+    clipboard::Clipboard,
+    collection_tree::{CollectionNodeValue, CollectionTree},
+    data_structures::{
+        Collection, Creator, Document, Note, RcCollection, RcDoc, ResolvedAttachment, StatefulList,
+    },
+    edit_form::EditForm,
+    export::{citation_key::assign_citation_keys, ExportFormat, ExportScope},
+    metadata::{
+        pdf::{PdfCandidate, PdfLookup},
+        Identifier, MetadataProvider, WebProvider,
+    },
+    tag_editor::TagEditor,
+    ui::{PopupType, RcUIBlock, UIBlock, UIBlockType},
+    user_config::ColumnConfig,
+    worker::Worker,
 };
 
 /// App holds the state of the application
 pub struct App {
     /// Current value of the input box
     pub search_input: String,
-    pub sqlite_pool: Option<SqlitePool>,
+    /// Where the documents were loaded from and where edits go
+    pub backend: Rc<dyn LibraryBackend>,
     /// History of recorded messages
     pub documents: Vec<RcDoc>,
     pub collection_tree: CollectionTree,
 
     pub row_num_to_doc: HashMap<usize, usize>,
+    /// Rows of the document table that show an attachment of the mapped document
+    pub row_num_to_attachment: HashMap<usize, usize>,
+    pub attachment_picker: StatefulList<ResolvedAttachment>,
+    pub copy_menu: StatefulList<ExportFormat>,
+    pub export_menu: StatefulList<ExportScope>,
+    pub prompt: Option<Prompt>,
+    pub style_menu: StatefulList<StyleInfo>,
+    pub preview: Option<Preview>,
+    pub edit_form: Option<EditForm>,
+    pub collection_menu: StatefulList<CollectionAction>,
+    pub collection_picker: Option<CollectionPicker>,
+    pub tag_editor: Option<TagEditor>,
+    /// Tags of the library and how many items have each
+    pub tag_manager: StatefulList<(String, usize)>,
+    /// Notes of the selected document, `None` to add a new one
+    pub note_picker: StatefulList<Option<Note>>,
+    /// Text to write in the editor before drawing again, see `suspend_terminal` in main.rs
+    pub editor_request: Option<EditorRequest>,
+    /// Looks up the items added by DOI, ISBN or arXiv ID
+    pub metadata_provider: Arc<dyn MetadataProvider>,
+    /// Looks up the item added by identifier, see `add_items` in handler.rs
+    pub item_lookup: Option<Worker<(Identifier, String)>>,
+    /// Reads the PDFs of the directory to import, see `open_pdf_import` in handler.rs
+    pub pdf_reader: Option<Worker<(PathBuf, Vec<PdfLookup>)>>,
+    /// Items proposed for the PDFs of a directory, to review before importing them
+    pub pdf_import: StatefulList<PdfCandidate>,
+    /// Files bound to a collection in this session, rewritten when the database changes
+    pub synced_files: Vec<PathBuf>,
+    pub db_watcher: Option<DbWatcher>,
+    pub citation_server: Option<CitationServer>,
+    pub clipboard: Clipboard,
     pub active_block: Option<Box<dyn Iterator<Item = RcUIBlock>>>,
     pub filtered_documents: StatefulList<RcDoc>,
     pub collections: StatefulList<RcCollection>,
@@ -34,12 +82,163 @@ pub struct App {
     pub previous_block_idx: Cell<usize>,
     pub sorted: Cell<bool>,
     pub show_popup: Cell<bool>,
+    pub popup_type: PopupType,
+    /// Columns of the document table, including hidden ones
+    pub columns: StatefulList<ColumnConfig>,
     pub sort_by_type: UIBlockType,
     pub error_message: String,
+    pub status_message: String,
     pub sort_direction: Cell<SortDirection>,
     pub ui_blocks: Vec<Rc<RefCell<UIBlock>>>,
 }
 
+/// Fuzzy match `query` against the header of `ty` of each document. Queries starting with
+/// `@` match citation keys instead.
+pub fn search_documents(docs: &[RcDoc], query: &str, ty: UIBlockType) -> Vec<RcDoc> {
+    let matcher = SkimMatcherV2::default();
+    docs.iter()
+        .filter(|doc| {
+            if let Some(key) = query.strip_prefix('@') {
+                return matcher
+                    .fuzzy_match(doc.borrow().get_citation_key(), key)
+                    .is_some();
+            }
+            // match fuzzy find
+            // TODO: maybe we should cache headers somewhere so we dont have to build
+            // string every time.
+            let entry = doc.borrow().build_header_for_block_type(ty);
+            matcher.fuzzy_match(&entry, query).is_some()
+        })
+        .cloned()
+        .collect()
+}
+
+/// What to do with the text entered in a prompt
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromptAction {
+    Export(ExportScope),
+    /// Export to this existing file once confirmed
+    OverwriteExport(ExportScope, PathBuf),
+    Import,
+    /// Bind the collection with this path to a file
+    Sync(String),
+    /// Set the value of this row of the edit form
+    EditRow(usize),
+    /// Add a field with the entered name to the edit form
+    AddField,
+    /// Create a collection with the entered name
+    NewCollection {
+        library_id: Option<i64>,
+        parent_id: Option<i64>,
+    },
+    RenameCollection(i64),
+    /// Delete the collection with this ID once confirmed
+    DeleteCollection(i64),
+    /// Rename this tag on all items
+    RenameTag(String),
+    /// Delete this tag from all items once confirmed
+    DeleteTag(String),
+    /// Add items by identifier or from records, see `metadata::read_records`
+    AddItem,
+    /// Store a copy of the entered file as an attachment of the item with this ID
+    AttachFile(i64),
+}
+
+/// Actions of the collection menu, on the collection under the cursor of the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionAction {
+    New,
+    NewSubcollection,
+    Rename,
+    Move,
+    Delete,
+}
+
+impl CollectionAction {
+    pub fn all() -> Vec<CollectionAction> {
+        vec![
+            CollectionAction::New,
+            CollectionAction::NewSubcollection,
+            CollectionAction::Rename,
+            CollectionAction::Move,
+            CollectionAction::Delete,
+        ]
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollectionAction::New => "New collection",
+            CollectionAction::NewSubcollection => "New sub-collection",
+            CollectionAction::Rename => "Rename",
+            CollectionAction::Move => "Move to…",
+            CollectionAction::Delete => "Delete (keeps the items)",
+        }
+    }
+}
+
+/// What to do with the collection chosen in the collection picker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionPick {
+    /// Add the marked documents to it
+    AddDocuments,
+    /// Move the collection with this ID into it
+    Move(i64),
+}
+
+/// Collections to choose from by path, `None` being the top level of the library
+pub struct CollectionPicker {
+    pub action: CollectionPick,
+    pub targets: StatefulList<(String, Option<RcCollection>)>,
+}
+
+/// A child note to edit, or to add when `note` is `None`
+pub struct NoteEdit {
+    pub doc: RcDoc,
+    pub note: Option<Note>,
+}
+
+/// What to let the user write in their editor
+pub enum EditorRequest {
+    Note(NoteEdit),
+    /// BibTeX or RIS records of items to add
+    NewItems,
+}
+
+/// A single line text input shown in a popup
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub title: String,
+    pub input: String,
+    pub action: PromptAction,
+}
+
+/// Marked documents rendered with a CSL style
+pub struct Preview {
+    pub docs: Vec<RcDoc>,
+    pub style: Style,
+    pub format: OutputFormat,
+    pub bibliography: Bibliography,
+    pub scroll: u16,
+}
+
+impl Preview {
+    pub fn new(docs: Vec<RcDoc>, style: Style) -> Self {
+        let format = OutputFormat::Text;
+        let bibliography = render_documents(&style, &docs, format);
+        Self {
+            docs,
+            style,
+            format,
+            bibliography,
+            scroll: 0,
+        }
+    }
+    /// Render again in the next output format
+    pub fn cycle_format(&mut self) {
+        self.format = self.format.next();
+        self.bibliography = render_documents(&self.style, &self.docs, self.format);
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SortDirection {
     Up,
@@ -51,12 +250,38 @@ impl Default for App {
             collection_tree: CollectionTree::new(),
             tbl_state: TableState::default(),
             row_num_to_doc: HashMap::new(),
+            row_num_to_attachment: HashMap::new(),
+            attachment_picker: StatefulList {
+                state: ListState::default(),
+                items: Vec::new(),
+            },
+            copy_menu: StatefulList::with_items(ExportFormat::all()),
+            export_menu: StatefulList::with_items(ExportScope::all()),
+            prompt: None,
+            style_menu: StatefulList::with_items(Vec::new()),
+            preview: None,
+            edit_form: None,
+            collection_menu: StatefulList::with_items(CollectionAction::all()),
+            collection_picker: None,
+            tag_editor: None,
+            tag_manager: StatefulList::with_items(Vec::new()),
+            note_picker: StatefulList::with_items(Vec::new()),
+            editor_request: None,
+            metadata_provider: Arc::new(WebProvider::default()),
+            item_lookup: None,
+            pdf_reader: None,
+            pdf_import: StatefulList::with_items(Vec::new()),
+            synced_files: Vec::new(),
+            db_watcher: None,
+            citation_server: None,
+            clipboard: Clipboard::default(),
             active_block: None,
             sort_direction: Cell::from(SortDirection::Up),
             search_input: String::new(),
-            sqlite_pool: None,
+            backend: Rc::new(MemoryBackend::default()),
             documents: Vec::new(),
             error_message: String::new(),
+            status_message: String::new(),
             collections: StatefulList {
                 state: ListState::default(),
                 items: Vec::new(),
@@ -71,6 +296,11 @@ impl Default for App {
             ui_blocks: Vec::new(),
             sorted: Cell::from(false),
             show_popup: Cell::from(false),
+            popup_type: PopupType::ColumnChooser,
+            columns: StatefulList {
+                state: ListState::default(),
+                items: Vec::new(),
+            },
         }
     }
 }
@@ -78,6 +308,182 @@ impl Default for App {
 impl App {
     pub fn update_on_tick(&self) {}
 
+    pub fn open_popup(&mut self, ty: PopupType) {
+        self.popup_type = ty;
+        self.show_popup.set(true);
+    }
+    pub fn close_popup(&mut self) {
+        self.show_popup.set(false);
+    }
+    pub fn open_prompt(&mut self, title: &str, input: &str, action: PromptAction) {
+        self.prompt = Some(Prompt {
+            title: title.to_string(),
+            input: input.to_string(),
+            action,
+        });
+        self.open_popup(PopupType::Prompt);
+    }
+
+    pub fn select_next_collection(&mut self) {
+        let len = self.collection_tree.get_visible_nodes().len();
+        if len > 0 {
+            let i = match self.collections.state.selected() {
+                Some(i) if i + 1 < len => i + 1,
+                _ => 0,
+            };
+            self.collections.state.select(Some(i));
+        }
+    }
+    pub fn select_prev_collection(&mut self) {
+        let len = self.collection_tree.get_visible_nodes().len();
+        if len > 0 {
+            let i = match self.collections.state.selected() {
+                Some(i) if i > 0 => i - 1,
+                _ => len - 1,
+            };
+            self.collections.state.select(Some(i));
+        }
+    }
+    /// The collection under the cursor of the collection tree, `None` on a library row
+    pub fn get_selected_collection(&self) -> Option<RcCollection> {
+        let nodes = self.collection_tree.get_visible_nodes();
+        let node = nodes.get(self.collections.state.selected()?)?;
+        let collection = match &node.borrow().value {
+            CollectionNodeValue::Collection(col) => Some(col.clone()),
+            CollectionNodeValue::Library(_) => None,
+        };
+        collection
+    }
+    /// Library of the collection or library under the cursor of the collection tree
+    pub fn get_selected_library_id(&self) -> Option<i64> {
+        let nodes = self.collection_tree.get_visible_nodes();
+        let node = nodes.get(self.collections.state.selected()?)?;
+        let id = match &node.borrow().value {
+            CollectionNodeValue::Collection(col) => col.borrow().libraryId,
+            CollectionNodeValue::Library(lib) => lib.borrow().libraryId,
+        };
+        Some(id)
+    }
+    /// Move the cursor of the collection tree to the collection
+    pub fn select_collection(&mut self, collection_id: i64) {
+        let idx = self
+            .collection_tree
+            .get_visible_nodes()
+            .iter()
+            .position(|node| match &node.borrow().value {
+                CollectionNodeValue::Collection(col) => col.borrow().collectionId == collection_id,
+                CollectionNodeValue::Library(_) => false,
+            });
+        if idx.is_some() {
+            self.collections.state.select(idx);
+        }
+    }
+    /// Build the collection tree again from `collections`, after some were added, moved or
+    /// deleted. The cursor stays on the same collection if it still exists.
+    pub fn rebuild_collection_tree(&mut self, mut collections: Vec<RcCollection>) {
+        let selected = self
+            .get_selected_collection()
+            .map(|col| col.borrow().collectionId);
+        collections.sort_by(|a, b| a.borrow().collectionName.cmp(&b.borrow().collectionName));
+        self.collection_tree = CollectionTree::new();
+        self.collection_tree.build_collection_tree(&mut collections);
+        let len = self.collection_tree.get_visible_nodes().len();
+        let idx = self.collections.state.selected().unwrap_or(0);
+        self.collections.state.select(if len == 0 {
+            None
+        } else {
+            Some(idx.min(len - 1))
+        });
+        if let Some(id) = selected {
+            self.select_collection(id);
+        }
+    }
+    /// Documents in the collection and its sub-collections
+    pub fn get_docs_in_collection(&self, collection_id: i64) -> Vec<RcDoc> {
+        let ids = self.collection_tree.get_descendant_ids(collection_id);
+        self.documents
+            .iter()
+            .filter(|doc| {
+                doc.borrow()
+                    .collections
+                    .iter()
+                    .any(|col| ids.contains(&col.borrow().collectionId))
+            })
+            .cloned()
+            .collect()
+    }
+    /// No documents for the collection scope while a library is under the cursor, its
+    /// documents aren't exported in place of a collection's
+    pub fn get_docs_for_scope(&self, scope: ExportScope) -> Vec<RcDoc> {
+        match scope {
+            ExportScope::Selected => self.get_marked_docs(),
+            ExportScope::Collection => match self.get_selected_collection() {
+                Some(col) => self.get_docs_in_collection(col.borrow().collectionId),
+                None => Vec::new(),
+            },
+            ExportScope::SearchResults => self.filtered_documents.items.clone(),
+        }
+    }
+
+    /// Add documents staged by the connector. Documents already shown, identified by their
+    /// item key, get the attachments and tags of their new version.
+    pub fn merge_staged_documents(&mut self, docs: Vec<RcDoc>) -> usize {
+        let mut new_docs = Vec::new();
+        for doc in docs {
+            let key = doc.borrow().item_data.key.clone();
+            match self
+                .documents
+                .iter()
+                .find(|existing| existing.borrow().item_data.key == key)
+            {
+                Some(existing) => {
+                    let mut doc = doc.borrow_mut();
+                    let mut existing = existing.borrow_mut();
+                    existing.attachments = doc.attachments.take();
+                    existing.tags = std::mem::take(&mut doc.tags);
+                }
+                None => new_docs.push(doc),
+            }
+        }
+        self.merge_documents(new_docs)
+    }
+
+    /// Add documents read from a file, skipping those already in the library (same DOI or
+    /// explicit citation key). Returns the number of added documents.
+    pub fn merge_documents(&mut self, docs: Vec<RcDoc>) -> usize {
+        let mut next_id = self
+            .documents
+            .iter()
+            .map(|doc| doc.borrow().item_data.itemId)
+            .min()
+            .unwrap_or(0)
+            .min(0);
+        let mut added = 0;
+        for doc in docs {
+            let is_duplicate = self.documents.iter().any(|existing| {
+                let (existing, doc) = (existing.borrow(), doc.borrow());
+                (!doc.citation_key.is_empty()
+                    && existing.get_citation_key() == doc.get_citation_key())
+                    || matches!(
+                        (existing.get_field("DOI"), doc.get_field("DOI")),
+                        (Some(a), Some(b)) if a.eq_ignore_ascii_case(b)
+                    )
+            });
+            if is_duplicate {
+                continue;
+            }
+            // Negative ids never clash with items of the Zotero database
+            next_id -= 1;
+            doc.borrow_mut().item_data.itemId = next_id;
+            self.documents.push(doc);
+            added += 1;
+        }
+        // Documents without a key in the file get one that is unique in the library
+        assign_citation_keys(&self.documents);
+        self.update_filtered_doc();
+        added
+    }
+
     pub fn toggle_sorted(&mut self) {
         // self.sorted.set(!self.sorted.get());
         self.sorted.set(true);
@@ -138,6 +544,48 @@ impl App {
     //     None => {}
     // }
     // pub fn get_selected_doc_idx(&self) -> Option<RcDoc> {}
+    /// Map the rows of the document table to the documents and attachments they show, an
+    /// expanded document is followed by a row per attachment. Returns the number of rows.
+    pub fn map_rows(&mut self) -> usize {
+        self.row_num_to_doc.clear();
+        self.row_num_to_attachment.clear();
+        let mut row = 0;
+        for (idx, doc) in self.filtered_documents.items.iter().enumerate() {
+            let doc = doc.borrow();
+            self.row_num_to_doc.insert(row, idx);
+            row += 1;
+            if !doc.toggled.get() {
+                continue;
+            }
+            if let Some(attachments) = &doc.attachments {
+                for att_idx in 0..attachments.items.len() {
+                    self.row_num_to_doc.insert(row, idx);
+                    self.row_num_to_attachment.insert(row, att_idx);
+                    row += 1;
+                }
+            }
+        }
+        row
+    }
+    /// Move the cursor of the document table down, from the last row to the first
+    pub fn select_next_row(&mut self) {
+        let rows = self.map_rows();
+        let i = match self.tbl_state.selected() {
+            Some(i) if i + 1 < rows => i + 1,
+            _ => 0,
+        };
+        self.tbl_state.select(Some(i));
+    }
+    /// Move the cursor of the document table up, from the first row to the last
+    pub fn select_previous_row(&mut self) {
+        let rows = self.map_rows();
+        let i = match self.tbl_state.selected() {
+            Some(0) => rows.saturating_sub(1),
+            Some(i) => (i - 1).min(rows.saturating_sub(1)),
+            None => 0,
+        };
+        self.tbl_state.select(Some(i));
+    }
     pub fn get_selected_doc(&self) -> Option<RcDoc> {
         if let Some(selected_idx) = self
             .row_num_to_doc
@@ -160,6 +608,30 @@ impl App {
         //     None
         // }
     }
+    /// The marked documents, or the document under the cursor if none is marked
+    pub fn get_marked_docs(&self) -> Vec<RcDoc> {
+        let marked: Vec<RcDoc> = self
+            .filtered_documents
+            .items
+            .iter()
+            .filter(|doc| doc.borrow().marked.get())
+            .cloned()
+            .collect();
+        if marked.is_empty() {
+            self.get_selected_doc().into_iter().collect()
+        } else {
+            marked
+        }
+    }
+    /// Index of the attachment under the cursor, if the cursor is on an attachment row
+    pub fn get_selected_attachment_idx(&self) -> Option<usize> {
+        self.row_num_to_attachment
+            .get(&self.tbl_state.selected().unwrap_or(0))
+            .copied()
+    }
+    pub fn set_status(&mut self, message: impl Into<String>) {
+        self.status_message = message.into();
+    }
     pub fn get_block_with_type(&self, ty: UIBlockType) -> Rc<RefCell<UIBlock>> {
         self.ui_blocks
             .iter()
@@ -196,26 +668,12 @@ impl App {
         }
     }
     pub fn update_filtered_doc(&mut self) {
-        let matcher = SkimMatcherV2::default();
         if !self.search_input.is_empty() {
             // TODO: adding search character is cheaper because we can reuse the current list to match.
             // Removing search character should clear and fuzzy search from begining (except for when we
             // store some kind of history). Leaving it for later
-            self.filtered_documents.items.clear();
-            self.filtered_documents.items.extend(
-                self.documents
-                    .iter()
-                    .filter(|doc| {
-                        // match fuzzy find
-                        // TODO: maybe we should cache headers somewhere so we dont have to build
-                        // string every time.
-                        let entry = doc.borrow().build_header_for_block_type(self.sort_by_type);
-                        matcher
-                            .fuzzy_match(&entry, self.search_input.as_str())
-                            .is_some()
-                    })
-                    .map(|item| item.clone()),
-            );
+            self.filtered_documents.items =
+                search_documents(&self.documents, &self.search_input, self.sort_by_type);
         } else {
             // Reclone only if it is less than max len.
             if self.filtered_documents.items.len() < self.documents.len() {
@@ -227,11 +685,139 @@ impl App {
         }
         self.filtered_documents.state.select(Some(0));
     }
-    pub async fn init_sqlite(&mut self, db_path: &Path) -> anyhow::Result<()> {
-        // dotenv::dotenv().ok();
-        // let url = env::var("DATABASE_URL");
-        self.sqlite_pool =
-            Some(SqlitePool::connect(&format!("sqlite:{}", db_path.to_str().unwrap())).await?);
+    /// A document by citation key or Zotero item key
+    pub fn find_document(&self, key: &str) -> Option<RcDoc> {
+        self.documents
+            .iter()
+            .find(|doc| {
+                let doc = doc.borrow();
+                doc.get_citation_key() == key || doc.item_data.key == key
+            })
+            .cloned()
+    }
+    /// Replace the documents and collections with those of `backend`, which receives the
+    /// edits from now on
+    pub async fn load_from(&mut self, backend: Rc<dyn LibraryBackend>) -> anyhow::Result<()> {
+        self.documents = Vec::from_iter(backend.load_items().await?);
+        let mut creators = backend.load_creators().await?;
+        let mut attachments = backend.load_attachments().await?;
+        let mut fields = backend.load_fields().await?;
+        let mut tags = backend.load_tags().await?;
+        let mut notes = backend.load_notes().await?;
+        for doc in &self.documents {
+            let mut doc = doc.borrow_mut();
+            let item_id = doc.item_data.itemId;
+            let mut item_creators = creators.remove(&item_id).unwrap_or_default();
+            if item_creators.is_empty() {
+                item_creators.push(Creator::default());
+            }
+            doc.creators.extend(item_creators);
+            let item_attachments = attachments.remove(&item_id).unwrap_or_default();
+            if !item_attachments.is_empty() {
+                doc.attachments = Some(StatefulList::with_items(item_attachments));
+            }
+            let item_fields = fields.remove(&item_id).unwrap_or_default();
+            doc.fields.extend(
+                item_fields
+                    .into_iter()
+                    .map(|rec| (rec.fieldName, rec.value)),
+            );
+            doc.tags = tags.remove(&item_id).unwrap_or_default();
+            doc.notes = notes.remove(&item_id).unwrap_or_default();
+        }
+        self.collections.items = backend
+            .load_collections()
+            .await?
+            .into_iter()
+            .map(|collection| Rc::new(RefCell::new(collection)))
+            .collect();
+        let docs_by_id: HashMap<i64, &RcDoc> = self
+            .documents
+            .iter()
+            .map(|doc| (doc.borrow().item_data.itemId, doc))
+            .collect();
+        let collections_by_id: HashMap<i64, &RcCollection> = self
+            .collections
+            .items
+            .iter()
+            .map(|col| (col.borrow().collectionId, col))
+            .collect();
+        // TODO: Handle multiple collection, same record.
+        for record in backend.load_collection_items().await? {
+            if let (Some(doc), Some(collection)) = (
+                docs_by_id.get(&record.itemId),
+                collections_by_id.get(&record.collectionId),
+            ) {
+                doc.borrow_mut().collections.push(Rc::clone(collection));
+            }
+        }
+        self.backend = backend;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data_structures::Attachment, export::new_document};
+
+    #[test]
+    fn test_collection_scope() {
+        let app = App {
+            documents: vec![new_document("book", "EFGH5678")],
+            ..App::default()
+        };
+        assert!(app.get_selected_collection().is_none());
+        assert!(app.get_docs_for_scope(ExportScope::Collection).is_empty());
+    }
+
+    #[test]
+    fn test_row_navigation() {
+        let mut app = App::default();
+        let paper = new_document("journalArticle", "ABCD1234");
+        paper.borrow_mut().attachments = Some(StatefulList::with_items(
+            ["paper.pdf", "slides.pdf"]
+                .iter()
+                .map(|name| Attachment {
+                    itemId: 0,
+                    linkMode: Some(0),
+                    contentType: Some("application/pdf".to_string()),
+                    path: Some(format!("storage:{}", name)),
+                    key: None,
+                    url: None,
+                })
+                .collect(),
+        ));
+        app.documents = vec![new_document("book", "EFGH5678"), paper.clone()];
+        app.update_filtered_doc();
+        app.tbl_state.select(Some(0));
+        paper.borrow().toggled.set(true);
+
+        // Down past the last item reaches its attachments
+        app.select_next_row();
+        assert_eq!(app.get_selected_attachment_idx(), None);
+        app.select_next_row();
+        assert_eq!(app.get_selected_attachment_idx(), Some(0));
+        app.select_next_row();
+        assert_eq!(app.get_selected_attachment_idx(), Some(1));
+        assert_eq!(
+            app.get_selected_doc().unwrap().borrow().item_data.key,
+            "ABCD1234"
+        );
+        app.select_next_row();
+        assert_eq!(app.tbl_state.selected(), Some(0));
+        // Up from the first row wraps to the last attachment
+        app.select_previous_row();
+        assert_eq!(app.tbl_state.selected(), Some(3));
+        assert_eq!(app.get_selected_attachment_idx(), Some(1));
+
+        // Collapsing keeps the cursor on a row that exists
+        paper.borrow().toggled.set(false);
+        app.select_previous_row();
+        assert_eq!(app.tbl_state.selected(), Some(1));
+        app.documents.clear();
+        app.filtered_documents.items.clear();
+        app.select_next_row();
+        assert_eq!(app.tbl_state.selected(), Some(0));
+    }
+}
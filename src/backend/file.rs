@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    data_structures::{
+        Attachment, Collection, CollectionItem, Creator, ItemData, ItemField, Note, Tag,
+    },
+    export::import_from_file,
+};
+
+use super::{BackendFuture, ByItem, LibraryBackend, MemoryBackend};
+
+/// A CSL-JSON, BibTeX or RIS file as the library, read when it is opened. It can't be
+/// changed from Rustero.
+pub struct FileBackend {
+    path: PathBuf,
+    library: MemoryBackend,
+}
+
+impl FileBackend {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let docs = import_from_file(path)?;
+        // Keep the keys of the file, they win over generated ones as pinned keys
+        for doc in &docs {
+            let mut doc = doc.borrow_mut();
+            if !doc.citation_key.is_empty() && doc.get_pinned_citation_key().is_none() {
+                let key = doc.citation_key.clone();
+                doc.fields.insert("citationKey".to_string(), key);
+            }
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            library: MemoryBackend::from_documents(&docs),
+        })
+    }
+}
+
+impl LibraryBackend for FileBackend {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load_items(&self) -> BackendFuture<'_, Vec<ItemData>> {
+        self.library.load_items()
+    }
+
+    fn load_creators(&self) -> BackendFuture<'_, ByItem<Creator>> {
+        self.library.load_creators()
+    }
+
+    fn load_attachments(&self) -> BackendFuture<'_, ByItem<Attachment>> {
+        self.library.load_attachments()
+    }
+
+    fn load_fields(&self) -> BackendFuture<'_, ByItem<ItemField>> {
+        self.library.load_fields()
+    }
+
+    fn load_tags(&self) -> BackendFuture<'_, ByItem<Tag>> {
+        self.library.load_tags()
+    }
+
+    fn load_notes(&self) -> BackendFuture<'_, ByItem<Note>> {
+        self.library.load_notes()
+    }
+
+    fn load_collections(&self) -> BackendFuture<'_, Vec<Collection>> {
+        self.library.load_collections()
+    }
+
+    fn load_collection_items(&self) -> BackendFuture<'_, Vec<CollectionItem>> {
+        self.library.load_collection_items()
+    }
+}
@@ -0,0 +1,123 @@
+use crate::data_structures::{
+    Attachment, Collection, CollectionItem, Creator, ItemData, ItemField, Note, RcDoc, Tag,
+};
+
+use super::{BackendFuture, ByItem, LibraryBackend};
+
+/// A library held in memory, like the documents of a file or those of a test. It is
+/// read-only, the writes of the trait answer with `read_only`.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    items: Vec<ItemData>,
+    creators: ByItem<Creator>,
+    attachments: ByItem<Attachment>,
+    fields: ByItem<ItemField>,
+    tags: ByItem<Tag>,
+    notes: ByItem<Note>,
+    collections: Vec<Collection>,
+    collection_items: Vec<CollectionItem>,
+}
+
+fn ready<'a, T: 'a>(value: T) -> BackendFuture<'a, T> {
+    Box::pin(async move { Ok(value) })
+}
+
+impl MemoryBackend {
+    /// A library of `docs` and their collections. Documents without an `itemId` get one
+    /// after the largest in `docs`.
+    pub fn from_documents(docs: &[RcDoc]) -> Self {
+        let mut library = Self::default();
+        let mut next_id = docs
+            .iter()
+            .map(|doc| doc.borrow().item_data.itemId)
+            .max()
+            .unwrap_or(0)
+            .max(0);
+        for doc in docs {
+            let doc = doc.borrow();
+            let mut item_data = doc.item_data.clone();
+            if item_data.itemId <= 0 {
+                next_id += 1;
+                item_data.itemId = next_id;
+            }
+            let item_id = item_data.itemId;
+            library.items.push(item_data);
+            library.creators.insert(item_id, doc.creators.clone());
+            if let Some(attachments) = &doc.attachments {
+                library
+                    .attachments
+                    .insert(item_id, attachments.items.clone());
+            }
+            let mut fields: Vec<ItemField> = doc
+                .fields
+                .iter()
+                .map(|(name, value)| ItemField {
+                    fieldName: name.clone(),
+                    value: value.clone(),
+                })
+                .collect();
+            fields.sort_by(|a, b| a.fieldName.cmp(&b.fieldName));
+            library.fields.insert(item_id, fields);
+            let mut tags = doc.tags.clone();
+            tags.sort_by(|a, b| a.name.cmp(&b.name));
+            library.tags.insert(item_id, tags);
+            library.notes.insert(item_id, doc.notes.clone());
+            for collection in &doc.collections {
+                let collection = collection.borrow();
+                library.collection_items.push(CollectionItem {
+                    collectionId: collection.collectionId,
+                    itemId: item_id,
+                });
+                if !library
+                    .collections
+                    .iter()
+                    .any(|known| known.collectionId == collection.collectionId)
+                {
+                    library.collections.push(collection.clone());
+                }
+            }
+        }
+        library
+            .collections
+            .sort_by(|a, b| a.collectionName.cmp(&b.collectionName));
+        library
+    }
+}
+
+impl LibraryBackend for MemoryBackend {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn load_items(&self) -> BackendFuture<'_, Vec<ItemData>> {
+        ready(self.items.clone())
+    }
+
+    fn load_creators(&self) -> BackendFuture<'_, ByItem<Creator>> {
+        ready(self.creators.clone())
+    }
+
+    fn load_attachments(&self) -> BackendFuture<'_, ByItem<Attachment>> {
+        ready(self.attachments.clone())
+    }
+
+    fn load_fields(&self) -> BackendFuture<'_, ByItem<ItemField>> {
+        ready(self.fields.clone())
+    }
+
+    fn load_tags(&self) -> BackendFuture<'_, ByItem<Tag>> {
+        ready(self.tags.clone())
+    }
+
+    fn load_notes(&self) -> BackendFuture<'_, ByItem<Note>> {
+        ready(self.notes.clone())
+    }
+
+    fn load_collections(&self) -> BackendFuture<'_, Vec<Collection>> {
+        ready(self.collections.clone())
+    }
+
+    fn load_collection_items(&self) -> BackendFuture<'_, Vec<CollectionItem>> {
+        ready(self.collection_items.clone())
+    }
+}
@@ -0,0 +1,332 @@
+//! Where the library is stored. `App` reads documents and collections through a
+//! `LibraryBackend` and sends edits to it, so the UI isn't tied to Zotero's database:
+//! `db_connector::ZoteroDb` reads and writes Zotero's SQLite database, `FileBackend` reads
+//! a CSL-JSON, BibTeX or RIS file and `MemoryBackend` holds a library in memory.
+
+mod file;
+mod memory;
+mod read_only;
+
+use std::{collections::HashMap, future::Future, path::Path, pin::Pin, rc::Rc};
+
+use anyhow::anyhow;
+
+pub use file::FileBackend;
+pub use memory::MemoryBackend;
+pub use read_only::ReadOnly;
+
+use crate::{
+    app::App,
+    connector::StagingStore,
+    data_structures::{
+        Attachment, Collection, CollectionItem, Creator, ItemData, ItemField, Note, Tag,
+    },
+    db_connector::{get_better_bibtex_keys, ItemChanges, NewItem, ZoteroDb},
+    export::citation_key::assign_citation_keys,
+    user_config::UserConfig,
+    web_sync,
+};
+
+/// What the methods of `LibraryBackend` return, the trait is used as `dyn LibraryBackend`
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = anyhow::Result<T>> + 'a>>;
+
+/// Values of all items keyed by the item they belong to
+pub type ByItem<T> = HashMap<i64, Vec<T>>;
+
+/// Group `(item id, value)` pairs by item, keeping the order of the values
+pub fn group_by_item<T>(values: impl IntoIterator<Item = (i64, T)>) -> ByItem<T> {
+    let mut groups: ByItem<T> = HashMap::new();
+    for (item_id, value) in values {
+        groups.entry(item_id).or_default().push(value);
+    }
+    groups
+}
+
+/// A library to read documents and collections from. The metadata of the items is loaded
+/// for all of them at once, a query per item is too slow for large libraries.
+///
+/// Writes are optional, backends that can't store changes answer them with `read_only`.
+/// Only `ZoteroDb` implements them: `FileBackend`, `MemoryBackend` and `ReadOnly` are
+/// read-only.
+pub trait LibraryBackend {
+    /// Shown in messages, e.g. the path of the database
+    fn name(&self) -> String;
+
+    fn load_items(&self) -> BackendFuture<'_, Vec<ItemData>>;
+    /// Creators of each item in order
+    fn load_creators(&self) -> BackendFuture<'_, ByItem<Creator>>;
+    /// Attachments by parent item
+    fn load_attachments(&self) -> BackendFuture<'_, ByItem<Attachment>>;
+    fn load_fields(&self) -> BackendFuture<'_, ByItem<ItemField>>;
+    /// Tags of each item by name
+    fn load_tags(&self) -> BackendFuture<'_, ByItem<Tag>>;
+    /// Child notes by parent item, in the order they were added
+    fn load_notes(&self) -> BackendFuture<'_, ByItem<Note>>;
+    /// All collections by name
+    fn load_collections(&self) -> BackendFuture<'_, Vec<Collection>>;
+    fn load_collection_items(&self) -> BackendFuture<'_, Vec<CollectionItem>>;
+
+    /// Whether the writes below store changes, rather than answering with `read_only`
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /// Release connections and the like, the backend isn't used afterwards
+    fn close(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// See `db_connector::write_item_changes`
+    fn write_item_changes<'a>(
+        &'a self,
+        _item_id: i64,
+        _changes: &'a ItemChanges,
+    ) -> BackendFuture<'a, ()> {
+        read_only(self.name())
+    }
+    /// Add an item, returns its item data and tags
+    fn create_item<'a>(&'a self, _item: &'a NewItem) -> BackendFuture<'a, (ItemData, Vec<Tag>)> {
+        read_only(self.name())
+    }
+    /// Store a copy of `file` as an attachment of the item `parent_id`
+    fn import_attachment<'a>(
+        &'a self,
+        _parent_id: i64,
+        _file: &'a Path,
+    ) -> BackendFuture<'a, Attachment> {
+        read_only(self.name())
+    }
+    fn create_collection<'a>(
+        &'a self,
+        _library_id: Option<i64>,
+        _parent_id: Option<i64>,
+        _name: &'a str,
+    ) -> BackendFuture<'a, Collection> {
+        read_only(self.name())
+    }
+    fn rename_collection<'a>(
+        &'a self,
+        _collection_id: i64,
+        _name: &'a str,
+    ) -> BackendFuture<'a, ()> {
+        read_only(self.name())
+    }
+    fn move_collection(
+        &self,
+        _collection_id: i64,
+        _parent_id: Option<i64>,
+    ) -> BackendFuture<'_, ()> {
+        read_only(self.name())
+    }
+    /// Delete a collection with its sub-collections, returns the ids of all of them
+    fn delete_collection(&self, _collection_id: i64) -> BackendFuture<'_, Vec<i64>> {
+        read_only(self.name())
+    }
+    /// Returns the number of items that weren't in the collection yet
+    fn add_to_collection<'a>(
+        &'a self,
+        _collection_id: i64,
+        _item_ids: &'a [i64],
+    ) -> BackendFuture<'a, usize> {
+        read_only(self.name())
+    }
+    /// Returns the number of items that were in the collection
+    fn remove_from_collection<'a>(
+        &'a self,
+        _collection_id: i64,
+        _item_ids: &'a [i64],
+    ) -> BackendFuture<'a, usize> {
+        read_only(self.name())
+    }
+    /// Returns the tag and the number of items that didn't have it yet
+    fn add_tag<'a>(
+        &'a self,
+        _item_ids: &'a [i64],
+        _name: &'a str,
+    ) -> BackendFuture<'a, (Tag, usize)> {
+        read_only(self.name())
+    }
+    /// Returns the number of items that had the tag
+    fn remove_tag<'a>(&'a self, _item_ids: &'a [i64], _name: &'a str) -> BackendFuture<'a, usize> {
+        read_only(self.name())
+    }
+    /// Rename a tag, merging it into `new_name` if that tag exists
+    fn rename_tag<'a>(&'a self, _old_name: &'a str, _new_name: &'a str) -> BackendFuture<'a, Tag> {
+        read_only(self.name())
+    }
+    /// Returns the number of items that had the tag
+    fn delete_tag<'a>(&'a self, _name: &'a str) -> BackendFuture<'a, usize> {
+        read_only(self.name())
+    }
+    /// Save the note `note_id` of the item `parent_id`, or add a new child note
+    fn save_note<'a>(
+        &'a self,
+        _parent_id: i64,
+        _note_id: Option<i64>,
+        _title: &'a str,
+        _html: &'a str,
+    ) -> BackendFuture<'a, Note> {
+        read_only(self.name())
+    }
+}
+
+/// The answer of a backend to changes it can't store
+pub fn read_only<'a, T: 'a>(name: String) -> BackendFuture<'a, T> {
+    Box::pin(async move { Err(anyhow!("The library in {} can't be changed", name)) })
+}
+
+/// The configured library: `library_file` if set, Zotero's database otherwise
+pub async fn open_backend(user_config: &UserConfig) -> anyhow::Result<Rc<dyn LibraryBackend>> {
+    let behavior = &user_config.behavior;
+    Ok(match &behavior.library_file {
+        Some(path) => Rc::new(FileBackend::open(path)?),
+        // A copy of a zotero.org library holds only that library. Pulls only download, so
+        // edits to the copy would be lost.
+        None if behavior.web_api.is_some() => Rc::new(ReadOnly(
+            ZoteroDb::open(
+                &behavior.zotero_db_path,
+                &behavior.zotero_storage_dir,
+                Some(web_sync::LIBRARY_ID),
+            )
+            .await?,
+        )),
+        None => Rc::new(
+            ZoteroDb::open(&behavior.zotero_db_path, &behavior.zotero_storage_dir, None).await?,
+        ),
+    })
+}
+
+/// Open the configured library and load all documents with their metadata, collections and
+/// citation keys into `app`.
+pub async fn load_library(app: &mut App, user_config: &UserConfig) -> anyhow::Result<()> {
+    app.load_from(open_backend(user_config).await?).await?;
+    if user_config.behavior.library_file.is_none() {
+        get_better_bibtex_keys(app, &user_config.behavior.better_bibtex_db_path).await?;
+    }
+    assign_citation_keys(&app.documents);
+    app.collection_tree
+        .build_collection_tree(&mut app.collections.items);
+    if let Some(store) = StagingStore::from_config(user_config) {
+        app.merge_documents(store.documents()?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, fs};
+
+    use super::*;
+    use crate::{
+        data_structures::{Collection, Creator, StatefulList, Tag},
+        export::new_document,
+        test_dir::TestDir,
+    };
+
+    #[test]
+    fn test_load_from_memory() {
+        let thesis = Rc::new(RefCell::new(Collection {
+            collectionId: 3,
+            libraryId: 1,
+            collectionName: "Thesis".to_string(),
+            parentCollectionId: None,
+        }));
+        let article = new_document("journalArticle", "ABCD1234");
+        {
+            let mut doc = article.borrow_mut();
+            doc.item_data.itemId = 7;
+            doc.creators.push(Creator {
+                firstName: Some("Jane".to_string()),
+                lastName: Some("Doe".to_string()),
+                creatorType: Some("author".to_string()),
+                fieldMode: Some(0),
+            });
+            doc.fields
+                .insert("publicationTitle".to_string(), "Nature".to_string());
+            doc.tags.push(Tag {
+                tagId: 1,
+                name: "rust".to_string(),
+            });
+            doc.attachments = Some(StatefulList::with_items(vec![Attachment {
+                itemId: 8,
+                linkMode: Some(0),
+                contentType: Some("application/pdf".to_string()),
+                path: Some("storage:paper.pdf".to_string()),
+                key: Some("EFGH5678".to_string()),
+                url: None,
+            }]));
+            doc.collections.push(thesis);
+        }
+        let book = new_document("book", "");
+
+        let mut app = App::default();
+        let library = MemoryBackend::from_documents(&[article, book]);
+        tokio_test::block_on(app.load_from(Rc::new(library))).unwrap();
+
+        assert_eq!(app.documents.len(), 2);
+        assert_eq!(app.backend.name(), "memory");
+        let article = app.documents[0].borrow();
+        assert_eq!(article.item_data.key, "ABCD1234");
+        assert_eq!(article.creators[0].lastName.as_deref(), Some("Doe"));
+        assert_eq!(article.fields["publicationTitle"], "Nature");
+        assert_eq!(article.tags[0].name, "rust");
+        assert_eq!(article.attachments.as_ref().unwrap().items[0].itemId, 8);
+        assert_eq!(article.collections[0].borrow().collectionName, "Thesis");
+        assert_eq!(app.collections.items.len(), 1);
+        // Without an id the document gets the next one, and a creator to show like
+        // Zotero's items without any
+        let book = app.documents[1].borrow();
+        assert_eq!(book.item_data.itemId, 8);
+        assert_eq!(book.creators, vec![Creator::default()]);
+        assert!(book.attachments.is_none());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let library = MemoryBackend::default();
+        let err = tokio_test::block_on(library.delete_tag("rust")).unwrap_err();
+        assert_eq!(err.to_string(), "The library in memory can't be changed");
+        assert!(tokio_test::block_on(library.rename_collection(1, "Thesis")).is_err());
+    }
+
+    #[test]
+    fn test_file_backend() {
+        let dir = TestDir::new("backend-test");
+        let path = dir.join("library.bib");
+        fs::write(
+            &path,
+            "@book{ferris,\n  title = {Ferris},\n  author = {Roe, Richard},\n  year = 2020,\n}\n",
+        )
+        .unwrap();
+        let library = FileBackend::open(&path).unwrap();
+        assert_eq!(library.name(), path.display().to_string());
+        assert!(tokio_test::block_on(library.delete_tag("rust")).is_err());
+
+        let mut app = App::default();
+        tokio_test::block_on(app.load_from(Rc::new(library))).unwrap();
+        assign_citation_keys(&app.documents);
+        let doc = app.documents[0].borrow();
+        assert_eq!(doc.item_data.itemId, 1);
+        assert_eq!(doc.get_title(), "Ferris");
+        // The key of the file wins over the generated one
+        assert_eq!(doc.get_citation_key(), "ferris");
+    }
+
+    #[test]
+    fn test_read_only_zotero_db() {
+        let dir = TestDir::new("read-only-test");
+        let path = dir.join("zotero.sqlite");
+        fs::File::create(&path).unwrap();
+        tokio_test::block_on(async {
+            let library = ReadOnly(ZoteroDb::open(&path, &dir, None).await.unwrap());
+            let err = library
+                .write_item_changes(1, &ItemChanges::default())
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("can't be changed"));
+            // Nothing was written to the database
+            assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+            library.close().await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,51 @@
+use crate::data_structures::{
+    Attachment, Collection, CollectionItem, Creator, ItemData, ItemField, Note, Tag,
+};
+
+use super::{BackendFuture, ByItem, LibraryBackend};
+
+/// Only the reads of another backend, its writes answer with `read_only`. The copy of a
+/// zotero.org library is opened like this: the next pull would overwrite any edit.
+pub struct ReadOnly<B>(pub B);
+
+impl<B: LibraryBackend> LibraryBackend for ReadOnly<B> {
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn load_items(&self) -> BackendFuture<'_, Vec<ItemData>> {
+        self.0.load_items()
+    }
+
+    fn load_creators(&self) -> BackendFuture<'_, ByItem<Creator>> {
+        self.0.load_creators()
+    }
+
+    fn load_attachments(&self) -> BackendFuture<'_, ByItem<Attachment>> {
+        self.0.load_attachments()
+    }
+
+    fn load_fields(&self) -> BackendFuture<'_, ByItem<ItemField>> {
+        self.0.load_fields()
+    }
+
+    fn load_tags(&self) -> BackendFuture<'_, ByItem<Tag>> {
+        self.0.load_tags()
+    }
+
+    fn load_notes(&self) -> BackendFuture<'_, ByItem<Note>> {
+        self.0.load_notes()
+    }
+
+    fn load_collections(&self) -> BackendFuture<'_, Vec<Collection>> {
+        self.0.load_collections()
+    }
+
+    fn load_collection_items(&self) -> BackendFuture<'_, Vec<CollectionItem>> {
+        self.0.load_collection_items()
+    }
+
+    fn close(&self) -> BackendFuture<'_, ()> {
+        self.0.close()
+    }
+}
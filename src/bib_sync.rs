@@ -0,0 +1,370 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::App,
+    data_structures::{RcCollection, RcDoc},
+    export::{citation_key::suffix, export_documents, ExportFormat, ExportOptions},
+};
+
+/// Detects changes of the Zotero database by polling the modification time and size of
+/// the database and its journal files. Zotero writes in bursts, so a change is only
+/// reported once the files stopped changing for one poll.
+pub struct DbWatcher {
+    paths: Vec<PathBuf>,
+    /// Fingerprint at the last reported change
+    reported: Vec<Option<(SystemTime, u64)>>,
+    /// Fingerprint at the last poll
+    last: Vec<Option<(SystemTime, u64)>>,
+}
+
+impl DbWatcher {
+    pub fn new(db_path: &Path) -> Self {
+        let db = db_path.to_string_lossy();
+        let paths: Vec<PathBuf> = vec![
+            db_path.to_path_buf(),
+            PathBuf::from(format!("{}-wal", db)),
+            PathBuf::from(format!("{}-journal", db)),
+        ];
+        let fingerprint = Self::fingerprint(&paths);
+        Self {
+            paths,
+            reported: fingerprint.clone(),
+            last: fingerprint,
+        }
+    }
+
+    fn fingerprint(paths: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+        paths
+            .iter()
+            .map(|path| {
+                let metadata = fs::metadata(path).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    }
+
+    /// Whether the database changed since the last reported change
+    pub fn poll(&mut self) -> bool {
+        let current = Self::fingerprint(&self.paths);
+        let settled = current == self.last;
+        self.last = current;
+        if settled && self.last != self.reported {
+            self.reported = self.last.clone();
+            return true;
+        }
+        false
+    }
+}
+
+/// A collection kept in sync with a bibliography file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncBinding {
+    /// Collection name, or its path from the top level collection, e.g. `Thesis/Chapter 2`
+    pub collection: String,
+    pub path: PathBuf,
+    /// Citation keys written last time by Zotero item key, so keys don't change when an
+    /// item's title or date is edited
+    #[serde(default)]
+    pub keys: HashMap<String, String>,
+}
+
+/// Bindings stored between runs, see `UserConfigPaths::sync_state_path`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncState {
+    pub bindings: Vec<SyncBinding>,
+}
+
+impl SyncState {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+    /// The binding writing to `path`, created if needed. Binding a file to another
+    /// collection starts over with fresh keys.
+    pub fn bind(&mut self, collection: &str, path: &Path) -> &mut SyncBinding {
+        let path = absolute(path);
+        let idx = match self.bindings.iter().position(|b| b.path == path) {
+            Some(idx) => idx,
+            None => {
+                self.bindings.push(SyncBinding {
+                    path,
+                    ..SyncBinding::default()
+                });
+                self.bindings.len() - 1
+            }
+        };
+        let binding = &mut self.bindings[idx];
+        if binding.collection != collection {
+            binding.collection = collection.to_string();
+            binding.keys.clear();
+        }
+        binding
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// `Parent/Child` path of a collection
+pub fn collection_path(app: &App, collection: &RcCollection) -> String {
+    let collections = app.collection_tree.get_collections();
+    let mut names = vec![collection.borrow().collectionName.clone()];
+    let mut parent_id = collection.borrow().parentCollectionId;
+    while let Some(id) = parent_id {
+        let parent = collections
+            .iter()
+            .find(|col| col.borrow().collectionId == id);
+        match parent {
+            Some(parent) => {
+                names.insert(0, parent.borrow().collectionName.clone());
+                parent_id = parent.borrow().parentCollectionId;
+            }
+            None => break,
+        }
+    }
+    names.join("/")
+}
+
+/// Find a collection by its path, or by its name when that is unique
+pub fn find_collection(app: &App, name: &str) -> anyhow::Result<RcCollection> {
+    let name = name.trim_matches('/');
+    let collections = app.collection_tree.get_collections();
+    if let Some(col) = collections
+        .iter()
+        .find(|col| collection_path(app, col) == name)
+    {
+        return Ok(col.clone());
+    }
+    let matching: Vec<&RcCollection> = collections
+        .iter()
+        .filter(|col| col.borrow().collectionName == name)
+        .collect();
+    match matching.as_slice() {
+        [col] => Ok((*col).clone()),
+        [] => Err(anyhow!("No collection named \"{}\"", name)),
+        _ => Err(anyhow!(
+            "Several collections are named \"{}\", use its path, e.g. \"{}\"",
+            name,
+            collection_path(app, matching[0])
+        )),
+    }
+}
+
+/// Reuse the keys of the last sync unless a key is pinned meanwhile. Documents new to the
+/// collection get a suffix when their key is already used.
+fn stabilize_keys(docs: &[RcDoc], previous: &HashMap<String, String>) {
+    let mut taken: HashSet<String> = docs
+        .iter()
+        .filter_map(|doc| doc.borrow().get_pinned_citation_key().map(str::to_owned))
+        .collect();
+    let mut rest = Vec::new();
+    for doc in docs {
+        if doc.borrow().get_pinned_citation_key().is_some() {
+            continue;
+        }
+        let previous_key = previous.get(&doc.borrow().item_data.key).cloned();
+        match previous_key {
+            Some(key) if taken.insert(key.clone()) => doc.borrow_mut().citation_key = key,
+            _ => rest.push(doc),
+        }
+    }
+    for doc in rest {
+        let key = doc.borrow().get_citation_key().to_owned();
+        let mut candidate = key.clone();
+        let mut idx = 0;
+        while !taken.insert(candidate.clone()) {
+            candidate = format!("{}{}", key, suffix(idx));
+            idx += 1;
+        }
+        doc.borrow_mut().citation_key = candidate;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// The file already had the same content
+    Unchanged(usize),
+    Written(usize),
+}
+
+/// Write the documents of the bound collection, including sub-collections, to the bound
+/// file if its content differs.
+pub fn sync_binding(
+    app: &App,
+    binding: &mut SyncBinding,
+    options: &ExportOptions,
+    prefer_biblatex: bool,
+) -> anyhow::Result<SyncOutcome> {
+    let format = match ExportFormat::from_path(&binding.path, prefer_biblatex) {
+        Some(format @ (ExportFormat::BibTeX | ExportFormat::BibLaTeX | ExportFormat::CslJson)) => {
+            format
+        }
+        _ => {
+            return Err(anyhow!(
+                "Cannot sync to {}, use a .bib or .json file",
+                binding.path.display()
+            ))
+        }
+    };
+    let collection = find_collection(app, &binding.collection)?;
+    // Copies, the keys kept for the file must not change those of the library
+    let mut docs: Vec<RcDoc> = app
+        .get_docs_in_collection(collection.borrow().collectionId)
+        .iter()
+        .map(|doc| Rc::new(RefCell::new(doc.borrow().clone())))
+        .collect();
+    stabilize_keys(&docs, &binding.keys);
+    // A stable order keeps diffs of the file small
+    docs.sort_by(|a, b| {
+        a.borrow()
+            .get_citation_key()
+            .cmp(b.borrow().get_citation_key())
+    });
+    binding.keys = docs
+        .iter()
+        .map(|doc| {
+            let doc = doc.borrow();
+            (doc.item_data.key.clone(), doc.get_citation_key().to_owned())
+        })
+        .collect();
+
+    let mut content = export_documents(&docs, format, options)?;
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    if fs::read_to_string(&binding.path).ok().as_deref() == Some(content.as_str()) {
+        return Ok(SyncOutcome::Unchanged(docs.len()));
+    }
+    // Replace the file at once, so LaTeX never reads a half written file
+    let tmp_path = binding.path.with_extension("rustero-tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, &binding.path)?;
+    Ok(SyncOutcome::Written(docs.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_structures::{new_test_document, Collection, Creator},
+        test_dir::TestDir,
+    };
+
+    fn library() -> App {
+        let mut app = App::default();
+        let thesis = Rc::new(RefCell::new(Collection {
+            collectionId: 1,
+            libraryId: 1,
+            collectionName: "Thesis".to_string(),
+            parentCollectionId: None,
+        }));
+        let chapter = Rc::new(RefCell::new(Collection {
+            collectionId: 2,
+            libraryId: 1,
+            collectionName: "Chapter".to_string(),
+            parentCollectionId: Some(1),
+        }));
+        app.collections.items = vec![thesis, chapter.clone()];
+        app.collection_tree
+            .build_collection_tree(&mut app.collections.items);
+        let doc = new_test_document(
+            "journalArticle",
+            &[("title", "A study"), ("date", "2020-00-00 2020")],
+            vec![Creator {
+                firstName: Some("Jane".to_string()),
+                lastName: Some("Doe".to_string()),
+                creatorType: Some("author".to_string()),
+                fieldMode: Some(0),
+            }],
+        );
+        doc.borrow_mut().citation_key = "doe2020study".to_string();
+        doc.borrow_mut().collections.push(chapter);
+        app.documents.push(doc);
+        app
+    }
+
+    #[test]
+    fn test_find_collection() {
+        let app = library();
+        let chapter = find_collection(&app, "Thesis/Chapter").unwrap();
+        assert_eq!(collection_path(&app, &chapter), "Thesis/Chapter");
+        assert_eq!(
+            find_collection(&app, "Chapter")
+                .unwrap()
+                .borrow()
+                .collectionId,
+            2
+        );
+        assert!(find_collection(&app, "Missing").is_err());
+    }
+
+    #[test]
+    fn test_sync_binding() {
+        let app = library();
+        let dir = TestDir::new("sync-test");
+        let path = dir.join("refs.bib");
+        let mut state = SyncState::default();
+        let binding = state.bind("Thesis", &path);
+        let options = ExportOptions::default();
+        assert_eq!(
+            sync_binding(&app, binding, &options, false).unwrap(),
+            SyncOutcome::Written(1)
+        );
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("@article{doe2020study,"));
+        assert_eq!(
+            sync_binding(&app, binding, &options, false).unwrap(),
+            SyncOutcome::Unchanged(1)
+        );
+
+        // Editing the title generates another key, the synced file keeps the old one
+        app.documents[0].borrow_mut().citation_key = "doe2020edited".to_string();
+        assert_eq!(
+            sync_binding(&app, binding, &options, false).unwrap(),
+            SyncOutcome::Unchanged(1)
+        );
+        assert_eq!(binding.keys["ABCD1234"], "doe2020study");
+        assert_eq!(
+            app.documents[0].borrow().get_citation_key(),
+            "doe2020edited"
+        );
+    }
+
+    #[test]
+    fn test_stabilize_keys() {
+        let app = library();
+        let doc = app.documents[0].clone();
+        doc.borrow_mut().item_data.key = "NEWITEM1".to_string();
+        let previous = HashMap::from([("ABCD1234".to_string(), "doe2020study".to_string())]);
+        let old = library().documents.remove(0);
+        old.borrow_mut().citation_key = "doe2020edited".to_string();
+        stabilize_keys(&[doc.clone(), old.clone()], &previous);
+        assert_eq!(old.borrow().get_citation_key(), "doe2020study");
+        assert_eq!(doc.borrow().get_citation_key(), "doe2020studya");
+    }
+}
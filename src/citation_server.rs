@@ -0,0 +1,515 @@
+//! A subset of Better BibTeX's HTTP API (`/better-bibtex/cayw` and `/better-bibtex/json-rpc`)
+//! served from the loaded library, so editor plugins written for Zotero work with Rustero.
+//! The same server answers the Zotero Connector, see `connector`.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::anyhow;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use serde_json::{json, Value};
+
+use crate::{
+    citeproc::{self, OutputFormat},
+    connector::{self, StagingStore},
+    data_structures::{Document, RcDoc},
+    export::{csl_json::to_csl_item, export_documents, ExportFormat, ExportOptions},
+};
+
+/// The port Better BibTeX listens on, which editor plugins expect
+pub const DEFAULT_PORT: u16 = 23119;
+
+/// Translator ids of Better BibTeX, sent by plugins instead of names
+const TRANSLATORS: [(&str, ExportFormat); 3] = [
+    ("ca65189f-8815-4afe-8c8b-8c7c15f0edca", ExportFormat::BibTeX),
+    (
+        "f895aa0d-f28e-47fe-b247-2ea77c6ed583",
+        ExportFormat::BibLaTeX,
+    ),
+    (
+        "f4b52ab0-f878-4556-85a0-c7aeedd09dfc",
+        ExportFormat::CslJson,
+    ),
+];
+
+/// Origins of the Zotero Connector browser extensions
+const EXTENSION_ORIGINS: [&str; 3] = [
+    "chrome-extension://",
+    "moz-extension://",
+    "safari-web-extension://",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+    pub fn json(value: &Value) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+}
+
+/// Whether a request with this `Origin` header may be answered. Browsers send it with the
+/// requests of web pages, which must not reach the library, editor plugins don't.
+pub fn allowed_origin(origin: Option<&str>) -> bool {
+    origin.is_none_or(|origin| {
+        EXTENSION_ORIGINS
+            .iter()
+            .any(|prefix| origin.starts_with(prefix))
+    })
+}
+
+/// Whether the `Content-Type` header announces JSON. Web pages can only send other types
+/// without asking the server first.
+pub fn is_json(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Answer a request. `pick` chooses the documents to cite for CAYW requests, an empty
+/// list means the user cancelled.
+pub fn handle(
+    method: &str,
+    url: &str,
+    body: &str,
+    docs: &[RcDoc],
+    pick: &mut dyn FnMut() -> anyhow::Result<Vec<RcDoc>>,
+    options: &ExportOptions,
+) -> Response {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    match (method, path.trim_end_matches('/')) {
+        (_, "/better-bibtex/cayw") => match cayw(&params, pick, options) {
+            Ok(body) => Response::text(200, body),
+            Err(err) => Response::text(500, err.to_string()),
+        },
+        ("POST", "/better-bibtex/json-rpc") => Response::json(&json_rpc(body, docs, options)),
+        (_, "/better-bibtex/json-rpc") => Response::text(405, "Use POST for JSON-RPC"),
+        _ => Response::text(404, format!("No endpoint {}", path)),
+    }
+}
+
+fn is_true(params: &HashMap<String, String>, name: &str) -> bool {
+    params
+        .get(name)
+        .is_some_and(|value| matches!(value.as_str(), "" | "1" | "true" | "yes"))
+}
+
+/// "Cite as you write": citations of the picked documents in the requested `format`
+fn cayw(
+    params: &HashMap<String, String>,
+    pick: &mut dyn FnMut() -> anyhow::Result<Vec<RcDoc>>,
+    options: &ExportOptions,
+) -> anyhow::Result<String> {
+    // Plugins probe whether the server is up before asking for a citation
+    if is_true(params, "probe") {
+        return Ok("ready".to_string());
+    }
+    let format = params.get("format").map(String::as_str).unwrap_or("latex");
+    let docs = pick()?;
+    if docs.is_empty() {
+        return Ok(String::new());
+    }
+    let keys: Vec<String> = docs
+        .iter()
+        .map(|doc| doc.borrow().get_citation_key().to_owned())
+        .collect();
+    let command = |default: &str| {
+        format!(
+            "\\{}{{{}}}",
+            params.get("command").map(String::as_str).unwrap_or(default),
+            keys.join(",")
+        )
+    };
+    let out = match format {
+        "latex" => command("cite"),
+        "biblatex" => command("autocite"),
+        "natbib" => command("citep"),
+        "pandoc" => {
+            let citation = keys
+                .iter()
+                .map(|key| format!("@{}", key))
+                .collect::<Vec<String>>()
+                .join("; ");
+            if is_true(params, "brackets") {
+                format!("[{}]", citation)
+            } else {
+                citation
+            }
+        }
+        "mmd" => keys.iter().map(|key| format!("[#{}][]", key)).collect(),
+        "citationkeys" => keys.join(","),
+        "json" => export_documents(&docs, ExportFormat::CslJson, options)?,
+        "formatted-citation" => {
+            let path = options
+                .csl_style
+                .as_ref()
+                .ok_or_else(|| anyhow!("No CSL style found, see the csl_style setting"))?;
+            let style = citeproc::load_style(path)?;
+            citeproc::render_documents(&style, &docs, OutputFormat::Text).citation
+        }
+        "formatted-bibliography" => export_documents(&docs, ExportFormat::Reference, options)?,
+        _ => return Err(anyhow!("Unsupported format \"{}\"", format)),
+    };
+    Ok(out)
+}
+
+fn rpc_error(id: &Value, code: i64, message: impl Into<String>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.into() },
+    })
+}
+
+fn json_rpc(body: &str, docs: &[RcDoc], options: &ExportOptions) -> Value {
+    let request: Value = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(err) => return rpc_error(&Value::Null, -32700, err.to_string()),
+    };
+    if !request.is_object() {
+        return rpc_error(&Value::Null, -32600, "Expected a JSON-RPC request object");
+    }
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let params: Vec<Value> = match request.get("params") {
+        Some(Value::Array(params)) => params.clone(),
+        Some(Value::Null) | None => Vec::new(),
+        Some(param) => vec![param.clone()],
+    };
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let result = match method {
+        "api.ready" => Ok(json!({
+            "zotero": "",
+            "betterbibtex": format!("rustero {}", env!("CARGO_PKG_VERSION")),
+        })),
+        "item.search" => {
+            let terms = params.first().and_then(Value::as_str).unwrap_or_default();
+            Ok(Value::Array(
+                search(docs, terms)
+                    .into_iter()
+                    .map(|doc| {
+                        let doc = doc.borrow();
+                        let mut item = to_csl_item(&doc);
+                        item["citekey"] = json!(doc.get_citation_key());
+                        item["libraryID"] = json!(1);
+                        item
+                    })
+                    .collect(),
+            ))
+        }
+        "item.export" => export(&params, docs, options),
+        _ => return rpc_error(&id, -32601, format!("Method not found: {}", method)),
+    };
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => rpc_error(&id, -32602, err.to_string()),
+    }
+}
+
+fn search_text(doc: &Document) -> String {
+    let creators: Vec<&str> = doc
+        .get_creators()
+        .filter_map(|creator| creator.lastName.as_deref().or(creator.firstName.as_deref()))
+        .collect();
+    format!(
+        "{} {} {} {}",
+        doc.get_citation_key(),
+        doc.get_title(),
+        creators.join(" "),
+        doc.get_year()
+    )
+}
+
+/// Documents matching all whitespace separated terms in their key, title, creators or year
+fn search(docs: &[RcDoc], terms: &str) -> Vec<RcDoc> {
+    let matcher = SkimMatcherV2::default();
+    docs.iter()
+        .filter(|doc| {
+            let text = search_text(&doc.borrow());
+            terms
+                .split_whitespace()
+                .all(|term| matcher.fuzzy_match(&text, term).is_some())
+        })
+        .cloned()
+        .collect()
+}
+
+/// `item.export(citekeys, translator)`, the translator given by name or id
+fn export(params: &[Value], docs: &[RcDoc], options: &ExportOptions) -> anyhow::Result<Value> {
+    let keys: Vec<&str> = params
+        .first()
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("Expected a list of citation keys"))?
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    let translator = params
+        .get(1)
+        .and_then(Value::as_str)
+        .unwrap_or("Better BibTeX");
+    let name = translator.to_lowercase();
+    let format = TRANSLATORS
+        .iter()
+        .find(|(id, _)| *id == name)
+        .map(|(_, format)| *format)
+        .or_else(|| match name.as_str() {
+            name if name.contains("biblatex") => Some(ExportFormat::BibLaTeX),
+            name if name.contains("bibtex") => Some(ExportFormat::BibTeX),
+            name if name.contains("json") => Some(ExportFormat::CslJson),
+            name if name.contains("ris") => Some(ExportFormat::Ris),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("Unknown translator \"{}\"", translator))?;
+
+    let mut selected = Vec::new();
+    let mut unknown = Vec::new();
+    for key in keys {
+        match docs
+            .iter()
+            .find(|doc| doc.borrow().get_citation_key() == key)
+        {
+            Some(doc) => selected.push(doc.clone()),
+            None => unknown.push(key),
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(anyhow!("Unknown citation key(s): {}", unknown.join(", ")));
+    }
+    Ok(json!(export_documents(&selected, format, options)?))
+}
+
+/// HTTP server on localhost. Requests are answered by the caller, so they can be served
+/// from the thread that owns the library.
+pub struct CitationServer {
+    server: tiny_http::Server,
+}
+
+impl CitationServer {
+    pub fn start(port: u16) -> anyhow::Result<Self> {
+        let server = tiny_http::Server::http(("127.0.0.1", port))
+            .map_err(|err| anyhow!("Cannot listen on port {}: {}", port, err))?;
+        Ok(Self { server })
+    }
+
+    /// The next request, waiting at most `timeout`
+    pub fn next_request(&self, timeout: Duration) -> Option<tiny_http::Request> {
+        self.server.recv_timeout(timeout).ok().flatten()
+    }
+
+    /// A request that is already waiting, if any
+    pub fn try_next_request(&self) -> Option<tiny_http::Request> {
+        self.server.try_recv().ok().flatten()
+    }
+
+    /// The port listened on, useful when started on port 0
+    pub fn port(&self) -> u16 {
+        self.server
+            .server_addr()
+            .to_ip()
+            .map(|addr| addr.port())
+            .unwrap_or_default()
+    }
+}
+
+/// Answer a request. Connector requests are only answered with a `staging` store, the
+/// documents it staged are returned.
+pub fn respond(
+    mut request: tiny_http::Request,
+    docs: &[RcDoc],
+    pick: &mut dyn FnMut() -> anyhow::Result<Vec<RcDoc>>,
+    options: &ExportOptions,
+    staging: Option<&StagingStore>,
+) -> anyhow::Result<Vec<RcDoc>> {
+    let mut body = Vec::new();
+    request.as_reader().read_to_end(&mut body)?;
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.to_string())
+    };
+    let origin = header("Origin");
+    let content_type = header("Content-Type");
+    let path = request.url().split('?').next().unwrap_or_default();
+    let mut staged = Vec::new();
+    let mut headers = Vec::new();
+    let response = match staging {
+        _ if !allowed_origin(origin.as_deref()) => {
+            Response::text(403, "Requests from web pages are not answered")
+        }
+        Some(store) if path.starts_with("/connector/") => {
+            headers.extend(connector::RESPONSE_HEADERS);
+            let metadata = header("X-Metadata");
+            match connector::handle(
+                store,
+                request.method().as_str(),
+                path,
+                content_type.as_deref(),
+                metadata.as_deref(),
+                &body,
+            ) {
+                Ok((response, items)) => {
+                    staged = items
+                        .iter()
+                        .map(|item| item.to_document(store.dir()))
+                        .collect();
+                    response
+                }
+                Err(err) => Response::text(400, err.to_string()),
+            }
+        }
+        _ if request.method() == &tiny_http::Method::Post
+            && path.trim_end_matches('/') == "/better-bibtex/json-rpc"
+            && !is_json(content_type.as_deref()) =>
+        {
+            Response::text(415, "JSON-RPC requests must be application/json")
+        }
+        _ => handle(
+            request.method().as_str(),
+            request.url(),
+            &String::from_utf8_lossy(&body),
+            docs,
+            pick,
+            options,
+        ),
+    };
+    let mut http_response =
+        tiny_http::Response::from_string(response.body).with_status_code(response.status);
+    headers.push(("Content-Type", response.content_type));
+    for (field, value) in headers {
+        let header = tiny_http::Header::from_bytes(field, value)
+            .map_err(|_| anyhow!("Invalid header {}", field))?;
+        http_response.add_header(header);
+    }
+    request.respond(http_response)?;
+    Ok(staged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::{new_test_document, Creator};
+
+    fn library() -> Vec<RcDoc> {
+        let doc = new_test_document(
+            "journalArticle",
+            &[("title", "A study of things"), ("date", "2020-00-00 2020")],
+            vec![Creator {
+                firstName: Some("Jane".to_string()),
+                lastName: Some("Doe".to_string()),
+                creatorType: Some("author".to_string()),
+                fieldMode: Some(0),
+            }],
+        );
+        doc.borrow_mut().citation_key = "doe2020study".to_string();
+        let other = new_test_document("book", &[("title", "Other work")], vec![]);
+        other.borrow_mut().citation_key = "other".to_string();
+        vec![doc, other]
+    }
+
+    fn get(url: &str, docs: &[RcDoc]) -> Response {
+        let picked = docs.to_vec();
+        handle(
+            "GET",
+            url,
+            "",
+            docs,
+            &mut || Ok(picked.clone()),
+            &ExportOptions::default(),
+        )
+    }
+
+    fn rpc(body: Value, docs: &[RcDoc]) -> Value {
+        let response = handle(
+            "POST",
+            "/better-bibtex/json-rpc",
+            &body.to_string(),
+            docs,
+            &mut || Ok(Vec::new()),
+            &ExportOptions::default(),
+        );
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_cayw() {
+        let docs = library();
+        assert_eq!(get("/better-bibtex/cayw?probe=true", &docs).body, "ready");
+        assert_eq!(
+            get("/better-bibtex/cayw", &docs).body,
+            "\\cite{doe2020study,other}"
+        );
+        assert_eq!(
+            get("/better-bibtex/cayw?format=pandoc&brackets=1", &docs).body,
+            "[@doe2020study; @other]"
+        );
+        assert_eq!(
+            get("/better-bibtex/cayw?format=latex&command=textcite", &docs).body,
+            "\\textcite{doe2020study,other}"
+        );
+        assert_eq!(
+            get("/better-bibtex/cayw?format=mmd", &docs).body,
+            "[#doe2020study][][#other][]"
+        );
+        assert_eq!(get("/better-bibtex/cayw?format=docx", &docs).status, 500);
+        assert_eq!(get("/connector/ping", &docs).status, 404);
+        // Nothing picked
+        assert_eq!(get("/better-bibtex/cayw", &[]).body, "");
+    }
+
+    #[test]
+    fn test_json_rpc() {
+        let docs = library();
+        let found = rpc(
+            json!({"jsonrpc": "2.0", "method": "item.search", "params": ["doe stu"], "id": 7}),
+            &docs,
+        );
+        assert_eq!(found["id"], 7);
+        let items = found["result"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["citekey"], "doe2020study");
+        assert_eq!(items[0]["title"], "A study of things");
+
+        let exported = rpc(
+            json!({"jsonrpc": "2.0", "method": "item.export",
+                   "params": [["doe2020study"], "Better BibLaTeX"], "id": 1}),
+            &docs,
+        );
+        assert!(exported["result"]
+            .as_str()
+            .unwrap()
+            .starts_with("@article{doe2020study,"));
+
+        let missing = rpc(
+            json!({"jsonrpc": "2.0", "method": "item.export", "params": [["nope"]], "id": 2}),
+            &docs,
+        );
+        assert_eq!(missing["error"]["code"], -32602);
+        let unknown = rpc(
+            json!({"jsonrpc": "2.0", "method": "user.groups", "id": 3}),
+            &docs,
+        );
+        assert_eq!(unknown["error"]["code"], -32601);
+        assert_eq!(rpc(json!("not json"), &docs)["error"]["code"], -32600);
+    }
+}
@@ -0,0 +1,153 @@
+//! Built-in `en-US` terms. Styles can override them with `cs:locale` elements.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TermForm {
+    #[default]
+    Long,
+    Short,
+    Verb,
+    VerbShort,
+    Symbol,
+}
+
+impl TermForm {
+    pub fn parse(form: Option<&str>) -> Self {
+        match form {
+            Some("short") => TermForm::Short,
+            Some("verb") => TermForm::Verb,
+            Some("verb-short") => TermForm::VerbShort,
+            Some("symbol") => TermForm::Symbol,
+            _ => TermForm::Long,
+        }
+    }
+    /// The form to try when a term doesn't have this one
+    pub fn fallback(&self) -> Option<TermForm> {
+        match self {
+            TermForm::Long => None,
+            TermForm::Short | TermForm::Verb => Some(TermForm::Long),
+            TermForm::VerbShort => Some(TermForm::Verb),
+            TermForm::Symbol => Some(TermForm::Short),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    pub single: String,
+    pub multiple: String,
+}
+
+impl Term {
+    pub fn same(value: &str) -> Self {
+        Self {
+            single: value.to_string(),
+            multiple: value.to_string(),
+        }
+    }
+    fn new(single: &str, multiple: &str) -> Self {
+        Self {
+            single: single.to_string(),
+            multiple: multiple.to_string(),
+        }
+    }
+}
+
+const MONTHS: [(&str, &str); 12] = [
+    ("January", "Jan."),
+    ("February", "Feb."),
+    ("March", "Mar."),
+    ("April", "Apr."),
+    ("May", "May"),
+    ("June", "Jun."),
+    ("July", "Jul."),
+    ("August", "Aug."),
+    ("September", "Sep."),
+    ("October", "Oct."),
+    ("November", "Nov."),
+    ("December", "Dec."),
+];
+
+/// The `en-US` term in exactly the given form
+pub fn builtin_term(name: &str, form: TermForm) -> Option<Term> {
+    use TermForm::*;
+    if let Some(month) = name.strip_prefix("month-") {
+        let (long, short) = MONTHS.get(month.parse::<usize>().ok()?.checked_sub(1)?)?;
+        return match form {
+            Long => Some(Term::same(long)),
+            Short => Some(Term::same(short)),
+            _ => None,
+        };
+    }
+    let term = match (name, form) {
+        ("and", Long) => Term::same("and"),
+        ("and", Symbol) => Term::same("&"),
+        ("et-al", Long) => Term::same("et al."),
+        ("and others", Long) => Term::same("and others"),
+        ("anonymous", Long) => Term::same("anonymous"),
+        ("anonymous", Short) => Term::same("anon."),
+        ("accessed", Long) => Term::same("accessed"),
+        ("available at", Long) => Term::same("available at"),
+        ("by", Long) => Term::same("by"),
+        ("circa", Long) => Term::same("circa"),
+        ("circa", Short) => Term::same("c."),
+        ("forthcoming", Long) => Term::same("forthcoming"),
+        ("from", Long) => Term::same("from"),
+        ("ibid", Long) => Term::same("ibid."),
+        ("in", Long) => Term::same("in"),
+        ("in press", Long) => Term::same("in press"),
+        ("no date", Long) => Term::same("no date"),
+        ("no date", Short) => Term::same("n.d."),
+        ("online", Long) => Term::same("online"),
+        ("presented at", Long) => Term::same("presented at the"),
+        ("retrieved", Long) => Term::same("retrieved"),
+        ("version", Long) => Term::same("version"),
+        ("edition", Long) => Term::new("edition", "editions"),
+        ("edition", Short) => Term::same("ed."),
+        ("page", Long) => Term::new("page", "pages"),
+        ("page", Short) => Term::new("p.", "pp."),
+        ("volume", Long) => Term::new("volume", "volumes"),
+        ("volume", Short) => Term::new("vol.", "vols."),
+        ("issue", Long) => Term::new("issue", "issues"),
+        ("issue", Short) => Term::new("no.", "nos."),
+        ("number", Long) => Term::new("number", "numbers"),
+        ("number", Short) => Term::new("no.", "nos."),
+        ("chapter", Long) => Term::new("chapter", "chapters"),
+        ("chapter", Short) => Term::new("chap.", "chaps."),
+        ("section", Long) => Term::new("section", "sections"),
+        ("section", Short) => Term::new("sec.", "secs."),
+        ("editor" | "collection-editor", Long) => Term::new("editor", "editors"),
+        ("editor" | "collection-editor", Short) => Term::new("ed.", "eds."),
+        ("editor", Verb) => Term::same("edited by"),
+        ("editor", VerbShort) => Term::same("ed."),
+        ("editortranslator", Long) => Term::new("editor & translator", "editors & translators"),
+        ("editortranslator", Short) => Term::same("ed. & trans."),
+        ("translator", Long) => Term::new("translator", "translators"),
+        ("translator", Short) => Term::same("trans."),
+        ("translator", Verb) => Term::same("translated by"),
+        ("translator", VerbShort) => Term::same("trans."),
+        ("director", Long) => Term::new("director", "directors"),
+        ("director", Short) => Term::new("dir.", "dirs."),
+        ("director", Verb) => Term::same("directed by"),
+        ("container-author", Verb) => Term::same("by"),
+        ("interviewer", Long) => Term::new("interviewer", "interviewers"),
+        ("interviewer", Verb) => Term::same("interview by"),
+        ("recipient", Verb) => Term::same("to"),
+        ("reviewed-author", Verb) => Term::same("by"),
+        ("open-quote", Long) => Term::same("\u{201c}"),
+        ("close-quote", Long) => Term::same("\u{201d}"),
+        ("open-inner-quote", Long) => Term::same("\u{2018}"),
+        ("close-inner-quote", Long) => Term::same("\u{2019}"),
+        ("page-range-delimiter", Long) => Term::same("\u{2013}"),
+        ("ordinal", Long) => Term::same("th"),
+        ("ordinal-01", Long) => Term::same("st"),
+        ("ordinal-02", Long) => Term::same("nd"),
+        ("ordinal-03", Long) => Term::same("rd"),
+        ("ordinal-11" | "ordinal-12" | "ordinal-13", Long) => Term::same("th"),
+        ("season-01", Long) => Term::same("Spring"),
+        ("season-02", Long) => Term::same("Summer"),
+        ("season-03", Long) => Term::same("Autumn"),
+        ("season-04", Long) => Term::same("Winter"),
+        _ => return None,
+    };
+    Some(term)
+}
@@ -0,0 +1,282 @@
+//! A small CSL 1.0 processor, enough to render citations and bibliographies of the
+//! common styles shipped with Zotero (APA, IEEE, Chicago author-date, ...).
+
+mod locale;
+mod render;
+mod style;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+pub use style::Style;
+
+use crate::{data_structures::RcDoc, export::csl_json::to_csl_item};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Markdown,
+    Html,
+}
+
+impl OutputFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "Plain text",
+            OutputFormat::Markdown => "Markdown",
+            OutputFormat::Html => "HTML",
+        }
+    }
+    pub fn next(&self) -> OutputFormat {
+        match self {
+            OutputFormat::Text => OutputFormat::Markdown,
+            OutputFormat::Markdown => OutputFormat::Html,
+            OutputFormat::Html => OutputFormat::Text,
+        }
+    }
+}
+
+/// A `.csl` file found in the styles directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleInfo {
+    pub id: String,
+    pub title: String,
+    pub path: PathBuf,
+}
+
+/// All styles in `dir`, sorted by title. Unreadable files are skipped.
+pub fn list_styles(dir: &Path) -> Vec<StyleInfo> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut styles: Vec<StyleInfo> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "csl"))
+        .filter_map(|path| {
+            let xml = fs::read_to_string(&path).ok()?;
+            let (id, title, _) = style::parse_info(&xml).ok()?;
+            Some(StyleInfo { id, title, path })
+        })
+        .collect();
+    styles.sort_by_key(|style| style.title.to_lowercase());
+    styles
+}
+
+/// Find a style by path, file name without extension (`apa`) or id
+pub fn find_style(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(name);
+    if path.extension().is_some_and(|ext| ext == "csl") && path.is_file() {
+        return Some(path);
+    }
+    let candidate = dir.join(format!("{}.csl", name));
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+    list_styles(dir)
+        .into_iter()
+        .find(|style| style.id == name)
+        .map(|style| style.path)
+}
+
+/// Load a style, resolving dependent styles through their parent in the same directory
+pub fn load_style(path: &Path) -> anyhow::Result<Style> {
+    let xml = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Cannot read style {}: {}", path.display(), e))?;
+    let style = style::parse_style(&xml)?;
+    let parent_id = match &style.parent {
+        Some(parent_id) => parent_id,
+        None => return Ok(style),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let parent = list_styles(dir)
+        .into_iter()
+        .find(|info| &info.id == parent_id)
+        .ok_or_else(|| anyhow!("Parent style {} of {} not found", parent_id, style.title))?;
+    let parent_xml = fs::read_to_string(&parent.path)?;
+    let mut parent_style = style::parse_style(&parent_xml)?;
+    parent_style.id = style.id;
+    parent_style.title = style.title;
+    Ok(parent_style)
+}
+
+/// A citation of all documents together and their bibliography entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bibliography {
+    pub citation: String,
+    pub entries: Vec<String>,
+}
+
+pub fn render_documents(style: &Style, docs: &[RcDoc], format: OutputFormat) -> Bibliography {
+    let items: Vec<_> = docs.iter().map(|doc| to_csl_item(&doc.borrow())).collect();
+    let (citation, entries) = render::render(style, &items, format);
+    Bibliography { citation, entries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_structures::{new_test_document, Creator},
+        test_dir::TestDir,
+    };
+
+    const AUTHOR_DATE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<style xmlns="http://purl.org/net/xbiblio/csl" class="in-text" version="1.0">
+  <info><id>http://www.zotero.org/styles/test-apa</id><title>Test APA</title></info>
+  <macro name="author">
+    <names variable="author">
+      <name name-as-sort-order="all" and="symbol" sort-separator=", " initialize-with=". "
+            delimiter=", " delimiter-precedes-last="always"/>
+      <substitute><names variable="editor"/><text variable="title"/></substitute>
+    </names>
+  </macro>
+  <macro name="author-short">
+    <names variable="author">
+      <name form="short" and="symbol" delimiter=", "/>
+      <substitute><names variable="editor"/><text variable="title"/></substitute>
+    </names>
+  </macro>
+  <macro name="issued">
+    <choose>
+      <if variable="issued"><date variable="issued"><date-part name="year"/></date></if>
+      <else><text term="no date" form="short"/></else>
+    </choose>
+  </macro>
+  <citation et-al-min="3" et-al-use-first="1">
+    <layout prefix="(" suffix=")" delimiter="; ">
+      <group delimiter=", "><text macro="author-short"/><text macro="issued"/></group>
+    </layout>
+  </citation>
+  <bibliography>
+    <sort><key macro="author"/><key variable="issued"/></sort>
+    <layout suffix=".">
+      <group delimiter=". ">
+        <text macro="author"/>
+        <text macro="issued" prefix="(" suffix=")"/>
+        <text variable="title"/>
+        <group delimiter=", ">
+          <text variable="container-title" font-style="italic"/>
+          <text variable="volume" font-style="italic"/>
+          <text variable="page"/>
+        </group>
+      </group>
+    </layout>
+  </bibliography>
+</style>"#;
+
+    const NUMERIC: &str = r#"<style xmlns="http://purl.org/net/xbiblio/csl" class="in-text" version="1.0">
+  <info><id>test-ieee</id><title>Test IEEE</title></info>
+  <citation>
+    <layout delimiter=", " prefix="[" suffix="]"><text variable="citation-number"/></layout>
+  </citation>
+  <bibliography second-field-align="flush">
+    <layout suffix=".">
+      <text variable="citation-number" prefix="[" suffix="]"/>
+      <group delimiter=", ">
+        <names variable="author"><name initialize-with=". " and="text"/></names>
+        <text variable="title" quotes="true"/>
+        <group delimiter=" ">
+          <label variable="page" form="short"/>
+          <text variable="page"/>
+        </group>
+        <date variable="issued" form="text" date-parts="year-month"/>
+      </group>
+    </layout>
+  </bibliography>
+</style>"#;
+
+    fn creator(first: &str, last: &str) -> Creator {
+        Creator {
+            firstName: Some(first.to_string()),
+            lastName: Some(last.to_string()),
+            creatorType: Some("author".to_string()),
+            fieldMode: Some(0),
+        }
+    }
+
+    fn documents() -> Vec<RcDoc> {
+        vec![
+            new_test_document(
+                "journalArticle",
+                &[
+                    ("title", "Why cite?"),
+                    ("date", "2019-03-15 2019-03-15"),
+                    ("publicationTitle", "Nature"),
+                    ("volume", "12"),
+                    ("pages", "10-20"),
+                ],
+                vec![creator("Jane Ann", "Doe"), creator("John", "Roe")],
+            ),
+            new_test_document(
+                "book",
+                &[("title", "Another book")],
+                vec![
+                    creator("Ada", "Byron"),
+                    creator("Alan", "Turing"),
+                    creator("Kurt", "Gödel"),
+                ],
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_author_date_style() {
+        let style = style::parse_style(AUTHOR_DATE).unwrap();
+        let bibliography = render_documents(&style, &documents(), OutputFormat::Text);
+        assert_eq!(
+            bibliography.citation,
+            "(Doe & Roe, 2019; Byron et al., n.d.)"
+        );
+        assert_eq!(
+            bibliography.entries,
+            vec![
+                "Byron, A., Turing, A., & Gödel, K. (n.d.). Another book.",
+                "Doe, J. A., & Roe, J. (2019). Why cite? Nature, 12, 10\u{2013}20.",
+            ]
+        );
+        let markdown = render_documents(&style, &documents(), OutputFormat::Markdown);
+        assert!(markdown.entries[1].ends_with("Why cite? *Nature*, *12*, 10\u{2013}20."));
+        let html = render_documents(&style, &documents(), OutputFormat::Html);
+        assert!(html.entries[1].contains("<i>Nature</i>, <i>12</i>"));
+    }
+
+    #[test]
+    fn test_numeric_style() {
+        let style = style::parse_style(NUMERIC).unwrap();
+        let bibliography = render_documents(&style, &documents(), OutputFormat::Text);
+        assert_eq!(bibliography.citation, "[1, 2]");
+        assert_eq!(
+            bibliography.entries,
+            vec![
+                "[1] J. A. Doe and J. Roe, \u{201c}Why cite?\u{201d}, pp. 10\u{2013}20, March 2019.",
+                "[2] A. Byron, A. Turing, and K. Gödel, \u{201c}Another book\u{201d}.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependent_style() {
+        let dir = TestDir::new("citeproc-test");
+        fs::write(dir.join("test-ieee.csl"), NUMERIC).unwrap();
+        let dependent = r#"<style xmlns="http://purl.org/net/xbiblio/csl" version="1.0">
+  <info><id>test-journal</id><title>Test Journal</title>
+    <link href="test-ieee" rel="independent-parent"/></info>
+</style>"#;
+        fs::write(dir.join("test-journal.csl"), dependent).unwrap();
+        assert_eq!(
+            find_style(&dir, "test-journal"),
+            Some(dir.join("test-journal.csl"))
+        );
+        let style = load_style(&dir.join("test-journal.csl")).unwrap();
+        assert_eq!(style.title, "Test Journal");
+        assert!(style.bibliography.is_some());
+        let titles: Vec<String> = list_styles(&dir).into_iter().map(|s| s.title).collect();
+        assert_eq!(titles, vec!["Test IEEE", "Test Journal"]);
+    }
+}
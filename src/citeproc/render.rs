@@ -0,0 +1,1004 @@
+use std::collections::HashSet;
+
+use serde_json::Value;
+
+use super::{
+    locale::{builtin_term, Term, TermForm},
+    style::{
+        Condition, DatePart, Element, Formatting, Layout, Match, NameOptions, NamesLabel, Plural,
+        SortSource, Style, Test, TextCase, TextSource,
+    },
+    OutputFormat,
+};
+
+/// A run of text with uniform formatting
+#[derive(Debug, Clone, PartialEq)]
+struct Span {
+    text: String,
+    italic: bool,
+    bold: bool,
+}
+
+type Output = Vec<Span>;
+
+fn plain(text: impl Into<String>) -> Output {
+    let text = text.into();
+    if text.is_empty() {
+        return Vec::new();
+    }
+    vec![Span {
+        text,
+        italic: false,
+        bold: false,
+    }]
+}
+
+fn join(parts: Vec<Output>, delimiter: &str) -> Output {
+    let mut out = Vec::new();
+    for part in parts.into_iter().filter(|part| !part.is_empty()) {
+        if !out.is_empty() {
+            out.extend(plain(delimiter));
+        }
+        out.extend(part);
+    }
+    out
+}
+
+fn to_plain_text(output: &Output) -> String {
+    output.iter().map(|span| span.text.as_str()).collect()
+}
+
+const STOP_WORDS: [&str; 22] = [
+    "a", "an", "and", "as", "at", "but", "by", "down", "for", "from", "in", "into", "nor", "of",
+    "on", "onto", "or", "over", "so", "the", "to", "with",
+];
+
+fn capitalize_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Capitalize words, except stop words that aren't the first word
+fn title_case(text: &str, mut first_word: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (idx, word) in text.split(' ').enumerate() {
+        if idx > 0 {
+            out.push(' ');
+        }
+        let is_stop_word = STOP_WORDS.contains(&word.to_lowercase().as_str());
+        if word.is_empty() || (is_stop_word && !first_word) {
+            out.push_str(word);
+        } else {
+            out.push_str(&capitalize_first(word));
+        }
+        if !word.is_empty() {
+            first_word = false;
+        }
+    }
+    out
+}
+
+fn apply_text_case(output: &mut Output, text_case: TextCase) {
+    for (idx, span) in output.iter_mut().enumerate() {
+        span.text = match text_case {
+            TextCase::Lowercase => span.text.to_lowercase(),
+            TextCase::Uppercase => span.text.to_uppercase(),
+            TextCase::CapitalizeFirst | TextCase::Sentence if idx == 0 => {
+                capitalize_first(&span.text)
+            }
+            TextCase::CapitalizeFirst | TextCase::Sentence => continue,
+            TextCase::CapitalizeAll => title_case(&span.text, true),
+            TextCase::Title => title_case(&span.text, idx == 0),
+        };
+    }
+}
+
+fn apply_formatting(mut output: Output, formatting: &Formatting) -> Output {
+    if output.is_empty() {
+        return output;
+    }
+    if let Some(text_case) = formatting.text_case {
+        apply_text_case(&mut output, text_case);
+    }
+    for span in output.iter_mut() {
+        if formatting.strip_periods {
+            span.text = span.text.replace('.', "");
+        }
+        span.italic |= formatting.italic;
+        span.bold |= formatting.bold;
+    }
+    if formatting.quotes {
+        output.insert(0, plain("\u{201c}").remove(0));
+        output.extend(plain("\u{201d}"));
+    }
+    let mut out = plain(formatting.prefix.as_str());
+    out.extend(output);
+    out.extend(plain(formatting.suffix.as_str()));
+    out
+}
+
+/// Merge spans and drop punctuation doubled by affixes, e.g. a title ending with `?`
+/// followed by a `.` suffix.
+fn normalize(output: Output) -> Output {
+    let mut out: Output = Vec::new();
+    for mut span in output {
+        if let Some(last) = out
+            .iter()
+            .rev()
+            .find_map(|span: &Span| span.text.chars().last())
+        {
+            if span.text.starts_with('.') && matches!(last, '.' | '?' | '!') {
+                span.text.remove(0);
+            }
+            if span.text.starts_with(' ') && last == ' ' {
+                span.text = span.text.trim_start().to_string();
+            }
+        }
+        match out.last_mut() {
+            Some(prev) if prev.italic == span.italic && prev.bold == span.bold => {
+                prev.text.push_str(&span.text)
+            }
+            _ if !span.text.is_empty() => out.push(span),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Wrap `text` in a Markdown marker, keeping surrounding whitespace outside of it
+fn markdown_emphasis(text: &str, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let start = text.len() - text.trim_start().len();
+    let end = text.trim_end().len();
+    format!(
+        "{}{}{}{}{}",
+        &text[..start],
+        marker,
+        trimmed,
+        marker,
+        &text[end..]
+    )
+}
+
+fn serialize(output: Output, format: OutputFormat) -> String {
+    let mut out = String::new();
+    for span in normalize(output) {
+        match format {
+            OutputFormat::Text => out.push_str(&span.text),
+            OutputFormat::Markdown => {
+                let mut text = span.text.replace('*', "\\*");
+                if span.italic {
+                    text = markdown_emphasis(&text, "*");
+                }
+                if span.bold {
+                    text = markdown_emphasis(&text, "**");
+                }
+                out.push_str(&text);
+            }
+            OutputFormat::Html => {
+                let mut text = escape_html(&span.text);
+                if span.italic {
+                    text = format!("<i>{}</i>", text);
+                }
+                if span.bold {
+                    text = format!("<b>{}</b>", text);
+                }
+                out.push_str(&text);
+            }
+        }
+    }
+    out
+}
+
+struct Context<'a> {
+    style: &'a Style,
+    item: &'a Value,
+    names: NameOptions,
+    /// Variables called and rendered so far, to suppress groups whose variables are empty
+    called: usize,
+    rendered: usize,
+    /// Variables already rendered through `cs:substitute`
+    suppressed: HashSet<String>,
+    rendered_vars: Vec<String>,
+    /// Render names in sort order, for sort keys
+    sorting: bool,
+}
+
+impl<'a> Context<'a> {
+    fn term(&self, name: &str, form: TermForm) -> Option<Term> {
+        let mut form = Some(form);
+        while let Some(current) = form {
+            if let Some(term) = self
+                .style
+                .terms
+                .get(&(name.to_string(), current))
+                .cloned()
+                .or_else(|| builtin_term(name, current))
+            {
+                return Some(term);
+            }
+            form = current.fallback();
+        }
+        None
+    }
+    fn term_text(&self, name: &str, form: TermForm, plural: bool) -> String {
+        match self.term(name, form) {
+            Some(term) if plural => term.multiple,
+            Some(term) => term.single,
+            None => String::new(),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Option<String> {
+        if self.suppressed.contains(name) {
+            return None;
+        }
+        match self.item.get(name)? {
+            Value::String(value) if !value.trim().is_empty() => Some(value.trim().to_string()),
+            Value::Number(number) => Some(number.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Note that a variable was called, and whether it rendered anything
+    fn track(&mut self, name: &str, rendered: bool) {
+        self.called += 1;
+        if rendered {
+            self.rendered += 1;
+            self.rendered_vars.push(name.to_string());
+        }
+    }
+}
+
+fn is_numeric(value: &str) -> bool {
+    value.chars().any(|c| c.is_ascii_digit())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '-' | '\u{2013}' | ',' | '&' | ' '))
+}
+
+fn is_plural(value: &str) -> bool {
+    value.contains(['-', '\u{2013}', ',', '&'])
+}
+
+fn format_page_range(value: &str, ctx: &Context) -> String {
+    let delimiter = ctx.term_text("page-range-delimiter", TermForm::Long, false);
+    value
+        .split(['-', '\u{2013}'])
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join(&delimiter)
+}
+
+fn ordinal(number: u32, ctx: &Context) -> String {
+    let specific = format!("ordinal-{:02}", number % 100);
+    let suffix = match ctx.term(&specific, TermForm::Long) {
+        Some(term) => term.single,
+        None => {
+            let last = format!("ordinal-{:02}", number % 10);
+            match ctx.term(&last, TermForm::Long) {
+                Some(term) if !(11..=13).contains(&(number % 100)) => term.single,
+                _ => ctx.term_text("ordinal", TermForm::Long, false),
+            }
+        }
+    };
+    format!("{}{}", number, suffix)
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Date {
+    year: Option<i64>,
+    month: Option<u32>,
+    day: Option<u32>,
+}
+
+fn parse_date(value: &Value) -> (Option<Date>, Option<String>) {
+    if let Some(parts) = value
+        .get("date-parts")
+        .and_then(|parts| parts.get(0))
+        .and_then(|parts| parts.as_array())
+    {
+        let part = |idx: usize| {
+            parts.get(idx).and_then(|part| match part {
+                Value::Number(number) => number.as_i64(),
+                Value::String(string) => string.trim().parse().ok(),
+                _ => None,
+            })
+        };
+        if let Some(year) = part(0) {
+            let date = Date {
+                year: Some(year),
+                month: part(1).map(|month| month as u32),
+                day: part(2).map(|day| day as u32),
+            };
+            return (Some(date), None);
+        }
+    }
+    let raw = value
+        .get("raw")
+        .or_else(|| value.get("literal"))
+        .and_then(|raw| raw.as_str())
+        .map(|raw| raw.to_string());
+    (None, raw)
+}
+
+fn render_date_part(part: &DatePart, date: &Date, ctx: &Context) -> Output {
+    let form = part.form.as_deref();
+    let text = match part.name.as_str() {
+        "year" => match (date.year, form) {
+            (Some(year), Some("short")) => format!("{:02}", year % 100),
+            (Some(year), _) if year < 0 => format!("{}BC", -year),
+            (Some(year), _) => year.to_string(),
+            (None, _) => String::new(),
+        },
+        "month" => match (date.month, form) {
+            (Some(month @ 1..=12), Some("short")) => {
+                ctx.term_text(&format!("month-{:02}", month), TermForm::Short, false)
+            }
+            (Some(month), Some("numeric")) => month.to_string(),
+            (Some(month), Some("numeric-leading-zeros")) => format!("{:02}", month),
+            (Some(month @ 1..=12), _) => {
+                ctx.term_text(&format!("month-{:02}", month), TermForm::Long, false)
+            }
+            // Seasons are stored as months 13 to 16
+            (Some(season @ 13..=16), _) => {
+                ctx.term_text(&format!("season-{:02}", season - 12), TermForm::Long, false)
+            }
+            _ => String::new(),
+        },
+        "day" => match (date.day, form) {
+            (Some(day), Some("numeric-leading-zeros")) => format!("{:02}", day),
+            (Some(day), Some("ordinal")) => ordinal(day, ctx),
+            (Some(day), _) => day.to_string(),
+            (None, _) => String::new(),
+        },
+        _ => String::new(),
+    };
+    apply_formatting(plain(text), &part.formatting)
+}
+
+/// The `en-US` localized date formats
+fn localized_parts(form: &str) -> Vec<DatePart> {
+    let part = |name: &str, form: &str, suffix: &str| DatePart {
+        name: name.to_string(),
+        form: Some(form.to_string()),
+        formatting: Formatting {
+            suffix: suffix.to_string(),
+            ..Formatting::default()
+        },
+    };
+    if form == "numeric" {
+        vec![
+            part("month", "numeric", "/"),
+            part("day", "numeric", "/"),
+            part("year", "long", ""),
+        ]
+    } else {
+        vec![
+            part("month", "long", " "),
+            part("day", "numeric", ", "),
+            part("year", "long", ""),
+        ]
+    }
+}
+
+fn render_date(
+    variable: &str,
+    form: Option<&str>,
+    date_parts: &str,
+    parts: &[DatePart],
+    delimiter: &str,
+    ctx: &mut Context,
+) -> Output {
+    let value = match ctx.item.get(variable) {
+        Some(value) if !ctx.suppressed.contains(variable) => value.clone(),
+        _ => {
+            ctx.track(variable, false);
+            return Vec::new();
+        }
+    };
+    let (date, raw) = parse_date(&value);
+    let date = match date {
+        Some(date) => date,
+        None => {
+            let raw = raw.unwrap_or_default();
+            ctx.track(variable, !raw.is_empty());
+            return plain(raw);
+        }
+    };
+    ctx.track(variable, true);
+
+    let rendered: Vec<Output> = match form {
+        Some(form) => {
+            let shown: Vec<&str> = date_parts.split('-').collect();
+            let mut localized: Vec<DatePart> = localized_parts(form)
+                .into_iter()
+                .filter(|part| shown.contains(&part.name.as_str()))
+                .filter(|part| match part.name.as_str() {
+                    "month" => date.month.is_some(),
+                    "day" => date.day.is_some() && date.month.is_some(),
+                    _ => true,
+                })
+                .collect();
+            // Child date-parts only override the form and formatting of localized parts
+            for part in localized.iter_mut() {
+                if let Some(custom) = parts.iter().find(|custom| custom.name == part.name) {
+                    if custom.form.is_some() {
+                        part.form = custom.form.clone();
+                    }
+                    let suffix = part.formatting.suffix.clone();
+                    part.formatting = Formatting {
+                        suffix: if custom.formatting.suffix.is_empty() {
+                            suffix
+                        } else {
+                            custom.formatting.suffix.clone()
+                        },
+                        ..custom.formatting.clone()
+                    };
+                }
+            }
+            if let Some(last) = localized.last_mut() {
+                last.formatting.suffix.clear();
+            }
+            localized
+                .iter()
+                .map(|part| render_date_part(part, &date, ctx))
+                .collect()
+        }
+        None => parts
+            .iter()
+            .map(|part| render_date_part(part, &date, ctx))
+            .collect(),
+    };
+    join(rendered, delimiter)
+}
+
+fn name_part<'v>(name: &'v Value, part: &str) -> &'v str {
+    name.get(part)
+        .and_then(|value| value.as_str())
+        .map(|value| value.trim())
+        .unwrap_or_default()
+}
+
+fn initialize(given: &str, initialize_with: &str) -> String {
+    given
+        .split_whitespace()
+        .map(|word| {
+            word.split('-')
+                .filter_map(|part| part.chars().next())
+                .map(|initial| format!("{}{}", initial.to_uppercase(), initialize_with))
+                .collect::<Vec<String>>()
+                .join(&format!("-{}", ""))
+        })
+        .collect::<Vec<String>>()
+        .join(if initialize_with.ends_with(' ') {
+            ""
+        } else {
+            " "
+        })
+        .trim_end()
+        .to_string()
+}
+
+fn format_name(name: &Value, options: &NameOptions, inverted: bool, ctx: &Context) -> String {
+    let literal = name_part(name, "literal");
+    let family = name_part(name, "family");
+    if !literal.is_empty() || family.is_empty() {
+        return if literal.is_empty() {
+            name_part(name, "given").to_string()
+        } else {
+            literal.to_string()
+        };
+    }
+    let particle = name_part(name, "non-dropping-particle");
+    let dropping = name_part(name, "dropping-particle");
+    let suffix = name_part(name, "suffix");
+    let mut given = name_part(name, "given").to_string();
+    if let Some(initialize_with) = &options.initialize_with {
+        if options.initialize != Some(false) {
+            given = initialize(&given, initialize_with);
+        }
+    }
+    let join_words = |words: &[&str]| {
+        words
+            .iter()
+            .filter(|word| !word.is_empty())
+            .copied()
+            .collect::<Vec<&str>>()
+            .join(" ")
+    };
+    if options.form.as_deref() == Some("short") {
+        return join_words(&[particle, family]);
+    }
+    if inverted {
+        let separator = options.sort_separator.as_deref().unwrap_or(", ");
+        let demote = ctx.style.demote_particle != "never";
+        let (last, first) = if demote {
+            (
+                family.to_string(),
+                join_words(&[&given, dropping, particle]),
+            )
+        } else {
+            (
+                join_words(&[particle, family]),
+                join_words(&[&given, dropping]),
+            )
+        };
+        let mut out = last;
+        for part in [first.as_str(), suffix] {
+            if !part.is_empty() {
+                out.push_str(separator);
+                out.push_str(part);
+            }
+        }
+        out
+    } else {
+        let out = join_words(&[&given, dropping, particle, family]);
+        if suffix.is_empty() {
+            out
+        } else {
+            format!("{} {}", out, suffix)
+        }
+    }
+}
+
+fn render_name_list(
+    names: &[Value],
+    options: &NameOptions,
+    formatting: &Formatting,
+    et_al_term: &str,
+    ctx: &Context,
+) -> Output {
+    if options.form.as_deref() == Some("count") {
+        return plain(names.len().to_string());
+    }
+    let delimiter = options.delimiter.as_deref().unwrap_or(", ");
+    let truncate = match (options.et_al_min, options.et_al_use_first) {
+        (Some(min), Some(first)) if names.len() >= min && first < names.len() => Some(first),
+        _ => None,
+    };
+    let shown = &names[..truncate.unwrap_or(names.len())];
+    let sort_order = if ctx.sorting {
+        "all"
+    } else {
+        options.name_as_sort_order.as_deref().unwrap_or_default()
+    };
+    let formatted: Vec<String> = shown
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let inverted = sort_order == "all" || (sort_order == "first" && idx == 0);
+            format_name(name, options, inverted, ctx)
+        })
+        .collect();
+
+    let mut out = String::new();
+    for (idx, name) in formatted.iter().enumerate() {
+        if idx > 0 {
+            let is_last = idx + 1 == formatted.len() && truncate.is_none();
+            let and = match options.and.as_deref() {
+                Some("symbol") => Some(ctx.term_text("and", TermForm::Symbol, false)),
+                Some(_) => Some(ctx.term_text("and", TermForm::Long, false)),
+                None => None,
+            };
+            match and {
+                Some(and) if is_last => {
+                    let precedes = match options.delimiter_precedes_last.as_deref() {
+                        Some("always") => true,
+                        Some("never") => false,
+                        Some("after-inverted-name") => !sort_order.is_empty() && idx == 1,
+                        _ => formatted.len() > 2,
+                    };
+                    out.push_str(if precedes { delimiter } else { " " });
+                    out.push_str(&and);
+                    out.push(' ');
+                }
+                _ => out.push_str(delimiter),
+            }
+        }
+        out.push_str(name);
+    }
+    if truncate.is_some() {
+        let precedes = match options.delimiter_precedes_et_al.as_deref() {
+            Some("always") => true,
+            Some("never") => false,
+            Some("after-inverted-name") => !sort_order.is_empty() && shown.len() == 1,
+            _ => shown.len() > 1,
+        };
+        out.push_str(if precedes { delimiter } else { " " });
+        out.push_str(&ctx.term_text(et_al_term, TermForm::Long, false));
+    }
+    apply_formatting(plain(out), formatting)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_names(
+    variables: &[String],
+    name: Option<&(NameOptions, Formatting)>,
+    inherited: Option<&(NameOptions, Formatting)>,
+    et_al_term: Option<&str>,
+    label: Option<&NamesLabel>,
+    substitute: &[Element],
+    delimiter: Option<&str>,
+    ctx: &mut Context,
+) -> Output {
+    let (options, name_formatting) = match name.or(inherited) {
+        Some((options, formatting)) => (options.merge(&ctx.names), formatting.clone()),
+        None => (ctx.names.clone(), Formatting::default()),
+    };
+    let mut lists = Vec::new();
+    for variable in variables {
+        let names = match ctx.item.get(variable).and_then(|names| names.as_array()) {
+            Some(names) if !names.is_empty() && !ctx.suppressed.contains(variable) => names,
+            _ => {
+                ctx.track(variable, false);
+                continue;
+            }
+        };
+        ctx.track(variable, true);
+        let mut list = render_name_list(
+            names,
+            &options,
+            &name_formatting,
+            et_al_term.unwrap_or("et-al"),
+            ctx,
+        );
+        if let Some(label) = label {
+            let plural = match label.plural {
+                Plural::Always => true,
+                Plural::Never => false,
+                Plural::Contextual => names.len() > 1,
+            };
+            let term = ctx.term_text(variable, label.form, plural);
+            list.extend(apply_formatting(plain(term), &label.formatting));
+        }
+        lists.push(list);
+    }
+    if !lists.is_empty() {
+        return join(lists, delimiter.unwrap_or(", "));
+    }
+
+    // Render the first substitute that produces output, and don't repeat its variables
+    let inherited = name.or(inherited).cloned();
+    for element in substitute {
+        let before = ctx.rendered_vars.len();
+        let output = match element {
+            Element::Names {
+                variables,
+                name,
+                et_al_term,
+                label,
+                substitute,
+                delimiter,
+                formatting,
+            } => {
+                let output = render_names(
+                    variables,
+                    name.as_deref(),
+                    inherited.as_ref(),
+                    et_al_term.as_deref(),
+                    label.as_ref(),
+                    substitute,
+                    delimiter.as_deref(),
+                    ctx,
+                );
+                apply_formatting(output, formatting)
+            }
+            element => render_element(element, ctx),
+        };
+        if !output.is_empty() {
+            let substituted: Vec<String> = ctx.rendered_vars[before..].to_vec();
+            ctx.suppressed.extend(substituted);
+            return output;
+        }
+    }
+    Vec::new()
+}
+
+fn test(test: &Test, ctx: &Context) -> bool {
+    match test {
+        Test::Type(ty) => ctx.item.get("type").and_then(|value| value.as_str()) == Some(ty),
+        Test::Variable(variable) => match ctx.item.get(variable) {
+            Some(Value::Array(values)) => !values.is_empty(),
+            Some(Value::String(value)) => !value.trim().is_empty(),
+            Some(Value::Null) | None => false,
+            Some(_) => true,
+        },
+        Test::IsNumeric(variable) => ctx
+            .variable(variable)
+            .is_some_and(|value| is_numeric(&value)),
+        Test::IsUncertainDate(_) => false,
+        // Every cite is a first cite, without locators
+        Test::Position(position) => position == "first",
+        Test::Locator(_) => false,
+    }
+}
+
+fn evaluate(condition: &Condition, ctx: &Context) -> bool {
+    let mut results = condition.tests.iter().map(|t| test(t, ctx));
+    match condition.mode {
+        Match::All => results.all(|result| result),
+        Match::Any => results.any(|result| result),
+        Match::None => !results.any(|result| result),
+    }
+}
+
+fn render_elements(elements: &[Element], delimiter: &str, ctx: &mut Context) -> Output {
+    let outputs = elements
+        .iter()
+        .map(|element| render_element(element, ctx))
+        .collect();
+    join(outputs, delimiter)
+}
+
+fn render_element(element: &Element, ctx: &mut Context) -> Output {
+    match element {
+        Element::Text { source, formatting } => {
+            let output = match source {
+                TextSource::Variable { name, short } => {
+                    let value = if *short {
+                        ctx.variable(&format!("{}-short", name))
+                            .or_else(|| ctx.variable(name))
+                    } else {
+                        ctx.variable(name)
+                    };
+                    ctx.track(name, value.is_some());
+                    let value = value.unwrap_or_default();
+                    if name == "page" {
+                        plain(format_page_range(&value, ctx))
+                    } else {
+                        plain(value)
+                    }
+                }
+                TextSource::Macro(name) => match ctx.style.macros.get(name) {
+                    Some(elements) => render_elements(elements, "", ctx),
+                    None => Vec::new(),
+                },
+                TextSource::Term { name, form, plural } => {
+                    plain(ctx.term_text(name, *form, *plural))
+                }
+                TextSource::Value(value) => plain(value.as_str()),
+            };
+            apply_formatting(output, formatting)
+        }
+        Element::Number {
+            variable,
+            formatting,
+        } => {
+            let value = ctx.variable(variable);
+            ctx.track(variable, value.is_some());
+            let value = value.unwrap_or_default();
+            let value = if variable == "page" {
+                format_page_range(&value, ctx)
+            } else {
+                value
+            };
+            apply_formatting(plain(value), formatting)
+        }
+        Element::Label {
+            variable,
+            form,
+            plural,
+            formatting,
+        } => {
+            let value = match ctx.variable(variable) {
+                Some(value) => value,
+                None => return Vec::new(),
+            };
+            let plural = match plural {
+                Plural::Always => true,
+                Plural::Never => false,
+                Plural::Contextual => is_plural(&value),
+            };
+            let term = ctx.term_text(variable, *form, plural);
+            apply_formatting(plain(term), formatting)
+        }
+        Element::Date {
+            variable,
+            form,
+            date_parts,
+            parts,
+            delimiter,
+            formatting,
+        } => {
+            let output = render_date(variable, form.as_deref(), date_parts, parts, delimiter, ctx);
+            apply_formatting(output, formatting)
+        }
+        Element::Names {
+            variables,
+            name,
+            et_al_term,
+            label,
+            substitute,
+            delimiter,
+            formatting,
+        } => {
+            let output = render_names(
+                variables,
+                name.as_deref(),
+                None,
+                et_al_term.as_deref(),
+                label.as_ref(),
+                substitute,
+                delimiter.as_deref(),
+                ctx,
+            );
+            apply_formatting(output, formatting)
+        }
+        Element::Group {
+            children,
+            delimiter,
+            formatting,
+        } => {
+            let (called, rendered) = (ctx.called, ctx.rendered);
+            let output = render_elements(children, delimiter, ctx);
+            // Groups calling only empty variables are suppressed entirely
+            if ctx.called > called && ctx.rendered == rendered {
+                return Vec::new();
+            }
+            apply_formatting(output, formatting)
+        }
+        Element::Choose(branches) => {
+            for branch in branches {
+                let matches = match &branch.condition {
+                    Some(condition) => evaluate(condition, ctx),
+                    None => true,
+                };
+                if matches {
+                    return render_elements(&branch.children, "", ctx);
+                }
+            }
+            Vec::new()
+        }
+    }
+}
+
+fn new_context<'a>(style: &'a Style, layout: &Layout, item: &'a Value) -> Context<'a> {
+    Context {
+        style,
+        item,
+        names: layout.names.merge(&style.names),
+        called: 0,
+        rendered: 0,
+        suppressed: HashSet::new(),
+        rendered_vars: Vec::new(),
+        sorting: false,
+    }
+}
+
+fn render_layout_item(style: &Style, layout: &Layout, item: &Value) -> Output {
+    let mut ctx = new_context(style, layout, item);
+    if layout.second_field_align && layout.elements.len() > 1 {
+        let first = render_element(&layout.elements[0], &mut ctx);
+        let rest = render_elements(&layout.elements[1..], "", &mut ctx);
+        join(vec![first, rest], " ")
+    } else {
+        render_elements(&layout.elements, "", &mut ctx)
+    }
+}
+
+/// Comparable value of a sort key; `None` sorts last
+fn sort_value(style: &Style, layout: &Layout, source: &SortSource, item: &Value) -> Option<String> {
+    let mut ctx = new_context(style, layout, item);
+    ctx.sorting = true;
+    let value = match source {
+        SortSource::Macro(name) => {
+            let elements = style.macros.get(name)?;
+            to_plain_text(&render_elements(elements, "", &mut ctx))
+        }
+        SortSource::Variable(variable) => match item.get(variable)? {
+            Value::Array(names) => {
+                let options = ctx.names.clone();
+                to_plain_text(&render_name_list(
+                    names,
+                    &options,
+                    &Formatting::default(),
+                    "et-al",
+                    &ctx,
+                ))
+            }
+            Value::Object(_) => match parse_date(item.get(variable)?) {
+                (Some(date), _) => format!(
+                    "{:08}{:02}{:02}",
+                    date.year.unwrap_or(0) + 10000,
+                    date.month.unwrap_or(0),
+                    date.day.unwrap_or(0)
+                ),
+                (None, raw) => raw?,
+            },
+            Value::Number(number) => format!("{:020}", number.as_f64()? as i64),
+            Value::String(value) if value.chars().all(|c| c.is_ascii_digit()) => {
+                format!("{:0>20}", value)
+            }
+            Value::String(value) => value.to_string(),
+            _ => return None,
+        },
+    };
+    Some(value.to_lowercase()).filter(|value| !value.is_empty())
+}
+
+/// Indices of `items` in the order given by the sort keys of `layout`
+fn sort_order(style: &Style, layout: &Layout, items: &[Value]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    if layout.sort.is_empty() {
+        return order;
+    }
+    let keys: Vec<Vec<Option<String>>> = items
+        .iter()
+        .map(|item| {
+            layout
+                .sort
+                .iter()
+                .map(|key| sort_value(style, layout, &key.source, item))
+                .collect()
+        })
+        .collect();
+    order.sort_by(|&a, &b| {
+        for (idx, key) in layout.sort.iter().enumerate() {
+            let ordering = match (&keys[a][idx], &keys[b][idx]) {
+                (Some(x), Some(y)) if key.descending => y.cmp(x),
+                (Some(x), Some(y)) => x.cmp(y),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            };
+            if ordering != std::cmp::Ordering::Equal {
+                return ordering;
+            }
+        }
+        std::cmp::Ordering::Equal
+    });
+    order
+}
+
+/// A citation for all items and one bibliography entry per item
+pub fn render(style: &Style, items: &[Value], format: OutputFormat) -> (String, Vec<String>) {
+    // Number the items in bibliography order, so citation numbers match the entries
+    let mut items = items.to_vec();
+    let bibliography_order = match &style.bibliography {
+        Some(layout) => sort_order(style, layout, &items),
+        None => (0..items.len()).collect(),
+    };
+    for (number, &idx) in bibliography_order.iter().enumerate() {
+        if let Value::Object(map) = &mut items[idx] {
+            map.insert("citation-number".to_string(), Value::from(number + 1));
+        }
+    }
+
+    let citation = match &style.citation {
+        Some(layout) => {
+            let cites = sort_order(style, layout, &items)
+                .into_iter()
+                .map(|idx| render_layout_item(style, layout, &items[idx]))
+                .collect();
+            let output = apply_formatting(join(cites, &layout.delimiter), &layout.formatting);
+            serialize(output, format)
+        }
+        None => String::new(),
+    };
+    let entries = match &style.bibliography {
+        Some(layout) => bibliography_order
+            .iter()
+            .map(|&idx| {
+                let output = render_layout_item(style, layout, &items[idx]);
+                serialize(apply_formatting(output, &layout.formatting), format)
+            })
+            .filter(|entry| !entry.is_empty())
+            .collect(),
+        None => Vec::new(),
+    };
+    (citation, entries)
+}
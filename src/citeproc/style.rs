@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use roxmltree::Node;
+
+use super::locale::{Term, TermForm};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextCase {
+    Lowercase,
+    Uppercase,
+    CapitalizeFirst,
+    CapitalizeAll,
+    Title,
+    Sentence,
+}
+
+/// Affixes and formatting attributes shared by most rendering elements
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Formatting {
+    pub prefix: String,
+    pub suffix: String,
+    pub italic: bool,
+    pub bold: bool,
+    pub quotes: bool,
+    pub strip_periods: bool,
+    pub text_case: Option<TextCase>,
+}
+
+impl Formatting {
+    fn parse(node: Node) -> Self {
+        let attr = |name: &str| node.attribute(name).unwrap_or_default();
+        Self {
+            prefix: attr("prefix").to_string(),
+            suffix: attr("suffix").to_string(),
+            italic: attr("font-style") == "italic",
+            bold: attr("font-weight") == "bold",
+            quotes: attr("quotes") == "true",
+            strip_periods: attr("strip-periods") == "true",
+            text_case: match attr("text-case") {
+                "lowercase" => Some(TextCase::Lowercase),
+                "uppercase" => Some(TextCase::Uppercase),
+                "capitalize-first" => Some(TextCase::CapitalizeFirst),
+                "capitalize-all" => Some(TextCase::CapitalizeAll),
+                "title" => Some(TextCase::Title),
+                "sentence" => Some(TextCase::Sentence),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plural {
+    Contextual,
+    Always,
+    Never,
+}
+
+impl Plural {
+    fn parse(node: Node) -> Self {
+        match node.attribute("plural") {
+            Some("always") => Plural::Always,
+            Some("never") => Plural::Never,
+            _ => Plural::Contextual,
+        }
+    }
+}
+
+/// Inheritable name options. `None` means "inherit from the enclosing element".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameOptions {
+    pub and: Option<String>,
+    pub delimiter: Option<String>,
+    pub delimiter_precedes_last: Option<String>,
+    pub delimiter_precedes_et_al: Option<String>,
+    pub et_al_min: Option<usize>,
+    pub et_al_use_first: Option<usize>,
+    pub initialize: Option<bool>,
+    pub initialize_with: Option<String>,
+    pub name_as_sort_order: Option<String>,
+    pub sort_separator: Option<String>,
+    pub form: Option<String>,
+}
+
+impl NameOptions {
+    fn parse(node: Node) -> Self {
+        let attr = |name: &str| node.attribute(name).map(|value| value.to_string());
+        let number = |name: &str| node.attribute(name).and_then(|value| value.parse().ok());
+        Self {
+            and: attr("and"),
+            // On `cs:style` and friends the name delimiter is called `name-delimiter`
+            delimiter: if node.has_tag_name("name") {
+                attr("delimiter")
+            } else {
+                attr("name-delimiter")
+            },
+            delimiter_precedes_last: attr("delimiter-precedes-last"),
+            delimiter_precedes_et_al: attr("delimiter-precedes-et-al"),
+            et_al_min: number("et-al-min"),
+            et_al_use_first: number("et-al-use-first"),
+            initialize: node.attribute("initialize").map(|value| value != "false"),
+            initialize_with: attr("initialize-with"),
+            name_as_sort_order: attr("name-as-sort-order"),
+            sort_separator: attr("sort-separator"),
+            form: attr("form"),
+        }
+    }
+
+    /// Options of `self`, falling back to `outer` for the ones it doesn't set
+    pub fn merge(&self, outer: &NameOptions) -> NameOptions {
+        NameOptions {
+            and: self.and.clone().or_else(|| outer.and.clone()),
+            delimiter: self.delimiter.clone().or_else(|| outer.delimiter.clone()),
+            delimiter_precedes_last: self
+                .delimiter_precedes_last
+                .clone()
+                .or_else(|| outer.delimiter_precedes_last.clone()),
+            delimiter_precedes_et_al: self
+                .delimiter_precedes_et_al
+                .clone()
+                .or_else(|| outer.delimiter_precedes_et_al.clone()),
+            et_al_min: self.et_al_min.or(outer.et_al_min),
+            et_al_use_first: self.et_al_use_first.or(outer.et_al_use_first),
+            initialize: self.initialize.or(outer.initialize),
+            initialize_with: self
+                .initialize_with
+                .clone()
+                .or_else(|| outer.initialize_with.clone()),
+            name_as_sort_order: self
+                .name_as_sort_order
+                .clone()
+                .or_else(|| outer.name_as_sort_order.clone()),
+            sort_separator: self
+                .sort_separator
+                .clone()
+                .or_else(|| outer.sort_separator.clone()),
+            form: self.form.clone().or_else(|| outer.form.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextSource {
+    Variable {
+        name: String,
+        short: bool,
+    },
+    Macro(String),
+    Term {
+        name: String,
+        form: TermForm,
+        plural: bool,
+    },
+    Value(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatePart {
+    pub name: String,
+    pub form: Option<String>,
+    pub formatting: Formatting,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamesLabel {
+    pub form: TermForm,
+    pub plural: Plural,
+    pub formatting: Formatting,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    Type(String),
+    Variable(String),
+    IsNumeric(String),
+    IsUncertainDate(String),
+    Position(String),
+    Locator(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    All,
+    Any,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub tests: Vec<Test>,
+    pub mode: Match,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    /// `None` for `cs:else`
+    pub condition: Option<Condition>,
+    pub children: Vec<Element>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Text {
+        source: TextSource,
+        formatting: Formatting,
+    },
+    Number {
+        variable: String,
+        formatting: Formatting,
+    },
+    Label {
+        variable: String,
+        form: TermForm,
+        plural: Plural,
+        formatting: Formatting,
+    },
+    Date {
+        variable: String,
+        /// `text` or `numeric` for localized dates
+        form: Option<String>,
+        date_parts: String,
+        parts: Vec<DatePart>,
+        delimiter: String,
+        formatting: Formatting,
+    },
+    Names {
+        variables: Vec<String>,
+        /// `None` when there is no `cs:name` child, so it is inherited when substituting.
+        /// Boxed so the name options don't make every element as large as this one.
+        name: Option<Box<(NameOptions, Formatting)>>,
+        et_al_term: Option<String>,
+        label: Option<NamesLabel>,
+        substitute: Vec<Element>,
+        delimiter: Option<String>,
+        formatting: Formatting,
+    },
+    Group {
+        children: Vec<Element>,
+        delimiter: String,
+        formatting: Formatting,
+    },
+    Choose(Vec<Branch>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortSource {
+    Variable(String),
+    Macro(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub source: SortSource,
+    pub descending: bool,
+}
+
+/// `cs:citation` or `cs:bibliography` with its `cs:layout`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub elements: Vec<Element>,
+    pub formatting: Formatting,
+    pub delimiter: String,
+    pub sort: Vec<SortKey>,
+    pub names: NameOptions,
+    /// Numbered bibliographies put the first field (the number) in its own column
+    pub second_field_align: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    pub id: String,
+    pub title: String,
+    /// `href` of the `independent-parent` link of dependent styles
+    pub parent: Option<String>,
+    pub names: NameOptions,
+    /// `demote-non-dropping-particle`, `display-and-sort` by default
+    pub demote_particle: String,
+    pub macros: HashMap<String, Vec<Element>>,
+    pub citation: Option<Layout>,
+    pub bibliography: Option<Layout>,
+    pub terms: HashMap<(String, TermForm), Term>,
+}
+
+fn children<'a, 'i>(node: Node<'a, 'i>) -> impl Iterator<Item = Node<'a, 'i>> {
+    node.children().filter(|child| child.is_element())
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    children(node).find(|child| child.has_tag_name(name))
+}
+
+fn parse_children(node: Node) -> Result<Vec<Element>> {
+    children(node)
+        .filter_map(|node| parse_element(node).transpose())
+        .collect()
+}
+
+fn parse_condition(node: Node) -> Condition {
+    let mut tests = Vec::new();
+    for attribute in node.attributes() {
+        let values = attribute
+            .value()
+            .split_whitespace()
+            .map(|value| value.to_string());
+        match attribute.name() {
+            "type" => tests.extend(values.map(Test::Type)),
+            "variable" => tests.extend(values.map(Test::Variable)),
+            "is-numeric" => tests.extend(values.map(Test::IsNumeric)),
+            "is-uncertain-date" => tests.extend(values.map(Test::IsUncertainDate)),
+            "position" => tests.extend(values.map(Test::Position)),
+            "locator" => tests.extend(values.map(Test::Locator)),
+            _ => {}
+        }
+    }
+    let mode = match node.attribute("match") {
+        Some("any") => Match::Any,
+        Some("none") => Match::None,
+        _ => Match::All,
+    };
+    Condition { tests, mode }
+}
+
+/// Parse a rendering element, `None` for elements that aren't rendered (e.g. `cs:sort`)
+fn parse_element(node: Node) -> Result<Option<Element>> {
+    let attr = |name: &str| node.attribute(name).unwrap_or_default().to_string();
+    let formatting = Formatting::parse(node);
+    let element = match node.tag_name().name() {
+        "text" => {
+            let source = if let Some(variable) = node.attribute("variable") {
+                TextSource::Variable {
+                    name: variable.to_string(),
+                    short: node.attribute("form") == Some("short"),
+                }
+            } else if let Some(name) = node.attribute("macro") {
+                TextSource::Macro(name.to_string())
+            } else if let Some(name) = node.attribute("term") {
+                TextSource::Term {
+                    name: name.to_string(),
+                    form: TermForm::parse(node.attribute("form")),
+                    plural: node.attribute("plural") == Some("true"),
+                }
+            } else if let Some(value) = node.attribute("value") {
+                TextSource::Value(value.to_string())
+            } else {
+                return Err(anyhow!("cs:text without variable, macro, term or value"));
+            };
+            Element::Text { source, formatting }
+        }
+        "number" => Element::Number {
+            variable: attr("variable"),
+            formatting,
+        },
+        "label" => Element::Label {
+            variable: attr("variable"),
+            form: TermForm::parse(node.attribute("form")),
+            plural: Plural::parse(node),
+            formatting,
+        },
+        "date" => Element::Date {
+            variable: attr("variable"),
+            form: node.attribute("form").map(|form| form.to_string()),
+            date_parts: node
+                .attribute("date-parts")
+                .unwrap_or("year-month-day")
+                .to_string(),
+            parts: children(node)
+                .filter(|child| child.has_tag_name("date-part"))
+                .map(|child| DatePart {
+                    name: child.attribute("name").unwrap_or_default().to_string(),
+                    form: child.attribute("form").map(|form| form.to_string()),
+                    formatting: Formatting::parse(child),
+                })
+                .collect(),
+            delimiter: attr("delimiter"),
+            formatting,
+        },
+        "names" => Element::Names {
+            variables: attr("variable")
+                .split_whitespace()
+                .map(|variable| variable.to_string())
+                .collect(),
+            name: child(node, "name")
+                .map(|name| Box::new((NameOptions::parse(name), Formatting::parse(name)))),
+            et_al_term: child(node, "et-al")
+                .map(|et_al| et_al.attribute("term").unwrap_or("et-al").to_string()),
+            label: child(node, "label").map(|label| NamesLabel {
+                form: TermForm::parse(label.attribute("form")),
+                plural: Plural::parse(label),
+                formatting: Formatting::parse(label),
+            }),
+            substitute: match child(node, "substitute") {
+                Some(substitute) => parse_children(substitute)?,
+                None => Vec::new(),
+            },
+            delimiter: node.attribute("delimiter").map(|value| value.to_string()),
+            formatting,
+        },
+        "group" => Element::Group {
+            children: parse_children(node)?,
+            delimiter: attr("delimiter"),
+            formatting,
+        },
+        "choose" => Element::Choose(
+            children(node)
+                .map(|branch| {
+                    Ok(Branch {
+                        condition: if branch.has_tag_name("else") {
+                            None
+                        } else {
+                            Some(parse_condition(branch))
+                        },
+                        children: parse_children(branch)?,
+                    })
+                })
+                .collect::<Result<Vec<Branch>>>()?,
+        ),
+        _ => return Ok(None),
+    };
+    Ok(Some(element))
+}
+
+fn parse_layout(node: Node) -> Result<Layout> {
+    let layout = child(node, "layout").ok_or_else(|| anyhow!("Missing cs:layout"))?;
+    let sort = match child(node, "sort") {
+        Some(sort) => children(sort)
+            .filter_map(|key| {
+                let source = if let Some(variable) = key.attribute("variable") {
+                    SortSource::Variable(variable.to_string())
+                } else {
+                    SortSource::Macro(key.attribute("macro")?.to_string())
+                };
+                Some(SortKey {
+                    source,
+                    descending: key.attribute("sort") == Some("descending"),
+                })
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(Layout {
+        elements: parse_children(layout)?,
+        formatting: Formatting::parse(layout),
+        delimiter: layout
+            .attribute("delimiter")
+            .unwrap_or_default()
+            .to_string(),
+        sort,
+        names: NameOptions::parse(node),
+        second_field_align: node.attribute("second-field-align").is_some(),
+    })
+}
+
+fn parse_terms(node: Node, terms: &mut HashMap<(String, TermForm), Term>) {
+    for locale in children(node).filter(|child| child.has_tag_name("locale")) {
+        // Only English overrides apply, the built-in terms are English
+        if matches!(locale.attribute("lang"), Some(lang) if !lang.starts_with("en")) {
+            continue;
+        }
+        let term_nodes = child(locale, "terms").into_iter().flat_map(children);
+        for term in term_nodes {
+            let name = term.attribute("name").unwrap_or_default().to_string();
+            let form = TermForm::parse(term.attribute("form"));
+            let value = match (child(term, "single"), child(term, "multiple")) {
+                (Some(single), Some(multiple)) => Term {
+                    single: single.text().unwrap_or_default().to_string(),
+                    multiple: multiple.text().unwrap_or_default().to_string(),
+                },
+                _ => Term::same(term.text().unwrap_or_default()),
+            };
+            terms.insert((name, form), value);
+        }
+    }
+}
+
+/// Only the `cs:info` of a style, for listing styles without parsing all of them
+pub fn parse_info(xml: &str) -> Result<(String, String, Option<String>)> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let info = child(doc.root_element(), "info").ok_or_else(|| anyhow!("Missing cs:info"))?;
+    let text = |name: &str| {
+        child(info, name)
+            .and_then(|node| node.text())
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+    let parent = children(info)
+        .find(|link| {
+            link.has_tag_name("link") && link.attribute("rel") == Some("independent-parent")
+        })
+        .and_then(|link| link.attribute("href"))
+        .map(|href| href.to_string());
+    Ok((text("id"), text("title"), parent))
+}
+
+pub fn parse_style(xml: &str) -> Result<Style> {
+    let (id, title, parent) = parse_info(xml)?;
+    let doc = roxmltree::Document::parse(xml)?;
+    let root = doc.root_element();
+    if !root.has_tag_name("style") {
+        return Err(anyhow!("Not a CSL style"));
+    }
+    let mut macros = HashMap::new();
+    for node in children(root).filter(|child| child.has_tag_name("macro")) {
+        let name = node.attribute("name").unwrap_or_default().to_string();
+        macros.insert(name, parse_children(node)?);
+    }
+    let mut terms = HashMap::new();
+    parse_terms(root, &mut terms);
+    Ok(Style {
+        id,
+        title,
+        parent,
+        names: NameOptions::parse(root),
+        demote_particle: root
+            .attribute("demote-non-dropping-particle")
+            .unwrap_or("display-and-sort")
+            .to_string(),
+        macros,
+        citation: child(root, "citation").map(parse_layout).transpose()?,
+        bibliography: child(root, "bibliography").map(parse_layout).transpose()?,
+        terms,
+    })
+}
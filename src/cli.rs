@@ -0,0 +1,853 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    rc::Rc,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use clap::{crate_version, App as ClapApp, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::{json, Value};
+
+use crate::{
+    app::{search_documents, App},
+    backend::load_library,
+    bib_sync::{collection_path, find_collection, sync_binding, DbWatcher, SyncOutcome, SyncState},
+    citation_server::{self, CitationServer},
+    connector::StagingStore,
+    data_structures::{Creator, Document, RcDoc, ResolvedAttachment},
+    export::{
+        cited_keys::scan_paths, export_documents, export_to_file, ExportFormat, ExportOptions,
+    },
+    opener::OpenContext,
+    ui::UIBlockType,
+    user_config::UserConfig,
+    web_sync,
+};
+
+fn collection_arg() -> Arg<'static, 'static> {
+    Arg::with_name("collection")
+        .short("c")
+        .long("collection")
+        .takes_value(true)
+        .help("Only documents in this collection or its sub-collections")
+}
+
+fn format_arg(default: &'static str) -> Arg<'static, 'static> {
+    let arg = Arg::with_name("format")
+        .short("f")
+        .long("format")
+        .takes_value(true)
+        .default_value(default);
+    ExportFormat::all()
+        .iter()
+        .fold(arg, |arg, format| arg.possible_value(format.id()))
+}
+
+pub fn build_cli() -> ClapApp<'static, 'static> {
+    ClapApp::new("rustero")
+        .version(crate_version!())
+        .about("A terminal UI for your Zotero library")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .global(true)
+                .help("Print documents as JSON (search, list and show)"),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Fuzzy search the library, like the search of the UI")
+                .arg(
+                    Arg::with_name("query")
+                        .help("Search query, start it with @ to search citation keys")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("by")
+                        .long("by")
+                        .takes_value(true)
+                        .possible_values(&["title", "creator", "year"])
+                        .default_value("title")
+                        .help("What the query is matched against"),
+                )
+                .arg(collection_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List the documents of the library")
+                .arg(collection_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Show all fields of a document")
+                .arg(
+                    Arg::with_name("key")
+                        .help("Citation key or Zotero item key")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("open")
+                .about("Open the attachment of a document, or its URL")
+                .arg(
+                    Arg::with_name("key")
+                        .help("Citation key or Zotero item key")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about(
+                    "Export documents, all of them unless keys, a collection or a query are given",
+                )
+                .arg(
+                    Arg::with_name("keys")
+                        .help("Citation keys or Zotero item keys")
+                        .multiple(true),
+                )
+                .arg(format_arg("bibtex"))
+                .arg(collection_arg())
+                .arg(
+                    Arg::with_name("query")
+                        .short("q")
+                        .long("query")
+                        .takes_value(true)
+                        .help("Only documents whose title matches this search query"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("File to write instead of standard output"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pick")
+                .about("Print one line per document for fzf, rofi or dmenu, or act on the picked lines")
+                .after_help(
+                    "EXAMPLES:\n    rustero pick | fzf | rustero pick --open\n    \
+                     rustero pick | rofi -dmenu | rustero pick --key",
+                )
+                .arg(
+                    Arg::with_name("selection")
+                        .help("Picked line, read from standard input when not given"),
+                )
+                .arg(
+                    Arg::with_name("template")
+                        .short("t")
+                        .long("template")
+                        .takes_value(true)
+                        .help("Line template [default: pick_template]"),
+                )
+                .arg(
+                    Arg::with_name("open")
+                        .long("open")
+                        .help("Open the attachment of the picked documents"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .short("k")
+                        .long("key")
+                        .conflicts_with("open")
+                        .help("Print the citation key of the picked documents"),
+                )
+                .arg(collection_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve Better BibTeX's citation endpoints for editor plugins")
+                .arg(
+                    Arg::with_name("port")
+                        .short("p")
+                        .long("port")
+                        .takes_value(true)
+                        .help("Port to listen on [default: citation_server_port]"),
+                )
+                .arg(
+                    Arg::with_name("connector")
+                        .long("connector")
+                        .help("Save items sent by the Zotero Connector browser extension"),
+                )
+                .arg(
+                    Arg::with_name("picker")
+                        .long("picker")
+                        .takes_value(true)
+                        .help(
+                            "Command picking documents for \"cite as you write\", e.g. \
+                             \"rofi -dmenu -multi-select\". It gets the lines of `pick` on \
+                             standard input.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cite")
+                .about("Print citations of documents, formatted references by default")
+                .arg(
+                    Arg::with_name("keys")
+                        .help("Citation keys or Zotero item keys")
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(format_arg("reference")),
+        )
+        .subcommand(SubCommand::with_name("pull").about(
+            "Download the changes of the library on zotero.org into Rustero's copy \
+             (needs zotero_api_key and zotero_library)",
+        ))
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Write a collection to a .bib or CSL-JSON file and keep it up to date")
+                .arg(
+                    Arg::with_name("collection")
+                        .help("Collection name or path, e.g. \"Thesis/Chapter 2\"")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .help("File to write, .bib or .json")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("watch")
+                        .short("w")
+                        .long("watch")
+                        .help("Keep running and rewrite the file when the library changes"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("2")
+                        .help("Seconds between checks of the Zotero database with --watch"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cited")
+                .about("Write a bibliography of the documents cited in a LaTeX or Markdown project")
+                .arg(
+                    Arg::with_name("paths")
+                        .help("Files or directories with .aux, .tex or Markdown files")
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("File to write, .bib, .json or .ris [default: export_path]"),
+                ),
+        )
+}
+
+async fn load_app(user_config: &UserConfig) -> anyhow::Result<App> {
+    let mut app = App::default();
+    load_library(&mut app, user_config).await?;
+    Ok(app)
+}
+
+fn sync_state_path(user_config: &UserConfig) -> anyhow::Result<&Path> {
+    user_config
+        .path_to_config
+        .as_ref()
+        .map(|paths| paths.sync_state_path.as_path())
+        .ok_or_else(|| anyhow!("No configuration directory"))
+}
+
+/// Sync once and persist the keys that were written
+async fn sync_once(
+    user_config: &UserConfig,
+    state: &mut SyncState,
+    collection: &str,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let app = load_app(user_config).await?;
+    let binding = state.bind(collection, output);
+    let outcome = sync_binding(
+        &app,
+        binding,
+        &ExportOptions::from_config(user_config),
+        user_config.behavior.prefer_biblatex,
+    )?;
+    match outcome {
+        SyncOutcome::Written(count) => {
+            println!("Wrote {} document(s) to {}", count, output.display())
+        }
+        SyncOutcome::Unchanged(_) => println!("{} is up to date", output.display()),
+    }
+    state.save(sync_state_path(user_config)?)?;
+    app.backend.close().await
+}
+
+pub async fn run_sync(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let collection = matches.value_of("collection").unwrap();
+    let output = Path::new(matches.value_of("output").unwrap());
+    let interval: u64 = matches
+        .value_of("interval")
+        .unwrap()
+        .parse()
+        .map_err(|_| anyhow!("--interval must be a number of seconds"))?;
+    let mut state = SyncState::load(sync_state_path(user_config)?)?;
+
+    sync_once(user_config, &mut state, collection, output).await?;
+    if !matches.is_present("watch") {
+        return Ok(());
+    }
+    let mut watcher = DbWatcher::new(user_config.behavior.library_path());
+    let mut pending = false;
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+        pending |= watcher.poll();
+        if pending {
+            // Zotero may hold a write lock for a moment, retry until the sync succeeds
+            match sync_once(user_config, &mut state, collection, output).await {
+                Ok(()) => pending = false,
+                Err(err) => eprintln!("Sync failed: {}", err),
+            }
+        }
+    }
+}
+
+pub async fn run_pull(user_config: &UserConfig) -> anyhow::Result<()> {
+    let web_api = user_config.behavior.web_api.as_ref().ok_or_else(|| {
+        anyhow!("Set zotero_api_key and zotero_library in the behavior config to use zotero.org")
+    })?;
+    let report = web_sync::pull(web_api, &user_config.behavior.zotero_db_path).await?;
+    println!("{}", report);
+    Ok(())
+}
+
+/// The document with citation key `key`, ignoring case if no key matches exactly
+fn find_cited<'a>(docs: &'a [RcDoc], key: &str) -> Option<&'a RcDoc> {
+    docs.iter()
+        .find(|doc| doc.borrow().get_citation_key() == key)
+        .or_else(|| {
+            docs.iter()
+                .find(|doc| doc.borrow().get_citation_key().eq_ignore_ascii_case(key))
+        })
+}
+
+pub async fn run_cited(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let paths: Vec<&Path> = matches.values_of("paths").unwrap().map(Path::new).collect();
+    let output = matches
+        .value_of("output")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| user_config.behavior.export_path.clone());
+    let format = ExportFormat::from_path(&output, user_config.behavior.prefer_biblatex)
+        .filter(|format| {
+            matches!(
+                format,
+                ExportFormat::BibTeX
+                    | ExportFormat::BibLaTeX
+                    | ExportFormat::CslJson
+                    | ExportFormat::Ris
+            )
+        })
+        .ok_or_else(|| anyhow!("Cannot write {}, use .bib, .json or .ris", output.display()))?;
+
+    let keys = scan_paths(&paths)?;
+    let app = load_app(user_config).await?;
+    let mut cited = Vec::new();
+    let mut unknown = Vec::new();
+    for key in &keys {
+        match find_cited(&app.documents, key) {
+            Some(doc) if !cited.iter().any(|cited| Rc::ptr_eq(cited, doc)) => {
+                cited.push(doc.clone())
+            }
+            Some(_) => {}
+            None => unknown.push(key.as_str()),
+        }
+    }
+    let count = export_to_file(
+        &cited,
+        format,
+        &ExportOptions::from_config(user_config),
+        &output,
+    )?;
+    println!(
+        "Wrote {} of {} cited document(s) to {}",
+        count,
+        keys.len(),
+        output.display()
+    );
+    if !unknown.is_empty() {
+        return Err(anyhow!(
+            "{} citation key(s) not in the library: {}",
+            unknown.len(),
+            unknown.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Documents with the given citation or item keys, in order. Unknown keys are an error.
+fn find_documents<'a>(
+    app: &App,
+    keys: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<Vec<RcDoc>> {
+    let mut docs = Vec::new();
+    let mut unknown = Vec::new();
+    for key in keys {
+        match app.find_document(key) {
+            Some(doc) => docs.push(doc),
+            None => unknown.push(key),
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(anyhow!("Unknown key(s): {}", unknown.join(", ")));
+    }
+    Ok(docs)
+}
+
+fn find_one(app: &App, key: &str) -> anyhow::Result<RcDoc> {
+    app.find_document(key)
+        .ok_or_else(|| anyhow!("No document with key \"{}\"", key))
+}
+
+/// All documents, or those of the `--collection` argument
+fn documents_in_scope(app: &App, matches: &ArgMatches<'_>) -> anyhow::Result<Vec<RcDoc>> {
+    match matches.value_of("collection") {
+        Some(name) => {
+            let collection = find_collection(app, name)?;
+            let id = collection.borrow().collectionId;
+            Ok(app.get_docs_in_collection(id))
+        }
+        None => Ok(app.documents.clone()),
+    }
+}
+
+fn creator_name(creator: &Creator) -> String {
+    let last = creator.lastName.as_deref().unwrap_or_default();
+    match creator.firstName.as_deref() {
+        Some(first) if !creator.is_single_field() => format!("{} {}", first, last),
+        Some(first) if last.is_empty() => first.to_owned(),
+        _ => last.to_owned(),
+    }
+}
+
+fn resolve_attachments(doc: &Document, user_config: &UserConfig) -> Vec<ResolvedAttachment> {
+    match &doc.attachments {
+        Some(attachments) => attachments
+            .items
+            .iter()
+            .map(|att| {
+                att.resolve(
+                    &user_config.behavior.zotero_storage_dir,
+                    user_config.behavior.base_attachment_dir.as_deref(),
+                )
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// A document for `--json` output. `detailed` adds all item fields.
+fn document_json(app: &App, doc: &Document, user_config: &UserConfig, detailed: bool) -> Value {
+    let creators: Vec<Value> = doc
+        .get_creators()
+        .map(|creator| {
+            json!({
+                "name": creator_name(creator),
+                "firstName": creator.firstName,
+                "lastName": creator.lastName,
+                "creatorType": creator.role(),
+            })
+        })
+        .collect();
+    let attachments: Vec<Value> = resolve_attachments(doc, user_config)
+        .iter()
+        .map(|att| {
+            json!({
+                "key": att.attachment.key,
+                "contentType": att.attachment.contentType,
+                "path": att.path,
+                "url": att.url,
+                "exists": att.size.is_some(),
+            })
+        })
+        .collect();
+    let mut value = json!({
+        "key": doc.item_data.key,
+        "citationKey": doc.get_citation_key(),
+        "itemType": doc.item_data.typeName,
+        "title": doc.get_title(),
+        "creators": creators,
+        "year": doc.get_year(),
+        "url": doc.get_web_url(),
+        "tags": doc.tags.iter().map(|tag| tag.name.as_str()).collect::<Vec<&str>>(),
+        "collections": doc
+            .collections
+            .iter()
+            .map(|col| collection_path(app, col))
+            .collect::<Vec<String>>(),
+        "attachments": attachments,
+    });
+    if detailed {
+        value["fields"] = json!(doc.fields);
+    }
+    value
+}
+
+fn print_documents(
+    app: &App,
+    docs: &[RcDoc],
+    matches: &ArgMatches<'_>,
+    user_config: &UserConfig,
+) -> anyhow::Result<()> {
+    if matches.is_present("json") {
+        let values: Vec<Value> = docs
+            .iter()
+            .map(|doc| document_json(app, &doc.borrow(), user_config, false))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&values)?);
+    } else {
+        let template = user_config.behavior.pick_template.replace("\\t", "\t");
+        for doc in docs {
+            println!("{}", pick_line(&doc.borrow(), &template));
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_search(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let app = load_app(user_config).await?;
+    let by = match matches.value_of("by").unwrap() {
+        "creator" => UIBlockType::Creator,
+        "year" => UIBlockType::Year,
+        _ => UIBlockType::Title,
+    };
+    let docs = documents_in_scope(&app, matches)?;
+    let found = search_documents(&docs, matches.value_of("query").unwrap(), by);
+    print_documents(&app, &found, matches, user_config)
+}
+
+pub async fn run_list(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let app = load_app(user_config).await?;
+    let docs = documents_in_scope(&app, matches)?;
+    print_documents(&app, &docs, matches, user_config)
+}
+
+pub async fn run_show(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let app = load_app(user_config).await?;
+    let doc = find_one(&app, matches.value_of("key").unwrap())?;
+    let doc = doc.borrow();
+    if matches.is_present("json") {
+        let value = document_json(&app, &doc, user_config, true);
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+    println!("Title: {}", doc.get_title());
+    println!("Item type: {}", doc.item_data.typeName);
+    println!("Citation key: {}", doc.get_citation_key());
+    println!("Item key: {}", doc.item_data.key);
+    for creator in doc.get_creators() {
+        println!("Creator: {} ({})", creator_name(creator), creator.role());
+    }
+    let mut fields: Vec<(&String, &String)> = doc
+        .fields
+        .iter()
+        .filter(|(name, value)| name.as_str() != "title" && !value.is_empty())
+        .collect();
+    fields.sort();
+    for (name, value) in fields {
+        // Dates are stored as `YYYY-MM-DD <as entered>`, show them as entered
+        let value = match value.split_once(' ') {
+            Some((_, entered)) if name.as_str() == "date" => entered,
+            _ => value.as_str(),
+        };
+        println!("{}: {}", name, value);
+    }
+    for col in &doc.collections {
+        println!("Collection: {}", collection_path(&app, col));
+    }
+    for tag in &doc.tags {
+        println!("Tag: {}", tag.name);
+    }
+    for att in resolve_attachments(&doc, user_config) {
+        let location = match (&att.path, &att.url) {
+            (Some(path), _) if att.size.is_some() => path.display().to_string(),
+            (_, Some(url)) => url.to_owned(),
+            _ => format!("{} (missing)", att.attachment.display_name()),
+        };
+        println!(
+            "Attachment: {} [{}]",
+            location,
+            att.attachment.content_type()
+        );
+    }
+    Ok(())
+}
+
+/// Open the first attachment file of `doc`, PDFs first, else a web link. Returns what was
+/// opened.
+fn open_document(doc: &Document, user_config: &UserConfig) -> anyhow::Result<String> {
+    let attachments = resolve_attachments(doc, user_config);
+    let file = attachments
+        .iter()
+        .filter(|att| att.size.is_some())
+        .min_by_key(|att| att.attachment.contentType.as_deref() != Some("application/pdf"));
+    if let Some(att) = file {
+        let path = att.path.as_ref().unwrap();
+        let path_str = path.to_string_lossy();
+        let ctx = OpenContext {
+            path: &path_str,
+            page: None,
+            key: att.attachment.key.as_deref().unwrap_or_default(),
+        };
+        user_config
+            .openers
+            .open(att.attachment.contentType.as_deref(), &ctx)?;
+        return Ok(path.display().to_string());
+    }
+    let url = attachments
+        .iter()
+        .find_map(|att| att.url.clone())
+        .or_else(|| doc.get_web_url())
+        .ok_or_else(|| anyhow!("No openable attachment for \"{}\"", doc.get_title()))?;
+    user_config.openers.open_url(&url)?;
+    Ok(url)
+}
+
+pub async fn run_open(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let app = load_app(user_config).await?;
+    let doc = find_one(&app, matches.value_of("key").unwrap())?;
+    let opened = open_document(&doc.borrow(), user_config)?;
+    println!("Opened {}", opened);
+    Ok(())
+}
+
+/// One line for a picker: the placeholders of `template` filled in, line breaks and tabs
+/// within values replaced by spaces
+fn pick_line(doc: &Document, template: &str) -> String {
+    let authors: Vec<String> = doc.get_authors().map(creator_name).collect();
+    let values = [
+        ("{key}", doc.get_citation_key().to_owned()),
+        ("{item_key}", doc.item_data.key.clone()),
+        ("{title}", doc.get_title().to_owned()),
+        ("{authors}", authors.join(", ")),
+        ("{year}", doc.get_year().to_owned()),
+        ("{type}", doc.item_data.typeName.clone()),
+    ];
+    let mut line = String::new();
+    let mut rest = template;
+    while !rest.is_empty() {
+        match values
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                line.push_str(&value.replace(['\t', '\n', '\r'], " "));
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                let c = rest.chars().next().unwrap();
+                line.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    line
+}
+
+/// The document a picked line was printed for. Lines a picker changed, e.g. by trimming,
+/// fall back to their first field as a key.
+fn find_picked(app: &App, docs: &[RcDoc], template: &str, line: &str) -> Option<RcDoc> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    docs.iter()
+        .find(|doc| pick_line(&doc.borrow(), template) == line)
+        .or_else(|| {
+            docs.iter()
+                .find(|doc| pick_line(&doc.borrow(), template).trim() == line.trim())
+        })
+        .cloned()
+        .or_else(|| {
+            let first = line
+                .split(|c: char| c == '\t' || c.is_whitespace())
+                .find(|s| !s.is_empty())?;
+            app.find_document(first.trim_start_matches('@'))
+        })
+}
+
+pub async fn run_pick(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let template = matches
+        .value_of("template")
+        .unwrap_or(&user_config.behavior.pick_template)
+        .replace("\\t", "\t");
+    let app = load_app(user_config).await?;
+    let docs = documents_in_scope(&app, matches)?;
+    if !matches.is_present("open") && !matches.is_present("key") {
+        for doc in &docs {
+            println!("{}", pick_line(&doc.borrow(), &template));
+        }
+        return Ok(());
+    }
+
+    let lines: Vec<String> = match matches.value_of("selection") {
+        Some(selection) => vec![selection.to_owned()],
+        None => std::io::stdin().lines().collect::<Result<_, _>>()?,
+    };
+    // Nothing picked, e.g. the picker was cancelled
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        let doc = find_picked(&app, &docs, &template, line)
+            .ok_or_else(|| anyhow!("No document for the picked line \"{}\"", line))?;
+        let doc = doc.borrow();
+        if matches.is_present("key") {
+            println!("{}", doc.get_citation_key());
+        } else {
+            println!("Opened {}", open_document(&doc, user_config)?);
+        }
+    }
+    Ok(())
+}
+
+/// Let the user pick documents with an external picker, like `rustero pick | <picker>`
+fn run_picker(picker: &str, app: &App, template: &str) -> anyhow::Result<Vec<RcDoc>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(picker)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let lines: Vec<String> = app
+        .documents
+        .iter()
+        .map(|doc| pick_line(&doc.borrow(), template))
+        .collect();
+    // The picker may exit before reading everything
+    let _ = child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(lines.join("\n").as_bytes());
+    let output = child.wait_with_output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| find_picked(app, &app.documents, template, line))
+        .collect())
+}
+
+pub async fn run_serve(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let port = match matches.value_of("port") {
+        Some(port) => port
+            .parse()
+            .map_err(|_| anyhow!("--port must be a port number"))?,
+        None => user_config.behavior.citation_server_port,
+    };
+    let picker = matches.value_of("picker");
+    let template = user_config.behavior.pick_template.as_str();
+    let options = ExportOptions::from_config(user_config);
+    let staging = StagingStore::from_config(user_config)
+        .filter(|_| matches.is_present("connector") || user_config.behavior.connector);
+    let mut app = load_app(user_config).await?;
+    let server = CitationServer::start(port)?;
+    eprintln!(
+        "Serving {} document(s) on http://127.0.0.1:{}/better-bibtex/",
+        app.documents.len(),
+        server.port()
+    );
+    if let Some(store) = &staging {
+        eprintln!("Saving items from the browser to {}", store.dir().display());
+    }
+    let mut watcher = DbWatcher::new(user_config.behavior.library_path());
+    loop {
+        if let Some(request) = server.next_request(Duration::from_secs(1)) {
+            let result = citation_server::respond(
+                request,
+                &app.documents,
+                &mut || match picker {
+                    Some(picker) => run_picker(picker, &app, template),
+                    None => Err(anyhow!("No picker, start the server with --picker")),
+                },
+                &options,
+                staging.as_ref(),
+            );
+            match result {
+                Ok(staged) => {
+                    for doc in &staged {
+                        eprintln!("Saved \"{}\"", doc.borrow().get_title());
+                    }
+                    app.merge_staged_documents(staged);
+                }
+                Err(err) => eprintln!("Request failed: {}", err),
+            }
+        } else if watcher.poll() {
+            match load_app(user_config).await {
+                Ok(library) => {
+                    if let Err(err) = app.backend.close().await {
+                        eprintln!("Cannot close the previous library: {}", err);
+                    }
+                    app = library;
+                }
+                Err(err) => eprintln!("Cannot reload the library: {}", err),
+            }
+        }
+    }
+}
+
+pub async fn run_export(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let format = ExportFormat::from_id(matches.value_of("format").unwrap()).unwrap();
+    let app = load_app(user_config).await?;
+    let mut docs = match matches.values_of("keys") {
+        Some(keys) => find_documents(&app, keys)?,
+        None => documents_in_scope(&app, matches)?,
+    };
+    if let Some(query) = matches.value_of("query") {
+        docs = search_documents(&docs, query, UIBlockType::Title);
+    }
+    let options = ExportOptions::from_config(user_config);
+    match matches.value_of("output") {
+        Some(output) => {
+            let count = export_to_file(&docs, format, &options, Path::new(output))?;
+            eprintln!("Exported {} document(s) to {}", count, output);
+        }
+        None => println!("{}", export_documents(&docs, format, &options)?),
+    }
+    Ok(())
+}
+
+pub async fn run_cite(matches: &ArgMatches<'_>, user_config: &UserConfig) -> anyhow::Result<()> {
+    let format = ExportFormat::from_id(matches.value_of("format").unwrap()).unwrap();
+    let app = load_app(user_config).await?;
+    let docs = find_documents(&app, matches.values_of("keys").unwrap())?;
+    let options = ExportOptions::from_config(user_config);
+    println!("{}", export_documents(&docs, format, &options)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::new_test_document;
+
+    #[test]
+    fn test_pick_line() {
+        let mut app = App::default();
+        let doc = new_test_document(
+            "book",
+            &[("title", "A\tlong\nbook"), ("date", "2001-00-00 2001")],
+            vec![Creator {
+                firstName: Some("Jane".to_string()),
+                lastName: Some("Doe".to_string()),
+                creatorType: Some("author".to_string()),
+                fieldMode: Some(0),
+            }],
+        );
+        doc.borrow_mut().citation_key = "doe2001long".to_string();
+        app.documents.push(doc.clone());
+        let template = "{key}\t{year}\t{authors}\t{title} ({type})";
+        let line = pick_line(&doc.borrow(), template);
+        assert_eq!(line, "doe2001long\t2001\tJane Doe\tA long book (book)");
+
+        let docs = app.documents.clone();
+        let picked = find_picked(&app, &docs, template, &format!("{}\n", line)).unwrap();
+        assert!(Rc::ptr_eq(&picked, &doc));
+        // Lines with a changed title are still found by their key
+        let picked = find_picked(&app, &docs, template, "doe2001long  changed").unwrap();
+        assert!(Rc::ptr_eq(&picked, &doc));
+        assert!(find_picked(&app, &docs, template, "unknown line").is_none());
+    }
+}
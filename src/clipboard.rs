@@ -0,0 +1,86 @@
+use std::{
+    env,
+    io::{self, Write},
+};
+
+use anyhow::Result;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(input: &[u8]) -> String {
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// OSC 52 escape sequence asking the terminal to set its clipboard. Inside tmux the
+/// sequence has to be wrapped so that tmux passes it through.
+fn osc52_sequence(text: &str) -> String {
+    let osc = format!("\x1b]52;c;{}\x07", base64_encode(text.as_bytes()));
+    if env::var("TMUX").is_ok() {
+        format!("\x1bPtmux;\x1b{}\x1b\\", osc)
+    } else {
+        osc
+    }
+}
+
+/// System clipboard with a terminal (OSC 52) fallback for SSH sessions.
+#[derive(Default)]
+pub struct Clipboard {
+    // Connected on the first copy and kept alive for the whole session: on X11 the content
+    // is lost when the owner is dropped
+    system: Option<Option<arboard::Clipboard>>,
+}
+
+impl Clipboard {
+    fn connect() -> Option<arboard::Clipboard> {
+        let over_ssh = env::var("SSH_CONNECTION").is_ok() || env::var("SSH_TTY").is_ok();
+        if over_ssh {
+            None
+        } else {
+            arboard::Clipboard::new().ok()
+        }
+    }
+
+    /// Copy `text`, returning a short description of the mechanism that was used.
+    pub fn copy(&mut self, text: &str) -> Result<&'static str> {
+        if let Some(system) = self.system.get_or_insert_with(Self::connect).as_mut() {
+            if system.set_text(text.to_owned()).is_ok() {
+                return Ok("clipboard");
+            }
+        }
+        let mut stdout = io::stdout();
+        stdout.write_all(osc52_sequence(text).as_bytes())?;
+        stdout.flush()?;
+        Ok("terminal clipboard (OSC 52)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"[@doe2020]"), "W0Bkb2UyMDIwXQ==");
+    }
+}
@@ -104,6 +104,46 @@ impl CollectionTree {
         }
     }
 
+    fn push_visible_nodes(&self, node: &RcCollectionNode, nodes: &mut Vec<RcCollectionNode>) {
+        nodes.push(node.clone());
+        for child in self.get_node_children(node.clone()) {
+            self.push_visible_nodes(&child, nodes);
+        }
+    }
+    /// All nodes in the order they are drawn: each library followed by its collections,
+    /// depth first.
+    pub fn get_visible_nodes(&self) -> Vec<RcCollectionNode> {
+        let mut nodes = Vec::new();
+        for library in self.get_library_nodes() {
+            self.push_visible_nodes(&library, &mut nodes);
+        }
+        nodes
+    }
+    /// All collections, in the order they are drawn
+    pub fn get_collections(&self) -> Vec<RcCollection> {
+        self.get_visible_nodes()
+            .iter()
+            .filter_map(|node| match &node.borrow().value {
+                CollectionNodeValue::Collection(col) => Some(col.clone()),
+                CollectionNodeValue::Library(_) => None,
+            })
+            .collect()
+    }
+    /// IDs of the collection and all of its sub-collections
+    pub fn get_descendant_ids(&self, id: i64) -> Vec<i64> {
+        let mut nodes = Vec::new();
+        if let Some(node) = self.get_collection(id) {
+            self.push_visible_nodes(&node, &mut nodes);
+        }
+        nodes
+            .iter()
+            .filter_map(|node| match &node.borrow().value {
+                CollectionNodeValue::Collection(col) => Some(col.borrow().collectionId),
+                CollectionNodeValue::Library(_) => None,
+            })
+            .collect()
+    }
+
     pub fn build_collection_tree(&mut self, collections: &mut Vec<RcCollection>) {
         let mut unique_library_id = HashSet::new();
         for col in collections.iter() {
@@ -196,26 +236,20 @@ impl CollectionTree {
 
 #[cfg(test)]
 mod tests {
-    use crate::{
-        app::App,
-        db_connector::{get_all_item_data, get_attachments_for_docs, get_collections},
-        user_config::UserConfig,
-    };
+    use crate::{app::App, db_connector::ZoteroDb, user_config::UserConfig};
 
     use super::*;
     #[test]
     fn test_build_collection_tree() {
         let mut app = App::default();
         let user_config = UserConfig::new();
-        tokio_test::block_on(app.init_sqlite(&user_config.behavior.zotero_db_path)).unwrap();
-        let all_items =
-            tokio_test::block_on(get_all_item_data(&mut app)).expect("Expect read all docs");
-        // dbg!(&all_items);
-        // let all_docs: Vec<RcDoc> = Vec::from_iter(all_items);
-        // tokio_test::block_on(get_creators_for_docs(&mut app)).expect("Expect read all creators");
-        // tokio_test::block_on(get_attachments_for_docs(&mut app))
-        // .expect("Expect read all attachments");
-        tokio_test::block_on(get_collections(&mut app)).expect("Expect read all creators");
+        let db = tokio_test::block_on(ZoteroDb::open(
+            &user_config.behavior.zotero_db_path,
+            &user_config.behavior.zotero_storage_dir,
+            None,
+        ))
+        .unwrap();
+        tokio_test::block_on(app.load_from(Rc::new(db))).expect("Expect read all collections");
         app.collection_tree
             .build_collection_tree(&mut app.collections.items);
         // dbg!(&app.collection_tree.nodes);
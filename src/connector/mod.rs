@@ -0,0 +1,730 @@
+//! The endpoints of Zotero's local server used by the Zotero Connector browser extension.
+//! Saved items go to a staging store owned by Rustero, see `StagingStore`, and show up in
+//! the library next to the items of the Zotero database.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    citation_server::{self, Response},
+    data_structures::{
+        Attachment, Creator, DateParts, RcDoc, StatefulList, Tag, LINK_MODE_LINKED_FILE,
+        LINK_MODE_LINKED_URL,
+    },
+    export::new_document,
+    user_config::UserConfig,
+};
+
+/// Zotero version reported to the connector, which checks it before using newer endpoints
+const ZOTERO_VERSION: &str = "6.0.30";
+const ITEMS_FILE_NAME: &str = "items.json";
+/// Characters of Zotero item keys
+const KEY_CHARS: &[u8] = b"23456789ABCDEFGHIJKLMNPQRSTUVWXYZ";
+const LIBRARY_NAME: &str = "Rustero staging";
+
+/// A file uploaded by the connector, stored in `<staging>/<item key>/`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StagedFile {
+    /// Id the connector gave the attachment
+    pub id: String,
+    pub title: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StagedItem {
+    pub key: String,
+    pub session_id: String,
+    /// `YYYY-MM-DD HH:MM:SS` in UTC, like Zotero's `dateAdded`
+    pub date_added: String,
+    /// The item as sent by the connector, in Zotero's JSON item format
+    pub item: Value,
+    #[serde(default)]
+    pub files: Vec<StagedFile>,
+}
+
+impl StagedItem {
+    /// The connector's id of the item, used by later requests of the same session
+    fn connector_id(&self) -> Option<&str> {
+        self.item.get("id").and_then(Value::as_str)
+    }
+
+    pub fn to_document(&self, dir: &Path) -> RcDoc {
+        let item_type = self
+            .item
+            .get("itemType")
+            .and_then(Value::as_str)
+            .unwrap_or("document");
+        let doc = new_document(item_type, &self.key);
+        let mut doc_mut = doc.borrow_mut();
+        doc_mut.item_data.dateAdded = self.date_added.clone();
+        if let Some(object) = self.item.as_object() {
+            for (name, value) in object {
+                if name == "itemType" || name == "id" {
+                    continue;
+                }
+                if let Some(value) = value.as_str() {
+                    doc_mut.fields.insert(name.to_owned(), value.to_owned());
+                }
+            }
+        }
+        doc_mut.item_data.title = doc_mut.get_field("title").unwrap_or_default().to_owned();
+        doc_mut.item_data.abstracttext = doc_mut
+            .get_field("abstractNote")
+            .unwrap_or_default()
+            .to_owned();
+        if let Some(date) = doc_mut
+            .get_field("date")
+            .and_then(DateParts::zotero_date_from_text)
+        {
+            doc_mut.item_data.pubdate = date.clone();
+            doc_mut.fields.insert("date".to_string(), date);
+        }
+
+        let creators = self.item.get("creators").and_then(Value::as_array);
+        for creator in creators.into_iter().flatten() {
+            let text = |name: &str| creator.get(name).and_then(Value::as_str).map(str::to_owned);
+            // Institutions and other single field names come as `name`
+            doc_mut.creators.push(match text("name") {
+                Some(name) => Creator {
+                    firstName: None,
+                    lastName: Some(name),
+                    creatorType: text("creatorType"),
+                    fieldMode: Some(1),
+                },
+                None => Creator {
+                    firstName: text("firstName"),
+                    lastName: text("lastName"),
+                    creatorType: text("creatorType"),
+                    fieldMode: Some(
+                        creator
+                            .get("fieldMode")
+                            .and_then(Value::as_i64)
+                            .unwrap_or(0),
+                    ),
+                },
+            });
+        }
+        if doc_mut.creators.is_empty() {
+            doc_mut.creators.push(Creator::default());
+        }
+
+        let tags = self.item.get("tags").and_then(Value::as_array);
+        for tag in tags.into_iter().flatten() {
+            let name = tag
+                .as_str()
+                .or_else(|| tag.get("tag").and_then(Value::as_str));
+            if let Some(name) = name {
+                doc_mut.tags.push(Tag {
+                    tagId: 0,
+                    name: name.to_owned(),
+                });
+            }
+        }
+
+        // Uploaded files, then web links of attachments that were not uploaded
+        let mut attachments: Vec<Attachment> = self
+            .files
+            .iter()
+            .map(|file| Attachment {
+                itemId: 0,
+                linkMode: Some(LINK_MODE_LINKED_FILE),
+                contentType: Some(file.content_type.clone()),
+                path: Some(
+                    dir.join(&self.key)
+                        .join(&file.file_name)
+                        .to_string_lossy()
+                        .into_owned(),
+                ),
+                key: None,
+                url: file.url.clone(),
+            })
+            .collect();
+        let links = self.item.get("attachments").and_then(Value::as_array);
+        for link in links.into_iter().flatten() {
+            let id = link.get("id").and_then(Value::as_str);
+            if self.files.iter().any(|file| Some(file.id.as_str()) == id) {
+                continue;
+            }
+            if let Some(url) = link.get("url").and_then(Value::as_str) {
+                attachments.push(Attachment {
+                    itemId: 0,
+                    linkMode: Some(LINK_MODE_LINKED_URL),
+                    contentType: link
+                        .get("mimeType")
+                        .and_then(Value::as_str)
+                        .map(str::to_owned),
+                    path: None,
+                    key: None,
+                    url: Some(url.to_owned()),
+                });
+            }
+        }
+        if !attachments.is_empty() {
+            doc_mut.attachments = Some(StatefulList::with_items(attachments));
+        }
+        drop(doc_mut);
+        doc
+    }
+}
+
+/// Items saved from the browser, kept as `items.json` and a directory of files per item
+#[derive(Debug, Clone)]
+pub struct StagingStore {
+    dir: PathBuf,
+}
+
+impl StagingStore {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// The store in the configuration directory
+    pub fn from_config(user_config: &UserConfig) -> Option<Self> {
+        let paths = user_config.path_to_config.as_ref()?;
+        Some(Self::new(&paths.staging_dir))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn items(&self) -> anyhow::Result<Vec<StagedItem>> {
+        let path = self.dir.join(ITEMS_FILE_NAME);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn save(&self, items: &[StagedItem]) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let tmp_path = self.dir.join(format!("{}.tmp", ITEMS_FILE_NAME));
+        fs::write(&tmp_path, serde_json::to_string_pretty(items)?)?;
+        fs::rename(tmp_path, self.dir.join(ITEMS_FILE_NAME))?;
+        Ok(())
+    }
+
+    pub fn documents(&self) -> anyhow::Result<Vec<RcDoc>> {
+        Ok(self
+            .items()?
+            .iter()
+            .map(|item| item.to_document(&self.dir))
+            .collect())
+    }
+
+    /// Stage connector items, returning the stored items
+    fn add(&self, session_id: &str, new_items: Vec<Value>) -> anyhow::Result<Vec<StagedItem>> {
+        let mut items = self.items()?;
+        let mut added = Vec::new();
+        for item in new_items {
+            let key = loop {
+                let key = new_key();
+                if !items.iter().any(|staged| staged.key == key) {
+                    break key;
+                }
+            };
+            let staged = StagedItem {
+                key,
+                session_id: session_id.to_owned(),
+                date_added: now_utc(),
+                item,
+                files: Vec::new(),
+            };
+            items.push(staged.clone());
+            added.push(staged);
+        }
+        self.save(&items)?;
+        Ok(added)
+    }
+
+    /// Change the item of `session_id` with connector id `id`, or the last item of the
+    /// session without one, and return it
+    fn update(
+        &self,
+        session_id: &str,
+        id: Option<&str>,
+        change: impl FnOnce(&Path, &mut StagedItem) -> anyhow::Result<()>,
+    ) -> anyhow::Result<StagedItem> {
+        let mut items = self.items()?;
+        let staged = items
+            .iter_mut()
+            .rev()
+            .find(|staged| {
+                staged.session_id == session_id && (id.is_none() || staged.connector_id() == id)
+            })
+            .ok_or_else(|| anyhow!("No item saved in session {}", session_id))?;
+        change(&self.dir, staged)?;
+        let updated = staged.clone();
+        self.save(&items)?;
+        Ok(updated)
+    }
+}
+
+/// A random key in the format of Zotero's item and collection keys
+pub fn new_key() -> String {
+    let mut rng = rand::thread_rng();
+    (0..8)
+        .map(|_| KEY_CHARS[rng.gen_range(0..KEY_CHARS.len())] as char)
+        .collect()
+}
+
+/// Current time as `YYYY-MM-DD HH:MM:SS` in UTC
+fn now_utc() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// File name for an uploaded attachment, from its title and content type
+fn file_name(title: &str, content_type: &str, url: Option<&str>) -> String {
+    let extension = match content_type.split(';').next().unwrap_or_default().trim() {
+        "application/pdf" => "pdf".to_string(),
+        "text/html" => "html".to_string(),
+        "application/epub+zip" => "epub".to_string(),
+        _ => url
+            .and_then(|url| url.rsplit('/').next())
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_owned())
+            .filter(|extension| extension.len() <= 5)
+            .unwrap_or_else(|| "bin".to_string()),
+    };
+    let stem: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || " -_".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let stem = stem.trim();
+    format!(
+        "{}.{}",
+        if stem.is_empty() { "attachment" } else { stem },
+        extension
+    )
+}
+
+fn write_file(
+    dir: &Path,
+    staged: &mut StagedItem,
+    file: StagedFile,
+    content: &[u8],
+) -> anyhow::Result<()> {
+    let item_dir = dir.join(&staged.key);
+    fs::create_dir_all(&item_dir)?;
+    fs::write(item_dir.join(&file.file_name), content)?;
+    staged
+        .files
+        .retain(|existing| existing.file_name != file.file_name);
+    staged.files.push(file);
+    Ok(())
+}
+
+fn created() -> Response {
+    Response {
+        status: 201,
+        content_type: "application/json",
+        body: "{}".to_string(),
+    }
+}
+
+fn parse_body(content_type: Option<&str>, body: &[u8]) -> anyhow::Result<Value> {
+    if !citation_server::is_json(content_type) {
+        return Err(anyhow!("Invalid request: expected application/json"));
+    }
+    serde_json::from_slice(body).map_err(|err| anyhow!("Invalid request: {}", err))
+}
+
+fn text<'a>(value: &'a Value, name: &str) -> &'a str {
+    value.get(name).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Answer a connector request. `content_type` must be JSON for all but attachment uploads,
+/// whose metadata is the `X-Metadata` header. Returns the response and the staged items
+/// that were added or changed.
+pub fn handle(
+    store: &StagingStore,
+    method: &str,
+    path: &str,
+    content_type: Option<&str>,
+    metadata: Option<&str>,
+    body: &[u8],
+) -> anyhow::Result<(Response, Vec<StagedItem>)> {
+    let endpoint = path.trim_start_matches("/connector/").trim_end_matches('/');
+    let response = match (method, endpoint) {
+        ("GET", "ping") => Response {
+            status: 200,
+            content_type: "text/html",
+            body: "<!DOCTYPE html><html><body>Zotero is running</body></html>".to_string(),
+        },
+        ("POST", "ping") => Response::json(&json!({
+            "prefs": {
+                "downloadAssociatedFiles": true,
+                "automaticSnapshots": true,
+                "reportActiveURL": false,
+                "supportsAttachmentUpload": true,
+                "supportsTagsAutocomplete": false,
+            }
+        })),
+        (_, "getSelectedCollection") => Response::json(&json!({
+            "libraryID": 1,
+            "libraryName": LIBRARY_NAME,
+            "libraryEditable": true,
+            "filesEditable": true,
+            "editable": true,
+            "id": null,
+            "name": LIBRARY_NAME,
+            "targets": [{ "id": "L1", "name": LIBRARY_NAME, "filesEditable": true, "level": 0 }],
+        })),
+        ("POST", "saveItems") => {
+            let request = parse_body(content_type, body)?;
+            let items = request
+                .get("items")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            let added = store.add(text(&request, "sessionID"), items)?;
+            return Ok((created(), added));
+        }
+        ("POST", "saveSnapshot") => {
+            let request = parse_body(content_type, body)?;
+            let item = json!({
+                "itemType": "webpage",
+                "title": text(&request, "title"),
+                "url": text(&request, "url"),
+                "accessDate": now_utc(),
+            });
+            let mut added = store.add(text(&request, "sessionID"), vec![item])?;
+            // Older connectors send the page with the request
+            if let Some(html) = request.get("html").and_then(Value::as_str) {
+                added[0] = store.update(text(&request, "sessionID"), None, |dir, staged| {
+                    let file = StagedFile {
+                        id: "snapshot".to_string(),
+                        title: "Snapshot".to_string(),
+                        file_name: "snapshot.html".to_string(),
+                        content_type: "text/html".to_string(),
+                        url: Some(text(&request, "url").to_owned()),
+                    };
+                    write_file(dir, staged, file, html.as_bytes())
+                })?;
+            }
+            return Ok((created(), added));
+        }
+        ("POST", "saveSingleFile") => {
+            let request = parse_body(content_type, body)?;
+            let content = text(&request, "snapshotContent");
+            let updated = store.update(text(&request, "sessionID"), None, |dir, staged| {
+                let file = StagedFile {
+                    id: "snapshot".to_string(),
+                    title: "Snapshot".to_string(),
+                    file_name: "snapshot.html".to_string(),
+                    content_type: "text/html".to_string(),
+                    url: Some(text(&request, "url").to_owned()),
+                };
+                write_file(dir, staged, file, content.as_bytes())
+            })?;
+            return Ok((created(), vec![updated]));
+        }
+        ("POST", "saveAttachment") => {
+            let metadata: Value = serde_json::from_str(metadata.unwrap_or_default())
+                .map_err(|err| anyhow!("Invalid request: {}", err))?;
+            let content_type = text(&metadata, "contentType");
+            let title = text(&metadata, "title");
+            let url = metadata.get("url").and_then(Value::as_str);
+            let file = StagedFile {
+                id: text(&metadata, "id").to_owned(),
+                title: title.to_owned(),
+                file_name: file_name(title, content_type, url),
+                content_type: content_type.to_owned(),
+                url: url.map(str::to_owned),
+            };
+            let parent = metadata.get("parentItemID").and_then(Value::as_str);
+            let updated = store.update(text(&metadata, "sessionID"), parent, |dir, staged| {
+                write_file(dir, staged, file, body)
+            })?;
+            return Ok((created(), vec![updated]));
+        }
+        ("POST", "updateSession") => {
+            let request = parse_body(content_type, body)?;
+            let tags: Vec<Value> = match request.get("tags") {
+                Some(Value::String(tags)) => tags
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| json!(tag))
+                    .collect(),
+                Some(Value::Array(tags)) => tags.clone(),
+                _ => Vec::new(),
+            };
+            let session_id = text(&request, "sessionID");
+            let mut items = store.items()?;
+            let mut updated = Vec::new();
+            for staged in items
+                .iter_mut()
+                .filter(|staged| staged.session_id == session_id)
+            {
+                let mut item_tags = staged
+                    .item
+                    .get("tags")
+                    .and_then(Value::as_array)
+                    .cloned()
+                    .unwrap_or_default();
+                for tag in &tags {
+                    if !item_tags.contains(tag) {
+                        item_tags.push(tag.clone());
+                    }
+                }
+                staged.item["tags"] = Value::Array(item_tags);
+                updated.push(staged.clone());
+            }
+            store.save(&items)?;
+            return Ok((Response::json(&json!({})), updated));
+        }
+        (_, "sessionProgress") => Response::json(&json!({ "items": [], "done": true })),
+        _ => Response::text(404, format!("No endpoint {}", path)),
+    };
+    Ok((response, Vec::new()))
+}
+
+/// Headers Zotero adds to connector responses
+pub const RESPONSE_HEADERS: [(&str, &str); 2] = [
+    ("X-Zotero-Version", ZOTERO_VERSION),
+    ("X-Zotero-Connector-API-Version", "3"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        citation_server::{self, CitationServer},
+        test_dir::TestDir,
+    };
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+
+    const SAVE_ITEMS: &str = include_str!("fixtures/save_items.json");
+    const SAVE_SNAPSHOT: &str = include_str!("fixtures/save_snapshot.json");
+
+    /// Send a raw HTTP request to the server and serve it, returning the response
+    fn send(
+        server: &CitationServer,
+        store: &StagingStore,
+        request: String,
+        body: &[u8],
+    ) -> (String, Vec<RcDoc>) {
+        let port = server.port();
+        let mut bytes = request.into_bytes();
+        bytes.extend_from_slice(body);
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(&bytes).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let request = server.next_request(Duration::from_secs(5)).unwrap();
+        let staged = citation_server::respond(
+            request,
+            &[],
+            &mut || Ok(Vec::new()),
+            &Default::default(),
+            Some(store),
+        )
+        .unwrap();
+        (client.join().unwrap(), staged)
+    }
+
+    fn post(path: &str, headers: &str, body: &[u8]) -> String {
+        post_as(path, "application/json", headers, body)
+    }
+
+    fn post_as(path: &str, content_type: &str, headers: &str, body: &[u8]) -> String {
+        format!(
+            "POST {} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\
+             Content-Type: {}\r\nContent-Length: {}\r\n{}\r\n",
+            path,
+            content_type,
+            body.len(),
+            headers
+        )
+    }
+
+    #[test]
+    fn test_connector_session() {
+        let dir = TestDir::new("connector-test");
+        let store = StagingStore::new(&dir);
+        let server = CitationServer::start(0).unwrap();
+
+        let ping = "GET /connector/ping HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n";
+        let (response, _) = send(&server, &store, ping.to_string(), b"");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("X-Zotero-Version: 6.0.30"));
+        assert!(response.ends_with("Zotero is running</body></html>"));
+
+        let (response, staged) = send(
+            &server,
+            &store,
+            post("/connector/saveItems", "", SAVE_ITEMS.as_bytes()),
+            SAVE_ITEMS.as_bytes(),
+        );
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert_eq!(staged.len(), 1);
+        let doc = staged[0].borrow();
+        assert_eq!(doc.get_title(), "Array programming with NumPy");
+        assert_eq!(doc.get_year(), "2020");
+        assert_eq!(doc.get_field("DOI"), Some("10.1038/s41586-020-2649-2"));
+        assert_eq!(doc.creators.len(), 3);
+        assert_eq!(
+            doc.creators[2].lastName.as_deref(),
+            Some("NumPy Developers")
+        );
+        assert!(doc.creators[2].is_single_field());
+        let tags: Vec<&str> = doc.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(tags, vec!["Computer science", "Software"]);
+        assert_eq!(doc.attachments.as_ref().unwrap().items.len(), 2);
+        drop(doc);
+
+        // The PDF is uploaded after the item, referring to it by the connector's ids
+        let metadata = r#"{"id":"mk2d9c1a","parentItemID":"c8d1b1f2","sessionID":"x7kq2m9p",
+            "title":"Full Text PDF","contentType":"application/pdf",
+            "url":"https://www.nature.com/articles/s41586-020-2649-2.pdf"}"#
+            .replace('\n', "");
+        let pdf = b"%PDF-1.4 fake";
+        let (response, staged) = send(
+            &server,
+            &store,
+            post(
+                "/connector/saveAttachment",
+                &format!("X-Metadata: {}\r\n", metadata),
+                pdf,
+            ),
+            pdf,
+        );
+        assert!(response.starts_with("HTTP/1.1 201"));
+        let doc = staged[0].borrow();
+        let attachments = &doc.attachments.as_ref().unwrap().items;
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(
+            attachments[0].contentType.as_deref(),
+            Some("application/pdf")
+        );
+        let path = PathBuf::from(attachments[0].path.clone().unwrap());
+        assert!(path.ends_with("Full Text PDF.pdf"));
+        assert_eq!(fs::read(path).unwrap(), pdf);
+        // The snapshot was not uploaded and stays a link
+        assert_eq!(attachments[1].linkMode, Some(LINK_MODE_LINKED_URL));
+        drop(doc);
+
+        let (_, staged) = send(
+            &server,
+            &store,
+            post("/connector/saveSnapshot", "", SAVE_SNAPSHOT.as_bytes()),
+            SAVE_SNAPSHOT.as_bytes(),
+        );
+        let snapshot = staged[0].borrow();
+        assert_eq!(snapshot.item_data.typeName, "webpage");
+        assert_eq!(snapshot.get_title(), "Announcing Rust 1.56.0 and Rust 2021");
+        let path = snapshot.attachments.as_ref().unwrap().items[0].path.clone();
+        assert!(fs::read_to_string(path.unwrap())
+            .unwrap()
+            .contains("<h1>Rust 2021</h1>"));
+
+        let documents = store.documents().unwrap();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0].borrow().get_field("volume"), Some("585"));
+    }
+
+    #[test]
+    fn test_rejected_requests() {
+        let dir = TestDir::new("connector-rejected-test");
+        let store = StagingStore::new(&dir);
+        let server = CitationServer::start(0).unwrap();
+        let body = SAVE_ITEMS.as_bytes();
+
+        // A web page posting a form or plain text, which needs no preflight
+        let (response, staged) = send(
+            &server,
+            &store,
+            post_as("/connector/saveItems", "text/plain", "", body),
+            body,
+        );
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(staged.is_empty());
+        // Any request of a web page
+        let (response, staged) = send(
+            &server,
+            &store,
+            post(
+                "/connector/saveItems",
+                "Origin: https://example.com\r\n",
+                body,
+            ),
+            body,
+        );
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert!(staged.is_empty());
+        let rpc = br#"{"jsonrpc":"2.0","method":"item.search","params":["x"],"id":1}"#;
+        let (response, _) = send(
+            &server,
+            &store,
+            post_as("/better-bibtex/json-rpc", "text/plain", "", rpc),
+            rpc,
+        );
+        assert!(response.starts_with("HTTP/1.1 415"));
+        assert!(store.items().unwrap().is_empty());
+
+        // The browser extension itself is answered
+        let (response, staged) = send(
+            &server,
+            &store,
+            post(
+                "/connector/saveItems",
+                "Origin: moz-extension://8b1e2c4d\r\n",
+                body,
+            ),
+            body,
+        );
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert_eq!(staged.len(), 1);
+    }
+}
@@ -1,13 +1,19 @@
 use std::{
     cell::{Cell, RefCell},
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use tui::widgets::{ListState, Row, TableState};
 
-use crate::ui::UIBlockType;
+use crate::{
+    metadata::strip_doi_prefix,
+    ui::{ColumnKind, UIBlockType},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StatefulList<T> {
     pub state: ListState,
     pub items: Vec<T>,
@@ -101,13 +107,22 @@ impl<T> StatefulList<T> {
     }
 }
 pub type RcDoc = Rc<RefCell<Document>>;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Document {
     pub item_data: ItemData,
     pub creators: Vec<Creator>,
     pub collections: Vec<RcCollection>,
     pub attachments: Option<StatefulList<Attachment>>,
+    /// All item fields keyed by Zotero field name (e.g. `DOI`, `publicationTitle`)
+    pub fields: HashMap<String, String>,
+    pub tags: Vec<Tag>,
+    /// Child notes, in the order they were added
+    pub notes: Vec<Note>,
     pub toggled: Cell<bool>,
+    /// Marked for actions on several documents at once
+    pub marked: Cell<bool>,
+    /// Citation key from the Better BibTeX database or generated, see `export::citation_key`
+    pub citation_key: String,
 }
 impl FromIterator<ItemData> for Vec<RcDoc> {
     fn from_iter<T: IntoIterator<Item = ItemData>>(iter: T) -> Self {
@@ -115,10 +130,15 @@ impl FromIterator<ItemData> for Vec<RcDoc> {
             .map(|item| {
                 Rc::new(RefCell::new(Document {
                     toggled: Cell::from(false),
+                    marked: Cell::from(false),
+                    citation_key: String::new(),
                     item_data: item,
                     collections: Vec::new(),
                     creators: Vec::new(),
                     attachments: None,
+                    fields: HashMap::new(),
+                    tags: Vec::new(),
+                    notes: Vec::new(),
                 }))
             })
             .collect()
@@ -129,6 +149,20 @@ impl Document {
     pub fn toggle(&mut self) {
         self.toggled.set(!self.toggled.get());
     }
+    pub fn toggle_marked(&self) {
+        self.marked.set(!self.marked.get());
+    }
+    /// Creators, without the placeholder used for items that have none
+    pub fn get_creators(&self) -> impl Iterator<Item = &Creator> {
+        self.creators
+            .iter()
+            .filter(|creator| **creator != Creator::default())
+    }
+    /// Creators in the item type's primary role (author, programmer, director, ...)
+    pub fn get_authors(&self) -> impl Iterator<Item = &Creator> {
+        self.get_creators()
+            .filter(|creator| PRIMARY_CREATOR_TYPES.contains(&creator.role()))
+    }
     pub fn get_cmp_str_for_block_type(&self, ty: UIBlockType) -> &str {
         match ty {
             UIBlockType::Title => self.get_title(),
@@ -197,24 +231,124 @@ impl Document {
             }
         }
     }
+    pub fn build_cell_for_column(&self, kind: &ColumnKind) -> String {
+        match kind {
+            ColumnKind::Title => self.get_title().to_owned(),
+            ColumnKind::Creator => self.build_header_for_block_type(UIBlockType::Creator),
+            ColumnKind::Year => self.get_year().to_owned(),
+            ColumnKind::DateAdded => self.get_date_added().to_owned(),
+            ColumnKind::Attachments => match &self.attachments {
+                Some(attachments) => attachments.items.len().to_string(),
+                None => "0".to_string(),
+            },
+            ColumnKind::Tags => self
+                .tags
+                .iter()
+                .map(|tag| tag.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", "),
+            ColumnKind::CitationKey => self.get_citation_key().to_owned(),
+            ColumnKind::ItemType => self.item_data.typeName.to_owned(),
+            ColumnKind::Field(name) => self.get_field(name).unwrap_or_default().to_owned(),
+        }
+    }
+    pub fn get_field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(|value| value.as_str())
+    }
+    /// Key pinned by Better BibTeX, either in the Zotero 7 `citationKey` field or as a
+    /// `Citation Key: <key>` line in `Extra`
+    pub fn get_pinned_citation_key(&self) -> Option<&str> {
+        if let Some(key) = self.get_field("citationKey").map(|key| key.trim()) {
+            if !key.is_empty() {
+                return Some(key);
+            }
+        }
+        self.get_field("extra")?.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let name = name.trim().to_lowercase();
+            if name == "citation key" || name == "bibtex" {
+                Some(value.trim()).filter(|value| !value.is_empty())
+            } else {
+                None
+            }
+        })
+    }
+    /// The canonical citation key: pinned keys win over keys from the Better BibTeX
+    /// database, which win over generated keys.
+    pub fn get_citation_key(&self) -> &str {
+        match self.get_pinned_citation_key() {
+            Some(key) => key,
+            None if !self.citation_key.is_empty() => self.citation_key.as_str(),
+            None => self.item_data.key.as_str(),
+        }
+    }
+    /// The item's URL, or a doi.org link when only a DOI is known
+    pub fn get_web_url(&self) -> Option<String> {
+        if let Some(url) = self.get_field("url").filter(|url| !url.trim().is_empty()) {
+            return Some(url.trim().to_owned());
+        }
+        let doi = strip_doi_prefix(self.get_field("DOI")?);
+        if doi.is_empty() {
+            None
+        } else {
+            Some(format!("https://doi.org/{}", doi))
+        }
+    }
+    pub fn get_date_added(&self) -> &str {
+        // Zotero stores `YYYY-MM-DD HH:MM:SS`, only show the date part
+        self.item_data
+            .dateAdded
+            .get(..10)
+            .unwrap_or(self.item_data.dateAdded.as_str())
+    }
     pub fn get_title(&self) -> &str {
         self.item_data.title.as_str()
     }
     // pub fn try_get_first_name(&self) -> Option<&str> {
     //     // self.creators.get(0).unwrap().firstName.unwrap().as_str()
     // }
+    pub fn get_date_parts(&self) -> DateParts {
+        DateParts::parse(&self.item_data.pubdate)
+    }
     pub fn get_year(&self) -> &str {
-        &self.item_data.pubdate[..4]
+        self.item_data.pubdate.get(..4).unwrap_or_default()
     }
 }
 #[derive(Debug, Clone)]
 #[allow(non_snake_case)]
 pub struct ItemData {
     pub itemId: i64,
+    /// Zotero library of the item, item keys are only unique within one
+    pub libraryId: i64,
     pub title: String,
     pub abstracttext: String,
     pub pubdate: String,
     pub key: String,
+    pub dateAdded: String,
+    pub typeName: String,
+}
+
+#[derive(Debug, Clone)]
+#[allow(non_snake_case)]
+pub struct ItemField {
+    pub fieldName: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(non_snake_case)]
+pub struct Tag {
+    pub tagId: i64,
+    pub name: String,
+}
+
+/// A child note, `note` is the HTML of Zotero's note editor
+#[derive(Debug, Clone, PartialEq)]
+#[allow(non_snake_case)]
+pub struct Note {
+    pub itemId: i64,
+    pub title: String,
+    pub note: String,
 }
 
 #[derive(Debug, Clone)]
@@ -243,22 +377,378 @@ pub struct Library {
 #[derive(Debug, Clone)]
 #[allow(non_snake_case)]
 pub struct Attachment {
+    pub itemId: i64,
+    pub linkMode: Option<i64>,
     pub contentType: Option<String>,
     pub path: Option<String>,
     pub key: Option<String>,
+    pub url: Option<String>,
+}
+
+/// `itemAttachments.linkMode` values
+pub const LINK_MODE_IMPORTED_FILE: i64 = 0;
+pub const LINK_MODE_IMPORTED_URL: i64 = 1;
+pub const LINK_MODE_LINKED_FILE: i64 = 2;
+pub const LINK_MODE_LINKED_URL: i64 = 3;
+
+impl Attachment {
+    pub fn content_type(&self) -> &str {
+        self.contentType.as_deref().unwrap_or("unknown")
+    }
+    /// File name shown to the user, without Zotero's `storage:`/`attachments:` prefixes
+    pub fn display_name(&self) -> String {
+        if self.linkMode == Some(LINK_MODE_LINKED_URL) {
+            if let Some(url) = &self.url {
+                return url.to_owned();
+            }
+        }
+        match &self.path {
+            Some(path) => path
+                .trim_start_matches("storage:")
+                .trim_start_matches("attachments:")
+                .to_owned(),
+            None => format!("<{}>", self.content_type()),
+        }
+    }
+    /// Locate the attachment, honoring its link mode.
+    ///
+    /// Stored files live in `<storage>/<key>/`, linked files are either absolute or
+    /// relative to Zotero's base attachment directory (`attachments:` prefix), and
+    /// linked URLs only have an URL.
+    pub fn resolve(
+        &self,
+        zotero_storage_dir: &Path,
+        base_attachment_dir: Option<&Path>,
+    ) -> ResolvedAttachment {
+        let mut path = None;
+        let mut url = None;
+        match self.linkMode {
+            Some(LINK_MODE_LINKED_URL) => url = self.url.clone(),
+            Some(LINK_MODE_LINKED_FILE) => {
+                path = match &self.path {
+                    Some(p) if p.starts_with("attachments:") => base_attachment_dir
+                        .map(|base| base.join(p.trim_start_matches("attachments:"))),
+                    Some(p) => Some(PathBuf::from(p)),
+                    None => None,
+                }
+            }
+            // Imported files and URL snapshots, which are stored files as well
+            _ => {
+                path = match (&self.key, &self.path) {
+                    (Some(key), Some(p)) => p
+                        .strip_prefix("storage:")
+                        .map(|file_name| zotero_storage_dir.join(key).join(file_name)),
+                    _ => None,
+                };
+                // A snapshot whose file is gone can still be opened from the web
+                if self.linkMode == Some(LINK_MODE_IMPORTED_URL) {
+                    url = self.url.clone();
+                }
+            }
+        }
+        let size = path
+            .as_ref()
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len());
+        ResolvedAttachment {
+            attachment: self.clone(),
+            path,
+            url,
+            size,
+        }
+    }
 }
 
+/// An attachment together with where it can be opened from.
 #[derive(Debug, Clone)]
+pub struct ResolvedAttachment {
+    pub attachment: Attachment,
+    pub path: Option<PathBuf>,
+    pub url: Option<String>,
+    /// `None` when the file does not exist
+    pub size: Option<u64>,
+}
+
+impl ResolvedAttachment {
+    pub fn is_openable(&self) -> bool {
+        self.size.is_some() || self.url.is_some()
+    }
+    pub fn human_size(&self) -> String {
+        match self.size {
+            Some(size) if size >= 1024 * 1024 => format!("{:.1} MB", size as f64 / 1048576.0),
+            Some(size) if size >= 1024 => format!("{:.1} KB", size as f64 / 1024.0),
+            Some(size) => format!("{} B", size),
+            None if self.url.is_some() => "link".to_string(),
+            None => "missing".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 #[allow(non_snake_case)]
 pub struct Creator {
     pub firstName: Option<String>,
     pub lastName: Option<String>,
+    /// Role of the creator, e.g. `author` or `editor`
+    pub creatorType: Option<String>,
+    /// 1 when the whole name is stored in `lastName` (institutions)
+    pub fieldMode: Option<i64>,
 }
 impl Default for Creator {
     fn default() -> Self {
         Self {
             firstName: Some("Unknown author(s)".to_string()),
             lastName: None,
+            creatorType: None,
+            fieldMode: None,
+        }
+    }
+}
+impl Creator {
+    pub fn is_single_field(&self) -> bool {
+        self.fieldMode == Some(1)
+            || self
+                .firstName
+                .as_deref()
+                .is_none_or(|first| first.trim().is_empty())
+    }
+    pub fn role(&self) -> &str {
+        self.creatorType.as_deref().unwrap_or("author")
+    }
+}
+
+/// Creator types that are the main creator of their item type
+pub const PRIMARY_CREATOR_TYPES: [&str; 12] = [
+    "author",
+    "artist",
+    "cartographer",
+    "composer",
+    "director",
+    "interviewee",
+    "inventor",
+    "performer",
+    "podcaster",
+    "presenter",
+    "programmer",
+    "sponsor",
+];
+
+/// Year, month and day of a Zotero date. Zotero stores dates as
+/// `YYYY-MM-DD <original string>`, with `00` for unknown parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DateParts {
+    pub year: Option<i32>,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+impl DateParts {
+    pub fn parse(date: &str) -> Self {
+        let sql_date = date.split_whitespace().next().unwrap_or_default();
+        let mut parts = sql_date.split('-');
+        let parse_part = |part: Option<&str>| {
+            part.and_then(|part| part.parse::<u32>().ok())
+                .filter(|value| *value != 0)
+        };
+        let year = parse_part(parts.next()).map(|year| year as i32);
+        let month = parse_part(parts.next());
+        let day = parse_part(parts.next());
+        Self { year, month, day }
+    }
+    /// Parse a date as entered by a user or sent by a translator, usually ISO 8601 but
+    /// possibly free text like `March 2020`, of which only the year is recognized
+    pub fn from_text(raw: &str) -> Self {
+        let raw = raw.trim();
+        let mut parts = raw.split(['-', '/', 'T', ' ']);
+        let part = |part: Option<&str>| part.and_then(|part| part.parse::<u32>().ok());
+        match parts.next().filter(|year| year.len() == 4) {
+            Some(year) if year.parse::<i32>().is_ok() => Self {
+                year: year.parse().ok(),
+                month: part(parts.next()).filter(|month| (1..=12).contains(month)),
+                day: part(parts.next()).filter(|day| (1..=31).contains(day)),
+            },
+            _ => Self {
+                year: raw
+                    .split(|c: char| !c.is_ascii_digit())
+                    .find(|part| part.len() == 4)
+                    .and_then(|year| year.parse().ok()),
+                month: None,
+                day: None,
+            },
         }
     }
+    /// Zotero's `YYYY-MM-DD <as entered>` form of a date read with `from_text`
+    pub fn zotero_date_from_text(raw: &str) -> Option<String> {
+        Self::from_text(raw).to_zotero_date(Some(raw.trim()))
+    }
+    /// Zotero's `YYYY-MM-DD <original>` representation, `original` defaulting to ISO 8601
+    pub fn to_zotero_date(self, original: Option<&str>) -> Option<String> {
+        let iso = self.to_iso()?;
+        Some(format!(
+            "{:04}-{:02}-{:02} {}",
+            self.year?,
+            self.month.unwrap_or(0),
+            self.day.unwrap_or(0),
+            original.unwrap_or(&iso)
+        ))
+    }
+    /// ISO 8601 representation with as much precision as known
+    pub fn to_iso(self) -> Option<String> {
+        let year = self.year?;
+        Some(match (self.month, self.day) {
+            (Some(month), Some(day)) => format!("{:04}-{:02}-{:02}", year, month, day),
+            (Some(month), None) => format!("{:04}-{:02}", year, month),
+            _ => format!("{:04}", year),
+        })
+    }
+}
+
+/// Build a document from raw Zotero fields, for tests that don't have a database.
+#[cfg(test)]
+pub fn new_test_document(
+    item_type: &str,
+    fields: &[(&str, &str)],
+    creators: Vec<Creator>,
+) -> RcDoc {
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default()
+    };
+    let doc: RcDoc = Vec::<RcDoc>::from_iter(vec![ItemData {
+        itemId: 1,
+        libraryId: 1,
+        title: field("title"),
+        abstracttext: field("abstractNote"),
+        pubdate: field("date"),
+        key: "ABCD1234".to_string(),
+        dateAdded: "2021-01-01 00:00:00".to_string(),
+        typeName: item_type.to_string(),
+    }])
+    .remove(0);
+    doc.borrow_mut().fields.extend(
+        fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+    );
+    doc.borrow_mut().creators = creators;
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(link_mode: i64, path: Option<&str>, url: Option<&str>) -> Attachment {
+        Attachment {
+            itemId: 1,
+            linkMode: Some(link_mode),
+            contentType: Some("application/pdf".to_string()),
+            path: path.map(|p| p.to_string()),
+            key: Some("ABCD1234".to_string()),
+            url: url.map(|u| u.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_get_web_url() {
+        let doc = new_test_document("journalArticle", &[("title", "A")], Vec::new());
+        assert_eq!(doc.borrow().get_web_url(), None);
+        for doi in [
+            "10.1000/xyz",
+            "doi:10.1000/xyz",
+            "DOI: 10.1000/xyz",
+            "https://doi.org/10.1000/xyz",
+            "http://doi.org/10.1000/xyz",
+            "https://dx.doi.org/10.1000/xyz",
+            "HTTP://DX.DOI.ORG/10.1000/xyz",
+            " Https://Doi.org/10.1000/xyz ",
+        ] {
+            doc.borrow_mut()
+                .fields
+                .insert("DOI".to_string(), doi.to_string());
+            assert_eq!(
+                doc.borrow().get_web_url().as_deref(),
+                Some("https://doi.org/10.1000/xyz"),
+                "{}",
+                doi
+            );
+        }
+        doc.borrow_mut()
+            .fields
+            .insert("DOI".to_string(), "https://doi.org/".to_string());
+        assert_eq!(doc.borrow().get_web_url(), None);
+        doc.borrow_mut()
+            .fields
+            .insert("url".to_string(), "https://example.org".to_string());
+        assert_eq!(
+            doc.borrow().get_web_url().as_deref(),
+            Some("https://example.org")
+        );
+    }
+
+    #[test]
+    fn test_get_citation_key() {
+        let doc = new_test_document(
+            "book",
+            &[("extra", "Some note\nCitation Key: doe2020pinned")],
+            Vec::new(),
+        );
+        assert_eq!(doc.borrow().get_citation_key(), "doe2020pinned");
+        doc.borrow_mut()
+            .fields
+            .insert("citationKey".to_string(), "zotero7".to_string());
+        assert_eq!(doc.borrow().get_citation_key(), "zotero7");
+
+        let doc = new_test_document("book", &[], Vec::new());
+        assert_eq!(doc.borrow().get_citation_key(), "ABCD1234");
+        doc.borrow_mut().citation_key = "fromdb".to_string();
+        assert_eq!(doc.borrow().get_citation_key(), "fromdb");
+    }
+
+    #[test]
+    fn test_resolve_attachment() {
+        let storage = Path::new("/zotero/storage");
+        let base = Path::new("/papers");
+
+        let stored = attachment(0, Some("storage:a.pdf"), None);
+        assert_eq!(
+            stored.resolve(storage, Some(base)).path,
+            Some(PathBuf::from("/zotero/storage/ABCD1234/a.pdf"))
+        );
+
+        let linked = attachment(LINK_MODE_LINKED_FILE, Some("/home/me/b.pdf"), None);
+        assert_eq!(
+            linked.resolve(storage, Some(base)).path,
+            Some(PathBuf::from("/home/me/b.pdf"))
+        );
+
+        let relative = attachment(LINK_MODE_LINKED_FILE, Some("attachments:x/c.pdf"), None);
+        assert_eq!(
+            relative.resolve(storage, Some(base)).path,
+            Some(PathBuf::from("/papers/x/c.pdf"))
+        );
+        assert_eq!(relative.resolve(storage, None).path, None);
+
+        let link = attachment(LINK_MODE_LINKED_URL, None, Some("https://example.org"));
+        let resolved = link.resolve(storage, Some(base));
+        assert_eq!(resolved.path, None);
+        assert!(resolved.is_openable());
+        assert_eq!(link.display_name(), "https://example.org");
+    }
+
+    #[test]
+    fn test_zotero_date_from_text() {
+        assert_eq!(
+            DateParts::zotero_date_from_text("2020-09-16").as_deref(),
+            Some("2020-09-16 2020-09-16")
+        );
+        assert_eq!(
+            DateParts::zotero_date_from_text("March 2020").as_deref(),
+            Some("2020-00-00 March 2020")
+        );
+        assert_eq!(DateParts::zotero_date_from_text("n.d."), None);
+    }
 }
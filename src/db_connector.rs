@@ -1,42 +1,235 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use crate::data_structures::*;
 
-use sqlx::query_as;
+use anyhow::{anyhow, bail};
+use sqlx::{
+    query, query_as, query_scalar,
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+    ConnectOptions, Connection, SqlitePool,
+};
 
-use crate::app::App;
+use crate::{
+    app::App,
+    backend::{group_by_item, BackendFuture, ByItem, LibraryBackend},
+    connector::new_key,
+    export::ris::content_type_for_path,
+};
 // use sqlx::sql
 
-#[allow(non_snake_case)]
-pub async fn get_attachments_for_docs(app: &mut App) -> anyhow::Result<()> {
-    let pool = app.sqlite_pool.as_ref().unwrap();
+/// Zotero's database as the library, see `LibraryBackend`. Reads share a pool, writes use
+/// a connection of their own, see `begin_write`.
+pub struct ZoteroDb {
+    pool: SqlitePool,
+    db_path: PathBuf,
+    /// Where imported attachments are stored
+    storage_dir: PathBuf,
+    /// Library new items and collections go to, the personal library if `None`
+    library_id: Option<i64>,
+}
 
-    for doc in &app.documents {
-        let itemId = doc.borrow().item_data.itemId;
+impl ZoteroDb {
+    pub async fn open(
+        db_path: &Path,
+        storage_dir: &Path,
+        library_id: Option<i64>,
+    ) -> anyhow::Result<Self> {
+        let pool = SqlitePool::connect(&format!("sqlite:{}", db_path.to_str().unwrap())).await?;
+        Ok(Self {
+            pool,
+            db_path: db_path.to_path_buf(),
+            storage_dir: storage_dir.to_path_buf(),
+            library_id,
+        })
+    }
+}
+
+impl LibraryBackend for ZoteroDb {
+    fn name(&self) -> String {
+        self.db_path.display().to_string()
+    }
+
+    fn load_items(&self) -> BackendFuture<'_, Vec<ItemData>> {
+        Box::pin(get_all_item_data(&self.pool))
+    }
+
+    fn load_creators(&self) -> BackendFuture<'_, ByItem<Creator>> {
+        Box::pin(get_creators(&self.pool))
+    }
+
+    fn load_attachments(&self) -> BackendFuture<'_, ByItem<Attachment>> {
+        Box::pin(get_attachments(&self.pool))
+    }
+
+    fn load_fields(&self) -> BackendFuture<'_, ByItem<ItemField>> {
+        Box::pin(get_fields(&self.pool))
+    }
+
+    fn load_tags(&self) -> BackendFuture<'_, ByItem<Tag>> {
+        Box::pin(get_tags(&self.pool))
+    }
+
+    fn load_notes(&self) -> BackendFuture<'_, ByItem<Note>> {
+        Box::pin(get_notes(&self.pool))
+    }
+
+    fn load_collections(&self) -> BackendFuture<'_, Vec<Collection>> {
+        Box::pin(get_collections(&self.pool))
+    }
+
+    fn load_collection_items(&self) -> BackendFuture<'_, Vec<CollectionItem>> {
+        Box::pin(get_collection_items(&self.pool))
+    }
 
-        let records = query_as!(
-            Attachment,
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn close(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async {
+            self.pool.close().await;
+            Ok(())
+        })
+    }
+
+    fn write_item_changes<'a>(
+        &'a self,
+        item_id: i64,
+        changes: &'a ItemChanges,
+    ) -> BackendFuture<'a, ()> {
+        Box::pin(write_item_changes(&self.db_path, item_id, changes))
+    }
+
+    fn create_item<'a>(&'a self, item: &'a NewItem) -> BackendFuture<'a, (ItemData, Vec<Tag>)> {
+        Box::pin(create_item(&self.db_path, self.library_id, item))
+    }
+
+    fn import_attachment<'a>(
+        &'a self,
+        parent_id: i64,
+        file: &'a Path,
+    ) -> BackendFuture<'a, Attachment> {
+        Box::pin(import_attachment(
+            &self.db_path,
+            &self.storage_dir,
+            parent_id,
+            file,
+        ))
+    }
+
+    fn create_collection<'a>(
+        &'a self,
+        library_id: Option<i64>,
+        parent_id: Option<i64>,
+        name: &'a str,
+    ) -> BackendFuture<'a, Collection> {
+        Box::pin(create_collection(
+            &self.db_path,
+            library_id.or(self.library_id),
+            parent_id,
+            name,
+        ))
+    }
+
+    fn rename_collection<'a>(&'a self, collection_id: i64, name: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(rename_collection(&self.db_path, collection_id, name))
+    }
+
+    fn move_collection(&self, collection_id: i64, parent_id: Option<i64>) -> BackendFuture<'_, ()> {
+        Box::pin(move_collection(&self.db_path, collection_id, parent_id))
+    }
+
+    fn delete_collection(&self, collection_id: i64) -> BackendFuture<'_, Vec<i64>> {
+        Box::pin(delete_collection(&self.db_path, collection_id))
+    }
+
+    fn add_to_collection<'a>(
+        &'a self,
+        collection_id: i64,
+        item_ids: &'a [i64],
+    ) -> BackendFuture<'a, usize> {
+        Box::pin(add_to_collection(&self.db_path, collection_id, item_ids))
+    }
+
+    fn remove_from_collection<'a>(
+        &'a self,
+        collection_id: i64,
+        item_ids: &'a [i64],
+    ) -> BackendFuture<'a, usize> {
+        Box::pin(remove_from_collection(
+            &self.db_path,
+            collection_id,
+            item_ids,
+        ))
+    }
+
+    fn add_tag<'a>(
+        &'a self,
+        item_ids: &'a [i64],
+        name: &'a str,
+    ) -> BackendFuture<'a, (Tag, usize)> {
+        Box::pin(add_tag(&self.db_path, item_ids, name))
+    }
+
+    fn remove_tag<'a>(&'a self, item_ids: &'a [i64], name: &'a str) -> BackendFuture<'a, usize> {
+        Box::pin(remove_tag(&self.db_path, item_ids, name))
+    }
+
+    fn rename_tag<'a>(&'a self, old_name: &'a str, new_name: &'a str) -> BackendFuture<'a, Tag> {
+        Box::pin(rename_tag(&self.db_path, old_name, new_name))
+    }
+
+    fn delete_tag<'a>(&'a self, name: &'a str) -> BackendFuture<'a, usize> {
+        Box::pin(delete_tag(&self.db_path, name))
+    }
+
+    fn save_note<'a>(
+        &'a self,
+        parent_id: i64,
+        note_id: Option<i64>,
+        title: &'a str,
+        html: &'a str,
+    ) -> BackendFuture<'a, Note> {
+        Box::pin(save_note(&self.db_path, parent_id, note_id, title, html))
+    }
+}
+
+/// Attachments of all items by parent item
+pub async fn get_attachments(pool: &SqlitePool) -> anyhow::Result<ByItem<Attachment>> {
+    let records = query!(
             r#"
-SELECT contentType as "contentType?", path as  "path?", key as "key?"
+SELECT parentItemID as "parentId!", items.itemID as "itemId!", linkMode as "linkMode?", contentType as "contentType?", path as  "path?", key as "key?",
+    (SELECT value FROM itemData
+        JOIN itemDataValues ON itemDataValues.valueID = itemData.valueID
+        JOIN fields ON fields.fieldID = itemData.fieldID
+    WHERE itemData.itemID = items.itemID AND fieldName = 'url') as "url?"
 FROM itemAttachments JOIN items on itemAttachments.itemID = items.itemID
-WHERE parentItemID = ?
-"#,
-            itemId
-        )
-        .fetch_all(pool)
-        .await?;
-        if !records.is_empty() {
-            doc.borrow_mut().attachments = Some(StatefulList::with_items(records));
-        }
-    }
-    Ok(())
+WHERE parentItemID IS NOT NULL
+ORDER BY items.itemID
+"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(group_by_item(records.into_iter().map(|rec| {
+        let attachment = Attachment {
+            itemId: rec.itemId,
+            linkMode: rec.linkMode,
+            contentType: rec.contentType,
+            path: rec.path,
+            key: rec.key,
+            url: rec.url,
+        };
+        (rec.parentId, attachment)
+    })))
 }
 
 #[allow(non_snake_case)]
-pub async fn get_collections_items(app: &mut App) -> anyhow::Result<()> {
-    let pool = app.sqlite_pool.as_ref().unwrap();
-
-    let records = query_as!(
+pub async fn get_collection_items(pool: &SqlitePool) -> anyhow::Result<Vec<CollectionItem>> {
+    Ok(query_as!(
         CollectionItem,
         r#"
 SELECT collectionID as "collectionId!", itemID as "itemId!"
@@ -44,34 +237,11 @@ FROM collectionItems
 "#,
     )
     .fetch_all(pool)
-    .await?;
-    assert!(!app.documents.is_empty());
-    // TODO: Handle multiple collection, same record.
-    for record in records {
-        if let Some(doc) = app
-            .documents
-            .iter()
-            .find(|doc| doc.borrow().item_data.itemId == record.itemId)
-        {
-            if let Some(collection) = app
-                .collections
-                .items
-                .iter()
-                .find(|col| col.borrow().collectionId == record.collectionId)
-            {
-                doc.borrow_mut().collections.push(collection.clone());
-            }
-        };
-    }
-
-    // app.collections.items = records;
-    Ok(())
+    .await?)
 }
 #[allow(non_snake_case)]
-pub async fn get_collections(app: &mut App) -> anyhow::Result<()> {
-    let pool = app.sqlite_pool.as_ref().unwrap();
-
-    let records = query_as!(
+pub async fn get_collections(pool: &SqlitePool) -> anyhow::Result<Vec<Collection>> {
+    Ok(query_as!(
             Collection,
             r#"
 SELECT collectionID as "collectionId!", libraryID as "libraryId!", collectionName as "collectionName!", parentCollectionId as "parentCollectionId?"
@@ -80,57 +250,154 @@ ORDER BY collectionName
 "#,
         )
         .fetch_all(pool)
-        .await?;
-    for record in records {
-        app.collections.items.push(Rc::new(RefCell::new(record)));
-    }
-    Ok(())
+        .await?)
 }
-#[allow(non_snake_case)]
-pub async fn get_creators_for_docs(app: &mut App) -> anyhow::Result<()> {
-    let pool = app.sqlite_pool.as_ref().unwrap();
-    for doc in &mut app.documents {
-        let itemId = doc.borrow().item_data.itemId;
-        let records = query_as!(
-            Creator,
+/// Creators of all items, each in order
+pub async fn get_creators(pool: &SqlitePool) -> anyhow::Result<ByItem<Creator>> {
+    let records = query!(
             r#"
-SELECT firstName as "firstName?", lastName as  "lastName?" 
+SELECT itemID as "itemId!", firstName as "firstName?", lastName as  "lastName?", creatorType as "creatorType?", fieldMode as "fieldMode?"
 FROM creators JOIN itemCreators on itemCreators.creatorID = creators.creatorID
-WHERE itemID = ?
-ORDER BY itemCreators.orderIndex
-"#,
-            itemId
-        )
-        .fetch_all(pool)
+    LEFT JOIN creatorTypes on creatorTypes.creatorTypeID = itemCreators.creatorTypeID
+ORDER BY itemID, itemCreators.orderIndex
+"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(group_by_item(records.into_iter().map(|rec| {
+        let creator = Creator {
+            firstName: rec.firstName,
+            lastName: rec.lastName,
+            creatorType: rec.creatorType,
+            fieldMode: rec.fieldMode,
+        };
+        (rec.itemId, creator)
+    })))
+}
+
+/// Fields of all items
+pub async fn get_fields(pool: &SqlitePool) -> anyhow::Result<ByItem<ItemField>> {
+    let records = query!(
+        r#"
+SELECT itemID as "itemId!", fieldName as "fieldName!", value as "value!"
+FROM itemData
+    JOIN itemDataValues ON itemDataValues.valueID = itemData.valueID
+    JOIN fields ON fields.fieldID = itemData.fieldID
+"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(group_by_item(records.into_iter().map(|rec| {
+        let field = ItemField {
+            fieldName: rec.fieldName,
+            value: rec.value,
+        };
+        (rec.itemId, field)
+    })))
+}
+
+/// Tags of all items, each by name
+pub async fn get_tags(pool: &SqlitePool) -> anyhow::Result<ByItem<Tag>> {
+    let records = query!(
+        r#"
+SELECT itemID as "itemId!", tags.tagID as "tagId!", name as "name!"
+FROM tags JOIN itemTags ON itemTags.tagID = tags.tagID
+ORDER BY itemID, name
+"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(group_by_item(records.into_iter().map(|rec| {
+        let tag = Tag {
+            tagId: rec.tagId,
+            name: rec.name,
+        };
+        (rec.itemId, tag)
+    })))
+}
+
+/// Child notes of all items, each in the order they were added
+pub async fn get_notes(pool: &SqlitePool) -> anyhow::Result<ByItem<Note>> {
+    let records = query!(
+            r#"
+SELECT parentItemID as "parentId!", itemNotes.itemID as "itemId!", COALESCE(title, '') as "title!: String", COALESCE(note, '') as "note!: String"
+FROM itemNotes JOIN items ON items.itemID = itemNotes.itemID
+WHERE parentItemID IS NOT NULL
+ORDER BY items.dateAdded, itemNotes.itemID
+"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(group_by_item(records.into_iter().map(|rec| {
+        let note = Note {
+            itemId: rec.itemId,
+            title: rec.title,
+            note: rec.note,
+        };
+        (rec.parentId, note)
+    })))
+}
+
+/// Load the citation keys of the Better BibTeX plugin from its own database. Keys pinned
+/// in the items themselves still take precedence, see `Document::get_citation_key`.
+pub async fn get_better_bibtex_keys(app: &mut App, bbt_db_path: &Path) -> anyhow::Result<()> {
+    if !bbt_db_path.exists() {
+        return Ok(());
+    }
+    let mut conn = SqliteConnectOptions::new()
+        .filename(bbt_db_path)
+        .read_only(true)
+        .connect()
         .await?;
-        if !records.is_empty() {
-            doc.borrow_mut().creators.extend(records);
-        } else {
-            doc.borrow_mut().creators.extend(vec![Creator::default()]);
+    // Not part of the Zotero schema, so this can't be checked by `query_as!`
+    let records: Vec<(i64, String, String)> =
+        match sqlx::query_as("SELECT libraryID, itemKey, citationKey FROM citationkey")
+            .fetch_all(&mut conn)
+            .await
+        {
+            Ok(records) => records,
+            // Older Better BibTeX versions store their keys differently
+            Err(_) => return Ok(()),
+        };
+    let keys: HashMap<(i64, String), String> = records
+        .into_iter()
+        .map(|(library_id, item_key, citation_key)| ((library_id, item_key), citation_key))
+        .collect();
+    for doc in &app.documents {
+        let item = (
+            doc.borrow().item_data.libraryId,
+            doc.borrow().item_data.key.clone(),
+        );
+        if let Some(key) = keys.get(&item) {
+            doc.borrow_mut().citation_key = key.clone();
         }
     }
+    conn.close().await?;
     Ok(())
 }
 
 #[allow(non_snake_case)]
-pub async fn get_all_item_data(app: &mut App) -> anyhow::Result<Vec<ItemData>> {
-    let pool = app.sqlite_pool.as_ref().unwrap();
-
+pub async fn get_all_item_data(pool: &SqlitePool) -> anyhow::Result<Vec<ItemData>> {
     // let conn = pool.acquire().await?;
     // let docs = Vec::new();
     let records = query_as!(
     ItemData,
         r#"
 SELECT d1.itemID as "itemId!", 
+    items.libraryID as "libraryId!",
     title as "title!", 
     key as "key!", 
-    abstract as "abstracttext!", 
-    pubdate as "pubdate!" 
+    COALESCE(abstract, '') as "abstracttext!: String",
+    COALESCE(pubdate, '') as "pubdate!: String",
+    CAST(items.dateAdded AS TEXT) as "dateAdded!",
+    itemTypes.typeName as "typeName!"
 FROM 
 	(SELECT value as title,itemID	from itemDataValues JOIN itemData on itemDataValues.valueID = itemData.valueID WHERE fieldID = 1) as d1
-	    JOIN (SELECT value as abstract, itemID	from itemDataValues JOIN itemData on itemDataValues.valueID = itemData.valueID WHERE fieldID = 2) as d2 ON d1.itemID = d2.itemID
-	    JOIN (SELECT value as pubdate, itemID	from itemDataValues JOIN itemData on itemDataValues.valueID = itemData.valueID WHERE fieldID = 6) as d3 ON d1.itemID = d3.itemID	
+	    LEFT JOIN (SELECT value as abstract, itemID	from itemDataValues JOIN itemData on itemDataValues.valueID = itemData.valueID WHERE fieldID = 2) as d2 ON d1.itemID = d2.itemID
+	    LEFT JOIN (SELECT value as pubdate, itemID	from itemDataValues JOIN itemData on itemDataValues.valueID = itemData.valueID WHERE fieldID = 6) as d3 ON d1.itemID = d3.itemID	
         JOIN items ON items.itemID = d1.itemID  
+        JOIN itemTypes ON itemTypes.itemTypeID = items.itemTypeID
+WHERE d1.itemID NOT IN (SELECT itemID FROM itemAttachments WHERE parentItemID IS NOT NULL)
 "#
     )
     .fetch_all(pool)
@@ -138,23 +405,1533 @@ FROM
     Ok(records)
 }
 
+/// Changes to the fields and creators of one item, see `write_item_changes`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemChanges {
+    /// New values by Zotero field name, an empty value removes the field. Dates must
+    /// already be in Zotero's `YYYY-MM-DD <as entered>` form.
+    pub fields: Vec<(String, String)>,
+    /// All creators of the item in order, if they changed
+    pub creators: Option<Vec<Creator>>,
+}
+
+impl ItemChanges {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.creators.is_none()
+    }
+}
+
+/// SQLite's `SQLITE_BUSY` and `SQLITE_LOCKED`, the primary codes of the extended ones
+fn is_lock_error(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(err) => err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6)),
+        _ => false,
+    }
+}
+
+fn write_error(err: sqlx::Error) -> anyhow::Error {
+    if is_lock_error(&err) {
+        anyhow!("The Zotero database is locked, close Zotero to edit items")
+    } else {
+        err.into()
+    }
+}
+
+/// Open the database and start a write transaction, see `end_write`.
+///
+/// Zotero holds an exclusive lock on its database while it runs. Rather than waiting for
+/// it, this fails right away and leaves the database untouched.
+pub async fn begin_write(db_path: &Path) -> anyhow::Result<SqliteConnection> {
+    let mut conn = SqliteConnectOptions::new()
+        .filename(db_path)
+        .busy_timeout(Duration::ZERO)
+        .connect()
+        .await
+        .map_err(write_error)?;
+    // Take the write lock up front, a deferred transaction could fail halfway through
+    query("BEGIN IMMEDIATE")
+        .execute(&mut conn)
+        .await
+        .map_err(write_error)?;
+    Ok(conn)
+}
+
+/// Commit the transaction of `begin_write` if `result` is a success, roll it back otherwise
+pub async fn end_write<T>(
+    mut conn: SqliteConnection,
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    query(end).execute(&mut conn).await.map_err(write_error)?;
+    conn.close().await?;
+    result
+}
+
+/// Mark an item as changed, so that Zotero uploads it on its next sync (`synced = 0`).
+/// `version` is the library version the server assigned at the last sync, Zotero sends it
+/// along to detect conflicts, so it must be left alone.
+#[allow(non_snake_case)]
+async fn mark_item_modified(conn: &mut SqliteConnection, itemId: i64) -> anyhow::Result<()> {
+    query!(
+        "UPDATE items SET dateModified = CURRENT_TIMESTAMP,
+            clientDateModified = CURRENT_TIMESTAMP, synced = 0
+        WHERE itemID = ?",
+        itemId
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Write `changes` to the item `item_id` in a single transaction. Values of `itemDataValues`
+/// are shared between items, so they are reused when they exist and removed once no item
+/// uses them anymore, the same goes for `creators`.
+pub async fn write_item_changes(
+    db_path: &Path,
+    item_id: i64,
+    changes: &ItemChanges,
+) -> anyhow::Result<()> {
+    let mut conn = begin_write(db_path).await?;
+    let result = write_item_changes_locked(&mut conn, item_id, changes).await;
+    end_write(conn, result).await
+}
+
+#[allow(non_snake_case)]
+pub async fn write_item_changes_locked(
+    conn: &mut SqliteConnection,
+    itemId: i64,
+    changes: &ItemChanges,
+) -> anyhow::Result<()> {
+    let itemTypeId = query_scalar!(
+        r#"SELECT itemTypeID as "itemTypeId!" FROM items WHERE itemID = ?"#,
+        itemId
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| anyhow!("Item {} isn't in the Zotero database", itemId))?;
+
+    for (name, value) in &changes.fields {
+        let fieldId = query_scalar!(
+            r#"SELECT fieldID as "fieldId!" FROM fields WHERE fieldName = ?"#,
+            name
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| anyhow!("Unknown field \"{}\"", name))?;
+        if !is_valid_field(conn, itemTypeId, fieldId).await {
+            bail!("The field \"{}\" isn't valid for this item type", name);
+        }
+        let oldValueId = query_scalar!(
+            r#"SELECT valueID as "valueId!: i64" FROM itemData WHERE itemID = ? AND fieldID = ?"#,
+            itemId,
+            fieldId
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if value.is_empty() {
+            query!(
+                "DELETE FROM itemData WHERE itemID = ? AND fieldID = ?",
+                itemId,
+                fieldId
+            )
+            .execute(&mut *conn)
+            .await?;
+        } else {
+            query!(
+                "INSERT OR IGNORE INTO itemDataValues (value) VALUES (?)",
+                value
+            )
+            .execute(&mut *conn)
+            .await?;
+            let valueId = query_scalar!(
+                r#"SELECT valueID as "valueId!" FROM itemDataValues WHERE value = ?"#,
+                value
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            query!(
+                "INSERT OR REPLACE INTO itemData (itemID, fieldID, valueID) VALUES (?, ?, ?)",
+                itemId,
+                fieldId,
+                valueId
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        if let Some(oldValueId) = oldValueId {
+            query!(
+                "DELETE FROM itemDataValues WHERE valueID = ?1
+                    AND NOT EXISTS (SELECT 1 FROM itemData WHERE valueID = ?1)",
+                oldValueId
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    if let Some(creators) = &changes.creators {
+        let oldCreatorIds = query_scalar!(
+            r#"SELECT creatorID as "creatorId!" FROM itemCreators WHERE itemID = ?"#,
+            itemId
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        query!("DELETE FROM itemCreators WHERE itemID = ?", itemId)
+            .execute(&mut *conn)
+            .await?;
+        for (orderIndex, creator) in (0_i64..).zip(creators) {
+            // Single field names are stored in `lastName`, with an empty `firstName`
+            let (firstName, lastName, fieldMode) = match creator.is_single_field() {
+                true => ("", creator.lastName.as_deref().unwrap_or_default(), 1_i64),
+                false => (
+                    creator.firstName.as_deref().unwrap_or_default(),
+                    creator.lastName.as_deref().unwrap_or_default(),
+                    0,
+                ),
+            };
+            let creatorType = creator.role();
+            let creatorTypeId = query_scalar!(
+                r#"SELECT creatorTypeID as "creatorTypeId!" FROM creatorTypes WHERE creatorType = ?"#,
+                creatorType
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| anyhow!("Unknown creator type \"{}\"", creatorType))?;
+            query!(
+                "INSERT OR IGNORE INTO creators (firstName, lastName, fieldMode) VALUES (?, ?, ?)",
+                firstName,
+                lastName,
+                fieldMode
+            )
+            .execute(&mut *conn)
+            .await?;
+            let creatorId = query_scalar!(
+                r#"SELECT creatorID as "creatorId!" FROM creators
+                WHERE firstName = ? AND lastName = ? AND fieldMode = ?"#,
+                firstName,
+                lastName,
+                fieldMode
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            query!(
+                "INSERT INTO itemCreators (itemID, creatorID, creatorTypeID, orderIndex)
+                VALUES (?, ?, ?, ?)",
+                itemId,
+                creatorId,
+                creatorTypeId,
+                orderIndex
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        for creatorId in oldCreatorIds {
+            query!(
+                "DELETE FROM creators WHERE creatorID = ?1
+                    AND NOT EXISTS (SELECT 1 FROM itemCreators WHERE creatorID = ?1)",
+                creatorId
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    mark_item_modified(conn, itemId).await
+}
+
+/// Whether the item type has the field. Without the `itemTypeFields` table, as in
+/// trimmed down copies of the database, every field is accepted.
+async fn is_valid_field(conn: &mut SqliteConnection, item_type_id: i64, field_id: i64) -> bool {
+    // Not in every copy of the schema, so this can't be checked by `query!`
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM itemTypeFields WHERE itemTypeID = ? AND fieldID = ?",
+    )
+    .bind(item_type_id)
+    .bind(field_id)
+    .fetch_one(conn)
+    .await
+    .map_or(true, |count| count > 0)
+}
+
+/// Apply changes written by `write_item_changes` to the loaded document
+pub fn apply_item_changes(doc: &mut Document, changes: &ItemChanges) {
+    for (name, value) in &changes.fields {
+        if value.is_empty() {
+            doc.fields.remove(name);
+        } else {
+            doc.fields.insert(name.clone(), value.clone());
+        }
+        let target = match name.as_str() {
+            "title" => &mut doc.item_data.title,
+            "abstractNote" => &mut doc.item_data.abstracttext,
+            "date" => &mut doc.item_data.pubdate,
+            _ => continue,
+        };
+        *target = value.clone();
+    }
+    if let Some(creators) = &changes.creators {
+        doc.creators = match creators.is_empty() {
+            true => vec![Creator::default()],
+            false => creators.clone(),
+        };
+    }
+}
+
+/// IDs of the collection and all of its sub-collections, parents first
+async fn collection_and_descendants(
+    conn: &mut SqliteConnection,
+    collection_id: i64,
+) -> anyhow::Result<Vec<i64>> {
+    // Recursive queries can't be checked by `query_scalar!`
+    Ok(sqlx::query_scalar(
+        "WITH RECURSIVE tree(id, depth) AS (
+            SELECT collectionID, 0 FROM collections WHERE collectionID = ?
+            UNION ALL
+            SELECT collectionID, depth + 1 FROM collections JOIN tree ON parentCollectionID = id
+        )
+        SELECT id FROM tree ORDER BY depth",
+    )
+    .bind(collection_id)
+    .fetch_all(conn)
+    .await?)
+}
+
+/// `library_id`, or the personal library if `None`
+async fn default_library(
+    conn: &mut SqliteConnection,
+    library_id: Option<i64>,
+) -> anyhow::Result<i64> {
+    if let Some(library_id) = library_id {
+        return Ok(library_id);
+    }
+    query_scalar!(r#"SELECT libraryID as "libraryId!" FROM libraries WHERE type = 'user'"#)
+        .fetch_optional(conn)
+        .await?
+        .ok_or_else(|| anyhow!("The database has no personal library"))
+}
+
+#[allow(non_snake_case)]
+async fn collection_library(conn: &mut SqliteConnection, collectionId: i64) -> anyhow::Result<i64> {
+    query_scalar!(
+        r#"SELECT libraryID as "libraryId!" FROM collections WHERE collectionID = ?"#,
+        collectionId
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| anyhow!("Collection {} isn't in the Zotero database", collectionId))
+}
+
+/// Create a collection in `parent_id`, or at the top level of `library_id` (the personal
+/// library by default)
+#[allow(non_snake_case)]
+pub async fn create_collection(
+    db_path: &Path,
+    library_id: Option<i64>,
+    parent_id: Option<i64>,
+    name: &str,
+) -> anyhow::Result<Collection> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let libraryId = match (parent_id, library_id) {
+            (Some(parent_id), _) => collection_library(&mut conn, parent_id).await?,
+            (None, library_id) => default_library(&mut conn, library_id).await?,
+        };
+        let key = new_key();
+        // New collections have never been synced, the server assigns their `version`
+        let collectionId = query!(
+            "INSERT INTO collections (collectionName, parentCollectionID, libraryID, key, synced)
+            VALUES (?, ?, ?, ?, 0)",
+            name,
+            parent_id,
+            libraryId,
+            key
+        )
+        .execute(&mut conn)
+        .await?
+        .last_insert_rowid();
+        Ok(Collection {
+            collectionId,
+            libraryId,
+            collectionName: name.to_string(),
+            parentCollectionId: parent_id,
+        })
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+#[allow(non_snake_case)]
+pub async fn rename_collection(
+    db_path: &Path,
+    collectionId: i64,
+    name: &str,
+) -> anyhow::Result<()> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let updated = query!(
+            "UPDATE collections SET collectionName = ?, clientDateModified = CURRENT_TIMESTAMP,
+                synced = 0
+            WHERE collectionID = ?",
+            name,
+            collectionId
+        )
+        .execute(&mut conn)
+        .await?;
+        if updated.rows_affected() == 0 {
+            bail!("Collection {} isn't in the Zotero database", collectionId);
+        }
+        Ok(())
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Move a collection into `parent_id`, or to the top level of its library
+#[allow(non_snake_case)]
+pub async fn move_collection(
+    db_path: &Path,
+    collectionId: i64,
+    parent_id: Option<i64>,
+) -> anyhow::Result<()> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let libraryId = collection_library(&mut conn, collectionId).await?;
+        if let Some(parent_id) = parent_id {
+            if collection_and_descendants(&mut conn, collectionId)
+                .await?
+                .contains(&parent_id)
+            {
+                bail!("A collection can't be moved into itself");
+            }
+            if collection_library(&mut conn, parent_id).await? != libraryId {
+                bail!("Collections can't be moved to another library");
+            }
+        }
+        query!(
+            "UPDATE collections SET parentCollectionID = ?,
+                clientDateModified = CURRENT_TIMESTAMP, synced = 0
+            WHERE collectionID = ?",
+            parent_id,
+            collectionId
+        )
+        .execute(&mut conn)
+        .await?;
+        Ok(())
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Delete a collection with its sub-collections, not the items in them. Collections the
+/// server knows are recorded in `syncDeleteLog`, so that Zotero deletes them there too.
+/// Returns the IDs of the deleted collections.
+#[allow(non_snake_case)]
+pub async fn delete_collection(db_path: &Path, collection_id: i64) -> anyhow::Result<Vec<i64>> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        collection_library(&mut conn, collection_id).await?;
+        let ids = collection_and_descendants(&mut conn, collection_id).await?;
+        for collectionId in ids.iter().rev() {
+            // The collections of an item are part of the item for the sync
+            query!(
+                "UPDATE items SET clientDateModified = CURRENT_TIMESTAMP, synced = 0
+                WHERE itemID IN (SELECT itemID FROM collectionItems WHERE collectionID = ?)",
+                collectionId
+            )
+            .execute(&mut conn)
+            .await?;
+            query!(
+                "DELETE FROM collectionItems WHERE collectionID = ?",
+                collectionId
+            )
+            .execute(&mut conn)
+            .await?;
+            query!(
+                "INSERT OR REPLACE INTO syncDeleteLog (syncObjectTypeID, libraryID, key)
+                SELECT syncObjectTypeID, libraryID, key FROM collections, syncObjectTypes
+                WHERE collectionID = ? AND name = 'collection' AND version > 0",
+                collectionId
+            )
+            .execute(&mut conn)
+            .await?;
+            query!(
+                "DELETE FROM collections WHERE collectionID = ?",
+                collectionId
+            )
+            .execute(&mut conn)
+            .await?;
+        }
+        Ok(ids)
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Add items to a collection of their library. Returns the number of items that weren't
+/// in it yet.
+#[allow(non_snake_case)]
+pub async fn add_to_collection(
+    db_path: &Path,
+    collectionId: i64,
+    item_ids: &[i64],
+) -> anyhow::Result<usize> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let libraryId = collection_library(&mut conn, collectionId).await?;
+        let mut added = 0;
+        for itemId in item_ids {
+            let itemLibraryId = query_scalar!(
+                r#"SELECT libraryID as "libraryId!" FROM items WHERE itemID = ?"#,
+                itemId
+            )
+            .fetch_optional(&mut conn)
+            .await?;
+            match itemLibraryId {
+                Some(id) if id == libraryId => {}
+                Some(_) => bail!("Items can only be added to collections of their library"),
+                None => bail!("Item {} isn't in the Zotero database", itemId),
+            }
+            let inserted = query!(
+                "INSERT OR IGNORE INTO collectionItems (collectionID, itemID, orderIndex)
+                SELECT ?1, ?2, IFNULL(MAX(orderIndex) + 1, 0) FROM collectionItems
+                WHERE collectionID = ?1",
+                collectionId,
+                itemId
+            )
+            .execute(&mut conn)
+            .await?;
+            if inserted.rows_affected() > 0 {
+                mark_item_modified(&mut conn, *itemId).await?;
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Remove items from a collection. Returns the number of items that were in it.
+#[allow(non_snake_case)]
+pub async fn remove_from_collection(
+    db_path: &Path,
+    collectionId: i64,
+    item_ids: &[i64],
+) -> anyhow::Result<usize> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let mut removed = 0;
+        for itemId in item_ids {
+            let deleted = query!(
+                "DELETE FROM collectionItems WHERE collectionID = ? AND itemID = ?",
+                collectionId,
+                itemId
+            )
+            .execute(&mut conn)
+            .await?;
+            if deleted.rows_affected() > 0 {
+                mark_item_modified(&mut conn, *itemId).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+#[allow(non_snake_case)]
+pub async fn find_tag(conn: &mut SqliteConnection, name: &str) -> anyhow::Result<Option<i64>> {
+    Ok(
+        query_scalar!(r#"SELECT tagID as "tagId!" FROM tags WHERE name = ?"#, name)
+            .fetch_optional(conn)
+            .await?,
+    )
+}
+
+/// Mark the items with the tag as changed, tags are part of the item for the sync
+#[allow(non_snake_case)]
+async fn mark_tagged_items_modified(conn: &mut SqliteConnection, tagId: i64) -> anyhow::Result<()> {
+    query!(
+        "UPDATE items SET dateModified = CURRENT_TIMESTAMP,
+            clientDateModified = CURRENT_TIMESTAMP, synced = 0
+        WHERE itemID IN (SELECT itemID FROM itemTags WHERE tagID = ?)",
+        tagId
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Tag items, creating the tag if no item has it yet. Returns the tag and the number of
+/// items that didn't have it.
+#[allow(non_snake_case)]
+pub async fn add_tag(db_path: &Path, item_ids: &[i64], name: &str) -> anyhow::Result<(Tag, usize)> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        query!("INSERT OR IGNORE INTO tags (name) VALUES (?)", name)
+            .execute(&mut conn)
+            .await?;
+        let tagId = find_tag(&mut conn, name)
+            .await?
+            .ok_or_else(|| anyhow!("Cannot create the tag \"{}\"", name))?;
+        let mut added = 0;
+        for itemId in item_ids {
+            // Type 0 marks tags added by hand, 1 those from translators and feeds
+            let inserted = query!(
+                "INSERT OR IGNORE INTO itemTags (itemID, tagID, type) VALUES (?, ?, 0)",
+                itemId,
+                tagId
+            )
+            .execute(&mut conn)
+            .await?;
+            if inserted.rows_affected() > 0 {
+                mark_item_modified(&mut conn, *itemId).await?;
+                added += 1;
+            }
+        }
+        let tag = Tag {
+            tagId,
+            name: name.to_string(),
+        };
+        Ok((tag, added))
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Remove a tag from items. Tags are removed once no item has them. Returns the number of
+/// items that had the tag.
+#[allow(non_snake_case)]
+pub async fn remove_tag(db_path: &Path, item_ids: &[i64], name: &str) -> anyhow::Result<usize> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let tagId = match find_tag(&mut conn, name).await? {
+            Some(tagId) => tagId,
+            None => return Ok(0),
+        };
+        let mut removed = 0;
+        for itemId in item_ids {
+            let deleted = query!(
+                "DELETE FROM itemTags WHERE itemID = ? AND tagID = ?",
+                itemId,
+                tagId
+            )
+            .execute(&mut conn)
+            .await?;
+            if deleted.rows_affected() > 0 {
+                mark_item_modified(&mut conn, *itemId).await?;
+                removed += 1;
+            }
+        }
+        query!(
+            "DELETE FROM tags WHERE tagID = ?1
+                AND NOT EXISTS (SELECT 1 FROM itemTags WHERE tagID = ?1)",
+            tagId
+        )
+        .execute(&mut conn)
+        .await?;
+        Ok(removed)
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Rename a tag on all items. Renaming to an existing tag merges both. Returns the tag
+/// with the new name.
+#[allow(non_snake_case)]
+pub async fn rename_tag(db_path: &Path, old_name: &str, new_name: &str) -> anyhow::Result<Tag> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let oldTagId = find_tag(&mut conn, old_name)
+            .await?
+            .ok_or_else(|| anyhow!("No tag named \"{}\"", old_name))?;
+        mark_tagged_items_modified(&mut conn, oldTagId).await?;
+        let tagId = match find_tag(&mut conn, new_name).await? {
+            Some(tagId) if tagId != oldTagId => {
+                query!(
+                    "INSERT OR IGNORE INTO itemTags (itemID, tagID, type)
+                    SELECT itemID, ?, type FROM itemTags WHERE tagID = ?",
+                    tagId,
+                    oldTagId
+                )
+                .execute(&mut conn)
+                .await?;
+                query!("DELETE FROM itemTags WHERE tagID = ?", oldTagId)
+                    .execute(&mut conn)
+                    .await?;
+                query!("DELETE FROM tags WHERE tagID = ?", oldTagId)
+                    .execute(&mut conn)
+                    .await?;
+                tagId
+            }
+            _ => {
+                query!(
+                    "UPDATE tags SET name = ? WHERE tagID = ?",
+                    new_name,
+                    oldTagId
+                )
+                .execute(&mut conn)
+                .await?;
+                oldTagId
+            }
+        };
+        Ok(Tag {
+            tagId,
+            name: new_name.to_string(),
+        })
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Delete a tag from all items. Like Zotero, the deletion is recorded in `syncDeleteLog`
+/// for each library that used the tag, so that the server deletes it too. Returns the
+/// number of items that had the tag.
+#[allow(non_snake_case)]
+pub async fn delete_tag(db_path: &Path, name: &str) -> anyhow::Result<usize> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let tagId = match find_tag(&mut conn, name).await? {
+            Some(tagId) => tagId,
+            None => return Ok(0),
+        };
+        mark_tagged_items_modified(&mut conn, tagId).await?;
+        query!(
+            "INSERT OR REPLACE INTO syncDeleteLog (syncObjectTypeID, libraryID, key)
+            SELECT DISTINCT syncObjectTypeID, libraryID, ?1
+            FROM itemTags JOIN items ON items.itemID = itemTags.itemID, syncObjectTypes
+            WHERE tagID = ?2 AND name = 'tag'",
+            name,
+            tagId
+        )
+        .execute(&mut conn)
+        .await?;
+        let deleted = query!("DELETE FROM itemTags WHERE tagID = ?", tagId)
+            .execute(&mut conn)
+            .await?;
+        query!("DELETE FROM tags WHERE tagID = ?", tagId)
+            .execute(&mut conn)
+            .await?;
+        Ok(deleted.rows_affected() as usize)
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Save the note `note_id` of the item `parent_id`, or add a new child note to the item.
+/// `title` is shown by Zotero in place of the note until it renders it again.
+#[allow(non_snake_case)]
+pub async fn save_note(
+    db_path: &Path,
+    parent_id: i64,
+    note_id: Option<i64>,
+    title: &str,
+    html: &str,
+) -> anyhow::Result<Note> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let itemId = match note_id {
+            Some(itemId) => {
+                let updated = query!(
+                    "UPDATE itemNotes SET note = ?, title = ? WHERE itemID = ? AND parentItemID = ?",
+                    html,
+                    title,
+                    itemId,
+                    parent_id
+                )
+                .execute(&mut conn)
+                .await?;
+                if updated.rows_affected() == 0 {
+                    bail!("Note {} isn't in the Zotero database", itemId);
+                }
+                mark_item_modified(&mut conn, itemId).await?;
+                itemId
+            }
+            None => {
+                let itemTypeId = query_scalar!(
+                    r#"SELECT itemTypeID as "itemTypeId!" FROM itemTypes WHERE typeName = 'note'"#
+                )
+                .fetch_optional(&mut conn)
+                .await?
+                .ok_or_else(|| anyhow!("The Zotero database has no note item type"))?;
+                let libraryId = query_scalar!(
+                    r#"SELECT libraryID as "libraryId!" FROM items WHERE itemID = ?"#,
+                    parent_id
+                )
+                .fetch_optional(&mut conn)
+                .await?
+                .ok_or_else(|| anyhow!("Item {} isn't in the Zotero database", parent_id))?;
+                let key = new_key();
+                let itemId = query!(
+                    "INSERT INTO items (itemTypeID, libraryID, key, synced) VALUES (?, ?, ?, 0)",
+                    itemTypeId,
+                    libraryId,
+                    key
+                )
+                .execute(&mut conn)
+                .await?
+                .last_insert_rowid();
+                query!(
+                    "INSERT INTO itemNotes (itemID, parentItemID, note, title) VALUES (?, ?, ?, ?)",
+                    itemId,
+                    parent_id,
+                    html,
+                    title
+                )
+                .execute(&mut conn)
+                .await?;
+                itemId
+            }
+        };
+        Ok(Note {
+            itemId,
+            title: title.to_string(),
+            note: html.to_string(),
+        })
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// An item to add to the library, see `create_item`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewItem {
+    pub type_name: String,
+    /// All fields and creators of the item
+    pub changes: ItemChanges,
+    pub tags: Vec<String>,
+}
+
+impl NewItem {
+    /// The fields, creators and tags of a document that isn't in the library yet
+    pub fn from_document(doc: &Document) -> Self {
+        let mut fields: Vec<(String, String)> = doc
+            .fields
+            .iter()
+            .filter(|(_, value)| !value.trim().is_empty())
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        for (name, value) in [
+            ("title", &doc.item_data.title),
+            ("abstractNote", &doc.item_data.abstracttext),
+            ("date", &doc.item_data.pubdate),
+        ] {
+            if !doc.fields.contains_key(name) && !value.trim().is_empty() {
+                fields.push((name.to_string(), value.clone()));
+            }
+        }
+        fields.sort();
+        let creators = (!doc.creators.is_empty()).then(|| doc.creators.clone());
+        Self {
+            type_name: doc.item_data.typeName.clone(),
+            changes: ItemChanges { fields, creators },
+            tags: doc.tags.iter().map(|tag| tag.name.clone()).collect(),
+        }
+    }
+}
+
+/// Add `item` to the library `library_id`, the personal library by default. Fields the item
+/// type doesn't have in this database are left out and unknown creator types are written
+/// as authors. Returns the item data and tags of the new item.
+#[allow(non_snake_case)]
+pub async fn create_item(
+    db_path: &Path,
+    library_id: Option<i64>,
+    item: &NewItem,
+) -> anyhow::Result<(ItemData, Vec<Tag>)> {
+    let mut conn = begin_write(db_path).await?;
+    let result = async {
+        let typeName = item.type_name.as_str();
+        let itemTypeId = query_scalar!(
+            r#"SELECT itemTypeID as "itemTypeId!" FROM itemTypes WHERE typeName = ?"#,
+            typeName
+        )
+        .fetch_optional(&mut conn)
+        .await?
+        .ok_or_else(|| anyhow!("Unknown item type \"{}\"", typeName))?;
+        let libraryId = default_library(&mut conn, library_id).await?;
+        let key = new_key();
+        let itemId = query!(
+            "INSERT INTO items (itemTypeID, libraryID, key, synced) VALUES (?, ?, ?, 0)",
+            itemTypeId,
+            libraryId,
+            key
+        )
+        .execute(&mut conn)
+        .await?
+        .last_insert_rowid();
+
+        let mut changes = ItemChanges::default();
+        for (name, value) in &item.changes.fields {
+            let fieldId = query_scalar!(
+                r#"SELECT fieldID as "fieldId!" FROM fields WHERE fieldName = ?"#,
+                name
+            )
+            .fetch_optional(&mut conn)
+            .await?;
+            if let Some(fieldId) = fieldId {
+                if is_valid_field(&mut conn, itemTypeId, fieldId).await {
+                    changes.fields.push((name.clone(), value.clone()));
+                }
+            }
+        }
+        if let Some(creators) = &item.changes.creators {
+            let mut known_creators = Vec::new();
+            for creator in creators {
+                let creatorType = creator.role();
+                let known = query_scalar!(
+                    r#"SELECT creatorTypeID as "creatorTypeId!" FROM creatorTypes WHERE creatorType = ?"#,
+                    creatorType
+                )
+                .fetch_optional(&mut conn)
+                .await?
+                .is_some();
+                let mut creator = creator.clone();
+                if !known {
+                    creator.creatorType = Some("author".to_string());
+                }
+                known_creators.push(creator);
+            }
+            changes.creators = Some(known_creators);
+        }
+        write_item_changes_locked(&mut conn, itemId, &changes).await?;
+
+        let mut tags = Vec::new();
+        for name in &item.tags {
+            query!("INSERT OR IGNORE INTO tags (name) VALUES (?)", name)
+                .execute(&mut conn)
+                .await?;
+            let tagId = find_tag(&mut conn, name)
+                .await?
+                .ok_or_else(|| anyhow!("Cannot create the tag \"{}\"", name))?;
+            // Tags that came with the metadata, like those of translators
+            query!(
+                "INSERT OR IGNORE INTO itemTags (itemID, tagID, type) VALUES (?, ?, 1)",
+                itemId,
+                tagId
+            )
+            .execute(&mut conn)
+            .await?;
+            tags.push(Tag {
+                tagId,
+                name: name.clone(),
+            });
+        }
+        let dateAdded = query_scalar!(
+            r#"SELECT CAST(dateAdded AS TEXT) as "dateAdded!: String" FROM items WHERE itemID = ?"#,
+            itemId
+        )
+        .fetch_one(&mut conn)
+        .await?;
+        let field = |name: &str| {
+            changes
+                .fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default()
+        };
+        let item_data = ItemData {
+            itemId,
+            libraryId,
+            title: field("title"),
+            abstracttext: field("abstractNote"),
+            pubdate: field("date"),
+            key,
+            dateAdded,
+            typeName: typeName.to_string(),
+        };
+        Ok((item_data, tags))
+    }
+    .await;
+    end_write(conn, result).await
+}
+
+/// Store a copy of `file` in Zotero's storage directory as an attachment of the item
+/// `parent_id`. The copy is removed again if the item can't be written.
+#[allow(non_snake_case)]
+pub async fn import_attachment(
+    db_path: &Path,
+    storage_dir: &Path,
+    parent_id: i64,
+    file: &Path,
+) -> anyhow::Result<Attachment> {
+    let file_name = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("{} isn't a file", file.display()))?
+        .to_string();
+    if !file.is_file() {
+        bail!("{} isn't a file", file.display());
+    }
+    let mut conn = begin_write(db_path).await?;
+    let key = new_key();
+    let dir = storage_dir.join(&key);
+    let result = async {
+        let itemTypeId = query_scalar!(
+            r#"SELECT itemTypeID as "itemTypeId!" FROM itemTypes WHERE typeName = 'attachment'"#
+        )
+        .fetch_optional(&mut conn)
+        .await?
+        .ok_or_else(|| anyhow!("The Zotero database has no attachment item type"))?;
+        let libraryId = query_scalar!(
+            r#"SELECT libraryID as "libraryId!" FROM items WHERE itemID = ?"#,
+            parent_id
+        )
+        .fetch_optional(&mut conn)
+        .await?
+        .ok_or_else(|| anyhow!("Item {} isn't in the Zotero database", parent_id))?;
+        let itemId = query!(
+            "INSERT INTO items (itemTypeID, libraryID, key, synced) VALUES (?, ?, ?, 0)",
+            itemTypeId,
+            libraryId,
+            key
+        )
+        .execute(&mut conn)
+        .await?
+        .last_insert_rowid();
+        let contentType = content_type_for_path(&file_name);
+        let path = format!("storage:{}", file_name);
+        query!(
+            "INSERT INTO itemAttachments (itemID, parentItemID, linkMode, contentType, path)
+            VALUES (?, ?, ?, ?, ?)",
+            itemId,
+            parent_id,
+            LINK_MODE_IMPORTED_FILE,
+            contentType,
+            path
+        )
+        .execute(&mut conn)
+        .await?;
+        let changes = ItemChanges {
+            fields: vec![("title".to_string(), file_name.clone())],
+            creators: None,
+        };
+        write_item_changes_locked(&mut conn, itemId, &changes).await?;
+        std::fs::create_dir_all(&dir)?;
+        std::fs::copy(file, dir.join(&file_name))
+            .map_err(|err| anyhow!("Cannot copy {}: {}", file.display(), err))?;
+        Ok(Attachment {
+            itemId,
+            linkMode: Some(LINK_MODE_IMPORTED_FILE),
+            contentType,
+            path: Some(path),
+            key: Some(key.clone()),
+            url: None,
+        })
+    }
+    .await;
+    let result = end_write(conn, result).await;
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::user_config::UserConfig;
+    use crate::{test_dir::TestDir, user_config::UserConfig};
 
     use super::*;
     #[test]
     fn test_get_all_item_data() {
-        let mut app = App::default();
         let user_config = UserConfig::new();
-        tokio_test::block_on(app.init_sqlite(&user_config.behavior.zotero_db_path)).unwrap();
-        let all_items =
-            tokio_test::block_on(get_all_item_data(&mut app)).expect("Expect read all docs");
-        // dbg!(&all_items);
-        let all_docs: Vec<RcDoc> = Vec::from_iter(all_items);
-        tokio_test::block_on(get_creators_for_docs(&mut app)).expect("Expect read all creators");
-        tokio_test::block_on(get_attachments_for_docs(&mut app))
-            .expect("Expect read all attachments");
-        dbg!(all_docs);
+        let db = tokio_test::block_on(ZoteroDb::open(
+            &user_config.behavior.zotero_db_path,
+            &user_config.behavior.zotero_storage_dir,
+            None,
+        ))
+        .unwrap();
+        let all_items = tokio_test::block_on(db.load_items()).expect("Expect read all docs");
+        assert!(all_items.iter().all(|item| item.itemId > 0));
+        tokio_test::block_on(db.load_creators()).expect("Expect read all creators");
+        tokio_test::block_on(db.load_attachments()).expect("Expect read all attachments");
+    }
+
+    const SCHEMA: &str = "
+CREATE TABLE fields (fieldID INTEGER PRIMARY KEY, fieldName TEXT, fieldFormatID INT);
+CREATE TABLE creatorTypes (creatorTypeID INTEGER PRIMARY KEY, creatorType TEXT);
+CREATE TABLE items (itemID INTEGER PRIMARY KEY, itemTypeID INT NOT NULL,
+    dateAdded TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    dateModified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    clientDateModified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, libraryID INT NOT NULL,
+    key TEXT NOT NULL, version INT NOT NULL DEFAULT 0, synced INT NOT NULL DEFAULT 0);
+CREATE TABLE itemDataValues (valueID INTEGER PRIMARY KEY, value TEXT UNIQUE);
+CREATE TABLE itemData (itemID INT, fieldID INT, valueID, PRIMARY KEY (itemID, fieldID));
+CREATE TABLE creators (creatorID INTEGER PRIMARY KEY, firstName TEXT, lastName TEXT,
+    fieldMode INT, UNIQUE (lastName, firstName, fieldMode));
+CREATE TABLE itemCreators (itemID INT NOT NULL, creatorID INT NOT NULL,
+    creatorTypeID INT NOT NULL DEFAULT 1, orderIndex INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (itemID, creatorID, creatorTypeID, orderIndex));
+CREATE TABLE libraries (libraryID INTEGER PRIMARY KEY, type TEXT NOT NULL);
+CREATE TABLE collections (collectionID INTEGER PRIMARY KEY, collectionName TEXT NOT NULL,
+    parentCollectionID INT DEFAULT NULL,
+    clientDateModified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, libraryID INT NOT NULL,
+    key TEXT NOT NULL, version INT NOT NULL DEFAULT 0, synced INT NOT NULL DEFAULT 0,
+    UNIQUE (libraryID, key));
+CREATE TABLE collectionItems (collectionID INT NOT NULL, itemID INT NOT NULL,
+    orderIndex INT NOT NULL DEFAULT 0, PRIMARY KEY (collectionID, itemID));
+CREATE TABLE syncObjectTypes (syncObjectTypeID INTEGER PRIMARY KEY, name TEXT);
+CREATE TABLE syncDeleteLog (syncObjectTypeID INT NOT NULL, libraryID INT NOT NULL,
+    key TEXT NOT NULL, dateDeleted TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (syncObjectTypeID, libraryID, key));
+CREATE TABLE itemTypes (itemTypeID INTEGER PRIMARY KEY, typeName TEXT);
+CREATE TABLE itemNotes (itemID INTEGER PRIMARY KEY, parentItemID INT, note TEXT, title TEXT);
+CREATE TABLE itemAttachments (itemID INTEGER PRIMARY KEY, parentItemID INT, linkMode INT,
+    contentType TEXT, path TEXT);
+CREATE TABLE tags (tagID INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+CREATE TABLE itemTags (itemID INT NOT NULL, tagID INT NOT NULL, type INT NOT NULL,
+    PRIMARY KEY (itemID, tagID));
+INSERT INTO libraries VALUES (1, 'user'), (2, 'group');
+INSERT INTO itemTypes VALUES (3, 'attachment'), (22, 'journalArticle'), (28, 'note');
+INSERT INTO syncObjectTypes VALUES (1, 'collection'), (3, 'item'), (5, 'tag');
+INSERT INTO collections (collectionID, collectionName, parentCollectionID, libraryID, key,
+        version, synced)
+    VALUES (1, 'Thesis', NULL, 1, 'COLL0001', 5, 1), (2, 'Drafts', 1, 1, 'COLL0002', 0, 0),
+        (3, 'Group', NULL, 2, 'COLL0003', 5, 1);
+INSERT INTO collectionItems VALUES (2, 2, 0);
+INSERT INTO fields VALUES (1, 'title', NULL), (6, 'date', NULL), (8, 'volume', NULL);
+INSERT INTO creatorTypes VALUES (1, 'author'), (2, 'editor');
+INSERT INTO items (itemID, itemTypeID, libraryID, key, version, synced, clientDateModified)
+    VALUES (1, 22, 1, 'AAAA1111', 7, 1, '2020-01-01 00:00:00'),
+        (2, 22, 1, 'BBBB2222', 7, 1, '2020-01-01 00:00:00');
+INSERT INTO itemDataValues VALUES (1, 'Old title'), (2, 'Shared'), (3, '12');
+INSERT INTO itemData VALUES (1, 1, 1), (1, 8, 3), (2, 1, 2);
+INSERT INTO creators VALUES (1, 'Jane', 'Doe', 0), (2, 'John', 'Roe', 0);
+INSERT INTO itemCreators VALUES (1, 1, 1, 0), (1, 2, 1, 1), (2, 1, 1, 0);
+INSERT INTO tags VALUES (1, 'reading'), (2, 'ml');
+INSERT INTO itemTags VALUES (1, 1, 0), (1, 2, 1), (2, 2, 0);
+";
+
+    async fn values(pool: &SqlitePool, sql: &str) -> Vec<String> {
+        sqlx::query_scalar(sql).fetch_all(pool).await.unwrap()
+    }
+
+    /// A new database with `SCHEMA` in a directory of the test
+    async fn test_db(name: &str) -> (TestDir, std::path::PathBuf, SqlitePool) {
+        let dir = TestDir::new(name);
+        let path = dir.join("zotero.sqlite");
+        std::fs::File::create(&path).unwrap();
+        let pool = SqlitePool::connect(&format!("sqlite:{}", path.to_str().unwrap()))
+            .await
+            .unwrap();
+        sqlx::query(SCHEMA).execute(&pool).await.unwrap();
+        (dir, path, pool)
+    }
+
+    #[test]
+    fn test_write_item_changes() {
+        tokio_test::block_on(async {
+            let (_dir, path, pool) = test_db("write-test").await;
+            let url = format!("sqlite:{}", path.to_str().unwrap());
+
+            let changes = ItemChanges {
+                fields: vec![
+                    ("title".to_string(), "Shared".to_string()),
+                    ("date".to_string(), "2021-00-00 2021".to_string()),
+                    ("volume".to_string(), String::new()),
+                ],
+                creators: Some(vec![
+                    Creator {
+                        firstName: None,
+                        lastName: Some("Acme".to_string()),
+                        creatorType: Some("editor".to_string()),
+                        fieldMode: Some(1),
+                    },
+                    Creator {
+                        firstName: Some("Jane".to_string()),
+                        lastName: Some("Doe".to_string()),
+                        creatorType: None,
+                        fieldMode: Some(0),
+                    },
+                ]),
+            };
+            write_item_changes(&path, 1, &changes).await.unwrap();
+
+            // The title reuses the value of item 2, unused values are removed
+            assert_eq!(
+                values(&pool, "SELECT value FROM itemDataValues ORDER BY valueID").await,
+                vec!["Shared", "2021-00-00 2021"]
+            );
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT fieldName || '=' || value FROM itemData NATURAL JOIN fields
+                    NATURAL JOIN itemDataValues WHERE itemID = 1 ORDER BY fieldID"
+                )
+                .await,
+                vec!["title=Shared", "date=2021-00-00 2021"]
+            );
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT creatorType || ':' || firstName || ':' || lastName FROM itemCreators
+                    NATURAL JOIN creators NATURAL JOIN creatorTypes
+                    WHERE itemID = 1 ORDER BY orderIndex"
+                )
+                .await,
+                vec!["editor::Acme", "author:Jane:Doe"]
+            );
+            assert_eq!(
+                values(&pool, "SELECT lastName FROM creators ORDER BY creatorID").await,
+                vec!["Doe", "Acme"]
+            );
+            let (version, synced, modified): (i64, i64, String) = sqlx::query_as(
+                "SELECT version, synced, clientDateModified FROM items WHERE itemID = 1",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            assert_eq!((version, synced), (7, 0));
+            assert_ne!(modified, "2020-01-01 00:00:00");
+
+            let err = write_item_changes(&path, 3, &changes).await.unwrap_err();
+            assert!(err.to_string().contains("isn't in the Zotero database"));
+            let unknown = ItemChanges {
+                fields: vec![("nonsense".to_string(), "x".to_string())],
+                creators: None,
+            };
+            let err = write_item_changes(&path, 2, &unknown).await.unwrap_err();
+            assert!(err.to_string().contains("Unknown field"));
+
+            // Like Zotero, which keeps its database locked while it runs
+            let mut lock = SqliteConnection::connect(&url).await.unwrap();
+            sqlx::query("BEGIN EXCLUSIVE")
+                .execute(&mut lock)
+                .await
+                .unwrap();
+            let retitle = ItemChanges {
+                fields: vec![("title".to_string(), "New".to_string())],
+                creators: None,
+            };
+            let err = write_item_changes(&path, 2, &retitle).await.unwrap_err();
+            assert!(err.to_string().contains("close Zotero"));
+            sqlx::query("ROLLBACK").execute(&mut lock).await.unwrap();
+            assert_eq!(
+                values(&pool, "SELECT value FROM itemDataValues ORDER BY valueID").await,
+                vec!["Shared", "2021-00-00 2021"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_collection_changes() {
+        tokio_test::block_on(async {
+            let (_dir, path, pool) = test_db("collections-test").await;
+            let synced = |id: i64| {
+                sqlx::query_scalar::<_, i64>("SELECT synced FROM items WHERE itemID = ?")
+                    .bind(id)
+                    .fetch_one(&pool)
+            };
+
+            let chapter = create_collection(&path, None, Some(1), "Chapter 1")
+                .await
+                .unwrap();
+            assert_eq!(
+                (chapter.libraryId, chapter.parentCollectionId),
+                (1, Some(1))
+            );
+            let top = create_collection(&path, None, None, "Reading")
+                .await
+                .unwrap();
+            assert_eq!((top.libraryId, top.parentCollectionId), (1, None));
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT collectionName || ':' || version || ':' || synced || ':' || length(key)
+                    FROM collections WHERE collectionID > 3 ORDER BY collectionID"
+                )
+                .await,
+                vec!["Chapter 1:0:0:8", "Reading:0:0:8"]
+            );
+
+            rename_collection(&path, 1, "PhD").await.unwrap();
+            assert_eq!(
+                values(&pool, "SELECT collectionName || ':' || synced FROM collections WHERE collectionID = 1")
+                    .await,
+                vec!["PhD:0"]
+            );
+            assert!(rename_collection(&path, 99, "x").await.is_err());
+
+            let err = move_collection(&path, 1, Some(2)).await.unwrap_err();
+            assert!(err.to_string().contains("into itself"));
+            let err = move_collection(&path, 2, Some(3)).await.unwrap_err();
+            assert!(err.to_string().contains("another library"));
+            move_collection(&path, chapter.collectionId, Some(2))
+                .await
+                .unwrap();
+
+            assert_eq!(add_to_collection(&path, 2, &[1, 2]).await.unwrap(), 1);
+            assert_eq!(synced(1).await.unwrap(), 0);
+            assert_eq!(synced(2).await.unwrap(), 1);
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT itemID || ':' || orderIndex FROM collectionItems ORDER BY itemID"
+                )
+                .await,
+                vec!["1:1", "2:0"]
+            );
+            let err = add_to_collection(&path, 3, &[1]).await.unwrap_err();
+            assert!(err.to_string().contains("their library"));
+            assert_eq!(remove_from_collection(&path, 2, &[2]).await.unwrap(), 1);
+            assert_eq!(synced(2).await.unwrap(), 0);
+
+            // Deleting Thesis deletes Drafts and Chapter 1 in it, only Thesis was synced
+            let mut deleted = delete_collection(&path, 1).await.unwrap();
+            deleted.sort();
+            assert_eq!(deleted, vec![1, 2, chapter.collectionId]);
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT collectionName FROM collections ORDER BY collectionID"
+                )
+                .await,
+                vec!["Group", "Reading"]
+            );
+            assert_eq!(
+                values(&pool, "SELECT key FROM syncDeleteLog").await,
+                vec!["COLL0001"]
+            );
+            assert!(values(&pool, "SELECT itemID FROM collectionItems")
+                .await
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn test_tag_changes() {
+        tokio_test::block_on(async {
+            let (_dir, path, pool) = test_db("tags-test").await;
+            let item_tags = || {
+                values(
+                    &pool,
+                    "SELECT itemID || ':' || name || ':' || type
+                    FROM itemTags JOIN tags ON tags.tagID = itemTags.tagID
+                    ORDER BY itemID, name",
+                )
+            };
+
+            let (tag, added) = add_tag(&path, &[1, 2], "reading").await.unwrap();
+            assert_eq!((tag.tagId, added), (1, 1));
+            let (tag, added) = add_tag(&path, &[2], "thesis").await.unwrap();
+            assert_eq!((tag.name.as_str(), added), ("thesis", 1));
+            assert_eq!(
+                values(&pool, "SELECT itemID || ':' || synced FROM items").await,
+                vec!["1:1", "2:0"]
+            );
+
+            assert_eq!(remove_tag(&path, &[1, 2], "thesis").await.unwrap(), 1);
+            assert_eq!(remove_tag(&path, &[1], "unknown").await.unwrap(), 0);
+            assert!(values(&pool, "SELECT name FROM tags WHERE name = 'thesis'")
+                .await
+                .is_empty());
+
+            // Renaming to an existing tag merges both
+            let tag = rename_tag(&path, "reading", "ml").await.unwrap();
+            assert_eq!(tag.tagId, 2);
+            assert_eq!(item_tags().await, vec!["1:ml:1", "2:ml:0"]);
+            rename_tag(&path, "ml", "Machine learning").await.unwrap();
+            assert!(rename_tag(&path, "reading", "x").await.is_err());
+
+            assert_eq!(delete_tag(&path, "Machine learning").await.unwrap(), 2);
+            assert!(item_tags().await.is_empty());
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT syncObjectTypeID || ':' || libraryID || ':' || key FROM syncDeleteLog"
+                )
+                .await,
+                vec!["5:1:Machine learning"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_save_note() {
+        tokio_test::block_on(async {
+            let (_dir, path, pool) = test_db("notes-test").await;
+            let note = save_note(&path, 2, None, "Summary", "<p>Summary</p>")
+                .await
+                .unwrap();
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT itemTypeID || ':' || libraryID || ':' || synced || ':' || length(key)
+                    FROM items WHERE itemID > 2"
+                )
+                .await,
+                vec!["28:1:0:8"]
+            );
+
+            save_note(&path, 2, Some(note.itemId), "Better", "<h1>Better</h1>")
+                .await
+                .unwrap();
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT parentItemID || ':' || title || ':' || note FROM itemNotes"
+                )
+                .await,
+                vec!["2:Better:<h1>Better</h1>"]
+            );
+            // The note belongs to another item
+            assert!(save_note(&path, 1, Some(note.itemId), "x", "x")
+                .await
+                .is_err());
+            assert!(save_note(&path, 99, None, "x", "x").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_create_item() {
+        tokio_test::block_on(async {
+            let (dir, path, pool) = test_db("create-item-test").await;
+            let doc = crate::export::new_document("journalArticle", "");
+            let mut item = {
+                let mut doc_mut = doc.borrow_mut();
+                doc_mut.item_data.title = "New".to_string();
+                doc_mut.fields.insert("volume".to_string(), "3".to_string());
+                doc_mut
+                    .fields
+                    .insert("publisher".to_string(), "Unknown field".to_string());
+                doc_mut.creators = vec![Creator {
+                    firstName: Some("Jane".to_string()),
+                    lastName: Some("Doe".to_string()),
+                    creatorType: Some("translator".to_string()),
+                    fieldMode: Some(0),
+                }];
+                doc_mut.tags = vec![Tag {
+                    tagId: 0,
+                    name: "ml".to_string(),
+                }];
+                NewItem::from_document(&doc_mut)
+            };
+            assert_eq!(
+                item.changes.fields,
+                vec![
+                    ("publisher".to_string(), "Unknown field".to_string()),
+                    ("title".to_string(), "New".to_string()),
+                    ("volume".to_string(), "3".to_string()),
+                ]
+            );
+            let (item_data, tags) = create_item(&path, None, &item).await.unwrap();
+            assert_eq!(item_data.itemId, 3);
+            assert_eq!(item_data.title, "New");
+            assert!(!item_data.dateAdded.is_empty());
+            // Without an abstract or a date the item is still read back
+            let loaded = get_all_item_data(&pool).await.unwrap();
+            let loaded = loaded.iter().find(|item| item.itemId == 3).unwrap();
+            assert_eq!(loaded.title, "New");
+            assert_eq!(loaded.abstracttext, "");
+            assert_eq!(loaded.pubdate, "");
+            assert_eq!(
+                tags,
+                vec![Tag {
+                    tagId: 2,
+                    name: "ml".to_string()
+                }]
+            );
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT fieldID || ':' || value FROM itemData
+                    JOIN itemDataValues USING (valueID) WHERE itemID = 3 ORDER BY fieldID"
+                )
+                .await,
+                vec!["1:New", "8:3"]
+            );
+            // The existing creator is reused, as an author
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT creatorID || ':' || creatorTypeID FROM itemCreators WHERE itemID = 3"
+                )
+                .await,
+                vec!["1:1"]
+            );
+            // A group library, like the copy of a group synced from zotero.org
+            let (item_data, _) = create_item(&path, Some(2), &item).await.unwrap();
+            assert_eq!(
+                values(
+                    &pool,
+                    &format!(
+                        "SELECT CAST(libraryID AS TEXT) FROM items WHERE itemID = {}",
+                        item_data.itemId
+                    )
+                )
+                .await,
+                vec!["2"]
+            );
+            item.type_name = "unknownType".to_string();
+            assert!(create_item(&path, None, &item).await.is_err());
+
+            let storage = dir.join("storage");
+            let file = dir.join("rustero-create-test.pdf");
+            std::fs::write(&file, "%PDF-1.4").unwrap();
+            let attachment = import_attachment(&path, &storage, 3, &file).await.unwrap();
+            assert_eq!(
+                attachment.path.as_deref(),
+                Some("storage:rustero-create-test.pdf")
+            );
+            assert_eq!(attachment.content_type(), "application/pdf");
+            let copy = storage
+                .join(attachment.key.as_deref().unwrap())
+                .join("rustero-create-test.pdf");
+            assert_eq!(std::fs::read_to_string(copy).unwrap(), "%PDF-1.4");
+            assert_eq!(
+                values(
+                    &pool,
+                    "SELECT parentItemID || ':' || linkMode FROM itemAttachments"
+                )
+                .await,
+                vec!["3:0"]
+            );
+            // Nothing is left behind for a missing parent
+            assert!(import_attachment(&path, &storage, 99, &file).await.is_err());
+            assert_eq!(std::fs::read_dir(&storage).unwrap().count(), 1);
+        });
+    }
+
+    #[test]
+    fn test_get_better_bibtex_keys() {
+        tokio_test::block_on(async {
+            let dir = TestDir::new("bbt-test");
+            let path = dir.join("better-bibtex.sqlite");
+            std::fs::File::create(&path).unwrap();
+            let pool = SqlitePool::connect(&format!("sqlite:{}", path.to_str().unwrap()))
+                .await
+                .unwrap();
+            sqlx::query(
+                "CREATE TABLE citationkey (itemID INT, itemKey TEXT, libraryID INT,
+                    citationKey TEXT);
+                INSERT INTO citationkey VALUES (1, 'AAAA1111', 1, 'doe2020'),
+                    (9, 'AAAA1111', 2, 'roe2021');",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            pool.close().await;
+
+            let mut app = App::default();
+            for library_id in [2, 1, 3] {
+                let doc = new_test_document("journalArticle", &[], vec![]);
+                doc.borrow_mut().item_data.key = "AAAA1111".to_string();
+                doc.borrow_mut().item_data.libraryId = library_id;
+                app.documents.push(doc);
+            }
+            get_better_bibtex_keys(&mut app, &path).await.unwrap();
+            let keys: Vec<String> = app
+                .documents
+                .iter()
+                .map(|doc| doc.borrow().citation_key.clone())
+                .collect();
+            assert_eq!(keys, vec!["roe2021", "doe2020", ""]);
+        });
     }
 }
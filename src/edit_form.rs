@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use crate::{
+    data_structures::{Creator, DateParts, RcDoc, StatefulList},
+    db_connector::ItemChanges,
+};
+
+/// Creator types offered when cycling the role of a creator
+const CREATOR_TYPES: [&str; 7] = [
+    "author",
+    "editor",
+    "contributor",
+    "translator",
+    "seriesEditor",
+    "bookAuthor",
+    "reviewedAuthor",
+];
+
+/// A field or creator of the edit form
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditRow {
+    Field { name: String, value: String },
+    Creator(Creator),
+}
+
+impl EditRow {
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Field { name, .. } => name,
+            Self::Creator(creator) => creator.role(),
+        }
+    }
+    /// The value as edited: dates as entered and names as `Last, First`
+    pub fn value(&self) -> String {
+        match self {
+            Self::Field { value, .. } => value.clone(),
+            Self::Creator(creator) if creator.is_single_field() => {
+                creator.lastName.clone().unwrap_or_default()
+            }
+            Self::Creator(creator) => format!(
+                "{}, {}",
+                creator.lastName.as_deref().unwrap_or_default(),
+                creator.firstName.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+    /// Set the value from its edited form. Names without a comma are stored in a single
+    /// field, like those of institutions.
+    pub fn set_value(&mut self, input: &str) {
+        let input = input.trim();
+        match self {
+            Self::Field { value, .. } => *value = input.to_string(),
+            Self::Creator(creator) => {
+                let (first, last, mode) = match input.split_once(',') {
+                    Some((last, first)) => (Some(first.trim().to_string()), last.trim(), 0),
+                    None => (None, input, 1),
+                };
+                creator.firstName = first;
+                creator.lastName = Some(last.to_string());
+                creator.fieldMode = Some(mode);
+            }
+        }
+    }
+    fn is_creator(&self) -> bool {
+        matches!(self, Self::Creator(_))
+    }
+}
+
+/// Fields and creators of a document being edited. Nothing is written until the changes
+/// are saved, see `db_connector::write_item_changes`.
+pub struct EditForm {
+    pub doc: RcDoc,
+    pub rows: StatefulList<EditRow>,
+}
+
+impl EditForm {
+    pub fn new(doc: RcDoc) -> Self {
+        let mut fields: Vec<(String, String)> = doc
+            .borrow()
+            .fields
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| (name.clone(), entered_value(name, value).to_string()))
+            .collect();
+        if !fields.iter().any(|(name, _)| name == "title") {
+            fields.push(("title".to_string(), String::new()));
+        }
+        // Title and date first, the rest alphabetically
+        fields.sort_by_key(|(name, _)| (name != "title", name != "date", name.clone()));
+        let mut rows: Vec<EditRow> = fields
+            .into_iter()
+            .map(|(name, value)| EditRow::Field { name, value })
+            .collect();
+        rows.extend(
+            doc.borrow()
+                .creators
+                .iter()
+                .filter(|creator| **creator != Creator::default())
+                .cloned()
+                .map(EditRow::Creator),
+        );
+        let mut rows = StatefulList::with_items(rows);
+        rows.state.select(Some(0));
+        Self { doc, rows }
+    }
+    pub fn selected(&self) -> Option<usize> {
+        self.rows
+            .state
+            .selected()
+            .filter(|idx| *idx < self.rows.items.len())
+    }
+    /// Add an empty field, or select it if it is already in the form. Returns its row.
+    pub fn add_field(&mut self, name: &str) -> usize {
+        let idx = match self.rows.items.iter().position(
+            |row| matches!(row, EditRow::Field { name: existing, .. } if existing == name),
+        ) {
+            Some(idx) => idx,
+            None => {
+                let idx = self
+                    .rows
+                    .items
+                    .iter()
+                    .position(EditRow::is_creator)
+                    .unwrap_or(self.rows.items.len());
+                let row = EditRow::Field {
+                    name: name.to_string(),
+                    value: String::new(),
+                };
+                self.rows.items.insert(idx, row);
+                idx
+            }
+        };
+        self.rows.state.select(Some(idx));
+        idx
+    }
+    /// Add an author after the last creator. Returns its row.
+    pub fn add_creator(&mut self) -> usize {
+        self.rows.items.push(EditRow::Creator(Creator {
+            firstName: None,
+            lastName: None,
+            creatorType: Some("author".to_string()),
+            fieldMode: Some(1),
+        }));
+        let idx = self.rows.items.len() - 1;
+        self.rows.state.select(Some(idx));
+        idx
+    }
+    pub fn remove_selected(&mut self) {
+        if let Some(idx) = self.selected() {
+            self.rows.items.remove(idx);
+            let len = self.rows.items.len();
+            self.rows.state.select(if len == 0 {
+                None
+            } else {
+                Some(idx.min(len - 1))
+            });
+        }
+    }
+    pub fn cycle_creator_type(&mut self) {
+        let idx = match self.selected() {
+            Some(idx) => idx,
+            None => return,
+        };
+        if let EditRow::Creator(creator) = &mut self.rows.items[idx] {
+            let next = CREATOR_TYPES
+                .iter()
+                .position(|ty| *ty == creator.role())
+                .map_or(0, |pos| (pos + 1) % CREATOR_TYPES.len());
+            creator.creatorType = Some(CREATOR_TYPES[next].to_string());
+        }
+    }
+    /// Swap the selected creator with the next (`down`) or previous one
+    pub fn move_creator(&mut self, down: bool) {
+        let idx = match self.selected() {
+            Some(idx) if self.rows.items[idx].is_creator() => idx,
+            _ => return,
+        };
+        let other = match down {
+            true => idx + 1,
+            false => match idx.checked_sub(1) {
+                Some(other) => other,
+                None => return,
+            },
+        };
+        if self.rows.items.get(other).is_some_and(EditRow::is_creator) {
+            self.rows.items.swap(idx, other);
+            self.rows.state.select(Some(other));
+        }
+    }
+    /// What differs from the document, in the form stored by Zotero
+    pub fn changes(&self) -> ItemChanges {
+        let doc = self.doc.borrow();
+        let mut new_fields = HashMap::new();
+        let mut fields = Vec::new();
+        for row in &self.rows.items {
+            if let EditRow::Field { name, value } = row {
+                let old = doc.fields.get(name).map_or("", String::as_str);
+                // Compared as entered, Zotero may have parsed the date better
+                if value != entered_value(name, old) {
+                    fields.push((name.clone(), stored_value(name, value)));
+                }
+                new_fields.insert(name.as_str(), value);
+            }
+        }
+        let mut removed: Vec<(String, String)> = doc
+            .fields
+            .iter()
+            .filter(|(name, value)| !value.is_empty() && !new_fields.contains_key(name.as_str()))
+            .map(|(name, _)| (name.clone(), String::new()))
+            .collect();
+        removed.sort();
+        fields.extend(removed);
+
+        let old_creators: Vec<&Creator> = doc
+            .creators
+            .iter()
+            .filter(|creator| **creator != Creator::default())
+            .collect();
+        let new_creators: Vec<Creator> = self
+            .rows
+            .items
+            .iter()
+            .filter_map(|row| match row {
+                EditRow::Creator(creator) => Some(creator.clone()),
+                EditRow::Field { .. } => None,
+            })
+            .filter(|creator| {
+                creator
+                    .lastName
+                    .as_deref()
+                    .is_some_and(|last| !last.is_empty())
+                    || creator
+                        .firstName
+                        .as_deref()
+                        .is_some_and(|first| !first.is_empty())
+            })
+            .collect();
+        let creators_changed = old_creators.len() != new_creators.len()
+            || old_creators
+                .iter()
+                .zip(&new_creators)
+                .any(|(old, new)| !same_creator(old, new));
+        ItemChanges {
+            fields,
+            creators: creators_changed.then_some(new_creators),
+        }
+    }
+}
+
+/// Whether both are stored as the same Zotero creator with the same role
+fn same_creator(a: &Creator, b: &Creator) -> bool {
+    let first = |creator: &Creator| match creator.is_single_field() {
+        true => String::new(),
+        false => creator.firstName.clone().unwrap_or_default(),
+    };
+    a.role() == b.role()
+        && a.is_single_field() == b.is_single_field()
+        && a.lastName.as_deref().unwrap_or_default() == b.lastName.as_deref().unwrap_or_default()
+        && first(a) == first(b)
+}
+
+/// Dates are stored as `YYYY-MM-DD <as entered>`, edit them as entered
+fn entered_value<'a>(name: &str, value: &'a str) -> &'a str {
+    match value.split_once(' ') {
+        Some((_, entered)) if name == "date" => entered,
+        _ => value,
+    }
+}
+
+fn stored_value(name: &str, value: &str) -> String {
+    if name != "date" || value.is_empty() {
+        return value.to_string();
+    }
+    // Zotero keeps dates it can't parse with an all zero date part
+    DateParts::zotero_date_from_text(value).unwrap_or_else(|| format!("0000-00-00 {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::new_test_document;
+
+    fn person(last: &str, first: &str, role: &str) -> Creator {
+        Creator {
+            firstName: Some(first.to_string()),
+            lastName: Some(last.to_string()),
+            creatorType: Some(role.to_string()),
+            fieldMode: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_edit_form_changes() {
+        let doc = new_test_document(
+            "journalArticle",
+            &[
+                ("title", "A study"),
+                ("date", "2020-03-00 March 2020"),
+                ("volume", "3"),
+                ("DOI", "10.1000/xyz"),
+            ],
+            vec![person("Doe", "Jane", "author")],
+        );
+        let mut form = EditForm::new(doc);
+        let labels: Vec<&str> = form.rows.items.iter().map(EditRow::label).collect();
+        assert_eq!(labels, vec!["title", "date", "DOI", "volume", "author"]);
+        assert_eq!(form.rows.items[1].value(), "March 2020");
+        assert_eq!(form.rows.items[4].value(), "Doe, Jane");
+        assert_eq!(form.changes(), ItemChanges::default());
+
+        form.rows.items[0].set_value(" A better study ");
+        form.rows.items[1].set_value("2021-05-04");
+        form.rows.state.select(Some(2));
+        form.remove_selected();
+        let idx = form.add_field("pages");
+        form.rows.items[idx].set_value("1-10");
+        let idx = form.add_creator();
+        form.rows.items[idx].set_value("Acme Institute");
+        form.cycle_creator_type();
+        form.move_creator(false);
+
+        let changes = form.changes();
+        assert_eq!(
+            changes.fields,
+            vec![
+                ("title".to_string(), "A better study".to_string()),
+                ("date".to_string(), "2021-05-04 2021-05-04".to_string()),
+                ("pages".to_string(), "1-10".to_string()),
+                ("DOI".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(
+            changes.creators,
+            Some(vec![
+                Creator {
+                    firstName: None,
+                    lastName: Some("Acme Institute".to_string()),
+                    creatorType: Some("editor".to_string()),
+                    fieldMode: Some(1),
+                },
+                person("Doe", "Jane", "author"),
+            ])
+        );
+    }
+}
@@ -1,6 +1,13 @@
 use crate::event::Key;
 use crossterm::event;
-use std::{sync::mpsc, thread, time::Duration};
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+  },
+  thread,
+  time::Duration,
+};
 
 #[derive(Debug, Clone, Copy)]
 /// Configuration for event handling.
@@ -34,6 +41,9 @@ pub struct Events {
   rx: mpsc::Receiver<Event<Key>>,
   // Need to be kept around to prevent disposing the sender side.
   _tx: mpsc::Sender<Event<Key>>,
+  /// Stops reading the terminal while another program uses it
+  paused: Arc<AtomicBool>,
+  tick_rate: Duration,
 }
 
 impl Events {
@@ -50,8 +60,14 @@ impl Events {
     let (tx, rx) = mpsc::channel();
 
     let event_tx = tx.clone();
+    let paused = Arc::new(AtomicBool::new(false));
+    let thread_paused = paused.clone();
     thread::spawn(move || {
       loop {
+        if thread_paused.load(Ordering::SeqCst) {
+          thread::sleep(config.tick_rate);
+          continue;
+        }
         // poll for tick rate duration, if no event, sent tick event.
         if event::poll(config.tick_rate).unwrap() {
           if let event::Event::Key(key) = event::read().unwrap() {
@@ -65,7 +81,23 @@ impl Events {
       }
     });
 
-    Events { rx, _tx: tx }
+    Events {
+      rx,
+      _tx: tx,
+      paused,
+      tick_rate: config.tick_rate,
+    }
+  }
+
+  /// Stop reading input, so that a program run in the foreground gets all of it. Waits
+  /// for the poll in progress to time out.
+  pub fn pause(&self) {
+    self.paused.store(true, Ordering::SeqCst);
+    thread::sleep(self.tick_rate);
+  }
+
+  pub fn resume(&self) {
+    self.paused.store(false, Ordering::SeqCst);
   }
 
   /// Attempts to read an event.
@@ -0,0 +1,952 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::anyhow;
+
+use super::{csl_json, new_document, ExportOptions};
+use crate::data_structures::{Creator, DateParts, Document, RcDoc, Tag};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    BibTeX,
+    BibLaTeX,
+}
+
+fn entry_type(doc: &Document, dialect: Dialect) -> &'static str {
+    let biblatex = dialect == Dialect::BibLaTeX;
+    match doc.item_data.typeName.as_str() {
+        "journalArticle" | "magazineArticle" | "newspaperArticle" => "article",
+        "book" => "book",
+        "bookSection" => "incollection",
+        "conferencePaper" => "inproceedings",
+        "encyclopediaArticle" | "dictionaryEntry" if biblatex => "inreference",
+        "encyclopediaArticle" | "dictionaryEntry" => "incollection",
+        "thesis" if biblatex => "thesis",
+        "thesis" => {
+            let thesis_type = doc.get_field("thesisType").unwrap_or_default();
+            if thesis_type.to_lowercase().contains("master") {
+                "mastersthesis"
+            } else {
+                "phdthesis"
+            }
+        }
+        "report" if biblatex => "report",
+        "report" => "techreport",
+        "manuscript" => "unpublished",
+        "webpage" | "blogPost" | "forumPost" | "preprint" if biblatex => "online",
+        "patent" if biblatex => "patent",
+        "dataset" if biblatex => "dataset",
+        "computerProgram" if biblatex => "software",
+        "letter" if biblatex => "letter",
+        _ => "misc",
+    }
+}
+
+/// Zotero field to BibTeX field. Fields missing from one dialect are `None`.
+fn field_name(zotero_field: &str, item_type: &str, dialect: Dialect) -> Option<&'static str> {
+    let biblatex = dialect == Dialect::BibLaTeX;
+    let name = match zotero_field {
+        "title" => "title",
+        "publicationTitle" => match item_type {
+            "bookSection" | "conferencePaper" | "encyclopediaArticle" | "dictionaryEntry" => {
+                "booktitle"
+            }
+            "webpage" | "blogPost" | "forumPost" if !biblatex => return None,
+            _ if biblatex => "journaltitle",
+            _ => "journal",
+        },
+        "bookTitle" | "proceedingsTitle" | "encyclopediaTitle" | "dictionaryTitle" => "booktitle",
+        "websiteTitle" | "blogTitle" | "forumTitle" if biblatex => "organization",
+        "volume" => "volume",
+        "issue" => "number",
+        "number" | "reportNumber" | "patentNumber" => "number",
+        "pages" => "pages",
+        "numPages" if biblatex => "pagetotal",
+        "edition" => "edition",
+        "series" => "series",
+        "publisher" => "publisher",
+        "place" if biblatex => "location",
+        "place" => "address",
+        "university" if biblatex => "institution",
+        "university" => "school",
+        "institution" => "institution",
+        "thesisType" | "reportType" => "type",
+        "DOI" => "doi",
+        "ISBN" => "isbn",
+        "ISSN" => "issn",
+        "url" => "url",
+        "accessDate" if biblatex => "urldate",
+        "abstractNote" => "abstract",
+        "language" if biblatex => "langid",
+        "shortTitle" if biblatex => "shorttitle",
+        "journalAbbreviation" if biblatex => "shortjournal",
+        "archivePrefix" | "repository" if biblatex => "eprinttype",
+        "archiveID" if biblatex => "eprint",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Creator role to BibTeX field
+fn creator_field(role: &str, dialect: Dialect) -> Option<&'static str> {
+    match role {
+        "editor" | "seriesEditor" => Some("editor"),
+        "translator" if dialect == Dialect::BibLaTeX => Some("translator"),
+        "bookAuthor" if dialect == Dialect::BibLaTeX => Some("bookauthor"),
+        role if crate::data_structures::PRIMARY_CREATOR_TYPES.contains(&role) => Some("author"),
+        _ => None,
+    }
+}
+
+/// Escape characters that have a special meaning in LaTeX.
+pub fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn format_creator(creator: &Creator) -> String {
+    let last = creator.lastName.as_deref().unwrap_or_default();
+    if creator.is_single_field() {
+        // Braces keep institution names from being split into first/last names
+        format!("{{{}}}", escape(last))
+    } else {
+        format!(
+            "{}, {}",
+            escape(last),
+            escape(creator.firstName.as_deref().unwrap_or_default())
+        )
+    }
+}
+
+/// BibTeX wants page ranges with an en dash, written `--`
+fn format_pages(pages: &str) -> String {
+    pages
+        .split(['-', '–', '—'])
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| part.trim())
+        .collect::<Vec<&str>>()
+        .join("--")
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+pub fn write_entry(doc: &Document, dialect: Dialect, options: &ExportOptions) -> String {
+    let item_type = doc.item_data.typeName.as_str();
+    // (name, value, escaped)
+    let mut fields: Vec<(&str, String, bool)> = Vec::new();
+
+    let mut roles: Vec<(&str, Vec<String>)> = Vec::new();
+    for creator in doc.get_creators() {
+        if let Some(field) = creator_field(creator.role(), dialect) {
+            let formatted = format_creator(creator);
+            match roles.iter_mut().find(|(name, _)| *name == field) {
+                Some((_, names)) => names.push(formatted),
+                None => roles.push((field, vec![formatted])),
+            }
+        }
+    }
+    for (field, names) in roles {
+        fields.push((field, names.join(" and "), false));
+    }
+
+    let date = doc.get_date_parts();
+    match dialect {
+        Dialect::BibLaTeX => {
+            if let Some(iso) = date.to_iso() {
+                fields.push(("date", iso, false));
+            }
+        }
+        Dialect::BibTeX => {
+            if let Some(year) = date.year {
+                fields.push(("year", year.to_string(), false));
+            }
+            if let Some(month) = date.month.filter(|month| (1..=12).contains(month)) {
+                fields.push(("month", MONTHS[month as usize - 1].to_string(), false));
+            }
+        }
+    }
+
+    let mut zotero_fields: Vec<(&String, &String)> = doc.fields.iter().collect();
+    // HashMap order is random, keep the output stable
+    zotero_fields.sort();
+    for (zotero_field, value) in zotero_fields {
+        if value.trim().is_empty() {
+            continue;
+        }
+        if let Some(name) = field_name(zotero_field, item_type, dialect) {
+            if fields.iter().any(|(existing, _, _)| *existing == name) {
+                continue;
+            }
+            match name {
+                "pages" => fields.push((name, escape(&format_pages(value)), false)),
+                // Verbatim fields
+                "doi" | "url" => fields.push((name, value.to_owned(), false)),
+                "urldate" => fields.push((
+                    name,
+                    value
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_owned(),
+                    false,
+                )),
+                _ => fields.push((name, value.to_owned(), true)),
+            }
+        }
+    }
+    if doc.get_field("title").is_none() && !doc.get_title().is_empty() {
+        fields.insert(0, ("title", doc.get_title().to_owned(), true));
+    }
+
+    if !doc.tags.is_empty() {
+        let keywords: Vec<&str> = doc.tags.iter().map(|tag| tag.name.as_str()).collect();
+        fields.push(("keywords", keywords.join(", "), true));
+    }
+
+    if let Some(storage_dir) = &options.zotero_storage_dir {
+        if let Some(attachments) = &doc.attachments {
+            let files: Vec<String> = attachments
+                .items
+                .iter()
+                .filter_map(|att| {
+                    att.resolve(storage_dir, options.base_attachment_dir.as_deref())
+                        .path
+                })
+                .map(|path| path.to_string_lossy().replace(';', "\\;"))
+                .collect();
+            if !files.is_empty() {
+                fields.push(("file", files.join(";"), false));
+            }
+        }
+    }
+
+    // Keep a conventional field order: title and creators first
+    fields.sort_by_key(|(name, _, _)| match *name {
+        "title" => 0,
+        "author" => 1,
+        "editor" | "bookauthor" | "translator" => 2,
+        _ => 3,
+    });
+
+    let mut out = format!(
+        "@{}{{{},\n",
+        entry_type(doc, dialect),
+        doc.get_citation_key()
+    );
+    for (name, value, escaped) in fields {
+        let value = if escaped { escape(&value) } else { value };
+        if name == "month" {
+            // Left bare so BibTeX expands the month macro
+            out.push_str(&format!("  {} = {},\n", name, value));
+        } else {
+            out.push_str(&format!("  {} = {{{}}},\n", name, value));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// BibTeX entry type to Zotero item type
+fn zotero_type(entry_type: &str) -> &'static str {
+    match entry_type {
+        "article" => "journalArticle",
+        "book" | "mvbook" => "book",
+        "inbook" | "incollection" | "bookinbook" => "bookSection",
+        "inproceedings" | "conference" => "conferencePaper",
+        "proceedings" => "book",
+        "phdthesis" | "mastersthesis" | "thesis" => "thesis",
+        "techreport" | "report" => "report",
+        "unpublished" => "manuscript",
+        "online" | "electronic" | "www" => "webpage",
+        "inreference" => "encyclopediaArticle",
+        "patent" => "patent",
+        "dataset" => "dataset",
+        "software" => "computerProgram",
+        "letter" => "letter",
+        _ => "document",
+    }
+}
+
+/// BibTeX field to Zotero field, the inverse of `field_name` for both dialects
+fn zotero_field(name: &str, item_type: &str) -> Option<&'static str> {
+    let field = match name {
+        "title" => "title",
+        "journal" | "journaltitle" => "publicationTitle",
+        "booktitle" => match item_type {
+            "bookSection" => "bookTitle",
+            "conferencePaper" => "proceedingsTitle",
+            "encyclopediaArticle" => "encyclopediaTitle",
+            _ => return None,
+        },
+        "volume" => "volume",
+        "number" | "issue" => match item_type {
+            "journalArticle" | "magazineArticle" | "newspaperArticle" => "issue",
+            "report" => "reportNumber",
+            "patent" => "patentNumber",
+            _ => "number",
+        },
+        "pages" => "pages",
+        "pagetotal" => "numPages",
+        "edition" => "edition",
+        "series" => "series",
+        "publisher" => "publisher",
+        "address" | "location" => "place",
+        "school" => "university",
+        "institution" if item_type == "thesis" => "university",
+        "institution" => "institution",
+        "type" if item_type == "thesis" => "thesisType",
+        "type" if item_type == "report" => "reportType",
+        "doi" => "DOI",
+        "isbn" => "ISBN",
+        "issn" => "ISSN",
+        "url" => "url",
+        "abstract" => "abstractNote",
+        "langid" | "language" => "language",
+        "shorttitle" => "shortTitle",
+        "shortjournal" => "journalAbbreviation",
+        "eprinttype" | "archiveprefix" => "repository",
+        "eprint" => "archiveID",
+        "note" => "extra",
+        _ => return None,
+    };
+    Some(field)
+}
+
+/// Type, key and fields of an entry, as written in the file
+type RawEntry = (String, String, Vec<(String, String)>);
+
+/// Reads the entries of a BibTeX file
+struct BibParser<'a> {
+    input: &'a str,
+    pos: usize,
+    /// `@string` abbreviations, with the month names predefined
+    macros: HashMap<String, String>,
+}
+
+impl<'a> BibParser<'a> {
+    fn new(input: &'a str) -> Self {
+        let macros = MONTHS
+            .iter()
+            .enumerate()
+            .map(|(idx, month)| (month.to_string(), (idx + 1).to_string()))
+            .collect();
+        Self {
+            input,
+            pos: 0,
+            macros,
+        }
+    }
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+    fn identifier(&mut self) -> String {
+        self.skip_whitespace();
+        let rest = &self.input[self.pos..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || "{}(),=#\"".contains(c))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        rest[..len].to_string()
+    }
+    /// The text up to the brace closing the one just read, braces inside kept
+    fn braced(&mut self) -> anyhow::Result<String> {
+        self.delimited('{', '}')
+    }
+    /// The text up to the `close` matching the `open` just read
+    fn delimited(&mut self, open: char, close: char) -> anyhow::Result<String> {
+        let start = self.pos;
+        let mut depth = 1;
+        for (idx, c) in self.input[start..].char_indices() {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+            }
+            if depth == 0 {
+                self.pos = start + idx + 1;
+                return Ok(self.input[start..start + idx].to_string());
+            }
+        }
+        Err(anyhow!("Unbalanced {}{} in BibTeX", open, close))
+    }
+    fn quoted(&mut self) -> anyhow::Result<String> {
+        let start = self.pos;
+        let mut depth = 0;
+        for (idx, c) in self.input[start..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '"' if depth == 0 => {
+                    self.pos = start + idx + 1;
+                    return Ok(self.input[start..start + idx].to_string());
+                }
+                _ => {}
+            }
+        }
+        Err(anyhow!("Unterminated quote in BibTeX"))
+    }
+    /// A value made of braced or quoted parts, numbers and abbreviations joined by `#`
+    fn value(&mut self) -> anyhow::Result<String> {
+        let mut value = String::new();
+        loop {
+            if self.eat('{') {
+                value.push_str(&self.braced()?);
+            } else if self.eat('"') {
+                value.push_str(&self.quoted()?);
+            } else {
+                let name = self.identifier();
+                if name.is_empty() {
+                    return Err(anyhow!("Missing BibTeX value"));
+                }
+                let expanded = self.macros.get(&name.to_lowercase()).cloned();
+                value.push_str(&expanded.unwrap_or(name));
+            }
+            if !self.eat('#') {
+                return Ok(value);
+            }
+        }
+    }
+    /// The next entry as its type, key and fields, skipping comments and abbreviations
+    fn next_entry(&mut self) -> anyhow::Result<Option<RawEntry>> {
+        loop {
+            match self.input[self.pos..].find('@') {
+                Some(idx) => self.pos += idx + 1,
+                None => return Ok(None),
+            }
+            let entry_type = self.identifier().to_lowercase();
+            let (open, close) = if self.eat('{') {
+                ('{', '}')
+            } else if self.eat('(') {
+                ('(', ')')
+            } else {
+                continue;
+            };
+            match entry_type.as_str() {
+                "comment" | "preamble" => {
+                    self.delimited(open, close)?;
+                    continue;
+                }
+                "string" => {
+                    let name = self.identifier().to_lowercase();
+                    if !self.eat('=') {
+                        return Err(anyhow!("Malformed @string \"{}\"", name));
+                    }
+                    let value = self.value()?;
+                    self.macros.insert(name, value);
+                    self.eat(close);
+                    continue;
+                }
+                _ => {}
+            }
+            let key = self.identifier();
+            let mut fields = Vec::new();
+            loop {
+                if self.eat(close) {
+                    break;
+                }
+                if self.eat(',') {
+                    continue;
+                }
+                let name = self.identifier().to_lowercase();
+                if name.is_empty() || !self.eat('=') {
+                    return Err(anyhow!("Malformed BibTeX entry \"{}\"", key));
+                }
+                fields.push((name, self.value()?));
+            }
+            return Ok(Some((entry_type, key, fields)));
+        }
+    }
+}
+
+/// Letter with an accent given by its LaTeX command, e.g. `\'e`
+fn accented(accent: char, letter: char) -> Option<char> {
+    let (from, to) = match accent {
+        '\'' => ("aeiouyAEIOUYcnszCNSZ", "áéíóúýÁÉÍÓÚÝćńśźĆŃŚŹ"),
+        '`' => ("aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+        '"' => ("aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+        '^' => ("aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+        '~' => ("anoANO", "ãñõÃÑÕ"),
+        'c' => ("cC", "çÇ"),
+        'v' => ("cszreCSZRE", "čšžřěČŠŽŘĚ"),
+        'r' => ("aA", "åÅ"),
+        _ => return None,
+    };
+    from.chars()
+        .position(|c| c == letter)
+        .and_then(|idx| to.chars().nth(idx))
+}
+
+/// Turn a LaTeX value into plain text: accents and escaped characters are decoded,
+/// commands like `\emph` and braces are dropped
+pub fn unescape(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut out = String::with_capacity(value.len());
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        idx += 1;
+        match c {
+            '{' | '}' => {}
+            '~' => out.push('\u{a0}'),
+            '\\' if idx < chars.len() => {
+                let next = chars[idx];
+                if next.is_ascii_alphabetic() {
+                    let start = idx;
+                    while idx < chars.len() && chars[idx].is_ascii_alphabetic() {
+                        idx += 1;
+                    }
+                    let command: String = chars[start..idx].iter().collect();
+                    // The letter of `\c{c}` or `\v s`
+                    let argument = |idx: &mut usize| {
+                        while *idx < chars.len() && matches!(chars[*idx], ' ' | '{') {
+                            *idx += 1;
+                        }
+                        let letter = chars.get(*idx).copied();
+                        *idx += 1;
+                        if chars.get(*idx) == Some(&'}') {
+                            *idx += 1;
+                        }
+                        letter
+                    };
+                    match command.as_str() {
+                        "c" | "v" | "r" => {
+                            let accent = command.chars().next().unwrap();
+                            let letter = argument(&mut idx).unwrap_or_default();
+                            out.push(accented(accent, letter).unwrap_or(letter));
+                            continue;
+                        }
+                        "ss" => out.push('ß'),
+                        "o" => out.push('ø'),
+                        "O" => out.push('Ø'),
+                        "ae" => out.push('æ'),
+                        "AE" => out.push('Æ'),
+                        "aa" => out.push('å'),
+                        "AA" => out.push('Å'),
+                        "l" => out.push('ł'),
+                        "L" => out.push('Ł'),
+                        "textbackslash" => out.push('\\'),
+                        "textasciitilde" => out.push('~'),
+                        "textasciicircum" => out.push('^'),
+                        // Formatting like `\emph{...}` keeps its text
+                        _ => {}
+                    }
+                    // A space after a command only ends it
+                    if chars.get(idx) == Some(&' ') {
+                        idx += 1;
+                    }
+                } else if "'`\"^~".contains(next) {
+                    idx += 1;
+                    while idx < chars.len() && chars[idx] == '{' {
+                        idx += 1;
+                    }
+                    match chars.get(idx) {
+                        Some(&letter) => {
+                            out.push(accented(next, letter).unwrap_or(letter));
+                            idx += 1;
+                            if chars.get(idx) == Some(&'}') {
+                                idx += 1;
+                            }
+                        }
+                        None => out.push(next),
+                    }
+                } else {
+                    // `\&`, `\%`, `\{` and the like
+                    out.push(next);
+                    idx += 1;
+                }
+            }
+            '-' if chars.get(idx) == Some(&'-') => {
+                let dashes = chars[idx - 1..].iter().take_while(|c| **c == '-').count();
+                out.push(if dashes >= 3 { '—' } else { '–' });
+                idx += dashes - 1;
+            }
+            c if c.is_whitespace() => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Split a list of names at the `and` between whitespace outside of braces
+fn split_names(value: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, c) in value.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 && c.is_whitespace() => {
+                let rest = &value[idx + c.len_utf8()..];
+                let is_and = rest
+                    .get(..3)
+                    .is_some_and(|word| word.eq_ignore_ascii_case("and"))
+                    && rest[3..].starts_with(char::is_whitespace);
+                if is_and {
+                    names.push(value[start..idx].trim());
+                    start = idx + c.len_utf8() + 3;
+                }
+            }
+            _ => {}
+        }
+    }
+    names.push(value[start..].trim());
+    names.retain(|name| !name.is_empty());
+    names
+}
+
+/// Parse `Last, First`, `First Last` or a braced institution name
+fn parse_name(name: &str, creator_type: &str) -> Creator {
+    let creator_type = Some(creator_type.to_string());
+    let is_braced = name.starts_with('{') && name.ends_with('}') && !name.contains(',');
+    if let Some((last, first)) = name.split_once(',').filter(|_| !is_braced) {
+        // `Last, Jr, First` keeps the suffix with the last name
+        let (last, first) = match first.split_once(',') {
+            Some((suffix, first)) => (format!("{}, {}", last, suffix.trim()), first),
+            None => (last.to_string(), first),
+        };
+        return Creator {
+            firstName: Some(unescape(first)),
+            lastName: Some(unescape(&last)),
+            creatorType: creator_type,
+            fieldMode: Some(0),
+        };
+    }
+    let name = unescape(name);
+    let words: Vec<&str> = name.split_whitespace().collect();
+    match words.split_last().filter(|_| !is_braced && words.len() > 1) {
+        Some((_, first_words)) => {
+            // `First von Last`: particles like `van` or `de la` belong to the last name
+            let von = first_words
+                .iter()
+                .position(|word| word.starts_with(char::is_lowercase))
+                .unwrap_or(first_words.len());
+            Creator {
+                firstName: Some(words[..von].join(" ")),
+                lastName: Some(words[von..].join(" ")),
+                creatorType: creator_type,
+                fieldMode: Some(0),
+            }
+        }
+        None => Creator {
+            firstName: None,
+            lastName: Some(name),
+            creatorType: creator_type,
+            fieldMode: Some(1),
+        },
+    }
+}
+
+fn from_entry(entry_type: &str, key: &str, fields: &[(String, String)]) -> RcDoc {
+    let item_type = zotero_type(entry_type);
+    let doc = new_document(item_type, key);
+    let mut doc_mut = doc.borrow_mut();
+    doc_mut.citation_key = key.to_string();
+    let get = |name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| unescape(value))
+    };
+    for (name, value) in fields {
+        let role = match name.as_str() {
+            "author" => Some(csl_json::primary_creator_type(item_type)),
+            "editor" => Some("editor"),
+            "translator" => Some("translator"),
+            "bookauthor" => Some("bookAuthor"),
+            _ => None,
+        };
+        if let Some(role) = role {
+            let names = split_names(value);
+            doc_mut
+                .creators
+                .extend(names.into_iter().map(|name| parse_name(name, role)));
+            continue;
+        }
+        if name == "keywords" {
+            doc_mut.tags.extend(
+                unescape(value)
+                    .split([',', ';'])
+                    .map(str::trim)
+                    .filter(|keyword| !keyword.is_empty())
+                    .map(|keyword| Tag {
+                        tagId: 0,
+                        name: keyword.to_string(),
+                    }),
+            );
+            continue;
+        }
+        if let Some(field) = zotero_field(name, item_type) {
+            let mut value = unescape(value);
+            if field == "pages" {
+                value = value.replace('–', "-");
+            }
+            if !value.is_empty() {
+                doc_mut.fields.entry(field.to_string()).or_insert(value);
+            }
+        }
+    }
+    if item_type == "thesis" && !doc_mut.fields.contains_key("thesisType") {
+        match entry_type {
+            "phdthesis" => doc_mut
+                .fields
+                .insert("thesisType".into(), "PhD thesis".into()),
+            "mastersthesis" => doc_mut
+                .fields
+                .insert("thesisType".into(), "Master's thesis".into()),
+            _ => None,
+        };
+    }
+    if let Some(eprint) = doc_mut.fields.get("archiveID").cloned() {
+        let repository = doc_mut.get_field("repository").unwrap_or_default();
+        if repository.eq_ignore_ascii_case("arxiv") && !eprint.starts_with("arXiv:") {
+            doc_mut
+                .fields
+                .insert("archiveID".to_string(), format!("arXiv:{}", eprint));
+        }
+    }
+    if let Some(date) = get("urldate").and_then(|date| DateParts::zotero_date_from_text(&date)) {
+        doc_mut.fields.insert("accessDate".to_string(), date);
+    }
+
+    // BibLaTeX has full dates, BibTeX a year and a month
+    let date = match get("date") {
+        Some(date) => DateParts::zotero_date_from_text(&date),
+        None => get("year").and_then(|year| {
+            let month = get("month").and_then(|month| {
+                month.parse::<u32>().ok().or_else(|| {
+                    let month = month.to_lowercase();
+                    MONTHS
+                        .iter()
+                        .position(|name| month.starts_with(name))
+                        .map(|idx| idx as u32 + 1)
+                })
+            });
+            let original = match month {
+                Some(month) => format!("{}-{:02}", year.trim(), month),
+                None => year.trim().to_string(),
+            };
+            DateParts::zotero_date_from_text(&original)
+        }),
+    };
+    if let Some(date) = date {
+        doc_mut.item_data.pubdate = date.clone();
+        doc_mut.fields.insert("date".to_string(), date);
+    }
+    let title = doc_mut.get_field("title").unwrap_or_default().to_owned();
+    doc_mut.item_data.title = title;
+    let abstract_note = doc_mut
+        .get_field("abstractNote")
+        .unwrap_or_default()
+        .to_owned();
+    doc_mut.item_data.abstracttext = abstract_note;
+    drop(doc_mut);
+    doc
+}
+
+/// Parse BibTeX or BibLaTeX entries into documents. `@string` abbreviations are expanded,
+/// `@comment` and `@preamble` are skipped.
+pub fn parse(input: &str) -> anyhow::Result<Vec<RcDoc>> {
+    let mut parser = BibParser::new(input);
+    let mut docs = Vec::new();
+    while let Some((entry_type, key, fields)) = parser.next_entry()? {
+        docs.push(from_entry(&entry_type, &key, &fields));
+    }
+    Ok(docs)
+}
+
+pub fn read_file(path: &Path) -> anyhow::Result<Vec<RcDoc>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::{new_test_document, Tag};
+
+    fn creator(first: Option<&str>, last: &str, role: &str) -> Creator {
+        Creator {
+            firstName: first.map(|first| first.to_string()),
+            lastName: Some(last.to_string()),
+            creatorType: Some(role.to_string()),
+            fieldMode: Some(if first.is_some() { 0 } else { 1 }),
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("50% & $5_#"), "50\\% \\& \\$5\\_\\#");
+        assert_eq!(
+            escape("a~b^c\\"),
+            "a\\textasciitilde{}b\\textasciicircum{}c\\textbackslash{}"
+        );
+    }
+
+    #[test]
+    fn test_write_entry() {
+        let doc = new_test_document(
+            "bookSection",
+            &[
+                ("title", "Rust & You"),
+                ("date", "2019-05-02 May 2, 2019"),
+                ("bookTitle", "Programming"),
+                ("pages", "10-20"),
+                ("place", "Berlin"),
+                ("DOI", "10.1000/a_b"),
+            ],
+            vec![
+                creator(Some("Jane"), "Doe", "author"),
+                creator(None, "ACME Corp", "author"),
+                creator(Some("Max"), "Mustermann", "editor"),
+            ],
+        );
+        doc.borrow_mut().citation_key = "doe2019rust".to_string();
+        doc.borrow_mut().tags.push(Tag {
+            tagId: 1,
+            name: "rust".to_string(),
+        });
+        let bibtex = write_entry(&doc.borrow(), Dialect::BibTeX, &ExportOptions::default());
+        assert_eq!(
+            bibtex,
+            "@incollection{doe2019rust,
+  title = {Rust \\& You},
+  author = {Doe, Jane and {ACME Corp}},
+  editor = {Mustermann, Max},
+  year = {2019},
+  month = may,
+  doi = {10.1000/a_b},
+  booktitle = {Programming},
+  pages = {10--20},
+  address = {Berlin},
+  keywords = {rust},
+}
+"
+        );
+        let biblatex = write_entry(&doc.borrow(), Dialect::BibLaTeX, &ExportOptions::default());
+        assert!(biblatex.contains("  date = {2019-05-02},\n"));
+        assert!(biblatex.contains("  location = {Berlin},\n"));
+    }
+
+    #[test]
+    fn test_write_month() {
+        let doc = new_test_document("book", &[("date", "2020-12-00 December 2020")], vec![]);
+        let bibtex = write_entry(&doc.borrow(), Dialect::BibTeX, &ExportOptions::default());
+        assert!(bibtex.lines().any(|line| line == "  month = dec,"));
+        let again = parse(&bibtex).unwrap();
+        assert_eq!(
+            again[0].borrow().get_field("date"),
+            Some("2020-12-00 2020-12")
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let docs = parse(
+            r#"@string{acm = "ACM Press"}
+@comment{Exported by hand}
+@InProceedings{doe2019rust,
+  title = {The {Rust} Book \& {M\"{u}ller}'s \emph{notes}},
+  author = {Doe, Jane and {ACME Corp} AND Fran{\c{c}}ois de la Tour and
+            Smith, Jr, John	and {Anderson and Sons}},
+  editor = "Max Mustermann",
+  booktitle = {Programming},
+  publisher = acm # { Inc},
+  year = 2019, month = may,
+  pages = {10--20},
+  doi = {10.1000/a_b},
+  keywords = {rust, systems},
+}
+@phdthesis{roe2020,
+  title = {Ferris},
+  author = {Roe, Richard},
+  school = {MIT},
+  date = {2020-02-03},
+}"#,
+        )
+        .unwrap();
+        assert_eq!(docs.len(), 2);
+        let doc = docs[0].borrow();
+        assert_eq!(doc.item_data.typeName, "conferencePaper");
+        assert_eq!(doc.citation_key, "doe2019rust");
+        assert_eq!(doc.get_title(), "The Rust Book & Müller's notes");
+        assert_eq!(
+            doc.creators,
+            vec![
+                creator(Some("Jane"), "Doe", "author"),
+                creator(None, "ACME Corp", "author"),
+                creator(Some("François"), "de la Tour", "author"),
+                creator(Some("John"), "Smith, Jr", "author"),
+                creator(None, "Anderson and Sons", "author"),
+                creator(Some("Max"), "Mustermann", "editor"),
+            ]
+        );
+        assert_eq!(doc.get_field("proceedingsTitle"), Some("Programming"));
+        assert_eq!(doc.get_field("publisher"), Some("ACM Press Inc"));
+        assert_eq!(doc.get_field("date"), Some("2019-05-00 2019-05"));
+        assert_eq!(doc.get_field("pages"), Some("10-20"));
+        assert_eq!(doc.get_field("DOI"), Some("10.1000/a_b"));
+        let tags: Vec<&str> = doc.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(tags, vec!["rust", "systems"]);
+
+        let thesis = docs[1].borrow();
+        assert_eq!(thesis.get_field("university"), Some("MIT"));
+        assert_eq!(thesis.get_field("thesisType"), Some("PhD thesis"));
+        assert_eq!(thesis.get_field("date"), Some("2020-02-03 2020-02-03"));
+
+        // What is exported can be read back
+        let exported = write_entry(&doc, Dialect::BibLaTeX, &ExportOptions::default());
+        let again = parse(&exported).unwrap();
+        assert_eq!(again[0].borrow().creators, doc.creators);
+        assert_eq!(again[0].borrow().get_title(), doc.get_title());
+
+        assert!(parse("@article{broken, title = {no end}").is_err());
+
+        // Records copied from web pages come with non-breaking and other wide spaces
+        let docs = parse(
+            "@book{a,\u{a0}title = {First}}\u{a0}\u{3000}\n@book\u{2009}(b, title =\u{a0}{Second})",
+        )
+        .unwrap();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].borrow().get_title(), "First");
+        assert_eq!(docs[1].borrow().citation_key, "b");
+        assert_eq!(docs[1].borrow().get_title(), "Second");
+
+        let docs = parse(
+            "@preamble(\"\\newcommand{\\noop}[1]{}\")\n@comment(see (below))\n@book{c, title = {Third}}",
+        )
+        .unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].borrow().get_title(), "Third");
+    }
+}
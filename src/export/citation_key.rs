@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::data_structures::{Document, RcDoc};
+
+const STOP_WORDS: [&str; 14] = [
+    "a", "an", "the", "on", "of", "in", "for", "to", "and", "at", "by", "with", "from", "is",
+];
+
+/// Replace common accented latin letters with their ASCII base letter.
+fn fold_ascii(c: char) -> Option<char> {
+    let folded = match c {
+        'a'..='z' | '0'..='9' => c,
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ą' => 'a',
+        'ç' | 'ć' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => 'e',
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => 'i',
+        'ł' => 'l',
+        'ñ' | 'ń' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => 'o',
+        'ř' => 'r',
+        'ś' | 'š' | 'ş' => 's',
+        'ť' | 'ţ' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => 'u',
+        'ý' | 'ÿ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        'ß' => 's',
+        _ => return None,
+    };
+    Some(folded)
+}
+
+fn clean(value: &str) -> String {
+    value
+        .to_lowercase()
+        .chars()
+        .filter_map(fold_ascii)
+        .collect()
+}
+
+/// `<first author last name><year><first significant title word>`, e.g. `doe2020study`
+pub fn generate_citation_key(doc: &Document) -> String {
+    let creator = doc
+        .get_authors()
+        .next()
+        .or_else(|| doc.get_creators().next());
+    let name = creator
+        .and_then(|creator| creator.lastName.as_deref().or(creator.firstName.as_deref()))
+        .map(|name| {
+            // Institutions: keep the first word only
+            clean(name.split_whitespace().next().unwrap_or_default())
+        })
+        .unwrap_or_default();
+    let year = doc
+        .get_date_parts()
+        .year
+        .map(|year| year.to_string())
+        .unwrap_or_default();
+    let word = doc
+        .get_title()
+        .split(|c: char| !c.is_alphanumeric())
+        .map(clean)
+        .find(|word| !word.is_empty() && !STOP_WORDS.contains(&word.as_str()))
+        .unwrap_or_default();
+    let key = format!("{}{}{}", name, year, word);
+    if key.is_empty() {
+        doc.item_data.key.to_lowercase()
+    } else {
+        key
+    }
+}
+
+/// Generate a citation key for every document that has neither a pinned nor a Better
+/// BibTeX key. Clashing keys get a suffix (`a`, `b`, ...) in item ID order so that keys
+/// stay the same between runs.
+pub fn assign_citation_keys(docs: &[RcDoc]) {
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut by_key: HashMap<String, Vec<&RcDoc>> = HashMap::new();
+    for doc in docs {
+        let doc_ref = doc.borrow();
+        if doc_ref.get_pinned_citation_key().is_some() || !doc_ref.citation_key.is_empty() {
+            taken.insert(doc_ref.get_citation_key().to_owned());
+        } else {
+            by_key
+                .entry(generate_citation_key(&doc_ref))
+                .or_default()
+                .push(doc);
+        }
+    }
+    let mut keys: Vec<String> = by_key.keys().cloned().collect();
+    keys.sort();
+    for key in keys {
+        let mut clashing = by_key.remove(&key).unwrap();
+        clashing.sort_by_key(|doc| doc.borrow().item_data.itemId);
+        let mut next_suffix = 0;
+        for (idx, doc) in clashing.iter().enumerate() {
+            let mut candidate = key.clone();
+            if idx > 0 || taken.contains(&candidate) {
+                loop {
+                    candidate = format!("{}{}", key, suffix(next_suffix));
+                    next_suffix += 1;
+                    if !taken.contains(&candidate) {
+                        break;
+                    }
+                }
+            }
+            taken.insert(candidate.clone());
+            doc.borrow_mut().citation_key = candidate;
+        }
+    }
+}
+
+/// a, b, ..., z, aa, ab, ...
+pub fn suffix(mut idx: usize) -> String {
+    let mut out = String::new();
+    loop {
+        out.insert(0, (b'a' + (idx % 26) as u8) as char);
+        if idx < 26 {
+            break;
+        }
+        idx = idx / 26 - 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::{new_test_document, Creator};
+
+    #[test]
+    fn test_generate_citation_key() {
+        let doc = new_test_document(
+            "journalArticle",
+            &[
+                ("title", "The Über study of things"),
+                ("date", "2020-03-00 March 2020"),
+            ],
+            vec![Creator {
+                firstName: Some("Jörg".to_string()),
+                lastName: Some("Müller".to_string()),
+                creatorType: Some("author".to_string()),
+                fieldMode: Some(0),
+            }],
+        );
+        assert_eq!(generate_citation_key(&doc.borrow()), "muller2020uber");
+    }
+
+    #[test]
+    fn test_assign_citation_keys() {
+        let docs: Vec<RcDoc> = (0..3)
+            .map(|idx| {
+                let doc = new_test_document("book", &[("title", "Same")], Vec::new());
+                doc.borrow_mut().item_data.itemId = 10 - idx;
+                doc
+            })
+            .collect();
+        assign_citation_keys(&docs);
+        assert_eq!(docs[2].borrow().citation_key, "same");
+        assert_eq!(docs[1].borrow().citation_key, "samea");
+        assert_eq!(docs[0].borrow().citation_key, "sameb");
+        assert_eq!(suffix(26), "aa");
+
+        // Pinned keys are kept and never reused
+        let pinned = new_test_document("book", &[("title", "Same")], Vec::new());
+        pinned
+            .borrow_mut()
+            .fields
+            .insert("citationKey".to_string(), "same".to_string());
+        let generated = new_test_document("book", &[("title", "Same")], Vec::new());
+        assign_citation_keys(&[pinned.clone(), generated.clone()]);
+        assert_eq!(pinned.borrow().get_citation_key(), "same");
+        assert_eq!(generated.borrow().get_citation_key(), "samea");
+    }
+}
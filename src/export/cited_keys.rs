@@ -0,0 +1,329 @@
+use std::{fs, path::Path};
+
+/// Extensions of the files searched for citations
+const EXTENSIONS: [&str; 6] = ["aux", "tex", "md", "markdown", "qmd", "rmd"];
+
+/// Add `key` once, keeping the order of first use
+fn push_key(keys: &mut Vec<String>, key: &str) {
+    let key = key.trim();
+    // `\nocite{*}` cites everything, which isn't a key to look up
+    if !key.is_empty() && key != "*" && !keys.iter().any(|k| k == key) {
+        keys.push(key.to_string());
+    }
+}
+
+fn push_key_list(keys: &mut Vec<String>, list: &str) {
+    for key in list.split(',') {
+        push_key(keys, key);
+    }
+}
+
+/// The content of the braced group starting at `start`, and the index after it
+fn braced(text: &str, start: usize) -> Option<(&str, usize)> {
+    let rest = text.get(start..)?;
+    if !rest.starts_with('{') {
+        return None;
+    }
+    let mut depth = 0;
+    for (idx, c) in rest.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&rest[1..idx], start + idx + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Keys of `\citation{a,b}` (BibTeX) and `\abx@aux@cite{..}{key}` (BibLaTeX) lines
+pub fn scan_aux(content: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    for line in content.lines() {
+        if let Some(rest) = line.strip_prefix("\\citation") {
+            if let Some((list, _)) = braced(rest, 0) {
+                push_key_list(&mut keys, list);
+            }
+        } else if let Some(rest) = line.strip_prefix("\\abx@aux@cite") {
+            // `{refsection}{key}` since BibLaTeX 3.8, `{key}` before
+            let (first, end) = match braced(rest, 0) {
+                Some(group) => group,
+                None => continue,
+            };
+            match braced(rest, end) {
+                Some((key, _)) => push_key(&mut keys, key),
+                None => push_key(&mut keys, first),
+            }
+        }
+    }
+    keys
+}
+
+fn strip_tex_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '%' if !escaped => return &line[..idx],
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+fn skip_whitespace(text: &str, mut idx: usize) -> usize {
+    while text[idx..].starts_with(char::is_whitespace) {
+        idx += text[idx..].chars().next().unwrap().len_utf8();
+    }
+    idx
+}
+
+/// Skip `[prenote][postnote]` and `(multi-cite pre)(post)` arguments
+fn skip_optional_args(text: &str, mut idx: usize) -> usize {
+    loop {
+        let next = skip_whitespace(text, idx);
+        let close = match text[next..].chars().next() {
+            Some('[') => ']',
+            Some('(') => ')',
+            _ => return idx,
+        };
+        match text[next..].find(close) {
+            Some(end) => idx = next + end + 1,
+            None => return idx,
+        }
+    }
+}
+
+/// Keys of `\cite`-like commands: natbib (`\citep`, `\citet*`), BibLaTeX (`\parencite`,
+/// `\textcite`, `\autocite`, multi-cite `\cites{a}{b}`) and `\nocite`
+pub fn scan_tex(content: &str) -> Vec<String> {
+    let text: String = content
+        .lines()
+        .map(strip_tex_comment)
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let mut keys = Vec::new();
+    let mut idx = 0;
+    while let Some(offset) = text[idx..].find('\\') {
+        let start = idx + offset + 1;
+        let name_len = text[start..]
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(text.len() - start);
+        let name = &text[start..start + name_len];
+        idx = start + name_len;
+        let lower = name.to_lowercase();
+        if !(lower.starts_with("cite") || lower.ends_with("cite") || lower.ends_with("cites")) {
+            continue;
+        }
+        if text[idx..].starts_with('*') {
+            idx += 1;
+        }
+        // Multi-cite commands take several key groups, each with optional arguments
+        loop {
+            idx = skip_optional_args(&text, idx);
+            let next = skip_whitespace(&text, idx);
+            match braced(&text, next) {
+                Some((list, end)) => {
+                    push_key_list(&mut keys, list);
+                    idx = end;
+                }
+                None => break,
+            }
+            if !lower.ends_with("cites") {
+                break;
+            }
+        }
+    }
+    keys
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Pandoc citation key at the start of `text`: alphanumerics and `_`, with internal
+/// punctuation, or anything in braces (`@{key with spaces}`)
+fn pandoc_key(text: &str) -> Option<&str> {
+    if text.starts_with('{') {
+        return braced(text, 0).map(|(key, _)| key);
+    }
+    let mut end = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if is_key_char(c) {
+            end = idx + c.len_utf8();
+        } else if ":.#$%&-+?<>~/".contains(c)
+            && end > 0
+            && chars.peek().is_some_and(|(_, next)| is_key_char(*next))
+        {
+            continue;
+        } else {
+            break;
+        }
+    }
+    match end {
+        0 => None,
+        _ => Some(&text[..end]),
+    }
+}
+
+/// Remove inline code spans, so `` `@decorator` `` isn't taken for a citation
+fn strip_inline_code(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut in_code = false;
+    for c in line.chars() {
+        if c == '`' {
+            in_code = !in_code;
+        } else if !in_code {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Keys of Pandoc citations: `[@key]`, `[see @a, p. 1; @b]`, `@key` and `[-@key]`.
+/// Fenced code blocks and e-mail addresses are skipped.
+pub fn scan_markdown(content: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut in_fence = false;
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let line = strip_inline_code(line);
+        let mut previous = ' ';
+        for (idx, c) in line.char_indices() {
+            if c == '@' && !is_key_char(previous) && !".@".contains(previous) {
+                if let Some(key) = pandoc_key(&line[idx + 1..]) {
+                    push_key(&mut keys, key);
+                }
+            }
+            previous = c;
+        }
+    }
+    keys
+}
+
+/// Citation keys in a single file, by its extension
+pub fn scan_file(path: &Path) -> anyhow::Result<Vec<String>> {
+    let content = fs::read_to_string(path)?;
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    Ok(match extension.as_str() {
+        "aux" => scan_aux(&content),
+        "tex" => scan_tex(&content),
+        _ => scan_markdown(&content),
+    })
+}
+
+fn collect_files(path: &Path, files: &mut Vec<std::path::PathBuf>) -> anyhow::Result<()> {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<_> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    for entry in entries {
+        let hidden = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if entry.is_dir() {
+            collect_files(&entry, files)?;
+        } else if entry
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Citation keys used in the given files and directories, searched recursively, in order
+/// of first use
+pub fn scan_paths(paths: &[&Path]) -> anyhow::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(path, &mut files)?;
+    }
+    let mut keys = Vec::new();
+    for file in files {
+        for key in scan_file(&file)? {
+            push_key(&mut keys, &key);
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_aux() {
+        let aux = "\\relax\n\\citation{doe2020,roe2019}\n\\abx@aux@cite{0}{smith2018}\n\
+                   \\abx@aux@cite{old2001}\n\\bibdata{refs}\n\\citation{doe2020}\n";
+        assert_eq!(
+            scan_aux(aux),
+            vec!["doe2020", "roe2019", "smith2018", "old2001"]
+        );
+    }
+
+    #[test]
+    fn test_scan_tex() {
+        let tex = r"As shown by \citet{doe2020} and \citep[see][p. 3]{roe2019, smith2018},
+\parencite*{lee2017} % \cite{commented}
+\cites[1]{multi1}[2]{multi2} and 50\% \textcite{after_percent}.
+\nocite{*}\Cite{upper} \section{No cite} \excitement{no}";
+        assert_eq!(
+            scan_tex(tex),
+            vec![
+                "doe2020",
+                "roe2019",
+                "smith2018",
+                "lee2017",
+                "multi1",
+                "multi2",
+                "after_percent",
+                "upper"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_markdown() {
+        let md = "Blah [see @doe2020, p. 33; -@roe:2019]. @smith.2018 says.\n\
+                  Mail me at me@example.com or `@not_a_key`.\n\
+                  ```python\n@decorator\n```\n\
+                  [@{key with spaces}] and @end.";
+        assert_eq!(
+            scan_markdown(md),
+            vec![
+                "doe2020",
+                "roe:2019",
+                "smith.2018",
+                "key with spaces",
+                "end"
+            ]
+        );
+    }
+}
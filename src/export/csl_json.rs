@@ -0,0 +1,596 @@
+use std::{fs, path::Path};
+
+use anyhow::anyhow;
+use serde_json::{json, Map, Value};
+
+use super::new_document;
+use crate::data_structures::{Creator, DateParts, Document, RcDoc, PRIMARY_CREATOR_TYPES};
+
+/// Zotero item type to CSL type. When importing, the first Zotero type of a CSL type wins.
+const ITEM_TYPES: [(&str, &str); 38] = [
+    ("journalArticle", "article-journal"),
+    ("magazineArticle", "article-magazine"),
+    ("newspaperArticle", "article-newspaper"),
+    ("book", "book"),
+    ("bookSection", "chapter"),
+    ("conferencePaper", "paper-conference"),
+    ("thesis", "thesis"),
+    ("report", "report"),
+    ("webpage", "webpage"),
+    ("blogPost", "post-weblog"),
+    ("forumPost", "post"),
+    ("encyclopediaArticle", "entry-encyclopedia"),
+    ("dictionaryEntry", "entry-dictionary"),
+    ("manuscript", "manuscript"),
+    ("letter", "personal_communication"),
+    ("email", "personal_communication"),
+    ("instantMessage", "personal_communication"),
+    ("interview", "interview"),
+    ("film", "motion_picture"),
+    ("videoRecording", "motion_picture"),
+    ("artwork", "graphic"),
+    ("audioRecording", "song"),
+    ("podcast", "song"),
+    ("radioBroadcast", "broadcast"),
+    ("tvBroadcast", "broadcast"),
+    ("presentation", "speech"),
+    ("map", "map"),
+    ("patent", "patent"),
+    ("bill", "bill"),
+    ("hearing", "bill"),
+    ("case", "legal_case"),
+    ("statute", "legislation"),
+    ("computerProgram", "software"),
+    ("dataset", "dataset"),
+    ("standard", "standard"),
+    ("preprint", "article"),
+    ("document", "article"),
+    ("attachment", "document"),
+];
+
+/// Zotero field to CSL variable. Type-specific fields (`bookTitle`, `university`) map to
+/// the same variable as their base field.
+const FIELDS: [(&str, &str); 63] = [
+    ("title", "title"),
+    ("shortTitle", "title-short"),
+    ("publicationTitle", "container-title"),
+    ("bookTitle", "container-title"),
+    ("proceedingsTitle", "container-title"),
+    ("websiteTitle", "container-title"),
+    ("blogTitle", "container-title"),
+    ("forumTitle", "container-title"),
+    ("encyclopediaTitle", "container-title"),
+    ("dictionaryTitle", "container-title"),
+    ("programTitle", "container-title"),
+    ("code", "container-title"),
+    ("reporter", "container-title"),
+    ("journalAbbreviation", "container-title-short"),
+    ("series", "collection-title"),
+    ("seriesTitle", "collection-title"),
+    ("seriesNumber", "collection-number"),
+    ("volume", "volume"),
+    ("codeVolume", "volume"),
+    ("reporterVolume", "volume"),
+    ("issue", "issue"),
+    ("pages", "page"),
+    ("codePages", "page"),
+    ("firstPage", "page"),
+    ("numPages", "number-of-pages"),
+    ("numberOfVolumes", "number-of-volumes"),
+    ("edition", "edition"),
+    ("versionNumber", "version"),
+    ("number", "number"),
+    ("reportNumber", "number"),
+    ("billNumber", "number"),
+    ("patentNumber", "number"),
+    ("docketNumber", "number"),
+    ("episodeNumber", "number"),
+    ("publicNumber", "number"),
+    ("publisher", "publisher"),
+    ("university", "publisher"),
+    ("institution", "publisher"),
+    ("company", "publisher"),
+    ("label", "publisher"),
+    ("distributor", "publisher"),
+    ("studio", "publisher"),
+    ("network", "publisher"),
+    ("place", "publisher-place"),
+    ("court", "authority"),
+    ("thesisType", "genre"),
+    ("reportType", "genre"),
+    ("websiteType", "genre"),
+    ("genre", "genre"),
+    ("medium", "medium"),
+    ("conferenceName", "event-title"),
+    ("meetingName", "event-title"),
+    ("section", "section"),
+    ("runningTime", "dimensions"),
+    ("abstractNote", "abstract"),
+    ("DOI", "DOI"),
+    ("ISBN", "ISBN"),
+    ("ISSN", "ISSN"),
+    ("url", "URL"),
+    ("language", "language"),
+    ("archive", "archive"),
+    ("archiveLocation", "archive_location"),
+    ("callNumber", "call-number"),
+];
+
+/// Zotero date fields to CSL date variables
+const DATE_FIELDS: [(&str, &str); 3] = [
+    ("date", "issued"),
+    ("accessDate", "accessed"),
+    ("filingDate", "submitted"),
+];
+
+/// Non-primary creator roles to CSL name variables
+const CREATOR_ROLES: [(&str, &str); 12] = [
+    ("editor", "editor"),
+    ("bookAuthor", "container-author"),
+    ("seriesEditor", "collection-editor"),
+    ("translator", "translator"),
+    ("contributor", "contributor"),
+    ("reviewedAuthor", "reviewed-author"),
+    ("recipient", "recipient"),
+    ("interviewer", "interviewer"),
+    ("castMember", "performer"),
+    ("producer", "producer"),
+    ("guest", "guest"),
+    ("counsel", "contributor"),
+];
+
+pub fn csl_type(item_type: &str) -> &'static str {
+    ITEM_TYPES
+        .iter()
+        .find(|(zotero, _)| *zotero == item_type)
+        .map(|(_, csl)| *csl)
+        .unwrap_or("article")
+}
+
+fn zotero_type(csl_type: &str) -> &'static str {
+    ITEM_TYPES
+        .iter()
+        .find(|(_, csl)| *csl == csl_type)
+        .map(|(zotero, _)| *zotero)
+        .unwrap_or("document")
+}
+
+/// The Zotero field a CSL variable is stored in for the given item type
+pub(super) fn zotero_field(variable: &str, item_type: &str) -> Option<&'static str> {
+    let field = match (variable, item_type) {
+        ("container-title", "bookSection") => "bookTitle",
+        ("container-title", "conferencePaper") => "proceedingsTitle",
+        ("container-title", "webpage") => "websiteTitle",
+        ("container-title", "blogPost") => "blogTitle",
+        ("container-title", "forumPost") => "forumTitle",
+        ("container-title", "encyclopediaArticle") => "encyclopediaTitle",
+        ("container-title", "dictionaryEntry") => "dictionaryTitle",
+        ("container-title", "radioBroadcast" | "tvBroadcast") => "programTitle",
+        ("publisher", "thesis") => "university",
+        ("publisher", "report") => "institution",
+        ("publisher", "computerProgram") => "company",
+        ("publisher", "audioRecording") => "label",
+        ("publisher", "film") => "distributor",
+        ("genre", "thesis") => "thesisType",
+        ("genre", "report") => "reportType",
+        ("genre", "webpage") => "websiteType",
+        ("number", "report") => "reportNumber",
+        ("number", "patent") => "patentNumber",
+        ("number", "bill") => "billNumber",
+        ("event-title", _) => "conferenceName",
+        ("page", "case") => "firstPage",
+        _ => {
+            return FIELDS
+                .iter()
+                .find(|(_, csl)| *csl == variable)
+                .map(|(zotero, _)| *zotero)
+        }
+    };
+    Some(field)
+}
+
+/// The primary creator role of an item type, which CSL calls `author`
+pub(super) fn primary_creator_type(item_type: &str) -> &'static str {
+    match item_type {
+        "computerProgram" => "programmer",
+        "film" | "videoRecording" | "radioBroadcast" | "tvBroadcast" => "director",
+        "artwork" => "artist",
+        "map" => "cartographer",
+        "patent" => "inventor",
+        "podcast" => "podcaster",
+        "presentation" => "presenter",
+        "audioRecording" => "performer",
+        "interview" => "interviewee",
+        "bill" => "sponsor",
+        _ => "author",
+    }
+}
+
+/// Zotero fields, base and type-specific, that map to a CSL variable
+pub(super) fn fields_for_variable(variable: &str) -> impl Iterator<Item = &'static str> + '_ {
+    FIELDS
+        .iter()
+        .filter(move |(_, csl)| *csl == variable)
+        .map(|(zotero, _)| *zotero)
+}
+
+fn csl_role(role: &str) -> Option<&'static str> {
+    if PRIMARY_CREATOR_TYPES.contains(&role) {
+        return Some("author");
+    }
+    CREATOR_ROLES
+        .iter()
+        .find(|(zotero, _)| *zotero == role)
+        .map(|(_, csl)| *csl)
+}
+
+fn is_particle(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_lowercase())
+}
+
+/// Split a name into CSL parts. Lowercase words leading the last name (`van der Waals`) are
+/// non-dropping particles, lowercase words trailing the first name (`Ludwig van`) are
+/// dropping particles and anything after a comma in the first name is a suffix.
+fn to_csl_name(creator: &Creator) -> Value {
+    let last = creator.lastName.as_deref().unwrap_or_default().trim();
+    let mut name = Map::new();
+    if creator.is_single_field() {
+        name.insert("literal".to_string(), json!(last));
+        return Value::Object(name);
+    }
+
+    let last_words: Vec<&str> = last.split_whitespace().collect();
+    let particles = last_words
+        .iter()
+        .take(last_words.len().saturating_sub(1))
+        .take_while(|word| is_particle(word))
+        .count();
+    if particles > 0 {
+        name.insert(
+            "non-dropping-particle".to_string(),
+            json!(last_words[..particles].join(" ")),
+        );
+    }
+    name.insert(
+        "family".to_string(),
+        json!(last_words[particles..].join(" ")),
+    );
+
+    let first = creator.firstName.as_deref().unwrap_or_default();
+    let (given, suffix) = match first.split_once(',') {
+        Some((given, suffix)) => (given, Some(suffix.trim())),
+        None => (first, None),
+    };
+    let given_words: Vec<&str> = given.split_whitespace().collect();
+    let particles = given_words
+        .iter()
+        .skip(1)
+        .rev()
+        .take_while(|word| is_particle(word))
+        .count();
+    let split = given_words.len() - particles;
+    name.insert("given".to_string(), json!(given_words[..split].join(" ")));
+    if particles > 0 {
+        name.insert(
+            "dropping-particle".to_string(),
+            json!(given_words[split..].join(" ")),
+        );
+    }
+    if let Some(suffix) = suffix.filter(|suffix| !suffix.is_empty()) {
+        name.insert("suffix".to_string(), json!(suffix));
+    }
+    Value::Object(name)
+}
+
+fn from_csl_name(name: &Value, creator_type: &str) -> Option<Creator> {
+    let part = |key: &str| {
+        name.get(key)
+            .and_then(|value| value.as_str())
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    };
+    if let Some(literal) = part("literal") {
+        return Some(Creator {
+            firstName: None,
+            lastName: Some(literal.to_string()),
+            creatorType: Some(creator_type.to_string()),
+            fieldMode: Some(1),
+        });
+    }
+    let last: Vec<&str> = [part("non-dropping-particle"), part("family")]
+        .into_iter()
+        .flatten()
+        .collect();
+    if last.is_empty() {
+        return None;
+    }
+    let mut first = [part("given"), part("dropping-particle")]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if let Some(suffix) = part("suffix") {
+        first.push_str(", ");
+        first.push_str(suffix);
+    }
+    Some(Creator {
+        firstName: Some(first),
+        lastName: Some(last.join(" ")),
+        creatorType: Some(creator_type.to_string()),
+        fieldMode: Some(0),
+    })
+}
+
+fn to_csl_date(date: &str) -> Option<Value> {
+    let parts = DateParts::parse(date);
+    match parts.year {
+        Some(year) => {
+            let mut date_parts = vec![json!(year)];
+            if let Some(month) = parts.month {
+                date_parts.push(json!(month));
+                if let Some(day) = parts.day {
+                    date_parts.push(json!(day));
+                }
+            }
+            Some(json!({ "date-parts": [date_parts] }))
+        }
+        None if !date.trim().is_empty() => Some(json!({ "raw": date.trim() })),
+        None => None,
+    }
+}
+
+/// Convert a CSL date to Zotero's `YYYY-MM-DD <original>` representation
+fn from_csl_date(date: &Value) -> Option<String> {
+    if let Some(parts) = date
+        .get("date-parts")
+        .and_then(|parts| parts.get(0))
+        .and_then(|parts| parts.as_array())
+    {
+        let part = |idx: usize| {
+            parts.get(idx).and_then(|part| match part {
+                Value::Number(number) => number.as_u64(),
+                Value::String(string) => string.trim().parse().ok(),
+                _ => None,
+            })
+        };
+        let date_parts = DateParts {
+            year: Some(part(0)? as i32),
+            month: part(1).map(|month| month as u32),
+            day: part(2).map(|day| day as u32),
+        };
+        return date_parts.to_zotero_date(None);
+    }
+    let raw = date
+        .get("raw")
+        .or_else(|| date.get("literal"))
+        .or(Some(date))
+        .and_then(|raw| raw.as_str())?
+        .trim();
+    let year = raw
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|part| part.len() == 4)
+        .unwrap_or("0000");
+    Some(format!("{}-00-00 {}", year, raw))
+}
+
+/// Convert a document to a CSL-JSON item.
+pub fn to_csl_item(doc: &Document) -> Value {
+    let mut item = Map::new();
+    item.insert("id".to_string(), json!(doc.get_citation_key()));
+    item.insert("citation-key".to_string(), json!(doc.get_citation_key()));
+    item.insert("type".to_string(), json!(csl_type(&doc.item_data.typeName)));
+    item.insert("title".to_string(), json!(doc.get_title()));
+
+    for creator in doc.get_creators() {
+        if let Some(role) = csl_role(creator.role()) {
+            let names = item
+                .entry(role.to_string())
+                .or_insert_with(|| Value::Array(Vec::new()));
+            if let Value::Array(names) = names {
+                names.push(to_csl_name(creator));
+            }
+        }
+    }
+
+    if let Some(issued) = to_csl_date(&doc.item_data.pubdate) {
+        item.insert("issued".to_string(), issued);
+    }
+    for (zotero_field, csl_variable) in DATE_FIELDS.iter().skip(1) {
+        if let Some(date) = doc.get_field(zotero_field).and_then(to_csl_date) {
+            item.insert(csl_variable.to_string(), date);
+        }
+    }
+
+    for (zotero_field, csl_variable) in FIELDS.iter().skip(1) {
+        let value = match doc.get_field(zotero_field) {
+            Some(value) if !value.trim().is_empty() => value.trim(),
+            _ => continue,
+        };
+        // Several Zotero fields map to the same variable; the first one wins
+        if !item.contains_key(*csl_variable) {
+            item.insert(csl_variable.to_string(), json!(value));
+        }
+    }
+    if let Some(extra) = doc
+        .get_field("extra")
+        .filter(|extra| !extra.trim().is_empty())
+    {
+        item.insert("note".to_string(), json!(extra.trim()));
+    }
+    if !doc.tags.is_empty() {
+        let keywords: Vec<&str> = doc.tags.iter().map(|tag| tag.name.as_str()).collect();
+        item.insert("keyword".to_string(), json!(keywords.join(", ")));
+    }
+    Value::Object(item)
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) if !string.trim().is_empty() => Some(string.trim().to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// Build an in-memory document from a CSL-JSON item. Imported documents don't exist in the
+/// Zotero database; their `itemId` is assigned when they are added to the library.
+pub fn from_csl_item(item: &Value) -> anyhow::Result<RcDoc> {
+    let item = item
+        .as_object()
+        .ok_or_else(|| anyhow!("CSL-JSON item is not an object"))?;
+    let get = |key: &str| item.get(key).and_then(value_to_string);
+    let item_type = zotero_type(&get("type").unwrap_or_default());
+    let id = get("citation-key")
+        .or_else(|| get("id"))
+        .ok_or_else(|| anyhow!("CSL-JSON item without id"))?;
+    let date = item
+        .get("issued")
+        .and_then(from_csl_date)
+        .unwrap_or_default();
+
+    let doc = new_document(item_type, &id);
+    let mut doc_mut = doc.borrow_mut();
+    doc_mut.item_data.title = get("title").unwrap_or_default();
+    doc_mut.item_data.abstracttext = get("abstract").unwrap_or_default();
+    doc_mut.item_data.pubdate = date;
+    doc_mut.citation_key = id;
+    for (variable, value) in item {
+        if let Some(field) = zotero_field(variable, item_type) {
+            if let Some(value) = value_to_string(value) {
+                doc_mut.fields.entry(field.to_string()).or_insert(value);
+            }
+        }
+    }
+    for (zotero_field, csl_variable) in DATE_FIELDS {
+        if let Some(date) = item.get(csl_variable).and_then(from_csl_date) {
+            doc_mut.fields.insert(zotero_field.to_string(), date);
+        }
+    }
+    if let Some(note) = get("note") {
+        doc_mut.fields.insert("extra".to_string(), note);
+    }
+
+    let roles = std::iter::once(("author", primary_creator_type(item_type))).chain(
+        CREATOR_ROLES
+            .iter()
+            .map(|(zotero, csl)| (*csl, *zotero))
+            .filter(|(_, zotero)| *zotero != "castMember" && *zotero != "counsel"),
+    );
+    for (variable, creator_type) in roles {
+        if let Some(names) = item.get(variable).and_then(|names| names.as_array()) {
+            doc_mut.creators.extend(
+                names
+                    .iter()
+                    .filter_map(|name| from_csl_name(name, creator_type)),
+            );
+        }
+    }
+    drop(doc_mut);
+    Ok(doc)
+}
+
+/// Parse a CSL-JSON array (or a single item) into documents.
+pub fn parse(input: &str) -> anyhow::Result<Vec<RcDoc>> {
+    let value: Value = serde_json::from_str(input)?;
+    match &value {
+        Value::Array(items) => items.iter().map(from_csl_item).collect(),
+        Value::Object(_) => Ok(vec![from_csl_item(&value)?]),
+        _ => Err(anyhow!("Expected a CSL-JSON array of items")),
+    }
+}
+
+pub fn read_file(path: &Path) -> anyhow::Result<Vec<RcDoc>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::new_test_document;
+
+    fn creator(first: &str, last: &str, role: &str) -> Creator {
+        Creator {
+            firstName: Some(first.to_string()),
+            lastName: Some(last.to_string()),
+            creatorType: Some(role.to_string()),
+            fieldMode: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_csl_names() {
+        assert_eq!(
+            to_csl_name(&creator("Ludwig van", "Beethoven", "author")),
+            json!({"family": "Beethoven", "given": "Ludwig", "dropping-particle": "van"})
+        );
+        assert_eq!(
+            to_csl_name(&creator("Johannes Diderik", "van der Waals", "author")),
+            json!({"family": "Waals", "given": "Johannes Diderik", "non-dropping-particle": "van der"})
+        );
+        assert_eq!(
+            to_csl_name(&creator("Martin Luther, Jr.", "King", "author")),
+            json!({"family": "King", "given": "Martin Luther", "suffix": "Jr."})
+        );
+        for original in [
+            creator("Ludwig van", "Beethoven", "author"),
+            creator("Johannes Diderik", "van der Waals", "editor"),
+            creator("Martin Luther, Jr.", "King", "author"),
+        ] {
+            let name = to_csl_name(&original);
+            assert_eq!(
+                from_csl_name(&name, original.role()),
+                Some(original.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_csl_round_trip() {
+        let doc = new_test_document(
+            "bookSection",
+            &[
+                ("title", "A chapter"),
+                ("date", "2019-03-00 March 2019"),
+                ("bookTitle", "A book"),
+                ("pages", "10-20"),
+                ("publisher", "Springer"),
+                ("DOI", "10.1000/xyz"),
+            ],
+            vec![
+                creator("Jane", "Doe", "author"),
+                creator("John", "Roe", "editor"),
+            ],
+        );
+        let item = to_csl_item(&doc.borrow());
+        assert_eq!(item["type"], "chapter");
+        assert_eq!(item["container-title"], "A book");
+        assert_eq!(item["issued"], json!({"date-parts": [[2019, 3]]}));
+        assert_eq!(item["editor"], json!([{"family": "Roe", "given": "John"}]));
+
+        let imported = from_csl_item(&item).unwrap();
+        let imported = imported.borrow();
+        assert_eq!(imported.item_data.typeName, "bookSection");
+        assert_eq!(imported.get_title(), "A chapter");
+        assert_eq!(imported.get_citation_key(), "ABCD1234");
+        assert_eq!(imported.get_field("bookTitle"), Some("A book"));
+        assert_eq!(imported.get_field("DOI"), Some("10.1000/xyz"));
+        assert_eq!(imported.get_date_parts(), doc.borrow().get_date_parts());
+        assert_eq!(imported.creators, doc.borrow().creators);
+    }
+
+    #[test]
+    fn test_parse() {
+        let docs = parse(
+            r#"[{"id": "smith2020", "type": "article-journal", "title": "T",
+                 "issued": {"raw": "Spring 2020"}, "volume": 12,
+                 "author": [{"literal": "ACME Corp."}]}]"#,
+        )
+        .unwrap();
+        let doc = docs[0].borrow();
+        assert_eq!(doc.item_data.typeName, "journalArticle");
+        assert_eq!(doc.get_year(), "2020");
+        assert_eq!(doc.get_field("volume"), Some("12"));
+        assert!(doc.creators[0].is_single_field());
+        assert!(parse("{}").is_err());
+    }
+}
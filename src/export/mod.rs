@@ -0,0 +1,344 @@
+pub mod bibtex;
+pub mod citation_key;
+pub mod cited_keys;
+pub mod csl_json;
+pub mod ris;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    citeproc::{self, OutputFormat},
+    data_structures::{Creator, Document, ItemData, RcDoc},
+    metadata::strip_doi_prefix,
+    user_config::UserConfig,
+};
+
+/// Formats the selected documents can be copied or exported as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    CitationKey,
+    BibTeX,
+    BibLaTeX,
+    CslJson,
+    Ris,
+    Pandoc,
+    Reference,
+}
+
+/// Which documents an export covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportScope {
+    Selected,
+    /// The collection under the cursor and all its sub-collections
+    Collection,
+    SearchResults,
+}
+
+impl ExportScope {
+    pub fn all() -> Vec<ExportScope> {
+        vec![
+            ExportScope::Selected,
+            ExportScope::Collection,
+            ExportScope::SearchResults,
+        ]
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportScope::Selected => "Selected documents",
+            ExportScope::Collection => "Current collection (recursive)",
+            ExportScope::SearchResults => "Search results",
+        }
+    }
+}
+
+/// Settings needed by exporters that reference files on disk
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub zotero_storage_dir: Option<PathBuf>,
+    pub base_attachment_dir: Option<PathBuf>,
+    /// CSL style for formatted references, `format_reference` is used without one
+    pub csl_style: Option<PathBuf>,
+}
+
+impl ExportOptions {
+    pub fn from_config(user_config: &UserConfig) -> Self {
+        Self {
+            zotero_storage_dir: Some(user_config.behavior.zotero_storage_dir.clone()),
+            base_attachment_dir: user_config.behavior.base_attachment_dir.clone(),
+            csl_style: citeproc::find_style(
+                &user_config.behavior.csl_styles_dir,
+                &user_config.behavior.csl_style,
+            ),
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn all() -> Vec<ExportFormat> {
+        vec![
+            ExportFormat::CitationKey,
+            ExportFormat::BibTeX,
+            ExportFormat::BibLaTeX,
+            ExportFormat::CslJson,
+            ExportFormat::Ris,
+            ExportFormat::Pandoc,
+            ExportFormat::Reference,
+        ]
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::CitationKey => "Citation key",
+            ExportFormat::BibTeX => "BibTeX entry",
+            ExportFormat::BibLaTeX => "BibLaTeX entry",
+            ExportFormat::CslJson => "CSL-JSON",
+            ExportFormat::Ris => "RIS",
+            ExportFormat::Pandoc => "Pandoc citation",
+            ExportFormat::Reference => "Formatted reference",
+        }
+    }
+    /// Short name used on the command line
+    pub fn id(&self) -> &'static str {
+        match self {
+            ExportFormat::CitationKey => "key",
+            ExportFormat::BibTeX => "bibtex",
+            ExportFormat::BibLaTeX => "biblatex",
+            ExportFormat::CslJson => "csl-json",
+            ExportFormat::Ris => "ris",
+            ExportFormat::Pandoc => "pandoc",
+            ExportFormat::Reference => "reference",
+        }
+    }
+    /// Parse a format from its `id`, ignoring case
+    pub fn from_id(id: &str) -> Option<ExportFormat> {
+        let id = id.to_lowercase();
+        ExportFormat::all()
+            .into_iter()
+            .find(|format| format.id() == id)
+    }
+    /// Guess the file format from the extension of `path`
+    pub fn from_path(path: &Path, prefer_biblatex: bool) -> Option<ExportFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "bib" if prefer_biblatex => Some(ExportFormat::BibLaTeX),
+            "bib" | "bibtex" => Some(ExportFormat::BibTeX),
+            "biblatex" => Some(ExportFormat::BibLaTeX),
+            "json" => Some(ExportFormat::CslJson),
+            "ris" => Some(ExportFormat::Ris),
+            "txt" => Some(ExportFormat::Reference),
+            _ => None,
+        }
+    }
+}
+
+/// Render `docs` in the given format, one entry per document.
+pub fn export_documents(
+    docs: &[RcDoc],
+    format: ExportFormat,
+    options: &ExportOptions,
+) -> anyhow::Result<String> {
+    if format == ExportFormat::Reference {
+        if let Some(path) = &options.csl_style {
+            let style = citeproc::load_style(path)?;
+            let bibliography = citeproc::render_documents(&style, docs, OutputFormat::Text);
+            return Ok(bibliography.entries.join("\n"));
+        }
+    }
+    let docs: Vec<_> = docs.iter().map(|doc| doc.borrow()).collect();
+    let out = match format {
+        ExportFormat::CitationKey => docs
+            .iter()
+            .map(|doc| doc.get_citation_key().to_owned())
+            .collect::<Vec<String>>()
+            .join(", "),
+        ExportFormat::BibTeX | ExportFormat::BibLaTeX => {
+            let dialect = if format == ExportFormat::BibTeX {
+                bibtex::Dialect::BibTeX
+            } else {
+                bibtex::Dialect::BibLaTeX
+            };
+            docs.iter()
+                .map(|doc| bibtex::write_entry(doc, dialect, options))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        ExportFormat::CslJson => {
+            let items: Vec<_> = docs.iter().map(|doc| csl_json::to_csl_item(doc)).collect();
+            serde_json::to_string_pretty(&items)?
+        }
+        ExportFormat::Ris => docs
+            .iter()
+            .map(|doc| ris::write_entry(doc, options))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        ExportFormat::Pandoc => format!(
+            "[{}]",
+            docs.iter()
+                .map(|doc| format!("@{}", doc.get_citation_key()))
+                .collect::<Vec<String>>()
+                .join("; ")
+        ),
+        ExportFormat::Reference => docs
+            .iter()
+            .map(|doc| format_reference(doc))
+            .collect::<Vec<String>>()
+            .join("\n"),
+    };
+    Ok(out)
+}
+
+/// Read documents from a file in one of the formats that can be imported.
+pub fn import_from_file(path: &Path) -> anyhow::Result<Vec<RcDoc>> {
+    match ExportFormat::from_path(path, false) {
+        Some(ExportFormat::CslJson) => csl_json::read_file(path),
+        Some(ExportFormat::Ris) => ris::read_file(path),
+        Some(ExportFormat::BibTeX | ExportFormat::BibLaTeX) => bibtex::read_file(path),
+        _ => Err(anyhow::anyhow!(
+            "Cannot import {}, use a .json, .ris or .bib file",
+            path.display()
+        )),
+    }
+}
+
+/// An empty document that only exists in memory, e.g. read from a file. Its `itemId` is
+/// assigned when it is added to the library, see `App::merge_documents`.
+pub fn new_document(item_type: &str, key: &str) -> RcDoc {
+    Vec::<RcDoc>::from_iter(vec![ItemData {
+        itemId: 0,
+        libraryId: 0,
+        title: String::new(),
+        abstracttext: String::new(),
+        pubdate: String::new(),
+        key: key.to_string(),
+        dateAdded: String::new(),
+        typeName: item_type.to_string(),
+    }])
+    .remove(0)
+}
+
+/// Export `docs` to `path`, returning the number of exported documents.
+pub fn export_to_file(
+    docs: &[RcDoc],
+    format: ExportFormat,
+    options: &ExportOptions,
+    path: &Path,
+) -> anyhow::Result<usize> {
+    let mut content = export_documents(docs, format, options)?;
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    fs::write(path, content)?;
+    Ok(docs.len())
+}
+
+fn format_creator_initials(creator: &Creator) -> String {
+    let last = creator.lastName.as_deref().unwrap_or_default();
+    let initials: Vec<String> = creator
+        .firstName
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|name| name.chars().next())
+        .map(|c| format!("{}.", c))
+        .collect();
+    if initials.is_empty() {
+        last.to_owned()
+    } else {
+        format!("{}, {}", last, initials.join(" "))
+    }
+}
+
+/// A plain text, APA-like reference used when no CSL style is available.
+pub fn format_reference(doc: &Document) -> String {
+    let authors: Vec<String> = doc.get_authors().map(format_creator_initials).collect();
+    let mut out = match authors.len() {
+        0 => String::new(),
+        1 => authors[0].to_owned(),
+        n => format!("{}, & {}", authors[..n - 1].join(", "), authors[n - 1]),
+    };
+    let year = doc.get_year().trim();
+    if !year.is_empty() {
+        out.push_str(&format!(" ({})", year));
+    }
+    if !out.is_empty() {
+        out.push_str(". ");
+    }
+    out.push_str(doc.get_title().trim_end_matches('.'));
+    out.push('.');
+    if let Some(container) = doc.get_field("publicationTitle") {
+        out.push_str(&format!(" {}.", container));
+    }
+    let doi = doc
+        .get_field("DOI")
+        .map(strip_doi_prefix)
+        .unwrap_or_default();
+    if !doi.is_empty() {
+        out.push_str(&format!(" https://doi.org/{}", doi));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::new_test_document;
+
+    fn creator(first: &str, last: &str) -> Creator {
+        Creator {
+            firstName: Some(first.to_string()),
+            lastName: Some(last.to_string()),
+            creatorType: Some("author".to_string()),
+            fieldMode: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_export_documents() {
+        let options = ExportOptions::default();
+        let doc = new_test_document(
+            "journalArticle",
+            &[
+                ("title", "A study"),
+                ("date", "2020-00-00 2020"),
+                ("publicationTitle", "Nature"),
+            ],
+            vec![creator("Jane Ann", "Doe"), creator("John", "Roe")],
+        );
+        assert_eq!(
+            export_documents(std::slice::from_ref(&doc), ExportFormat::Pandoc, &options).unwrap(),
+            "[@ABCD1234]"
+        );
+        assert_eq!(
+            export_documents(
+                std::slice::from_ref(&doc),
+                ExportFormat::Reference,
+                &options
+            )
+            .unwrap(),
+            "Doe, J. A., & Roe, J. (2020). A study. Nature."
+        );
+        let bibtex = export_documents(&[doc], ExportFormat::BibTeX, &options).unwrap();
+        assert!(bibtex.starts_with("@article{ABCD1234,"));
+        assert!(bibtex.contains("author = {Doe, Jane Ann and Roe, John}"));
+        let linked = new_test_document(
+            "journalArticle",
+            &[("title", "Linked"), ("DOI", "https://doi.org/10.1000/xyz")],
+            vec![],
+        );
+        assert_eq!(
+            export_documents(&[linked], ExportFormat::Reference, &options).unwrap(),
+            "Linked. https://doi.org/10.1000/xyz"
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("refs.bib"), false),
+            Some(ExportFormat::BibTeX)
+        );
+        assert_eq!(
+            ExportFormat::from_id("CSL-JSON"),
+            Some(ExportFormat::CslJson)
+        );
+        assert_eq!(ExportFormat::from_id("docx"), None);
+    }
+}
@@ -0,0 +1,528 @@
+use std::{fs, path::Path};
+
+use anyhow::anyhow;
+
+use super::{csl_json, new_document, ExportOptions};
+use crate::data_structures::{
+    Attachment, Creator, DateParts, Document, RcDoc, Tag, LINK_MODE_LINKED_FILE,
+    LINK_MODE_LINKED_URL, PRIMARY_CREATOR_TYPES,
+};
+
+/// Zotero item type to RIS `TY`. When importing, the first Zotero type of a RIS type wins.
+const ITEM_TYPES: [(&str, &str); 31] = [
+    ("journalArticle", "JOUR"),
+    ("magazineArticle", "MGZN"),
+    ("newspaperArticle", "NEWS"),
+    ("book", "BOOK"),
+    ("bookSection", "CHAP"),
+    ("conferencePaper", "CONF"),
+    ("thesis", "THES"),
+    ("report", "RPRT"),
+    ("webpage", "ELEC"),
+    ("blogPost", "BLOG"),
+    ("encyclopediaArticle", "ENCYC"),
+    ("dictionaryEntry", "DICT"),
+    ("manuscript", "MANSCPT"),
+    ("preprint", "UNPB"),
+    ("letter", "PCOMM"),
+    ("email", "ICOMM"),
+    ("instantMessage", "ICOMM"),
+    ("patent", "PAT"),
+    ("computerProgram", "COMP"),
+    ("dataset", "DATA"),
+    ("map", "MAP"),
+    ("film", "MPCT"),
+    ("videoRecording", "VIDEO"),
+    ("audioRecording", "SOUND"),
+    ("artwork", "ART"),
+    ("case", "CASE"),
+    ("statute", "STAT"),
+    ("bill", "BILL"),
+    ("hearing", "HEAR"),
+    ("standard", "STAND"),
+    ("document", "GEN"),
+];
+
+/// RIS tags holding a single field, by the CSL variable of that field. The Zotero field is
+/// looked up through the CSL mapping so type-specific fields (`bookTitle`) are honored.
+const FIELD_TAGS: [(&str, &str); 16] = [
+    ("T2", "container-title"),
+    ("T3", "collection-title"),
+    ("J2", "container-title-short"),
+    ("ST", "title-short"),
+    ("VL", "volume"),
+    ("IS", "issue"),
+    ("ET", "edition"),
+    ("PB", "publisher"),
+    ("CY", "publisher-place"),
+    ("M3", "genre"),
+    ("M1", "number"),
+    ("DO", "DOI"),
+    ("UR", "URL"),
+    ("LA", "language"),
+    ("CN", "call-number"),
+    ("AB", "abstract"),
+];
+
+/// Creator roles to RIS tags. `AU` holds the item type's primary creators.
+const CREATOR_TAGS: [(&str, &str); 4] = [
+    ("author", "AU"),
+    ("editor", "A2"),
+    ("seriesEditor", "A3"),
+    ("translator", "A4"),
+];
+
+fn ris_type(item_type: &str) -> &'static str {
+    ITEM_TYPES
+        .iter()
+        .find(|(zotero, _)| *zotero == item_type)
+        .map(|(_, ris)| *ris)
+        .unwrap_or("GEN")
+}
+
+fn zotero_type(ris_type: &str) -> &'static str {
+    match ris_type {
+        "JFULL" | "EJOUR" => "journalArticle",
+        "CPAPER" => "conferencePaper",
+        "EBOOK" => "book",
+        "ECHAP" => "bookSection",
+        "WEB" => "webpage",
+        _ => ITEM_TYPES
+            .iter()
+            .find(|(_, ris)| *ris == ris_type)
+            .map(|(zotero, _)| *zotero)
+            .unwrap_or("document"),
+    }
+}
+
+fn creator_tag(role: &str) -> Option<&'static str> {
+    if PRIMARY_CREATOR_TYPES.contains(&role) {
+        return Some("AU");
+    }
+    CREATOR_TAGS
+        .iter()
+        .find(|(zotero, _)| *zotero == role)
+        .map(|(_, tag)| *tag)
+}
+
+fn is_book(item_type: &str) -> bool {
+    matches!(item_type, "book" | "bookSection")
+}
+
+/// `YYYY/MM/DD/`, with empty parts when unknown
+fn format_date(date: &str) -> Option<String> {
+    let parts = DateParts::parse(date);
+    let part = |value: Option<u32>| value.map(|value| format!("{:02}", value));
+    Some(format!(
+        "{:04}/{}/{}/",
+        parts.year?,
+        part(parts.month).unwrap_or_default(),
+        part(parts.day).unwrap_or_default()
+    ))
+}
+
+/// Parse `YYYY/MM/DD/other`, or anything starting with a year, into Zotero's date format
+fn parse_date(value: &str) -> Option<String> {
+    let mut parts = value.split('/');
+    let mut part = || {
+        parts
+            .next()
+            .and_then(|part| part.trim().parse::<u32>().ok())
+            .filter(|part| *part != 0)
+    };
+    let year = part()?;
+    DateParts {
+        year: Some(year as i32),
+        month: part(),
+        day: part(),
+    }
+    .to_zotero_date(None)
+}
+
+fn format_creator(creator: &Creator) -> String {
+    let last = creator.lastName.as_deref().unwrap_or_default();
+    if creator.is_single_field() {
+        last.to_owned()
+    } else {
+        format!(
+            "{}, {}",
+            last,
+            creator.firstName.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+fn parse_creator(value: &str, creator_type: &str) -> Creator {
+    match value.split_once(',') {
+        Some((last, first)) => Creator {
+            firstName: Some(first.trim().to_string()),
+            lastName: Some(last.trim().to_string()),
+            creatorType: Some(creator_type.to_string()),
+            fieldMode: Some(0),
+        },
+        None => Creator {
+            firstName: None,
+            lastName: Some(value.to_string()),
+            creatorType: Some(creator_type.to_string()),
+            fieldMode: Some(1),
+        },
+    }
+}
+
+/// Write one RIS record. Attachment files are written as `L1`, linked URLs as `L2`.
+pub fn write_entry(doc: &Document, options: &ExportOptions) -> String {
+    let item_type = doc.item_data.typeName.as_str();
+    let mut lines = vec![
+        ("TY", ris_type(item_type).to_owned()),
+        ("ID", doc.get_citation_key().to_owned()),
+    ];
+    if !doc.get_title().is_empty() {
+        lines.push(("TI", doc.get_title().to_owned()));
+    }
+
+    let mut creators: Vec<(&str, String)> = doc
+        .get_creators()
+        .filter_map(|creator| Some((creator_tag(creator.role())?, format_creator(creator))))
+        .collect();
+    // Stable, so creators keep their order within a role
+    creators.sort_by_key(|(tag, _)| CREATOR_TAGS.iter().position(|(_, t)| t == tag));
+    lines.extend(creators);
+
+    if let Some(date) = format_date(&doc.item_data.pubdate) {
+        lines.push(("PY", date[..4].to_owned()));
+        lines.push(("DA", date));
+    }
+    if let Some(date) = doc.get_field("accessDate").and_then(format_date) {
+        lines.push(("Y2", date));
+    }
+    if let Some(pages) = doc.get_field("pages") {
+        let mut pages = pages.splitn(2, ['-', '–']);
+        if let Some(start) = pages.next().filter(|start| !start.trim().is_empty()) {
+            lines.push(("SP", start.trim().to_owned()));
+        }
+        if let Some(end) = pages.next().filter(|end| !end.trim().is_empty()) {
+            lines.push(("EP", end.trim().to_owned()));
+        }
+    }
+    for (tag, variable) in FIELD_TAGS {
+        let value = csl_json::fields_for_variable(variable).find_map(|field| {
+            doc.get_field(field)
+                .filter(|value| !value.trim().is_empty())
+        });
+        if let Some(value) = value {
+            lines.push((tag, value.trim().to_owned()));
+        }
+    }
+    let serial = if is_book(item_type) { "ISBN" } else { "ISSN" };
+    if let Some(serial) = doc.get_field(serial) {
+        lines.push(("SN", serial.to_owned()));
+    }
+    for tag in &doc.tags {
+        lines.push(("KW", tag.name.to_owned()));
+    }
+    if let Some(extra) = doc
+        .get_field("extra")
+        .filter(|extra| !extra.trim().is_empty())
+    {
+        lines.push(("N1", extra.trim().to_owned()));
+    }
+
+    if let (Some(storage_dir), Some(attachments)) = (&options.zotero_storage_dir, &doc.attachments)
+    {
+        for attachment in &attachments.items {
+            let resolved = attachment.resolve(storage_dir, options.base_attachment_dir.as_deref());
+            match (resolved.path, resolved.url) {
+                (Some(path), _) => lines.push(("L1", path.to_string_lossy().into_owned())),
+                (None, Some(url)) => lines.push(("L2", url)),
+                _ => {}
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for (tag, value) in lines {
+        // RIS values are single lines
+        out.push_str(&format!("{}  - {}\n", tag, value.replace('\n', " ")));
+    }
+    out.push_str("ER  - \n");
+    out
+}
+
+/// Content type of a file by its extension, for the common attachment formats
+pub fn content_type_for_path(path: &str) -> Option<String> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+    let content_type = match extension.as_str() {
+        "pdf" => "application/pdf",
+        "epub" => "application/epub+zip",
+        "html" | "htm" => "text/html",
+        "djvu" => "image/vnd.djvu",
+        _ => return None,
+    };
+    Some(content_type.to_string())
+}
+
+fn new_attachment(link_mode: i64, path: Option<String>, url: Option<String>) -> Attachment {
+    Attachment {
+        itemId: 0,
+        linkMode: Some(link_mode),
+        contentType: path.as_deref().and_then(content_type_for_path),
+        path,
+        key: None,
+        url,
+    }
+}
+
+/// Parse RIS records into documents. Records without `ID` get a citation key once they are
+/// added to the library.
+pub fn parse(input: &str) -> anyhow::Result<Vec<RcDoc>> {
+    let mut records: Vec<Vec<(String, String)>> = Vec::new();
+    let mut current: Option<Vec<(String, String)>> = None;
+    for line in input.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim_end();
+        let tag = line
+            .get(..2)
+            .filter(|_| is_separator(line.get(2..).unwrap_or_default()));
+        let value = line.get(6..).unwrap_or_default().trim().to_string();
+        match (tag, current.as_mut()) {
+            (Some("TY"), _) => current = Some(vec![("TY".to_string(), value)]),
+            (Some("ER"), Some(_)) => records.extend(current.take()),
+            (Some(tag), Some(record)) => record.push((tag.to_string(), value)),
+            // Continuation of a long value
+            (None, Some(record)) if !line.is_empty() => {
+                if let Some((_, value)) = record.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            }
+            _ => {}
+        }
+    }
+    if current.is_some() {
+        return Err(anyhow!("RIS record without ER"));
+    }
+    Ok(records
+        .iter()
+        .enumerate()
+        .map(|(idx, record)| from_record(idx, record))
+        .collect())
+}
+
+/// Tags are separated from their value by `  - `, empty values may lack the trailing space
+fn is_separator(rest: &str) -> bool {
+    rest.starts_with("  - ") || rest == "  -"
+}
+
+fn from_record(idx: usize, record: &[(String, String)]) -> RcDoc {
+    let get = |tag: &str| {
+        record
+            .iter()
+            .find(|(t, value)| t == tag && !value.is_empty())
+            .map(|(_, value)| value.as_str())
+    };
+    let item_type = zotero_type(get("TY").unwrap_or_default());
+    let id = get("ID");
+    let doc = new_document(
+        item_type,
+        &id.map_or_else(|| format!("RIS{}", idx + 1), |id| id.to_string()),
+    );
+    let mut doc_mut = doc.borrow_mut();
+    doc_mut.citation_key = id.unwrap_or_default().to_string();
+
+    let mut start_page = None;
+    let mut end_page = None;
+    let mut attachments = Vec::new();
+    for (tag, value) in record {
+        if value.is_empty() {
+            continue;
+        }
+        let field = match tag.as_str() {
+            "TI" | "T1" => Some("title"),
+            "AB" | "N2" => Some("abstractNote"),
+            "JO" | "JF" => csl_json::zotero_field("container-title", item_type),
+            "SN" if is_book(item_type) => Some("ISBN"),
+            "SN" => Some("ISSN"),
+            "N1" => Some("extra"),
+            tag => FIELD_TAGS
+                .iter()
+                .find(|(t, _)| *t == tag)
+                .and_then(|(_, variable)| csl_json::zotero_field(variable, item_type)),
+        };
+        if let Some(field) = field {
+            doc_mut
+                .fields
+                .entry(field.to_string())
+                .or_insert_with(|| value.to_owned());
+            continue;
+        }
+        match tag.as_str() {
+            "AU" | "A1" => doc_mut.creators.push(parse_creator(
+                value,
+                csl_json::primary_creator_type(item_type),
+            )),
+            "A2" | "ED" => doc_mut.creators.push(parse_creator(value, "editor")),
+            "A3" => doc_mut.creators.push(parse_creator(value, "seriesEditor")),
+            "A4" => doc_mut.creators.push(parse_creator(value, "translator")),
+            // `DA` holds the full date, `PY` only the year. Dates that can't be read don't
+            // replace one that could.
+            "DA" => {
+                if let Some(date) = parse_date(value) {
+                    doc_mut.item_data.pubdate = date;
+                }
+            }
+            "PY" | "Y1" if doc_mut.item_data.pubdate.is_empty() => {
+                doc_mut.item_data.pubdate = parse_date(value).unwrap_or_default();
+            }
+            "Y2" => {
+                if let Some(date) = parse_date(value) {
+                    doc_mut.fields.insert("accessDate".to_string(), date);
+                }
+            }
+            "SP" => start_page = Some(value.to_owned()),
+            "EP" => end_page = Some(value.to_owned()),
+            "KW" => doc_mut.tags.push(Tag {
+                tagId: 0,
+                name: value.to_owned(),
+            }),
+            "L1" | "L4" => {
+                let path = value.trim_start_matches("file://").to_string();
+                attachments.push(new_attachment(LINK_MODE_LINKED_FILE, Some(path), None));
+            }
+            "L2" => attachments.push(new_attachment(
+                LINK_MODE_LINKED_URL,
+                None,
+                Some(value.to_owned()),
+            )),
+            _ => {}
+        }
+    }
+
+    let title = doc_mut.get_field("title").unwrap_or_default().to_owned();
+    doc_mut.item_data.title = title;
+    let abstract_note = doc_mut
+        .get_field("abstractNote")
+        .unwrap_or_default()
+        .to_owned();
+    doc_mut.item_data.abstracttext = abstract_note;
+    let date = doc_mut.item_data.pubdate.clone();
+    if !date.is_empty() {
+        doc_mut.fields.insert("date".to_string(), date);
+    }
+    let pages = match (start_page, end_page) {
+        (Some(start), Some(end)) => Some(format!("{}-{}", start, end)),
+        (start, end) => start.or(end),
+    };
+    if let Some(pages) = pages {
+        doc_mut.fields.insert("pages".to_string(), pages);
+    }
+    if !attachments.is_empty() {
+        doc_mut.attachments = Some(crate::data_structures::StatefulList::with_items(
+            attachments,
+        ));
+    }
+    drop(doc_mut);
+    doc
+}
+
+pub fn read_file(path: &Path) -> anyhow::Result<Vec<RcDoc>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("fixtures/items.ris");
+
+    fn options() -> ExportOptions {
+        ExportOptions {
+            zotero_storage_dir: Some("/zotero/storage".into()),
+            base_attachment_dir: None,
+            csl_style: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_fixture() {
+        let docs = parse(FIXTURE).unwrap();
+        assert_eq!(docs.len(), 3);
+
+        let article = docs[0].borrow();
+        assert_eq!(article.item_data.typeName, "journalArticle");
+        assert_eq!(article.get_citation_key(), "vanderwaals1873");
+        assert_eq!(
+            article.get_title(),
+            "On the continuity of the gaseous and liquid states"
+        );
+        assert_eq!(
+            article.creators[0].lastName.as_deref(),
+            Some("van der Waals")
+        );
+        assert_eq!(article.get_year(), "1873");
+        assert_eq!(
+            article.get_field("publicationTitle"),
+            Some("Annalen der Physik")
+        );
+        assert_eq!(article.get_field("pages"), Some("10-25"));
+        assert_eq!(article.tags.len(), 2);
+
+        let chapter = docs[1].borrow();
+        assert_eq!(chapter.item_data.typeName, "bookSection");
+        assert_eq!(chapter.get_field("bookTitle"), Some("Handbook of Things"));
+        assert_eq!(chapter.get_field("ISBN"), Some("978-3-16-148410-0"));
+        assert_eq!(chapter.creators[1].role(), "editor");
+        assert_eq!(
+            chapter.get_date_parts(),
+            DateParts {
+                year: Some(2019),
+                month: Some(3),
+                day: Some(15)
+            }
+        );
+
+        let thesis = docs[2].borrow();
+        assert_eq!(
+            thesis.get_field("university"),
+            Some("University of Somewhere")
+        );
+        assert!(thesis.creators[0].is_single_field());
+        let attachment = &thesis.attachments.as_ref().unwrap().items[0];
+        assert_eq!(attachment.linkMode, Some(LINK_MODE_LINKED_FILE));
+        assert_eq!(attachment.content_type(), "application/pdf");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let docs = parse(FIXTURE).unwrap();
+        let written: Vec<String> = docs
+            .iter()
+            .map(|doc| write_entry(&doc.borrow(), &options()))
+            .collect();
+        assert_eq!(written.join("\n"), FIXTURE);
+
+        let reparsed = parse(&written.join("\n")).unwrap();
+        for (doc, again) in docs.iter().zip(&reparsed) {
+            let (doc, again) = (doc.borrow(), again.borrow());
+            assert_eq!(doc.item_data.typeName, again.item_data.typeName);
+            assert_eq!(doc.item_data.pubdate, again.item_data.pubdate);
+            assert_eq!(doc.fields, again.fields);
+            assert_eq!(doc.creators, again.creators);
+            assert_eq!(doc.tags, again.tags);
+        }
+    }
+
+    #[test]
+    fn test_unparsable_date() {
+        let docs = parse(include_str!("fixtures/unparsable_date.ris")).unwrap();
+        let doc = docs[0].borrow();
+        assert_eq!(doc.item_data.pubdate, "1999-00-00 1999");
+        assert_eq!(doc.get_year(), "1999");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("TY  - JOUR\nTI  - Unterminated\n").is_err());
+        let docs = parse("TY  - GEN\nTI  - A long\n  title\nER  -\n").unwrap();
+        assert_eq!(docs[0].borrow().get_title(), "A long title");
+        assert_eq!(docs[0].borrow().item_data.key, "RIS1");
+    }
+}
@@ -1,30 +1,1684 @@
-use std::{path::Path, process::Command};
-
-use crate::App;
-
-// TODO: Should use user_config instead of viewer
-pub async fn handle_enter(
-    app: &App,
-    zotero_storage_dir: &Path,
-    viewer: &str,
-) -> anyhow::Result<()> {
-    if let Some(selected) = app.get_selected_doc() {
-        match &selected.borrow().attachments {
-            Some(attachments) => {
-                for att in &attachments.items {
-                    // TODO: Choose between different attachments
-                    if att.contentType.as_ref().unwrap().contains("pdf") {
-                        let path = zotero_storage_dir
-                            .join(&att.key.as_ref().unwrap())
-                            .join(&att.path.as_ref().unwrap()[":storage".len()..]);
-                        Command::new(viewer).arg(&path).spawn()?;
-                        break;
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    app::{
+        CollectionAction, CollectionPick, CollectionPicker, EditorRequest, NoteEdit, Preview,
+        PromptAction,
+    },
+    backend::load_library,
+    bib_sync::{collection_path, sync_binding, DbWatcher, SyncOutcome, SyncState},
+    citation_server::{self, CitationServer},
+    citeproc::{list_styles, load_style},
+    connector::StagingStore,
+    data_structures::{RcCollection, RcDoc, ResolvedAttachment, StatefulList},
+    db_connector::{apply_item_changes, NewItem},
+    edit_form::{EditForm, EditRow},
+    event::Key,
+    export::{
+        citation_key::assign_citation_keys, export_documents, export_to_file, import_from_file,
+        ExportFormat, ExportOptions, ExportScope,
+    },
+    metadata::{
+        parse_answer,
+        pdf::{propose_item, read_pdfs, PdfCandidate, PdfLookup},
+        read_records, Identifier,
+    },
+    note::{edit_in_editor, html_to_markdown, markdown_to_html, note_title},
+    opener::OpenContext,
+    tag_editor::{count_tags, push_tag, TagEditor},
+    ui::PopupType,
+    user_config::UserConfig,
+    worker::{Worker, WorkerPoll},
+    App,
+};
+
+fn open_attachment(app: &mut App, attachment: &ResolvedAttachment, user_config: &UserConfig) {
+    match &attachment.path {
+        Some(path) if attachment.size.is_some() => {
+            let path_str = path.to_string_lossy();
+            let ctx = OpenContext {
+                path: &path_str,
+                page: None,
+                key: attachment.attachment.key.as_deref().unwrap_or_default(),
+            };
+            match user_config
+                .openers
+                .open(attachment.attachment.contentType.as_deref(), &ctx)
+            {
+                Ok(()) => app.set_status(format!("Opened {}", path.display())),
+                Err(err) => app.set_status(err.to_string()),
+            }
+        }
+        _ if attachment.url.is_some() => {
+            let url = attachment.url.as_deref().unwrap();
+            match user_config.openers.open_url(url) {
+                Ok(()) => app.set_status(format!("Opened {}", url)),
+                Err(err) => app.set_status(err.to_string()),
+            }
+        }
+        Some(path) => app.set_status(format!("File not found: {}", path.display())),
+        None => app.set_status(format!(
+            "Cannot open attachment {}",
+            attachment.attachment.display_name()
+        )),
+    }
+}
+
+/// Run the command bound to `key` outside the search input. Returns whether `key` was bound.
+pub async fn handle_command_key(app: &mut App, key: Key, user_config: &UserConfig) -> bool {
+    let keys = &user_config.keys;
+    match key {
+        key if key == keys.column_chooser => app.open_popup(PopupType::ColumnChooser),
+        key if key == keys.open_url => handle_open_url(app, user_config),
+        key if key == keys.copy => {
+            app.copy_menu.state.select(Some(0));
+            app.open_popup(PopupType::CopyMenu);
+        }
+        key if key == keys.export => {
+            app.export_menu.state.select(Some(0));
+            app.open_popup(PopupType::ExportMenu);
+        }
+        key if key == keys.import => app.open_prompt(
+            "Import CSL-JSON, RIS or BibTeX file",
+            "",
+            PromptAction::Import,
+        ),
+        key if key == keys.bibliography => open_style_menu(app, user_config),
+        key if key == keys.sync => open_sync_prompt(app, user_config),
+        key if key == keys.edit => open_edit_form(app),
+        key if key == keys.file => open_collection_picker(app),
+        key if key == keys.unfile => remove_from_selected_collection(app).await,
+        key if key == keys.collection_menu => {
+            app.collection_menu.state.select(Some(0));
+            app.open_popup(PopupType::CollectionMenu);
+        }
+        key if key == keys.tags => open_tag_editor(app),
+        key if key == keys.tag_manager => open_tag_manager(app, None),
+        key if key == keys.note => open_note_picker(app),
+        key if key == keys.add_item => app.open_prompt(
+            "Add by DOI, ISBN or arXiv ID (empty to paste BibTeX or RIS)",
+            "",
+            PromptAction::AddItem,
+        ),
+        key if key == keys.mark => {
+            if let Some(doc) = app.get_selected_doc() {
+                doc.borrow().toggle_marked();
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Open the attachment under the cursor, or pick one of the selected document's attachments.
+pub async fn handle_enter(app: &mut App, user_config: &UserConfig) {
+    let selected = match app.get_selected_doc() {
+        Some(selected) => selected,
+        None => return,
+    };
+    let storage_dir = &user_config.behavior.zotero_storage_dir;
+    let attachments: Vec<ResolvedAttachment> = match &selected.borrow().attachments {
+        Some(attachments) => attachments
+            .items
+            .iter()
+            .map(|att| {
+                att.resolve(
+                    storage_dir,
+                    user_config.behavior.base_attachment_dir.as_deref(),
+                )
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if let Some(att_idx) = app.get_selected_attachment_idx() {
+        if let Some(attachment) = attachments.get(att_idx) {
+            open_attachment(app, attachment, user_config);
+            return;
+        }
+    }
+
+    let openable = attachments.iter().filter(|att| att.is_openable()).count();
+    if openable == 0 {
+        app.set_status(format!(
+            "No openable attachment for \"{}\"",
+            selected.borrow().get_title()
+        ));
+    } else if attachments.len() == 1 {
+        open_attachment(app, &attachments[0], user_config);
+    } else {
+        app.attachment_picker.items = attachments;
+        app.attachment_picker.state.select(Some(0));
+        app.open_popup(PopupType::AttachmentPicker);
+    }
+}
+
+/// Open the selected document's URL, or its DOI, in the browser.
+pub fn handle_open_url(app: &mut App, user_config: &UserConfig) {
+    let selected = match app.get_selected_doc() {
+        Some(selected) => selected,
+        None => return,
+    };
+    let url = selected.borrow().get_web_url();
+    match url {
+        Some(url) => match user_config.openers.open_url(&url) {
+            Ok(()) => app.set_status(format!("Opened {}", url)),
+            Err(err) => app.set_status(err.to_string()),
+        },
+        None => app.set_status(format!(
+            "No URL or DOI for \"{}\"",
+            selected.borrow().get_title()
+        )),
+    }
+}
+
+pub fn copy_marked_docs(app: &mut App, format: ExportFormat, user_config: &UserConfig) {
+    let docs = app.get_marked_docs();
+    if docs.is_empty() {
+        app.set_status("Nothing to copy");
+        return;
+    }
+    let copied = export_documents(&docs, format, &ExportOptions::from_config(user_config))
+        .and_then(|text| app.clipboard.copy(&text));
+    match copied {
+        Ok(method) => app.set_status(format!(
+            "Copied {} of {} document(s) to the {}",
+            format.name(),
+            docs.len(),
+            method
+        )),
+        Err(err) => app.set_status(format!("Cannot copy {}: {}", format.name(), err)),
+    }
+}
+
+pub fn handle_copy_menu_key(app: &mut App, key: Key, user_config: &UserConfig) {
+    match key {
+        Key::Esc => app.close_popup(),
+        Key::Down | Key::Char('j') => app.copy_menu.next(),
+        Key::Up | Key::Char('k') => app.copy_menu.previous(),
+        Key::Enter => {
+            let selected = app.copy_menu.state.selected().unwrap_or(0);
+            if let Some(format) = app.copy_menu.items.get(selected).copied() {
+                app.close_popup();
+                copy_marked_docs(app, format, user_config);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn handle_export_menu_key(app: &mut App, key: Key, user_config: &UserConfig) {
+    match key {
+        Key::Esc => app.close_popup(),
+        Key::Down | Key::Char('j') => app.export_menu.next(),
+        Key::Up | Key::Char('k') => app.export_menu.previous(),
+        Key::Enter => {
+            let selected = app.export_menu.state.selected().unwrap_or(0);
+            if let Some(scope) = app.export_menu.items.get(selected).copied() {
+                if scope == ExportScope::Collection && app.get_selected_collection().is_none() {
+                    app.close_popup();
+                    return app.set_status("Select a collection to export, not a library");
+                }
+                app.open_prompt(
+                    &format!("Export {} to", scope.name().to_lowercase()),
+                    &user_config.behavior.export_path.to_string_lossy(),
+                    PromptAction::Export(scope),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Export the documents of `scope` to `path`. An existing file is only replaced once the
+/// user confirmed it, with `overwrite` set.
+fn export_scope(
+    app: &mut App,
+    scope: ExportScope,
+    path: &str,
+    overwrite: bool,
+    user_config: &UserConfig,
+) {
+    let path = Path::new(path.trim());
+    if path.exists() && !overwrite {
+        return app.open_prompt(
+            &format!("{} exists, overwrite it? (y/n)", path.display()),
+            "",
+            PromptAction::OverwriteExport(scope, path.to_path_buf()),
+        );
+    }
+    let format = match ExportFormat::from_path(path, user_config.behavior.prefer_biblatex) {
+        Some(format) => format,
+        None => {
+            app.set_status(format!(
+                "Unknown export format for {}, use .bib, .json or .ris",
+                path.display()
+            ));
+            return;
+        }
+    };
+    let docs = app.get_docs_for_scope(scope);
+    match export_to_file(
+        &docs,
+        format,
+        &ExportOptions::from_config(user_config),
+        path,
+    ) {
+        Ok(count) => app.set_status(format!(
+            "Exported {} document(s) as {} to {}",
+            count,
+            format.name(),
+            path.display()
+        )),
+        Err(err) => app.set_status(format!("Export failed: {}", err)),
+    }
+}
+
+fn import_file(app: &mut App, path: &str) {
+    let path = Path::new(path.trim());
+    if path.is_dir() {
+        return open_pdf_import(app, path);
+    }
+    match import_from_file(path) {
+        Ok(docs) => {
+            let total = docs.len();
+            let added = app.merge_documents(docs);
+            app.set_status(format!(
+                "Imported {} of {} document(s) from {} ({} already in the library)",
+                added,
+                total,
+                path.display(),
+                total - added
+            ));
+        }
+        Err(err) => app.set_status(format!("Import failed: {}", err)),
+    }
+}
+
+/// Ask for the file to keep in sync with the collection under the cursor.
+pub fn open_sync_prompt(app: &mut App, user_config: &UserConfig) {
+    match app.get_selected_collection() {
+        Some(collection) => {
+            let path = collection_path(app, &collection);
+            app.open_prompt(
+                &format!("Keep {} in sync with", path),
+                &user_config.behavior.export_path.to_string_lossy(),
+                PromptAction::Sync(path),
+            );
+        }
+        None => app.set_status("Select a collection to sync"),
+    }
+}
+
+fn sync_state_path(user_config: &UserConfig) -> Option<&Path> {
+    user_config
+        .path_to_config
+        .as_ref()
+        .map(|paths| paths.sync_state_path.as_path())
+}
+
+/// Write the files in `paths` bound to a collection, loaded in `library`. Returns a status
+/// message for each written file or error.
+fn sync_files(library: &App, paths: &[PathBuf], user_config: &UserConfig) -> Vec<String> {
+    let state_path = match sync_state_path(user_config) {
+        Some(path) => path,
+        None => return vec!["Sync failed: no configuration directory".to_string()],
+    };
+    let mut state = match SyncState::load(state_path) {
+        Ok(state) => state,
+        Err(err) => return vec![format!("Sync failed: {}", err)],
+    };
+    let options = ExportOptions::from_config(user_config);
+    let mut messages = Vec::new();
+    for binding in state
+        .bindings
+        .iter_mut()
+        .filter(|binding| paths.contains(&binding.path))
+    {
+        match sync_binding(
+            library,
+            binding,
+            &options,
+            user_config.behavior.prefer_biblatex,
+        ) {
+            Ok(SyncOutcome::Written(count)) => messages.push(format!(
+                "Wrote {} document(s) of {} to {}",
+                count,
+                binding.collection,
+                binding.path.display()
+            )),
+            Ok(SyncOutcome::Unchanged(_)) => {}
+            Err(err) => messages.push(format!(
+                "Sync of {} failed: {}",
+                binding.path.display(),
+                err
+            )),
+        }
+    }
+    if let Err(err) = state.save(state_path) {
+        messages.push(format!("Cannot save sync state: {}", err));
+    }
+    messages
+}
+
+fn sync_collection(app: &mut App, collection: &str, path: &str, user_config: &UserConfig) {
+    let state_path = match sync_state_path(user_config) {
+        Some(state_path) => state_path,
+        None => return app.set_status("Sync failed: no configuration directory"),
+    };
+    let path = match SyncState::load(state_path) {
+        Ok(mut state) => {
+            let path = state.bind(collection, Path::new(path.trim())).path.clone();
+            if let Err(err) = state.save(state_path) {
+                return app.set_status(format!("Cannot save sync state: {}", err));
+            }
+            path
+        }
+        Err(err) => return app.set_status(format!("Sync failed: {}", err)),
+    };
+    let messages = sync_files(app, std::slice::from_ref(&path), user_config);
+    if !app.synced_files.contains(&path) {
+        app.synced_files.push(path.clone());
+    }
+    if app.db_watcher.is_none() {
+        app.db_watcher = Some(DbWatcher::new(user_config.behavior.library_path()));
+    }
+    match messages.last() {
+        Some(message) => app.set_status(message.to_owned()),
+        None => app.set_status(format!("{} is up to date", path.display())),
+    }
+}
+
+/// Rewrite the synced files when the Zotero database changed. The library is loaded
+/// again separately, so the documents shown don't change under the cursor.
+pub async fn handle_db_change(app: &mut App, user_config: &UserConfig) {
+    let changed = match app.db_watcher.as_mut() {
+        Some(watcher) => watcher.poll(),
+        None => false,
+    };
+    if !changed || app.synced_files.is_empty() {
+        return;
+    }
+    let mut library = App::default();
+    if let Err(err) = load_library(&mut library, user_config).await {
+        return app.set_status(format!("Cannot reload the library to sync: {}", err));
+    }
+    let mut messages = sync_files(&library, &app.synced_files, user_config);
+    if let Err(err) = library.backend.close().await {
+        messages.push(format!("Cannot close the reloaded library: {}", err));
+    }
+    if !messages.is_empty() {
+        app.set_status(messages.join("; "));
+    }
+}
+
+pub fn start_citation_server(app: &mut App, user_config: &UserConfig) {
+    match CitationServer::start(user_config.behavior.citation_server_port) {
+        Ok(server) => app.citation_server = Some(server),
+        Err(err) => app.set_status(format!("Citation server not started: {}", err)),
+    }
+}
+
+/// Answer waiting requests of editor plugins and the browser connector. "Cite as you
+/// write" cites the marked documents, or the one under the cursor.
+pub fn handle_citation_requests(app: &mut App, user_config: &UserConfig) {
+    let server = match &app.citation_server {
+        Some(server) => server,
+        None => return,
+    };
+    let options = ExportOptions::from_config(user_config);
+    let staging = StagingStore::from_config(user_config).filter(|_| user_config.behavior.connector);
+    let mut errors = Vec::new();
+    let mut staged = Vec::new();
+    while let Some(request) = server.try_next_request() {
+        let picked = app.get_marked_docs();
+        let result = citation_server::respond(
+            request,
+            &app.documents,
+            &mut || Ok(picked.clone()),
+            &options,
+            staging.as_ref(),
+        );
+        match result {
+            Ok(docs) => staged.extend(docs),
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+    if let Some(err) = errors.pop() {
+        app.set_status(format!("Citation server: {}", err));
+    } else if !staged.is_empty() {
+        let title = staged[0].borrow().get_title().to_owned();
+        let added = app.merge_staged_documents(staged);
+        if added > 0 {
+            app.set_status(format!("Saved \"{}\" from the browser", title));
+        }
+    }
+}
+
+pub async fn handle_prompt_key(app: &mut App, key: Key, user_config: &UserConfig) {
+    let prompt = match app.prompt.as_mut() {
+        Some(prompt) => prompt,
+        None => {
+            app.close_popup();
+            return;
+        }
+    };
+    match key {
+        Key::Esc => {
+            let prompt = app.prompt.take().unwrap();
+            match prompt.action {
+                // Back to the form the prompt was opened from
+                PromptAction::EditRow(_) | PromptAction::AddField => {
+                    app.open_popup(PopupType::EditForm)
+                }
+                PromptAction::RenameTag(_) | PromptAction::DeleteTag(_) => {
+                    app.open_popup(PopupType::TagManager)
+                }
+                PromptAction::OverwriteExport(_, path) => {
+                    app.close_popup();
+                    app.set_status(format!("Not exported, {} is unchanged", path.display()));
+                }
+                _ => app.close_popup(),
+            }
+        }
+        Key::Backspace => {
+            prompt.input.pop();
+        }
+        Key::Char(c) => prompt.input.push(c),
+        Key::Enter => {
+            let prompt = app.prompt.take().unwrap();
+            app.close_popup();
+            match prompt.action {
+                PromptAction::Export(scope) => {
+                    export_scope(app, scope, &prompt.input, false, user_config)
+                }
+                PromptAction::OverwriteExport(scope, path) => {
+                    if matches!(prompt.input.trim(), "y" | "yes") {
+                        export_scope(app, scope, &path.to_string_lossy(), true, user_config)
+                    } else {
+                        app.set_status(format!("Not exported, {} is unchanged", path.display()))
+                    }
+                }
+                PromptAction::Import => import_file(app, &prompt.input),
+                PromptAction::Sync(collection) => {
+                    sync_collection(app, &collection, &prompt.input, user_config)
+                }
+                PromptAction::EditRow(idx) => {
+                    if let Some(row) = app
+                        .edit_form
+                        .as_mut()
+                        .and_then(|form| form.rows.items.get_mut(idx))
+                    {
+                        row.set_value(&prompt.input);
                     }
+                    app.open_popup(PopupType::EditForm);
+                }
+                PromptAction::AddField => add_edit_form_field(app, prompt.input.trim()),
+                PromptAction::NewCollection {
+                    library_id,
+                    parent_id,
+                } => new_collection(app, library_id, parent_id, prompt.input.trim()).await,
+                PromptAction::RenameCollection(id) => {
+                    rename_selected_collection(app, id, prompt.input.trim()).await
+                }
+                PromptAction::DeleteCollection(id) => {
+                    if matches!(prompt.input.trim(), "y" | "yes") {
+                        delete_selected_collection(app, id).await
+                    }
+                }
+                PromptAction::RenameTag(name) => {
+                    rename_library_tag(app, &name, prompt.input.trim()).await
+                }
+                PromptAction::DeleteTag(name) => {
+                    if matches!(prompt.input.trim(), "y" | "yes") {
+                        delete_library_tag(app, &name).await
+                    } else {
+                        open_tag_manager(app, Some(&name));
+                    }
+                }
+                PromptAction::AddItem => match prompt.input.trim() {
+                    "" => app.editor_request = Some(EditorRequest::NewItems),
+                    input => add_items(app, input).await,
+                },
+                PromptAction::AttachFile(item_id) => {
+                    if !prompt.input.trim().is_empty() {
+                        attach_file(app, item_id, prompt.input.trim()).await
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Add the items of `input` to the Zotero database, looked up by identifier in the
+/// background or read from BibTeX or RIS records
+pub async fn add_items(app: &mut App, input: &str) {
+    if let Some(id) = Identifier::parse(input) {
+        if app.item_lookup.is_some() {
+            return app.set_status("Still looking up the last item");
+        }
+        let provider = app.metadata_provider.clone();
+        app.set_status(format!("Looking up {}", id));
+        app.item_lookup = Some(Worker::spawn(move |_| {
+            let answer = provider.fetch(&id)?;
+            Ok((id, answer))
+        }));
+        return;
+    }
+    match read_records(input) {
+        Ok(docs) => create_items(app, docs).await,
+        Err(err) => app.set_status(format!("Cannot add items: {}", err)),
+    }
+}
+
+/// Store new items in the Zotero database. A single new item can get a file attached
+/// right away.
+async fn create_items(app: &mut App, docs: Vec<RcDoc>) {
+    let total = docs.len();
+    let mut added = Vec::new();
+    let mut errors = Vec::new();
+    for doc in docs {
+        let item = NewItem::from_document(&doc.borrow());
+        match app.backend.clone().create_item(&item).await {
+            Ok((item_data, tags)) => {
+                let mut doc_mut = doc.borrow_mut();
+                doc_mut.item_data = item_data;
+                doc_mut.tags = tags;
+                drop(doc_mut);
+                added.push(doc);
+            }
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+    app.documents.extend(added.iter().cloned());
+    assign_citation_keys(&app.documents);
+    app.update_filtered_doc();
+    if let Some(err) = errors.first() {
+        return app.set_status(format!(
+            "Added {} of {} item(s), cannot add the others: {}",
+            added.len(),
+            total,
+            err
+        ));
+    }
+    match added.as_slice() {
+        [doc] => {
+            let (item_id, title) = {
+                let doc = doc.borrow();
+                (doc.item_data.itemId, doc.get_title().to_owned())
+            };
+            app.set_status(format!("Added \"{}\"", title));
+            app.open_prompt(
+                "Attach a file (empty to skip)",
+                "",
+                PromptAction::AttachFile(item_id),
+            );
+        }
+        _ => app.set_status(format!("Added {} items", added.len())),
+    }
+}
+
+/// Read the PDFs of `dir` in the background, their items are proposed once it is done
+fn open_pdf_import(app: &mut App, dir: &Path) {
+    if app.pdf_reader.is_some() {
+        return app.set_status("Still reading the PDFs of the last import");
+    }
+    let provider = app.metadata_provider.clone();
+    let dir = dir.to_path_buf();
+    app.set_status(format!("Reading the PDFs of {}", dir.display()));
+    app.pdf_reader = Some(Worker::spawn(move |progress| {
+        let pdfs = read_pdfs(&dir, provider.as_ref(), progress)
+            .map_err(|err| anyhow::anyhow!("Cannot read {}: {}", dir.display(), err))?;
+        Ok((dir, pdfs))
+    }));
+}
+
+/// Propose items for the PDFs read from `dir` to review. Those that look like items of
+/// the library are not accepted at first.
+fn propose_pdf_items(app: &mut App, dir: &Path, pdfs: &[PdfLookup]) {
+    if pdfs.is_empty() {
+        return app.set_status(format!("No PDFs in {}", dir.display()));
+    }
+    let mut candidates: Vec<PdfCandidate> = pdfs.iter().map(propose_item).collect();
+    for candidate in &mut candidates {
+        let doc = candidate.doc.borrow();
+        let doi = doc.get_field("DOI");
+        let title = doc.get_title().to_lowercase();
+        candidate.duplicate = app.documents.iter().any(|existing| {
+            let existing = existing.borrow();
+            matches!(
+                (existing.get_field("DOI"), doi),
+                (Some(a), Some(b)) if a.eq_ignore_ascii_case(b)
+            ) || (!title.is_empty() && existing.get_title().to_lowercase() == title)
+        });
+        drop(doc);
+        candidate.accepted = !candidate.duplicate;
+    }
+    app.set_status(format!("Read {} PDF(s) from {}", pdfs.len(), dir.display()));
+    app.pdf_import.items = candidates;
+    app.pdf_import.state.select(Some(0));
+    app.open_popup(PopupType::PdfImport);
+}
+
+/// Show the progress of the work done in the background and go on with its result
+pub async fn handle_workers(app: &mut App) {
+    if let Some(WorkerPoll::Done(result)) = app.item_lookup.as_ref().map(Worker::poll) {
+        app.item_lookup = None;
+        match result.and_then(|(id, answer)| parse_answer(&id, &answer)) {
+            Ok(doc) => create_items(app, vec![doc]).await,
+            Err(err) => app.set_status(format!("Cannot add items: {}", err)),
+        }
+    }
+    if let Some(worker) = &app.pdf_reader {
+        match worker.poll() {
+            WorkerPoll::Running(Some(progress)) => app.set_status(progress),
+            WorkerPoll::Running(None) => {}
+            WorkerPoll::Done(result) => {
+                app.pdf_reader = None;
+                match result {
+                    Ok((dir, pdfs)) => propose_pdf_items(app, &dir, &pdfs),
+                    Err(err) => app.set_status(err.to_string()),
+                }
+            }
+        }
+    }
+}
+
+pub async fn handle_pdf_import_key(app: &mut App, key: Key) {
+    let selected = app.pdf_import.state.selected().unwrap_or(0);
+    match key {
+        Key::Esc => {
+            app.pdf_import.items.clear();
+            app.close_popup();
+        }
+        Key::Down | Key::Char('j') => app.pdf_import.next(),
+        Key::Up | Key::Char('k') => app.pdf_import.previous(),
+        Key::Char(' ') => {
+            if let Some(candidate) = app.pdf_import.items.get_mut(selected) {
+                candidate.accepted = !candidate.accepted;
+            }
+        }
+        Key::Char('a') => {
+            let accept = !app
+                .pdf_import
+                .items
+                .iter()
+                .all(|candidate| candidate.accepted);
+            for candidate in &mut app.pdf_import.items {
+                candidate.accepted = accept;
+            }
+        }
+        Key::Enter => {
+            app.close_popup();
+            import_pdfs(app).await;
+        }
+        _ => {}
+    }
+}
+
+/// Create an item for each accepted PDF of the review, with the PDF stored as its
+/// attachment
+async fn import_pdfs(app: &mut App) {
+    let candidates = std::mem::take(&mut app.pdf_import.items);
+    let accepted: Vec<_> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.accepted)
+        .collect();
+    let total = accepted.len();
+    let mut imported = 0;
+    let mut errors = Vec::new();
+    for candidate in accepted {
+        let item = NewItem::from_document(&candidate.doc.borrow());
+        let (item_data, tags) = match app.backend.clone().create_item(&item).await {
+            Ok(created) => created,
+            Err(err) => {
+                errors.push(err.to_string());
+                continue;
+            }
+        };
+        let item_id = item_data.itemId;
+        {
+            let mut doc = candidate.doc.borrow_mut();
+            doc.item_data = item_data;
+            doc.tags = tags;
+        }
+        app.documents.push(candidate.doc);
+        imported += 1;
+        let attached = app
+            .backend
+            .clone()
+            .import_attachment(item_id, &candidate.path)
+            .await;
+        match attached {
+            Ok(attachment) => {
+                if let Some(doc) = app.documents.last() {
+                    doc.borrow_mut().attachments = Some(StatefulList::with_items(vec![attachment]));
+                }
+            }
+            Err(err) => errors.push(format!("{}: {}", candidate.path.display(), err)),
+        }
+    }
+    assign_citation_keys(&app.documents);
+    app.update_filtered_doc();
+    match errors.first() {
+        Some(err) => app.set_status(format!(
+            "Imported {} of {} PDF(s), {} error(s): {}",
+            imported,
+            total,
+            errors.len(),
+            err
+        )),
+        None => app.set_status(format!("Imported {} PDF(s)", imported)),
+    }
+}
+
+/// Store a copy of the file at `path` as an attachment of the item `item_id`
+async fn attach_file(app: &mut App, item_id: i64, path: &str) {
+    let result = app
+        .backend
+        .clone()
+        .import_attachment(item_id, Path::new(path))
+        .await;
+    let attachment = match result {
+        Ok(attachment) => attachment,
+        Err(err) => return app.set_status(format!("Cannot attach {}: {}", path, err)),
+    };
+    let name = attachment.display_name();
+    if let Some(doc) = app
+        .documents
+        .iter()
+        .find(|doc| doc.borrow().item_data.itemId == item_id)
+    {
+        let mut doc = doc.borrow_mut();
+        match doc.attachments.as_mut() {
+            Some(attachments) => attachments.items.push(attachment),
+            None => doc.attachments = Some(StatefulList::with_items(vec![attachment])),
+        }
+    }
+    app.set_status(format!("Attached {}", name));
+}
+
+/// Offer the styles of the styles directory to preview the marked documents with.
+pub fn open_style_menu(app: &mut App, user_config: &UserConfig) {
+    if app.get_marked_docs().is_empty() {
+        app.set_status("Nothing to format");
+        return;
+    }
+    let styles = list_styles(&user_config.behavior.csl_styles_dir);
+    if styles.is_empty() {
+        app.set_status(format!(
+            "No CSL styles in {}",
+            user_config.behavior.csl_styles_dir.display()
+        ));
+        return;
+    }
+    // Start on the configured default style
+    let default = styles
+        .iter()
+        .position(|style| {
+            style.path.file_stem().and_then(|stem| stem.to_str())
+                == Some(user_config.behavior.csl_style.as_str())
+                || style.id == user_config.behavior.csl_style
+        })
+        .unwrap_or(0);
+    app.style_menu.items = styles;
+    app.style_menu.state.select(Some(default));
+    app.open_popup(PopupType::StyleMenu);
+}
+
+pub fn handle_style_menu_key(app: &mut App, key: Key) {
+    match key {
+        Key::Esc => app.close_popup(),
+        Key::Down | Key::Char('j') => app.style_menu.next(),
+        Key::Up | Key::Char('k') => app.style_menu.previous(),
+        Key::Enter => {
+            let selected = app.style_menu.state.selected().unwrap_or(0);
+            let info = match app.style_menu.items.get(selected) {
+                Some(info) => info.clone(),
+                None => return,
+            };
+            match load_style(&info.path) {
+                Ok(style) => {
+                    app.preview = Some(Preview::new(app.get_marked_docs(), style));
+                    app.open_popup(PopupType::Preview);
+                }
+                Err(err) => {
+                    app.close_popup();
+                    app.set_status(format!("Cannot load {}: {}", info.title, err));
                 }
-                // let attachment = &attachments[0];
             }
-            None => {}
+        }
+        _ => {}
+    }
+}
+
+pub fn handle_preview_key(app: &mut App, key: Key) {
+    let preview = match app.preview.as_mut() {
+        Some(preview) => preview,
+        None => {
+            app.close_popup();
+            return;
         }
     };
-    Ok(())
+    match key {
+        Key::Esc => {
+            app.preview = None;
+            app.close_popup();
+        }
+        Key::Down | Key::Char('j') => preview.scroll = preview.scroll.saturating_add(1),
+        Key::Up | Key::Char('k') => preview.scroll = preview.scroll.saturating_sub(1),
+        Key::Char('f') => preview.cycle_format(),
+        Key::Char('y') | Key::Char('c') => {
+            let (what, text) = if key == Key::Char('y') {
+                ("bibliography", preview.bibliography.entries.join("\n"))
+            } else {
+                ("citation", preview.bibliography.citation.clone())
+            };
+            let format = preview.format.name();
+            match app.clipboard.copy(&text) {
+                Ok(method) => {
+                    app.set_status(format!("Copied {} {} to the {}", format, what, method))
+                }
+                Err(err) => app.set_status(format!("Cannot copy {}: {}", what, err)),
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn handle_attachment_picker_key(app: &mut App, key: Key, user_config: &UserConfig) {
+    match key {
+        Key::Esc => app.close_popup(),
+        Key::Down | Key::Char('j') => app.attachment_picker.next(),
+        Key::Up | Key::Char('k') => app.attachment_picker.previous(),
+        Key::Enter => {
+            let selected = app.attachment_picker.state.selected().unwrap_or(0);
+            if let Some(attachment) = app.attachment_picker.items.get(selected).cloned() {
+                app.close_popup();
+                open_attachment(app, &attachment, user_config);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn handle_column_chooser_key(app: &mut App, key: Key) {
+    let selected = app.columns.state.selected().unwrap_or(0);
+    match key {
+        Key::Esc | Key::Enter => app.close_popup(),
+        Key::Down | Key::Char('j') => app.columns.next(),
+        Key::Up | Key::Char('k') => app.columns.previous(),
+        Key::Char(' ') => {
+            if let Some(col) = app.columns.items.get_mut(selected) {
+                col.visible = !col.visible;
+            }
+            // Never hide every column
+            if !app.columns.items.iter().any(|col| col.visible) {
+                app.columns.items[selected].visible = true;
+            }
+        }
+        Key::Char('<') if selected > 0 => {
+            app.columns.items.swap(selected, selected - 1);
+            app.columns.state.select(Some(selected - 1));
+        }
+        Key::Char('>') if selected + 1 < app.columns.items.len() => {
+            app.columns.items.swap(selected, selected + 1);
+            app.columns.state.select(Some(selected + 1));
+        }
+        Key::Char('+') => {
+            if let Some(col) = app.columns.items.get_mut(selected) {
+                col.width = (col.width + 5).min(100);
+            }
+        }
+        Key::Char('-') => {
+            if let Some(col) = app.columns.items.get_mut(selected) {
+                col.width = col.width.saturating_sub(5).max(5);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether the library can be changed, saying so in the status line if it can't. Checked
+/// before opening an editor, rather than failing only when its changes are saved.
+fn is_writable(app: &mut App) -> bool {
+    if !app.backend.is_writable() {
+        app.set_status(format!(
+            "The library in {} can't be changed",
+            app.backend.name()
+        ));
+    }
+    app.backend.is_writable()
+}
+
+/// Edit the fields and creators of the selected document
+pub fn open_edit_form(app: &mut App) {
+    let doc = match app.get_selected_doc() {
+        Some(doc) => doc,
+        None => return,
+    };
+    if !is_writable(app) {
+        return;
+    }
+    // Imported and staged documents have no row in the database
+    if doc.borrow().item_data.itemId <= 0 {
+        app.set_status("Only items of the Zotero database can be edited");
+        return;
+    }
+    app.edit_form = Some(EditForm::new(doc));
+    app.open_popup(PopupType::EditForm);
+}
+
+/// Prompt for the value of a row of the edit form
+fn edit_form_row(app: &mut App, idx: usize) {
+    let row = match app
+        .edit_form
+        .as_ref()
+        .and_then(|form| form.rows.items.get(idx))
+    {
+        Some(row) => row,
+        None => return,
+    };
+    let title = match row {
+        EditRow::Creator(_) => format!("{} (Last, First or a single name)", row.label()),
+        EditRow::Field { name, .. } => name.to_string(),
+    };
+    let value = row.value();
+    app.open_prompt(&title, &value, PromptAction::EditRow(idx));
+}
+
+fn add_edit_form_field(app: &mut App, name: &str) {
+    let form = match app.edit_form.as_mut() {
+        Some(form) => form,
+        None => return,
+    };
+    if name.is_empty() {
+        app.open_popup(PopupType::EditForm);
+        return;
+    }
+    let idx = form.add_field(name);
+    edit_form_row(app, idx);
+}
+
+pub async fn handle_edit_form_key(app: &mut App, key: Key) {
+    let form = match app.edit_form.as_mut() {
+        Some(form) => form,
+        None => {
+            app.close_popup();
+            return;
+        }
+    };
+    match key {
+        Key::Esc => {
+            app.edit_form = None;
+            app.close_popup();
+        }
+        Key::Down | Key::Char('j') => form.rows.next(),
+        Key::Up | Key::Char('k') => form.rows.previous(),
+        Key::Enter => {
+            if let Some(idx) = form.selected() {
+                edit_form_row(app, idx);
+            }
+        }
+        Key::Char('n') => {
+            app.open_prompt("New field (Zotero field name)", "", PromptAction::AddField)
+        }
+        Key::Char('a') => {
+            let idx = form.add_creator();
+            edit_form_row(app, idx);
+        }
+        Key::Char('d') => form.remove_selected(),
+        Key::Char('t') => form.cycle_creator_type(),
+        Key::Char('J') => form.move_creator(true),
+        Key::Char('K') => form.move_creator(false),
+        Key::Char('s') => save_edit_form(app).await,
+        _ => {}
+    }
+}
+
+/// Write the changes of the edit form to the database. The form stays open when that
+/// fails, e.g. because Zotero is running.
+async fn save_edit_form(app: &mut App) {
+    let (doc, changes) = match &app.edit_form {
+        Some(form) => (form.doc.clone(), form.changes()),
+        None => return,
+    };
+    if changes.is_empty() {
+        app.edit_form = None;
+        app.close_popup();
+        app.set_status("Nothing to save");
+        return;
+    }
+    let item_id = doc.borrow().item_data.itemId;
+    match app
+        .backend
+        .clone()
+        .write_item_changes(item_id, &changes)
+        .await
+    {
+        Ok(()) => {
+            apply_item_changes(&mut doc.borrow_mut(), &changes);
+            app.edit_form = None;
+            app.close_popup();
+            app.set_status(format!("Saved \"{}\"", doc.borrow().get_title()));
+        }
+        Err(err) => app.set_status(format!("Cannot save: {}", err)),
+    }
+}
+
+/// IDs of the documents that are items of the Zotero database, imported and staged
+/// documents can't be filed
+fn database_item_ids(docs: &[RcDoc]) -> Vec<i64> {
+    docs.iter()
+        .map(|doc| doc.borrow().item_data.itemId)
+        .filter(|id| *id > 0)
+        .collect()
+}
+
+fn open_picker(
+    app: &mut App,
+    action: CollectionPick,
+    targets: Vec<(String, Option<RcCollection>)>,
+) {
+    let mut targets = StatefulList::with_items(targets);
+    targets.state.select(Some(0));
+    app.collection_picker = Some(CollectionPicker { action, targets });
+    app.open_popup(PopupType::CollectionPicker);
+}
+
+/// Choose a collection to add the marked documents to
+pub fn open_collection_picker(app: &mut App) {
+    if !is_writable(app) {
+        return;
+    }
+    if database_item_ids(&app.get_marked_docs()).is_empty() {
+        app.set_status("Only items of the Zotero database can be added to collections");
+        return;
+    }
+    let mut targets: Vec<(String, Option<RcCollection>)> = app
+        .collection_tree
+        .get_collections()
+        .into_iter()
+        .map(|col| (collection_path(app, &col), Some(col)))
+        .collect();
+    if targets.is_empty() {
+        app.set_status("There are no collections yet");
+        return;
+    }
+    targets.sort_by(|a, b| a.0.cmp(&b.0));
+    open_picker(app, CollectionPick::AddDocuments, targets);
+}
+
+pub fn handle_collection_menu_key(app: &mut App, key: Key) {
+    match key {
+        Key::Esc => app.close_popup(),
+        Key::Down | Key::Char('j') => app.collection_menu.next(),
+        Key::Up | Key::Char('k') => app.collection_menu.previous(),
+        Key::Enter => {
+            app.close_popup();
+            let selected = app.collection_menu.state.selected().unwrap_or(0);
+            let action = match app.collection_menu.items.get(selected) {
+                Some(action) => *action,
+                None => return,
+            };
+            let collection = app.get_selected_collection();
+            let library_id = app.get_selected_library_id();
+            let collection = match (action, collection) {
+                (CollectionAction::New, _) => {
+                    let action = PromptAction::NewCollection {
+                        library_id,
+                        parent_id: None,
+                    };
+                    app.open_prompt("New collection", "", action);
+                    return;
+                }
+                (_, Some(collection)) => collection,
+                (_, None) => {
+                    app.set_status("Select a collection first");
+                    return;
+                }
+            };
+            let id = collection.borrow().collectionId;
+            let path = collection_path(app, &collection);
+            match action {
+                CollectionAction::New => {}
+                CollectionAction::NewSubcollection => app.open_prompt(
+                    &format!("New collection in {}", path),
+                    "",
+                    PromptAction::NewCollection {
+                        library_id,
+                        parent_id: Some(id),
+                    },
+                ),
+                CollectionAction::Rename => {
+                    let name = collection.borrow().collectionName.clone();
+                    app.open_prompt(
+                        &format!("Rename {}", path),
+                        &name,
+                        PromptAction::RenameCollection(id),
+                    )
+                }
+                CollectionAction::Move => {
+                    let library_id = collection.borrow().libraryId;
+                    let excluded = app.collection_tree.get_descendant_ids(id);
+                    let mut targets: Vec<(String, Option<RcCollection>)> = app
+                        .collection_tree
+                        .get_collections()
+                        .into_iter()
+                        .filter(|col| {
+                            let col = col.borrow();
+                            col.libraryId == library_id && !excluded.contains(&col.collectionId)
+                        })
+                        .map(|col| (collection_path(app, &col), Some(col)))
+                        .collect();
+                    targets.sort_by(|a, b| a.0.cmp(&b.0));
+                    targets.insert(0, ("(Top level)".to_string(), None));
+                    open_picker(app, CollectionPick::Move(id), targets);
+                }
+                CollectionAction::Delete => app.open_prompt(
+                    &format!("Delete {} and its sub-collections? (y/n)", path),
+                    "",
+                    PromptAction::DeleteCollection(id),
+                ),
+            }
+        }
+        _ => {}
+    }
+}
+
+pub async fn handle_collection_picker_key(app: &mut App, key: Key) {
+    let picker = match app.collection_picker.as_mut() {
+        Some(picker) => picker,
+        None => {
+            app.close_popup();
+            return;
+        }
+    };
+    match key {
+        Key::Esc => {
+            app.collection_picker = None;
+            app.close_popup();
+        }
+        Key::Down | Key::Char('j') => picker.targets.next(),
+        Key::Up | Key::Char('k') => picker.targets.previous(),
+        Key::Enter => {
+            let picker = app.collection_picker.take().unwrap();
+            app.close_popup();
+            let selected = picker.targets.state.selected().unwrap_or(0);
+            let target = match picker.targets.items.get(selected) {
+                Some((_, target)) => target.clone(),
+                None => return,
+            };
+            match (picker.action, target) {
+                (CollectionPick::AddDocuments, Some(collection)) => {
+                    add_marked_to_collection(app, collection).await
+                }
+                (CollectionPick::AddDocuments, None) => {}
+                (CollectionPick::Move(id), parent) => {
+                    move_selected_collection(app, id, parent).await
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+async fn add_marked_to_collection(app: &mut App, collection: RcCollection) {
+    let docs: Vec<RcDoc> = app
+        .get_marked_docs()
+        .into_iter()
+        .filter(|doc| doc.borrow().item_data.itemId > 0)
+        .collect();
+    let id = collection.borrow().collectionId;
+    let backend = app.backend.clone();
+    match backend
+        .add_to_collection(id, &database_item_ids(&docs))
+        .await
+    {
+        Ok(added) => {
+            for doc in &docs {
+                let mut doc = doc.borrow_mut();
+                if !doc
+                    .collections
+                    .iter()
+                    .any(|col| col.borrow().collectionId == id)
+                {
+                    doc.collections.push(collection.clone());
+                }
+            }
+            let path = collection_path(app, &collection);
+            app.set_status(format!("Added {} item(s) to {}", added, path));
+        }
+        Err(err) => app.set_status(format!("Cannot add to the collection: {}", err)),
+    }
+}
+
+/// Remove the marked documents from the collection under the cursor of the tree
+pub async fn remove_from_selected_collection(app: &mut App) {
+    let collection = match app.get_selected_collection() {
+        Some(collection) => collection,
+        None => {
+            app.set_status("Select the collection to remove items from");
+            return;
+        }
+    };
+    if !is_writable(app) {
+        return;
+    }
+    let id = collection.borrow().collectionId;
+    let docs: Vec<RcDoc> = app
+        .get_marked_docs()
+        .into_iter()
+        .filter(|doc| {
+            doc.borrow()
+                .collections
+                .iter()
+                .any(|col| col.borrow().collectionId == id)
+        })
+        .collect();
+    let path = collection_path(app, &collection);
+    if docs.is_empty() {
+        app.set_status(format!("No marked item is in {}", path));
+        return;
+    }
+    let backend = app.backend.clone();
+    match backend
+        .remove_from_collection(id, &database_item_ids(&docs))
+        .await
+    {
+        Ok(removed) => {
+            for doc in &docs {
+                doc.borrow_mut()
+                    .collections
+                    .retain(|col| col.borrow().collectionId != id);
+            }
+            app.set_status(format!("Removed {} item(s) from {}", removed, path));
+        }
+        Err(err) => app.set_status(format!("Cannot remove from the collection: {}", err)),
+    }
+}
+
+async fn new_collection(
+    app: &mut App,
+    library_id: Option<i64>,
+    parent_id: Option<i64>,
+    name: &str,
+) {
+    if name.is_empty() {
+        return;
+    }
+    let backend = app.backend.clone();
+    match backend.create_collection(library_id, parent_id, name).await {
+        Ok(collection) => {
+            let id = collection.collectionId;
+            let mut collections = app.collection_tree.get_collections();
+            collections.push(Rc::new(RefCell::new(collection)));
+            app.rebuild_collection_tree(collections);
+            app.select_collection(id);
+            app.set_status(format!("Created collection {}", name));
+        }
+        Err(err) => app.set_status(format!("Cannot create the collection: {}", err)),
+    }
+}
+
+fn find_tree_collection(app: &App, id: i64) -> Option<RcCollection> {
+    app.collection_tree
+        .get_collections()
+        .into_iter()
+        .find(|col| col.borrow().collectionId == id)
+}
+
+async fn rename_selected_collection(app: &mut App, id: i64, name: &str) {
+    let collection = match find_tree_collection(app, id) {
+        Some(collection) if !name.is_empty() => collection,
+        _ => return,
+    };
+    match app.backend.clone().rename_collection(id, name).await {
+        Ok(()) => {
+            // Shared with the documents, which see the new name too
+            collection.borrow_mut().collectionName = name.to_string();
+            let collections = app.collection_tree.get_collections();
+            app.rebuild_collection_tree(collections);
+            app.set_status(format!("Renamed the collection to {}", name));
+        }
+        Err(err) => app.set_status(format!("Cannot rename the collection: {}", err)),
+    }
+}
+
+async fn move_selected_collection(app: &mut App, id: i64, parent: Option<RcCollection>) {
+    let collection = match find_tree_collection(app, id) {
+        Some(collection) => collection,
+        None => return,
+    };
+    let parent_id = parent.as_ref().map(|parent| parent.borrow().collectionId);
+    match app.backend.clone().move_collection(id, parent_id).await {
+        Ok(()) => {
+            collection.borrow_mut().parentCollectionId = parent_id;
+            let collections = app.collection_tree.get_collections();
+            app.rebuild_collection_tree(collections);
+            app.set_status(format!("Moved to {}", collection_path(app, &collection)));
+        }
+        Err(err) => app.set_status(format!("Cannot move the collection: {}", err)),
+    }
+}
+
+async fn delete_selected_collection(app: &mut App, id: i64) {
+    let path = match find_tree_collection(app, id) {
+        Some(collection) => collection_path(app, &collection),
+        None => return,
+    };
+    match app.backend.clone().delete_collection(id).await {
+        Ok(deleted) => {
+            for doc in &app.documents {
+                doc.borrow_mut()
+                    .collections
+                    .retain(|col| !deleted.contains(&col.borrow().collectionId));
+            }
+            let collections = app
+                .collection_tree
+                .get_collections()
+                .into_iter()
+                .filter(|col| !deleted.contains(&col.borrow().collectionId))
+                .collect();
+            app.rebuild_collection_tree(collections);
+            app.set_status(format!("Deleted {}", path));
+        }
+        Err(err) => app.set_status(format!("Cannot delete the collection: {}", err)),
+    }
+}
+
+/// Add tags to and remove tags from the marked documents
+pub fn open_tag_editor(app: &mut App) {
+    if !is_writable(app) {
+        return;
+    }
+    let docs: Vec<RcDoc> = app
+        .get_marked_docs()
+        .into_iter()
+        .filter(|doc| doc.borrow().item_data.itemId > 0)
+        .collect();
+    if docs.is_empty() {
+        app.set_status("Only items of the Zotero database can be tagged");
+        return;
+    }
+    app.tag_editor = Some(TagEditor::new(docs, &app.documents));
+    app.open_popup(PopupType::TagEditor);
+}
+
+pub async fn handle_tag_editor_key(app: &mut App, key: Key) {
+    let backend = app.backend.clone();
+    let editor = match app.tag_editor.as_mut() {
+        Some(editor) => editor,
+        None => {
+            app.close_popup();
+            return;
+        }
+    };
+    match key {
+        Key::Esc => {
+            app.tag_editor = None;
+            app.close_popup();
+        }
+        Key::Down => editor.tags.next(),
+        Key::Up => editor.tags.previous(),
+        Key::Tab => editor.complete(),
+        Key::Backspace => editor.pop_char(),
+        Key::Char(c) => editor.push_char(c),
+        Key::Enter => {
+            let name = editor.input.trim().to_string();
+            if name.is_empty() {
+                return;
+            }
+            match backend
+                .add_tag(&database_item_ids(&editor.docs), &name)
+                .await
+            {
+                Ok((tag, added)) => {
+                    for doc in &editor.docs {
+                        push_tag(doc, &tag);
+                    }
+                    editor.add_library_tag(&name);
+                    editor.clear_input();
+                    editor.refresh();
+                    app.set_status(format!("Tagged {} item(s) with \"{}\"", added, name));
+                }
+                Err(err) => app.set_status(format!("Cannot add the tag: {}", err)),
+            }
+        }
+        Key::Delete | Key::Ctrl('d') => {
+            let name = match editor.selected_tag() {
+                Some(name) => name,
+                None => return,
+            };
+            match backend
+                .remove_tag(&database_item_ids(&editor.docs), &name)
+                .await
+            {
+                Ok(removed) => {
+                    for doc in &editor.docs {
+                        doc.borrow_mut().tags.retain(|tag| tag.name != name);
+                    }
+                    editor.refresh();
+                    app.set_status(format!("Removed \"{}\" from {} item(s)", name, removed));
+                }
+                Err(err) => app.set_status(format!("Cannot remove the tag: {}", err)),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// List the tags of the library to rename, merge or delete them, with the cursor on
+/// `selected`
+pub fn open_tag_manager(app: &mut App, selected: Option<&str>) {
+    app.tag_manager.items = count_tags(&app.documents);
+    if app.tag_manager.items.is_empty() {
+        app.close_popup();
+        app.set_status("The library has no tags");
+        return;
+    }
+    let idx = selected
+        .and_then(|name| {
+            app.tag_manager
+                .items
+                .iter()
+                .position(|(tag, _)| tag == name)
+        })
+        .or(app.tag_manager.state.selected())
+        .unwrap_or(0)
+        .min(app.tag_manager.items.len() - 1);
+    app.tag_manager.state.select(Some(idx));
+    app.open_popup(PopupType::TagManager);
+}
+
+pub fn handle_tag_manager_key(app: &mut App, key: Key) {
+    let selected = app
+        .tag_manager
+        .state
+        .selected()
+        .and_then(|idx| app.tag_manager.items.get(idx))
+        .cloned();
+    match key {
+        Key::Esc => app.close_popup(),
+        Key::Down | Key::Char('j') => app.tag_manager.next(),
+        Key::Up | Key::Char('k') => app.tag_manager.previous(),
+        Key::Char('r') | Key::Enter => {
+            if let Some((name, _)) = selected {
+                app.open_prompt(
+                    &format!("Rename \"{}\" (an existing tag merges both)", name),
+                    &name,
+                    PromptAction::RenameTag(name.clone()),
+                );
+            }
+        }
+        Key::Char('d') => {
+            if let Some((name, count)) = selected {
+                app.open_prompt(
+                    &format!("Delete \"{}\" from {} item(s)? (y/n)", name, count),
+                    "",
+                    PromptAction::DeleteTag(name.clone()),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+async fn rename_library_tag(app: &mut App, name: &str, new_name: &str) {
+    if new_name.is_empty() || new_name == name {
+        open_tag_manager(app, Some(name));
+        return;
+    }
+    match app.backend.clone().rename_tag(name, new_name).await {
+        Ok(tag) => {
+            for doc in &app.documents {
+                let had_tag = doc.borrow().tags.iter().any(|tag| tag.name == name);
+                if had_tag {
+                    doc.borrow_mut().tags.retain(|tag| tag.name != name);
+                    push_tag(doc, &tag);
+                }
+            }
+            open_tag_manager(app, Some(new_name));
+            app.set_status(format!("Renamed \"{}\" to \"{}\"", name, new_name));
+        }
+        Err(err) => {
+            open_tag_manager(app, Some(name));
+            app.set_status(format!("Cannot rename the tag: {}", err));
+        }
+    }
+}
+
+async fn delete_library_tag(app: &mut App, name: &str) {
+    match app.backend.clone().delete_tag(name).await {
+        Ok(count) => {
+            for doc in &app.documents {
+                doc.borrow_mut().tags.retain(|tag| tag.name != name);
+            }
+            open_tag_manager(app, None);
+            app.set_status(format!("Deleted \"{}\" from {} item(s)", name, count));
+        }
+        Err(err) => {
+            open_tag_manager(app, Some(name));
+            app.set_status(format!("Cannot delete the tag: {}", err));
+        }
+    }
+}
+
+/// Edit a note of the selected document, or add one if it has none yet
+pub fn open_note_picker(app: &mut App) {
+    let doc = match app.get_selected_doc() {
+        Some(doc) => doc,
+        None => return,
+    };
+    if !is_writable(app) {
+        return;
+    }
+    if doc.borrow().item_data.itemId <= 0 {
+        app.set_status("Only items of the Zotero database can have notes");
+        return;
+    }
+    let notes = doc.borrow().notes.clone();
+    if notes.is_empty() {
+        app.editor_request = Some(EditorRequest::Note(NoteEdit { doc, note: None }));
+        return;
+    }
+    app.note_picker.items = std::iter::once(None)
+        .chain(notes.into_iter().map(Some))
+        .collect();
+    app.note_picker.state.select(Some(1));
+    app.open_popup(PopupType::NotePicker);
+}
+
+pub fn handle_note_picker_key(app: &mut App, key: Key) {
+    match key {
+        Key::Esc => app.close_popup(),
+        Key::Down | Key::Char('j') => app.note_picker.next(),
+        Key::Up | Key::Char('k') => app.note_picker.previous(),
+        Key::Enter => {
+            let note = app
+                .note_picker
+                .state
+                .selected()
+                .and_then(|idx| app.note_picker.items.get(idx))
+                .cloned();
+            if let (Some(note), Some(doc)) = (note, app.get_selected_doc()) {
+                app.close_popup();
+                app.editor_request = Some(EditorRequest::Note(NoteEdit { doc, note }));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Open the note in the editor as Markdown, the terminal must be suspended. Returns the
+/// edited Markdown, or `None` if it is unchanged.
+pub fn edit_note(edit: &NoteEdit) -> anyhow::Result<Option<String>> {
+    match &edit.note {
+        Some(note) => edit_in_editor(
+            &html_to_markdown(&note.note),
+            &format!("note-{}", note.itemId),
+            "md",
+        ),
+        None => edit_in_editor("", "note-new", "md"),
+    }
+}
+
+/// Write the note edited by `edit_note` to the database
+pub async fn save_edited_note(
+    app: &mut App,
+    edit: NoteEdit,
+    edited: anyhow::Result<Option<String>>,
+) {
+    let markdown = match edited {
+        Ok(Some(markdown)) if !markdown.trim().is_empty() => markdown,
+        Ok(Some(_)) if edit.note.is_some() => {
+            app.set_status("Notes cannot be emptied, delete them in Zotero instead");
+            return;
+        }
+        Ok(_) => {
+            app.set_status("The note is unchanged");
+            return;
+        }
+        Err(err) => {
+            app.set_status(format!("Cannot edit the note: {}", err));
+            return;
+        }
+    };
+    let html = markdown_to_html(&markdown);
+    let title = note_title(&html);
+    let parent_id = edit.doc.borrow().item_data.itemId;
+    let note_id = edit.note.as_ref().map(|note| note.itemId);
+    match app
+        .backend
+        .clone()
+        .save_note(parent_id, note_id, &title, &html)
+        .await
+    {
+        Ok(note) => {
+            let mut doc = edit.doc.borrow_mut();
+            match doc.notes.iter_mut().find(|old| old.itemId == note.itemId) {
+                Some(old) => *old = note,
+                None => doc.notes.push(note),
+            }
+            drop(doc);
+            app.set_status(format!("Saved the note \"{}\"", title));
+        }
+        Err(err) => {
+            // Don't lose what was written, the database may just be locked by Zotero
+            let draft = std::env::temp_dir().join(format!("rustero-note-draft-{}.md", parent_id));
+            match std::fs::write(&draft, &markdown) {
+                Ok(()) => app.set_status(format!(
+                    "Cannot save the note, kept it in {}: {}",
+                    draft.display(),
+                    err
+                )),
+                Err(_) => app.set_status(format!("Cannot save the note: {}", err)),
+            }
+        }
+    }
 }
@@ -1,16 +1,34 @@
 mod app;
+mod backend;
+mod bib_sync;
+mod citation_server;
+mod citeproc;
+mod cli;
+mod clipboard;
 mod collection_tree;
+mod connector;
 mod data_structures;
 mod db_connector;
+mod edit_form;
 mod event;
+mod export;
 mod handler;
+mod metadata;
+mod note;
+mod opener;
+mod tag_editor;
+#[cfg(test)]
+mod test_dir;
 mod ui;
 mod user_config;
+mod web_sync;
+mod worker;
 
-use app::App;
+use app::{App, EditorRequest};
+use backend::load_library;
 use data_structures::Collection;
-use db_connector::get_all_item_data;
 use handler::*;
+use note::edit_in_editor;
 
 use anyhow::Result;
 
@@ -19,18 +37,57 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::io;
+use std::io::{self, Stdout};
 use std::{cell::RefCell, rc::Rc};
 use tokio;
 use tui::{backend::CrosstermBackend, Terminal};
-use ui::{UIBlock, UIBlockType};
+use ui::{PopupType, UIBlock, UIBlockType};
 
-use crate::db_connector::{get_attachments_for_docs, get_collections, get_creators_for_docs};
 use crate::event::Key;
 use crate::ui::draw_main_layout;
 use crate::user_config::UserConfig;
 
+/// Give the terminal back for a program run in the foreground, like an editor
+fn suspend_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+fn resume_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+    enable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        EnterAlternateScreen,
+        EnableMouseCapture
+    )?;
+    terminal.hide_cursor()?;
+    // The screen was used by the other program, everything must be drawn again
+    terminal.clear()?;
+    Ok(())
+}
+
 async fn start_ui(user_config: UserConfig) -> Result<()> {
+    // Downloads can take a while on the first sync, so this runs before the alternate
+    // screen hides what the terminal is waiting for
+    let sync_status = match &user_config.behavior.web_api {
+        Some(web_api) => {
+            println!("Syncing the library with zotero.org...");
+            Some(
+                match web_sync::pull(web_api, &user_config.behavior.zotero_db_path).await {
+                    Ok(report) => report.to_string(),
+                    Err(err) => format!("Cannot sync with zotero.org: {}", err),
+                },
+            )
+        }
+        None => None,
+    };
+
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -43,6 +100,11 @@ async fn start_ui(user_config: UserConfig) -> Result<()> {
     // create app and run it
     let events = event::Events::new(user_config.behavior.tick_rate_milliseconds);
     let mut app = App::default();
+    app.columns.items = user_config.build_columns();
+    app.columns.state.select(Some(0));
+    if let Some(status) = sync_status {
+        app.set_status(status);
+    }
 
     let mut is_first_render = true;
     app.ui_blocks.extend(vec![
@@ -73,22 +135,36 @@ async fn start_ui(user_config: UserConfig) -> Result<()> {
         })),
     ]);
     loop {
+        if let Some(request) = app.editor_request.take() {
+            events.pause();
+            suspend_terminal(&mut terminal)?;
+            let edited = match &request {
+                EditorRequest::Note(edit) => edit_note(edit),
+                EditorRequest::NewItems => edit_in_editor("", "new-items", "bib"),
+            };
+            resume_terminal(&mut terminal)?;
+            events.resume();
+            match request {
+                EditorRequest::Note(edit) => save_edited_note(&mut app, edit, edited).await,
+                EditorRequest::NewItems => match edited {
+                    Ok(Some(records)) => add_items(&mut app, &records).await,
+                    Ok(None) => app.set_status("Nothing to add"),
+                    Err(err) => app.set_status(format!("Cannot add items: {}", err)),
+                },
+            }
+        }
         terminal.draw(|f| draw_main_layout(f, &mut app))?;
         if is_first_render {
-            app.init_sqlite(&user_config.behavior.zotero_db_path)
-                .await?;
-
-            app.documents = Vec::from_iter(get_all_item_data(&mut app).await?);
-            get_creators_for_docs(&mut app).await?;
-            get_attachments_for_docs(&mut app).await?;
-            get_collections(&mut app).await?;
-            app.collection_tree.build_collection_tree(&mut app.collections.items);
+            load_library(&mut app, &user_config).await?;
             // log::debug!(stringify!(&app.collection_tree));
             // break;
             // build_collection_tree(&mut app.collection_tree, &app.collections);
             app.refresh_active_block();
 
             app.update_filtered_doc();
+            if user_config.behavior.citation_server || user_config.behavior.connector {
+                start_citation_server(&mut app, &user_config);
+            }
             is_first_render = false;
         }
         match events.next()? {
@@ -96,31 +172,44 @@ async fn start_ui(user_config: UserConfig) -> Result<()> {
                 if key == Key::Ctrl('c') {
                     break;
                 }
+                if app.show_popup.get() {
+                    match app.popup_type {
+                        PopupType::ColumnChooser => handle_column_chooser_key(&mut app, key),
+                        PopupType::AttachmentPicker => {
+                            handle_attachment_picker_key(&mut app, key, &user_config)
+                        }
+                        PopupType::CopyMenu => handle_copy_menu_key(&mut app, key, &user_config),
+                        PopupType::ExportMenu => {
+                            handle_export_menu_key(&mut app, key, &user_config)
+                        }
+                        PopupType::Prompt => handle_prompt_key(&mut app, key, &user_config).await,
+                        PopupType::StyleMenu => handle_style_menu_key(&mut app, key),
+                        PopupType::Preview => handle_preview_key(&mut app, key),
+                        PopupType::EditForm => handle_edit_form_key(&mut app, key).await,
+                        PopupType::CollectionMenu => handle_collection_menu_key(&mut app, key),
+                        PopupType::CollectionPicker => {
+                            handle_collection_picker_key(&mut app, key).await
+                        }
+                        PopupType::TagEditor => handle_tag_editor_key(&mut app, key).await,
+                        PopupType::TagManager => handle_tag_manager_key(&mut app, key),
+                        PopupType::NotePicker => handle_note_picker_key(&mut app, key),
+                        PopupType::PdfImport => handle_pdf_import_key(&mut app, key).await,
+                    }
+                    continue;
+                }
+                if app.get_active_block().borrow().ty != UIBlockType::Input
+                    && handle_command_key(&mut app, key, &user_config).await
+                {
+                    continue;
+                }
                 match key {
-                    Key::Down => match app
-                        .ui_blocks
-                        .get(app.active_block_idx.get())
-                        .unwrap()
-                        .borrow()
-                        .ty
-                    {
+                    Key::Down => match app.get_active_block().borrow().ty {
                         UIBlockType::Collections => {
-                            app.collections.next();
+                            app.select_next_collection();
                         }
                         _ => {
                             app.filtered_documents.next();
-
-                            let i = match app.tbl_state.selected() {
-                                Some(i) => {
-                                    if i >= app.filtered_documents.items.len() - 1 {
-                                        0
-                                    } else {
-                                        i + 1
-                                    }
-                                }
-                                None => 0,
-                            };
-                            app.tbl_state.select(Some(i));
+                            app.select_next_row();
                         }
                     },
                     Key::Right => {
@@ -131,29 +220,13 @@ async fn start_ui(user_config: UserConfig) -> Result<()> {
                         app.select_prev_block();
                         app.update_filtered_doc();
                     }
-                    Key::Up => match app
-                        .ui_blocks
-                        .get(app.active_block_idx.get())
-                        .unwrap()
-                        .borrow()
-                        .ty
-                    {
+                    Key::Up => match app.get_active_block().borrow().ty {
                         // TODO: make this more flexible
                         UIBlockType::Collections => {
-                            app.collections.previous();
+                            app.select_prev_collection();
                         }
                         _ => {
-                            let i = match app.tbl_state.selected() {
-                                Some(i) => {
-                                    if i == 0 {
-                                        app.filtered_documents.items.len() - 1
-                                    } else {
-                                        i - 1
-                                    }
-                                }
-                                None => 0,
-                            };
-                            app.tbl_state.select(Some(i));
+                            app.select_previous_row();
                             app.filtered_documents.previous();
                         }
                     },
@@ -201,12 +274,7 @@ async fn start_ui(user_config: UserConfig) -> Result<()> {
                         }
                     }
                     Key::Enter => {
-                        handle_enter(
-                            &app,
-                            &user_config.behavior.zotero_storage_dir,
-                            &user_config.behavior.pdf_viewer,
-                        )
-                        .await?;
+                        handle_enter(&mut app, &user_config).await;
                     }
                     _ => {}
                 }
@@ -214,25 +282,36 @@ async fn start_ui(user_config: UserConfig) -> Result<()> {
 
             event::Event::Tick => {
                 app.update_on_tick();
+                handle_db_change(&mut app, &user_config).await;
+                handle_citation_requests(&mut app, &user_config);
+                handle_workers(&mut app).await;
             }
         }
     }
     // restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    suspend_terminal(&mut terminal)?;
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // setup terminal
+    let matches = cli::build_cli().get_matches();
     let mut user_config = UserConfig::new();
     user_config.load_config().unwrap();
-    start_ui(user_config).await?;
+    match matches.subcommand() {
+        ("pull", Some(_)) => cli::run_pull(&user_config).await?,
+        ("sync", Some(sync_matches)) => cli::run_sync(sync_matches, &user_config).await?,
+        ("cited", Some(cited_matches)) => cli::run_cited(cited_matches, &user_config).await?,
+        ("search", Some(search_matches)) => cli::run_search(search_matches, &user_config).await?,
+        ("list", Some(list_matches)) => cli::run_list(list_matches, &user_config).await?,
+        ("show", Some(show_matches)) => cli::run_show(show_matches, &user_config).await?,
+        ("open", Some(open_matches)) => cli::run_open(open_matches, &user_config).await?,
+        ("export", Some(export_matches)) => cli::run_export(export_matches, &user_config).await?,
+        ("pick", Some(pick_matches)) => cli::run_pick(pick_matches, &user_config).await?,
+        ("serve", Some(serve_matches)) => cli::run_serve(serve_matches, &user_config).await?,
+        ("cite", Some(cite_matches)) => cli::run_cite(cite_matches, &user_config).await?,
+        _ => start_ui(user_config).await?,
+    }
     Ok(())
 }
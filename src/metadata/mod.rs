@@ -0,0 +1,538 @@
+//! Metadata of new items, looked up by identifier (see `MetadataProvider`) or read from
+//! pasted BibTeX and RIS records.
+
+pub mod pdf;
+
+use std::{fmt, sync::OnceLock, time::Duration};
+
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+
+use crate::{
+    data_structures::{Creator, DateParts, RcDoc},
+    export::{bibtex, csl_json, new_document, ris},
+};
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+const ARXIV_NS: &str = "http://arxiv.org/schemas/atom";
+
+/// An identifier the metadata of an item can be looked up with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identifier {
+    Doi(String),
+    /// Without hyphens
+    Isbn(String),
+    /// With its version if one was given, e.g. `2101.00001v2` or `hep-th/9901001`
+    Arxiv(String),
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Identifier::Doi(doi) => write!(f, "DOI {}", doi),
+            Identifier::Isbn(isbn) => write!(f, "ISBN {}", isbn),
+            Identifier::Arxiv(id) => write!(f, "arXiv:{}", id),
+        }
+    }
+}
+
+impl Identifier {
+    /// Recognize a DOI, ISBN or arXiv ID, bare or as an URL or with a prefix like `doi:`
+    pub fn parse(input: &str) -> Option<Identifier> {
+        let input = input.trim();
+        let doi = strip_doi_prefix(input);
+        if doi.starts_with("10.") && doi.contains('/') && !doi.contains(char::is_whitespace) {
+            return Some(Identifier::Doi(doi.to_string()));
+        }
+        let arxiv = strip_prefixes(
+            input,
+            &[
+                "https://arxiv.org/abs/",
+                "http://arxiv.org/abs/",
+                "https://arxiv.org/pdf/",
+                "http://arxiv.org/pdf/",
+                "arxiv:",
+            ],
+        );
+        let arxiv = arxiv.trim_end_matches(".pdf");
+        if is_arxiv_id(arxiv) {
+            return Some(Identifier::Arxiv(arxiv.to_string()));
+        }
+        let isbn: String = strip_prefixes(input, &["isbn:", "isbn"])
+            .chars()
+            .filter(|c| !matches!(c, '-' | ' ' | ':'))
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        is_isbn(&isbn).then_some(Identifier::Isbn(isbn))
+    }
+}
+
+/// How DOIs are written as links or with a prefix
+const DOI_PREFIXES: &[&str] = &[
+    "https://doi.org/",
+    "http://doi.org/",
+    "https://dx.doi.org/",
+    "http://dx.doi.org/",
+    "dx.doi.org/",
+    "doi.org/",
+    "doi:",
+];
+
+/// `doi` without the link or prefix it is written with, like `https://doi.org/` or
+/// `DOI:`, ignoring case
+pub fn strip_doi_prefix(doi: &str) -> &str {
+    strip_prefixes(doi.trim(), DOI_PREFIXES)
+}
+
+/// `input` without the first of `prefixes` it starts with, ignoring case
+fn strip_prefixes<'a>(input: &'a str, prefixes: &[&str]) -> &'a str {
+    prefixes
+        .iter()
+        .find(|prefix| {
+            input
+                .get(..prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        })
+        .map_or(input, |prefix| input[prefix.len()..].trim_start())
+}
+
+/// New style IDs (`YYMM.NNNNN`) and old style ones (`archive.XX/YYMMNNN`), both with an
+/// optional version
+fn is_arxiv_id(id: &str) -> bool {
+    let id = match id.rsplit_once('v') {
+        Some((id, version))
+            if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            id
+        }
+        _ => id,
+    };
+    let digits = |part: &str, lengths: &[usize]| {
+        lengths.contains(&part.len()) && part.chars().all(|c| c.is_ascii_digit())
+    };
+    match id.split_once('/') {
+        Some((archive, number)) => {
+            let name = archive.split('.').next().unwrap_or_default();
+            !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+                && digits(number, &[7])
+        }
+        None => match id.split_once('.') {
+            Some((month, number)) => digits(month, &[4]) && digits(number, &[4, 5]),
+            None => false,
+        },
+    }
+}
+
+/// ISBN-10 or ISBN-13 with a valid check digit
+fn is_isbn(isbn: &str) -> bool {
+    let digits: Vec<u32> = isbn
+        .chars()
+        .enumerate()
+        .filter_map(|(idx, c)| match c {
+            'X' if idx == 9 && isbn.len() == 10 => Some(10),
+            c => c.to_digit(10),
+        })
+        .collect();
+    if digits.len() != isbn.len() {
+        return false;
+    }
+    match digits.len() {
+        10 => {
+            let sum: u32 = (0..10).map(|idx| (10 - idx as u32) * digits[idx]).sum();
+            sum.is_multiple_of(11)
+        }
+        13 => {
+            let sum: u32 = (0..13)
+                .map(|idx| digits[idx] * if idx % 2 == 0 { 1 } else { 3 })
+                .sum();
+            sum.is_multiple_of(10)
+        }
+        _ => false,
+    }
+}
+
+/// Looks up the metadata of an item by its identifier. The documents it returns only
+/// exist in memory until they are added to the library. Fetching is kept apart from
+/// reading the answer, so that lookups can run on a worker thread while the documents,
+/// which cannot be sent between threads, are made on the thread that owns the library.
+pub trait MetadataProvider: Send + Sync {
+    /// The answer of the service that knows `id`, see `parse_answer`
+    fn fetch(&self, id: &Identifier) -> anyhow::Result<String>;
+}
+
+/// The item described by the answer fetched for `id`
+pub fn parse_answer(id: &Identifier, answer: &str) -> anyhow::Result<RcDoc> {
+    match id {
+        Identifier::Doi(doi) => from_csl_json(answer, doi),
+        Identifier::Isbn(isbn) => from_open_library(answer, isbn),
+        Identifier::Arxiv(arxiv_id) => from_arxiv_atom(answer, arxiv_id),
+    }
+}
+
+/// Looks DOIs up at doi.org, ISBNs at Open Library and arXiv IDs with the arXiv API
+#[derive(Default)]
+pub struct WebProvider {
+    /// Built on the first lookup
+    agent: OnceLock<ureq::Agent>,
+}
+
+impl WebProvider {
+    fn agent(&self) -> &ureq::Agent {
+        self.agent.get_or_init(|| {
+            ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(20))
+                .user_agent(concat!("rustero/", env!("CARGO_PKG_VERSION")))
+                .build()
+        })
+    }
+
+    fn get(&self, url: &str, accept: &str) -> anyhow::Result<String> {
+        match self.agent().get(url).set("Accept", accept).call() {
+            Ok(response) => Ok(response.into_string()?),
+            Err(ureq::Error::Status(404, _)) => Err(anyhow!("Nothing found at {}", url)),
+            Err(err) => Err(anyhow!("Cannot get {}: {}", url, err)),
+        }
+    }
+}
+
+impl MetadataProvider for WebProvider {
+    fn fetch(&self, id: &Identifier) -> anyhow::Result<String> {
+        match id {
+            Identifier::Doi(doi) => {
+                // Characters like `#` are part of some DOIs, only the slashes stay
+                let path: Vec<String> = doi
+                    .split('/')
+                    .map(|part| form_urlencoded::byte_serialize(part.as_bytes()).collect())
+                    .collect();
+                let url = format!("https://doi.org/{}", path.join("/"));
+                self.get(&url, "application/vnd.citationstyles.csl+json")
+            }
+            Identifier::Isbn(isbn) => {
+                let url = format!(
+                    "https://openlibrary.org/api/books?bibkeys=ISBN:{}&format=json&jscmd=data",
+                    isbn
+                );
+                self.get(&url, "application/json")
+            }
+            Identifier::Arxiv(arxiv_id) => {
+                let url = format!("https://export.arxiv.org/api/query?id_list={}", arxiv_id);
+                self.get(&url, "application/atom+xml")
+            }
+        }
+    }
+}
+
+/// A creator from a name written `First Last` or `Last, First`
+fn creator_from_name(name: &str, creator_type: &str) -> Creator {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let parts = match name.split_once(", ") {
+        Some((last, first)) => Some((first, last)),
+        None => name.rsplit_once(' '),
+    };
+    match parts {
+        Some((first, last)) => Creator {
+            firstName: Some(first.to_string()),
+            lastName: Some(last.to_string()),
+            creatorType: Some(creator_type.to_string()),
+            fieldMode: Some(0),
+        },
+        None => Creator {
+            firstName: None,
+            lastName: Some(name),
+            creatorType: Some(creator_type.to_string()),
+            fieldMode: Some(1),
+        },
+    }
+}
+
+/// The CSL-JSON item doi.org answers with for a DOI
+pub fn from_csl_json(json: &str, doi: &str) -> anyhow::Result<RcDoc> {
+    let mut item: Value = serde_json::from_str(json)?;
+    let object = item
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Unexpected metadata for DOI {}", doi))?;
+    object
+        .entry("id")
+        .or_insert_with(|| Value::String(doi.to_string()));
+    let doc = csl_json::from_csl_item(&item)?;
+    let mut doc_mut = doc.borrow_mut();
+    // The id is no citation key, one is generated for the new item
+    doc_mut.citation_key.clear();
+    let date = doc_mut.item_data.pubdate.clone();
+    if !date.is_empty() {
+        doc_mut.fields.insert("date".to_string(), date);
+    }
+    doc_mut
+        .fields
+        .entry("DOI".to_string())
+        .or_insert_with(|| doi.to_string());
+    drop(doc_mut);
+    Ok(doc)
+}
+
+/// A book from the answer of the Open Library books API
+pub fn from_open_library(json: &str, isbn: &str) -> anyhow::Result<RcDoc> {
+    let value: Value = serde_json::from_str(json)?;
+    let book = value
+        .get(format!("ISBN:{}", isbn))
+        .ok_or_else(|| anyhow!("No book found for ISBN {}", isbn))?;
+    let get = |key: &str| book.get(key).and_then(Value::as_str).map(str::trim);
+    let names = |key: &str| -> Vec<&str> {
+        book.get(key)
+            .and_then(Value::as_array)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| entry.get("name").and_then(Value::as_str))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let doc = new_document("book", "");
+    let mut doc_mut = doc.borrow_mut();
+    let title = match (get("title"), get("subtitle")) {
+        (Some(title), Some(subtitle)) => format!("{}: {}", title, subtitle),
+        (title, _) => title.unwrap_or_default().to_string(),
+    };
+    doc_mut.item_data.title = title.clone();
+    doc_mut.fields.insert("title".to_string(), title);
+    doc_mut.fields.insert("ISBN".to_string(), isbn.to_string());
+    if let Some(publisher) = names("publishers").first() {
+        doc_mut
+            .fields
+            .insert("publisher".to_string(), publisher.to_string());
+    }
+    if let Some(place) = names("publish_places").first() {
+        doc_mut
+            .fields
+            .insert("place".to_string(), place.to_string());
+    }
+    if let Some(pages) = book.get("number_of_pages").and_then(Value::as_u64) {
+        doc_mut
+            .fields
+            .insert("numPages".to_string(), pages.to_string());
+    }
+    if let Some(date) = get("publish_date").and_then(DateParts::zotero_date_from_text) {
+        doc_mut.item_data.pubdate = date.clone();
+        doc_mut.fields.insert("date".to_string(), date);
+    }
+    doc_mut.creators = names("authors")
+        .into_iter()
+        .map(|name| creator_from_name(name, "author"))
+        .collect();
+    drop(doc_mut);
+    Ok(doc)
+}
+
+/// A preprint from the Atom feed of the arXiv API
+pub fn from_arxiv_atom(xml: &str, arxiv_id: &str) -> anyhow::Result<RcDoc> {
+    let feed = roxmltree::Document::parse(xml)?;
+    let entry = feed
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name((ATOM_NS, "entry")))
+        .ok_or_else(|| anyhow!("arXiv has no paper {}", arxiv_id))?;
+    let text = |ns: &str, name: &str| {
+        entry
+            .children()
+            .find(|node| node.has_tag_name((ns, name)))
+            .and_then(|node| node.text())
+            .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|text| !text.is_empty())
+    };
+    // Unknown IDs give an entry describing the error
+    if text(ATOM_NS, "id").is_some_and(|id| id.contains("api/errors")) {
+        bail!("arXiv has no paper {}", arxiv_id);
+    }
+
+    let doc = new_document("preprint", "");
+    let mut doc_mut = doc.borrow_mut();
+    let title = text(ATOM_NS, "title").unwrap_or_default();
+    doc_mut.item_data.title = title.clone();
+    doc_mut.fields.insert("title".to_string(), title);
+    if let Some(summary) = text(ATOM_NS, "summary") {
+        doc_mut.item_data.abstracttext = summary.clone();
+        doc_mut.fields.insert("abstractNote".to_string(), summary);
+    }
+    if let Some(date) = text(ATOM_NS, "published").and_then(|published| {
+        DateParts::zotero_date_from_text(published.get(..10).unwrap_or(&published))
+    }) {
+        doc_mut.item_data.pubdate = date.clone();
+        doc_mut.fields.insert("date".to_string(), date);
+    }
+    if let Some(doi) = text(ARXIV_NS, "doi") {
+        doc_mut.fields.insert("DOI".to_string(), doi);
+    }
+    doc_mut
+        .fields
+        .insert("repository".to_string(), "arXiv".to_string());
+    doc_mut
+        .fields
+        .insert("archiveID".to_string(), format!("arXiv:{}", arxiv_id));
+    doc_mut.fields.insert(
+        "url".to_string(),
+        format!("https://arxiv.org/abs/{}", arxiv_id),
+    );
+    doc_mut.creators = entry
+        .children()
+        .filter(|node| node.has_tag_name((ATOM_NS, "author")))
+        .filter_map(|author| {
+            author
+                .children()
+                .find(|node| node.has_tag_name((ATOM_NS, "name")))
+                .and_then(|node| node.text())
+        })
+        .map(|name| creator_from_name(name, "author"))
+        .collect();
+    drop(doc_mut);
+    Ok(doc)
+}
+
+/// Items to add to the library from the BibTeX or RIS records the user entered, when it
+/// isn't an identifier to look up (see `Identifier::parse`)
+pub fn read_records(input: &str) -> anyhow::Result<Vec<RcDoc>> {
+    let input = input.trim();
+    let docs = if input.starts_with('@') || input.contains("\n@") {
+        bibtex::parse(input)?
+    } else if input.lines().any(|line| line.starts_with("TY  -")) {
+        ris::parse(input)?
+    } else {
+        let start: String = input.chars().take(40).collect();
+        bail!("\"{}\" isn't a DOI, ISBN, arXiv ID, BibTeX or RIS", start);
+    };
+    if docs.is_empty() {
+        bail!("No BibTeX or RIS record found");
+    }
+    // Keep the citation keys of the records, as Better BibTeX does when importing
+    for doc in &docs {
+        let mut doc = doc.borrow_mut();
+        if doc.citation_key.is_empty() || doc.get_pinned_citation_key().is_some() {
+            continue;
+        }
+        let pinned = format!("Citation Key: {}", doc.citation_key);
+        let extra = match doc.get_field("extra") {
+            Some(extra) if !extra.trim().is_empty() => format!("{}\n{}", extra, pinned),
+            _ => pinned,
+        };
+        doc.fields.insert("extra".to_string(), extra);
+    }
+    Ok(docs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers from fixtures instead of the web
+    struct MockProvider;
+
+    impl MetadataProvider for MockProvider {
+        fn fetch(&self, id: &Identifier) -> anyhow::Result<String> {
+            let answer = match id {
+                Identifier::Doi(doi) if doi == "10.1000/xyz" => {
+                    r#"{"type": "article-journal", "title": "A study",
+                        "author": [{"family": "Doe", "given": "Jane"}],
+                        "container-title": "Journal", "issued": {"date-parts": [[2020, 3]]}}"#
+                }
+                Identifier::Isbn(_) => include_str!("fixtures/openlibrary.json"),
+                Identifier::Arxiv(_) => include_str!("fixtures/arxiv.xml"),
+                id => bail!("Nothing found for {}", id),
+            };
+            Ok(answer.to_string())
+        }
+    }
+
+    #[test]
+    fn test_parse_identifier() {
+        let doi = |doi: &str| Some(Identifier::Doi(doi.to_string()));
+        assert_eq!(Identifier::parse(" 10.1000/xyz "), doi("10.1000/xyz"));
+        assert_eq!(Identifier::parse("doi: 10.1000/xyz"), doi("10.1000/xyz"));
+        assert_eq!(
+            Identifier::parse("HTTP://DX.DOI.ORG/10.1000/xyz"),
+            doi("10.1000/xyz")
+        );
+        assert_eq!(
+            Identifier::parse("https://doi.org/10.1000/a(b)#c"),
+            doi("10.1000/a(b)#c")
+        );
+        let isbn = |isbn: &str| Some(Identifier::Isbn(isbn.to_string()));
+        assert_eq!(
+            Identifier::parse("978-0-262-03384-8"),
+            isbn("9780262033848")
+        );
+        assert_eq!(Identifier::parse("ISBN 0-8044-2957-x"), isbn("080442957X"));
+        assert_eq!(Identifier::parse("978-0-262-03384-9"), None);
+        let arxiv = |id: &str| Some(Identifier::Arxiv(id.to_string()));
+        assert_eq!(Identifier::parse("1706.03762v7"), arxiv("1706.03762v7"));
+        assert_eq!(Identifier::parse("arXiv:2101.00001"), arxiv("2101.00001"));
+        assert_eq!(
+            Identifier::parse("https://arxiv.org/pdf/hep-th/9901001.pdf"),
+            arxiv("hep-th/9901001")
+        );
+        assert_eq!(
+            Identifier::parse("math.GT/0309136"),
+            arxiv("math.GT/0309136")
+        );
+        assert_eq!(Identifier::parse("a study of things"), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let lookup = |input: &str| {
+            let id = Identifier::parse(input).unwrap();
+            parse_answer(&id, &MockProvider.fetch(&id)?)
+        };
+        let doc = lookup("https://doi.org/10.1000/xyz").unwrap();
+        let doc = doc.borrow();
+        assert_eq!(doc.item_data.typeName, "journalArticle");
+        assert_eq!(doc.get_field("DOI"), Some("10.1000/xyz"));
+        assert_eq!(doc.get_field("date"), Some("2020-03-00 2020-03"));
+        assert_eq!(doc.citation_key, "");
+        assert!(lookup("10.1000/unknown").is_err());
+
+        let book = lookup("9780262033848").unwrap();
+        let book = book.borrow();
+        assert_eq!(
+            book.get_title(),
+            "Introduction to algorithms: third edition"
+        );
+        assert_eq!(book.get_field("publisher"), Some("MIT Press"));
+        assert_eq!(book.get_field("numPages"), Some("1292"));
+        assert_eq!(book.get_field("date"), Some("2009-00-00 2009"));
+        assert_eq!(
+            book.creators[1],
+            Creator {
+                firstName: Some("Charles E.".to_string()),
+                lastName: Some("Leiserson".to_string()),
+                creatorType: Some("author".to_string()),
+                fieldMode: Some(0),
+            }
+        );
+
+        let preprint = lookup("arXiv:1706.03762v7").unwrap();
+        let preprint = preprint.borrow();
+        assert_eq!(preprint.item_data.typeName, "preprint");
+        assert_eq!(preprint.get_title(), "Attention Is All You Need");
+        assert_eq!(preprint.get_field("date"), Some("2017-06-12 2017-06-12"));
+        assert_eq!(preprint.get_field("archiveID"), Some("arXiv:1706.03762v7"));
+        assert_eq!(preprint.get_field("DOI"), Some("10.48550/arXiv.1706.03762"));
+        assert_eq!(preprint.creators.len(), 2);
+    }
+
+    #[test]
+    fn test_read_records() {
+        let docs =
+            read_records("@article{doe2020,\n  title = {Pasted},\n  note = {Read twice},\n}")
+                .unwrap();
+        assert_eq!(
+            docs[0].borrow().get_field("extra"),
+            Some("Read twice\nCitation Key: doe2020")
+        );
+        let docs = read_records("TY  - BOOK\nTI  - From RIS\nER  - \n").unwrap();
+        assert_eq!(docs[0].borrow().get_title(), "From RIS");
+        assert_eq!(docs[0].borrow().get_field("extra"), None);
+        assert!(read_records("not an identifier").is_err());
+    }
+}
@@ -0,0 +1,568 @@
+//! Items proposed for the PDFs of a directory, from the metadata embedded in the files
+//! (the Info dictionary and XMP) and DOIs printed on their first page.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use lopdf::{decode_text_string, Dictionary};
+
+use super::{creator_from_name, parse_answer, Identifier, MetadataProvider};
+use crate::{data_structures::RcDoc, export::new_document};
+
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// What was found in a PDF
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PdfMetadata {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub doi: Option<String>,
+}
+
+/// Where the metadata of a proposed item comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PdfSource {
+    /// The DOI found in the PDF, looked up with the metadata provider
+    Lookup,
+    Embedded,
+    FileName,
+}
+
+impl PdfSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PdfSource::Lookup => "DOI lookup",
+            PdfSource::Embedded => "PDF metadata",
+            PdfSource::FileName => "file name",
+        }
+    }
+}
+
+/// An item to create for a PDF, which is stored as its attachment once accepted
+pub struct PdfCandidate {
+    pub path: PathBuf,
+    pub doc: RcDoc,
+    pub source: PdfSource,
+    pub accepted: bool,
+    /// An item of the library has the same DOI or title
+    pub duplicate: bool,
+}
+
+/// A PDF to import, read and with its DOI looked up on a worker thread, see `read_pdfs`
+pub struct PdfLookup {
+    pub path: PathBuf,
+    pub metadata: PdfMetadata,
+    /// What the metadata provider answered for the DOI, see `parse_answer`
+    pub answer: Option<String>,
+}
+
+/// The PDFs in `dir` and its subdirectories, hidden ones left out. Symbolic links to
+/// directories are not followed, they could lead back to `dir`.
+pub fn find_pdfs(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut pdfs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let visible = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| !name.starts_with('.'));
+        if !visible {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            pdfs.extend(find_pdfs(&path)?);
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+        {
+            pdfs.push(path);
+        }
+    }
+    pdfs.sort();
+    Ok(pdfs)
+}
+
+/// Read the title, authors and DOI of the PDF at `path`
+pub fn read_pdf(path: &Path) -> anyhow::Result<PdfMetadata> {
+    Ok(pdf_metadata(&lopdf::Document::load(path)?))
+}
+
+/// XMP takes precedence over the Info dictionary, which is often left to the authoring
+/// tool. The DOI is looked for in both and then on the first page.
+fn pdf_metadata(pdf: &lopdf::Document) -> PdfMetadata {
+    let mut metadata = xmp_packet(pdf)
+        .map(|xmp| xmp_metadata(&xmp))
+        .unwrap_or_default();
+    if let Some(info) = info_dict(pdf) {
+        let text = |key: &[u8]| {
+            info.get(key)
+                .ok()
+                .and_then(|obj| pdf.dereference(obj).ok())
+                .and_then(|(_, obj)| decode_text_string(obj).ok())
+                .map(|text| collapse_whitespace(&text))
+                .filter(|text| !text.is_empty())
+        };
+        if metadata.title.is_none() {
+            metadata.title = text(b"Title").filter(|title| is_useful_title(title));
+        }
+        if metadata.authors.is_empty() {
+            metadata.authors = text(b"Author")
+                .map(|authors| split_authors(&authors))
+                .unwrap_or_default();
+        }
+        if metadata.doi.is_none() {
+            metadata.doi = [&b"doi"[..], b"DOI", b"Subject", b"Keywords"]
+                .into_iter()
+                .find_map(|key| text(key).and_then(|text| find_doi(&text)));
+        }
+    }
+    if metadata.doi.is_none() {
+        metadata.doi = pdf.extract_text(&[1]).ok().and_then(|text| find_doi(&text));
+    }
+    metadata
+}
+
+fn info_dict(pdf: &lopdf::Document) -> Option<&Dictionary> {
+    let info = pdf.trailer.get(b"Info").ok()?;
+    pdf.dereference(info).ok()?.1.as_dict().ok()
+}
+
+/// The XMP packet of the `Metadata` stream of the catalog
+fn xmp_packet(pdf: &lopdf::Document) -> Option<String> {
+    let metadata = pdf.catalog().ok()?.get(b"Metadata").ok()?;
+    let stream = pdf.dereference(metadata).ok()?.1.as_stream().ok()?;
+    let content = stream
+        .decompressed_content()
+        .unwrap_or_else(|_| stream.content.clone());
+    String::from_utf8(content).ok()
+}
+
+fn xmp_metadata(xmp: &str) -> PdfMetadata {
+    let mut metadata = PdfMetadata::default();
+    // Some writers pad the packet or leave garbage after it
+    let xml = match xmp.find("<x:xmpmeta").or_else(|| xmp.find("<rdf:RDF")) {
+        Some(start) => &xmp[start..],
+        None => return metadata,
+    };
+    let end = ["</x:xmpmeta>", "</rdf:RDF>"]
+        .iter()
+        .find_map(|tag| xml.rfind(tag).map(|idx| idx + tag.len()));
+    let xml = match end {
+        Some(end) => &xml[..end],
+        None => return metadata,
+    };
+    let doc = match roxmltree::Document::parse(xml) {
+        Ok(doc) => doc,
+        Err(_) => return metadata,
+    };
+    let items = |name: &str| -> Vec<String> {
+        doc.descendants()
+            .filter(|node| node.has_tag_name((DC_NS, name)))
+            .flat_map(|node| node.descendants())
+            .filter(|node| node.has_tag_name((RDF_NS, "li")))
+            .filter_map(|node| node.text())
+            .map(collapse_whitespace)
+            .filter(|text| !text.is_empty())
+            .collect()
+    };
+    metadata.title = items("title")
+        .into_iter()
+        .find(|title| is_useful_title(title));
+    metadata.authors = items("creator");
+    // `prism:doi` and `pdfx:doi` are written as elements or as attributes
+    let doi_elements = doc
+        .descendants()
+        .filter(|node| node.tag_name().name() == "doi")
+        .filter_map(|node| node.text().map(str::to_string));
+    let doi_attributes = doc.descendants().flat_map(|node| {
+        node.attributes()
+            .filter(|attr| attr.name() == "doi")
+            .map(|attr| attr.value().to_string())
+            .collect::<Vec<_>>()
+    });
+    metadata.doi = doi_elements
+        .chain(doi_attributes)
+        .chain(items("identifier"))
+        .find_map(|text| find_doi(&text));
+    metadata
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether a title is a real one, rather than a file name or a placeholder left by the
+/// authoring tool
+fn is_useful_title(title: &str) -> bool {
+    let lower = title.trim().to_lowercase();
+    let placeholder = ["untitled", "title", "no title", "document", "slide 1"];
+    let file_name = [".pdf", ".doc", ".docx", ".dvi", ".tex", ".ps", ".indd"]
+        .iter()
+        .any(|ext| lower.ends_with(ext));
+    lower.chars().count() > 3
+        && !placeholder.contains(&lower.as_str())
+        && !file_name
+        && !lower.starts_with("microsoft word - ")
+}
+
+/// Authors of the Info dictionary, separated by semicolons, `and` or commas
+fn split_authors(authors: &str) -> Vec<String> {
+    let authors = authors.replace(" and ", ";").replace(" & ", ";");
+    let parts: Vec<&str> = if authors.contains(';') {
+        authors.split(';').collect()
+    } else if authors.split(',').all(|part| part.trim().contains(' ')) {
+        // `Jane Doe, John Roe`, but not `Doe, Jane`
+        authors.split(',').collect()
+    } else {
+        vec![authors.as_str()]
+    };
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The first DOI in `text`, without the punctuation that ends the sentence around it
+pub fn find_doi(text: &str) -> Option<String> {
+    let mut start = 0;
+    while let Some(idx) = text[start..].find("10.") {
+        let idx = start + idx;
+        start = idx + 3;
+        if text[..idx]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+        {
+            continue;
+        }
+        let candidate = &text[idx..];
+        let end = candidate
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '<' | '>'))
+            .unwrap_or(candidate.len());
+        let mut doi = &candidate[..end];
+        loop {
+            let trimmed = doi.trim_end_matches(['.', ',', ';', ':', '\'']);
+            let unbalanced = |open: char, close: char| {
+                trimmed.ends_with(close)
+                    && trimmed.matches(open).count() < trimmed.matches(close).count()
+            };
+            doi = if unbalanced('(', ')') || unbalanced('[', ']') {
+                &trimmed[..trimmed.len() - 1]
+            } else {
+                trimmed
+            };
+            if doi.len() == trimmed.len() {
+                break;
+            }
+        }
+        let valid = match doi.split_once('/') {
+            Some((prefix, suffix)) => {
+                prefix.len() >= 7
+                    && prefix[3..].chars().all(|c| c.is_ascii_digit() || c == '.')
+                    && !suffix.is_empty()
+            }
+            None => false,
+        };
+        if valid {
+            return Some(doi.to_string());
+        }
+    }
+    None
+}
+
+/// Read the PDFs of `dir` and look their DOIs up with `provider`, telling `progress`
+/// about each PDF. PDFs that cannot be read are kept without metadata.
+pub fn read_pdfs(
+    dir: &Path,
+    provider: &dyn MetadataProvider,
+    progress: &dyn Fn(String),
+) -> anyhow::Result<Vec<PdfLookup>> {
+    let paths = find_pdfs(dir)?;
+    let total = paths.len();
+    Ok(paths
+        .into_iter()
+        .enumerate()
+        .map(|(idx, path)| {
+            let name = path.strip_prefix(dir).unwrap_or(&path).display();
+            progress(format!("Reading PDF {} of {}: {}", idx + 1, total, name));
+            let metadata = read_pdf(&path).unwrap_or_default();
+            let answer = metadata
+                .doi
+                .as_ref()
+                .and_then(|doi| provider.fetch(&Identifier::Doi(doi.clone())).ok());
+            PdfLookup {
+                path,
+                metadata,
+                answer,
+            }
+        })
+        .collect())
+}
+
+/// The item to create for a PDF, from the answer to the lookup of its DOI. Without one or
+/// if the answer cannot be read, the item is made of what the PDF says about itself.
+pub fn propose_item(pdf: &PdfLookup) -> PdfCandidate {
+    let PdfLookup {
+        path,
+        metadata,
+        answer,
+    } = pdf;
+    let candidate = |doc, source| PdfCandidate {
+        path: path.clone(),
+        doc,
+        source,
+        accepted: true,
+        duplicate: false,
+    };
+    if let (Some(doi), Some(answer)) = (&metadata.doi, answer) {
+        if let Ok(doc) = parse_answer(&Identifier::Doi(doi.clone()), answer) {
+            return candidate(doc, PdfSource::Lookup);
+        }
+    }
+    let item_type = match metadata.doi {
+        Some(_) => "journalArticle",
+        None => "document",
+    };
+    let doc = new_document(item_type, "");
+    let mut doc_mut = doc.borrow_mut();
+    let title = metadata.title.clone().unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().replace('_', " "))
+            .unwrap_or_default()
+    });
+    let source = match metadata == &PdfMetadata::default() {
+        true => PdfSource::FileName,
+        false => PdfSource::Embedded,
+    };
+    doc_mut.item_data.title = title.clone();
+    doc_mut.fields.insert("title".to_string(), title);
+    if let Some(doi) = &metadata.doi {
+        doc_mut.fields.insert("DOI".to_string(), doi.clone());
+    }
+    doc_mut.creators = metadata
+        .authors
+        .iter()
+        .map(|name| creator_from_name(name, "author"))
+        .collect();
+    drop(doc_mut);
+    candidate(doc, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use lopdf::{
+        content::{Content, Operation},
+        dictionary, text_string as text, Object, Stream,
+    };
+
+    use super::*;
+    use crate::{data_structures::Creator, test_dir::TestDir};
+
+    /// A one page PDF with the given Info dictionary, XMP packet and text on the page
+    fn pdf(info: Option<Dictionary>, xmp: Option<&str>, page_text: &str) -> Vec<u8> {
+        let mut pdf = lopdf::Document::with_version("1.5");
+        let pages_id = pdf.new_object_id();
+        let font_id = pdf.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        let content = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![72.into(), 700.into()]),
+                Operation::new("Tj", vec![Object::string_literal(page_text)]),
+                Operation::new("ET", vec![]),
+            ],
+        };
+        let content_id = pdf.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+        let page_id = pdf.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        pdf.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let mut catalog = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+        if let Some(xmp) = xmp {
+            let metadata_id = pdf.add_object(Stream::new(
+                dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+                xmp.as_bytes().to_vec(),
+            ));
+            catalog.set("Metadata", metadata_id);
+        }
+        let catalog_id = pdf.add_object(catalog);
+        pdf.trailer.set("Root", catalog_id);
+        if let Some(info) = info {
+            let info_id = pdf.add_object(info);
+            pdf.trailer.set("Info", info_id);
+        }
+        let mut buffer = Vec::new();
+        pdf.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn metadata_of(buffer: &[u8]) -> PdfMetadata {
+        pdf_metadata(&lopdf::Document::load_mem(buffer).unwrap())
+    }
+
+    const XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:prism="http://prismstandard.org/namespaces/basic/2.0/" prism:doi="10.1016/j.cell.2020.01.001">
+<dc:title><rdf:Alt><rdf:li xml:lang="x-default">Cells  in
+  context</rdf:li></rdf:Alt></dc:title>
+<dc:creator><rdf:Seq><rdf:li>Jane Doe</rdf:li><rdf:li>John Roe</rdf:li></rdf:Seq></dc:creator>
+</rdf:Description></rdf:RDF></x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn test_pdf_metadata() {
+        let info = dictionary! {
+            "Title" => text("Microsoft Word - draft3.docx"),
+            "Author" => text("Doe, Jane; Roe, John"),
+        };
+        let from_info = metadata_of(&pdf(
+            Some(info.clone()),
+            None,
+            "Available at https://doi.org/10.1000/abc(1)2. Received 2020",
+        ));
+        assert_eq!(
+            from_info,
+            PdfMetadata {
+                title: None,
+                authors: vec!["Doe, Jane".to_string(), "Roe, John".to_string()],
+                doi: Some("10.1000/abc(1)2".to_string()),
+            }
+        );
+
+        let from_xmp = metadata_of(&pdf(Some(info), Some(XMP), "doi:10.1000/other"));
+        assert_eq!(from_xmp.title.as_deref(), Some("Cells in context"));
+        assert_eq!(from_xmp.authors, vec!["Jane Doe", "John Roe"]);
+        assert_eq!(from_xmp.doi.as_deref(), Some("10.1016/j.cell.2020.01.001"));
+
+        let utf16 =
+            dictionary! { "Title" => text("Über Zahlen"), "Author" => text("A Bé and C Dé") };
+        let from_utf16 = metadata_of(&pdf(Some(utf16), None, "No identifier here"));
+        assert_eq!(from_utf16.title.as_deref(), Some("Über Zahlen"));
+        assert_eq!(from_utf16.authors, vec!["A Bé", "C Dé"]);
+        assert_eq!(from_utf16.doi, None);
+    }
+
+    #[test]
+    fn test_find_doi() {
+        assert_eq!(
+            find_doi("see (doi:10.1145/3290605.3300233)."),
+            Some("10.1145/3290605.3300233".to_string())
+        );
+        assert_eq!(
+            find_doi("10.1002/(SICI)1097-4571(199806)49:8<693::AID-ASI4>3.0.CO;2-0"),
+            Some("10.1002/(SICI)1097-4571(199806)49:8".to_string())
+        );
+        assert_eq!(find_doi("version 110.1234/x and 10.12/x"), None);
+        assert_eq!(
+            find_doi("pages 10.5 to 11, doi 10.5555/12345678,"),
+            Some("10.5555/12345678".to_string())
+        );
+    }
+
+    /// Knows a single DOI
+    struct OneDoi;
+
+    impl MetadataProvider for OneDoi {
+        fn fetch(&self, id: &Identifier) -> anyhow::Result<String> {
+            match id {
+                Identifier::Doi(doi) if doi == "10.1000/known" => {
+                    Ok(r#"{"type": "article-journal", "title": "Looked up"}"#.to_string())
+                }
+                id => Err(anyhow::anyhow!("Nothing found for {}", id)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_propose_items() {
+        let dir = TestDir::new("pdf-import-test");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        let info = dictionary! { "Title" => text("Embedded title"), "Author" => text("Jane Doe") };
+        fs::write(dir.join("a.pdf"), pdf(None, None, "DOI: 10.1000/known")).unwrap();
+        fs::write(
+            dir.join("sub/b.PDF"),
+            pdf(Some(info), None, "10.1000/unknown"),
+        )
+        .unwrap();
+        fs::write(dir.join("sub/my_paper.pdf"), "not a pdf").unwrap();
+        fs::write(dir.join(".hidden/c.pdf"), pdf(None, None, "")).unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        // Would be found again and again if links to directories were followed
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("..", dir.join("sub/loop")).unwrap();
+
+        let progress = RefCell::new(Vec::new());
+        let pdfs = read_pdfs(&dir, &OneDoi, &|message| {
+            progress.borrow_mut().push(message)
+        })
+        .unwrap();
+        assert_eq!(
+            progress.borrow().first().map(String::as_str),
+            Some("Reading PDF 1 of 3: a.pdf")
+        );
+        let candidates: Vec<PdfCandidate> = pdfs.iter().map(propose_item).collect();
+        let summary: Vec<(String, PdfSource, String)> = candidates
+            .iter()
+            .map(|candidate| {
+                let doc = candidate.doc.borrow();
+                (
+                    candidate.path.file_name().unwrap().to_string_lossy().into(),
+                    candidate.source,
+                    doc.get_title().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.pdf".into(), PdfSource::Lookup, "Looked up".into()),
+                ("b.PDF".into(), PdfSource::Embedded, "Embedded title".into()),
+                (
+                    "my_paper.pdf".into(),
+                    PdfSource::FileName,
+                    "my paper".into()
+                ),
+            ]
+        );
+        let embedded = candidates[1].doc.borrow();
+        assert_eq!(
+            embedded.creators[0],
+            Creator {
+                firstName: Some("Jane".to_string()),
+                lastName: Some("Doe".to_string()),
+                creatorType: Some("author".to_string()),
+                fieldMode: Some(0),
+            }
+        );
+        assert_eq!(embedded.item_data.typeName, "journalArticle");
+        assert_eq!(embedded.get_field("DOI"), Some("10.1000/unknown"));
+        assert_eq!(candidates[2].doc.borrow().item_data.typeName, "document");
+    }
+}
@@ -0,0 +1,496 @@
+use std::{fs, process::Command};
+
+use anyhow::anyhow;
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::opener::{expand_template, OpenContext};
+
+/// Zotero cuts note titles after this many characters
+const MAX_TITLE_LENGTH: usize = 120;
+
+/// A tag or the text between tags of a note
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open { name: String, href: Option<String> },
+    Close(String),
+    Text(String),
+}
+
+/// Split note HTML into tags and decoded text. Comments and unknown markup are dropped,
+/// notes are edited in Zotero's rich text editor and only use a handful of tags.
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.split_once("-->").map_or("", |(_, after)| after);
+            continue;
+        }
+        let tag = rest
+            .strip_prefix('<')
+            .filter(|tag| tag.starts_with(|c: char| c.is_ascii_alphabetic() || c == '/'))
+            .and_then(|tag| tag.split_once('>'));
+        match tag {
+            Some((tag, after)) => {
+                rest = after;
+                let tag = tag.trim_end_matches('/');
+                let (name, attrs) = tag
+                    .split_once(|c: char| c.is_whitespace())
+                    .unwrap_or((tag, ""));
+                match name.strip_prefix('/') {
+                    Some(name) => tokens.push(Token::Close(name.to_lowercase())),
+                    None => tokens.push(Token::Open {
+                        name: name.to_lowercase(),
+                        href: attribute(attrs, "href"),
+                    }),
+                }
+            }
+            None => {
+                // A lone '<' is text too, the run goes on to the next tag
+                let first = rest.chars().next().map_or(1, char::len_utf8);
+                let end = rest[first..]
+                    .find('<')
+                    .map_or(rest.len(), |idx| idx + first);
+                tokens.push(Token::Text(decode_entities(&rest[..end])));
+                rest = &rest[end..];
+            }
+        }
+    }
+    tokens
+}
+
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    let start = attrs.find(&format!("{}=", name))? + name.len() + 1;
+    let value = &attrs[start..];
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split(char::is_whitespace).next()?,
+    };
+    Some(decode_entities(value))
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find('&') {
+        decoded.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "li"
+            | "pre"
+            | "blockquote"
+            | "hr"
+            | "table"
+            | "tr"
+    )
+}
+
+/// Builds the Markdown text, prefixing lines inside block quotes. Line breaks are only
+/// written before the next text, so that blocks don't leave trailing empty lines.
+#[derive(Default)]
+struct MarkdownWriter {
+    out: String,
+    quote_depth: usize,
+    /// Line breaks to write before the next text, 2 for an empty line
+    pending: usize,
+    /// Lowest quote depth since the break was requested, for the empty line
+    break_depth: usize,
+    line_empty: bool,
+    /// A heading or list marker was just written, the text follows
+    after_marker: bool,
+}
+
+impl MarkdownWriter {
+    fn request_break(&mut self, breaks: usize) {
+        if self.out.is_empty() {
+            return;
+        }
+        if self.pending == 0 {
+            self.break_depth = self.quote_depth;
+        }
+        self.break_depth = self.break_depth.min(self.quote_depth);
+        self.pending = self.pending.max(breaks);
+    }
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.pending > 0 || self.line_empty || self.after_marker
+    }
+    fn prefix(depth: usize) -> String {
+        "> ".repeat(depth)
+    }
+    fn write(&mut self, text: &str) {
+        if self.pending > 0 {
+            // Trailing spaces would make a hard line break
+            self.out.truncate(self.out.trim_end_matches(' ').len());
+            if self.pending > 1 {
+                self.out.push('\n');
+                self.out.push_str(Self::prefix(self.break_depth).trim_end());
+            }
+            self.out.push('\n');
+            self.pending = 0;
+            self.line_empty = true;
+        }
+        if self.line_empty || self.out.is_empty() {
+            self.out.push_str(&Self::prefix(self.quote_depth));
+        }
+        self.out.push_str(text);
+        self.line_empty = false;
+        self.after_marker = false;
+    }
+    /// Write preformatted text, which may span several lines
+    fn write_lines(&mut self, text: &str) {
+        let newline = format!("\n{}", Self::prefix(self.quote_depth));
+        self.write(&text.replace('\n', &newline));
+    }
+    /// Remove the line breaks written at the end of preformatted text
+    fn trim_lines(&mut self) {
+        let newline = format!("\n{}", Self::prefix(self.quote_depth));
+        while self.out.ends_with(&newline) {
+            self.out.truncate(self.out.len() - newline.len());
+        }
+    }
+}
+
+/// Escape characters that would otherwise be read as Markdown
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Convert the HTML of a Zotero note to Markdown for editing, see `markdown_to_html`.
+/// Formatting without a Markdown equivalent (colors, underlines) is dropped.
+pub fn html_to_markdown(html: &str) -> String {
+    let mut md = MarkdownWriter::default();
+    // Ordered lists with the next item number, `None` for bullet lists
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    let mut links: Vec<Option<String>> = Vec::new();
+    let mut in_pre = false;
+    let mut in_code = false;
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) if in_pre => {
+                // A line break right after `<pre>` isn't part of the text
+                let text = match md.out.ends_with("```") {
+                    true => text.strip_prefix('\n').unwrap_or(&text),
+                    false => &text,
+                };
+                md.write_lines(text);
+            }
+            Token::Text(text) => {
+                let mut collapsed = text
+                    .split(|c: char| c.is_whitespace() && c != '\u{a0}')
+                    .filter(|word| !word.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if text.starts_with(|c: char| c.is_whitespace() && c != '\u{a0}')
+                    && !md.at_line_start()
+                    && !md.out.ends_with(' ')
+                {
+                    collapsed.insert(0, ' ');
+                }
+                if text.ends_with(|c: char| c.is_whitespace() && c != '\u{a0}')
+                    && !collapsed.is_empty()
+                {
+                    collapsed.push(' ');
+                }
+                if collapsed.trim().is_empty() && md.at_line_start() {
+                    continue;
+                }
+                let collapsed = collapsed.replace('\u{a0}', " ");
+                match in_code {
+                    true => md.write(&collapsed),
+                    false => md.write(&escape_markdown(&collapsed)),
+                }
+            }
+            Token::Open { name, href } => match name.as_str() {
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    md.request_break(2);
+                    let level = name[1..].parse().unwrap_or(1);
+                    md.write(&format!("{} ", "#".repeat(level)));
+                    md.after_marker = true;
+                }
+                "p" | "div" | "table" if lists.is_empty() => md.request_break(2),
+                "ul" | "ol" => {
+                    md.request_break(if lists.is_empty() { 2 } else { 1 });
+                    lists.push((name == "ol").then_some(1));
+                }
+                "li" => {
+                    md.request_break(1);
+                    let indent = "   ".repeat(lists.len().saturating_sub(1));
+                    match lists.last_mut() {
+                        Some(Some(number)) => {
+                            md.write(&format!("{}{}. ", indent, number));
+                            *number += 1;
+                        }
+                        _ => md.write(&format!("{}- ", indent)),
+                    }
+                    md.after_marker = true;
+                }
+                "blockquote" => {
+                    md.request_break(2);
+                    md.quote_depth += 1;
+                }
+                "pre" => {
+                    md.request_break(2);
+                    md.write("```");
+                    md.request_break(1);
+                    in_pre = true;
+                }
+                "hr" => {
+                    md.request_break(2);
+                    md.write("---");
+                    md.request_break(2);
+                }
+                "tr" => md.request_break(1),
+                "br" => {
+                    md.write("\\");
+                    md.request_break(1);
+                }
+                "strong" | "b" => md.write("**"),
+                "em" | "i" => md.write("*"),
+                "s" | "del" | "strike" => md.write("~~"),
+                "code" if !in_pre => {
+                    md.write("`");
+                    in_code = true;
+                }
+                "a" => {
+                    if href.is_some() {
+                        md.write("[");
+                    }
+                    links.push(href);
+                }
+                _ => {}
+            },
+            Token::Close(name) => match name.as_str() {
+                "ul" | "ol" => {
+                    lists.pop();
+                    if lists.is_empty() {
+                        md.request_break(2);
+                    }
+                }
+                "blockquote" => {
+                    md.quote_depth = md.quote_depth.saturating_sub(1);
+                    md.request_break(2);
+                }
+                "pre" => {
+                    in_pre = false;
+                    md.trim_lines();
+                    md.request_break(1);
+                    md.write("```");
+                    md.request_break(2);
+                }
+                "strong" | "b" => md.write("**"),
+                "em" | "i" => md.write("*"),
+                "s" | "del" | "strike" => md.write("~~"),
+                "code" if !in_pre => {
+                    md.write("`");
+                    in_code = false;
+                }
+                "a" => {
+                    if let Some(Some(href)) = links.pop() {
+                        md.write(&format!("]({})", href.replace(' ', "%20")));
+                    }
+                }
+                "td" | "th" => md.write(" "),
+                name if is_block(name) && lists.is_empty() => md.request_break(2),
+                _ => {}
+            },
+        }
+    }
+    let mut markdown = md.out.trim_end().to_string();
+    markdown.push('\n');
+    markdown
+}
+
+/// Convert Markdown back to note HTML, see `html_to_markdown`
+pub fn markdown_to_html(markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH);
+    let mut body = String::new();
+    html::push_html(&mut body, parser);
+    body.trim_end().to_string()
+}
+
+/// The text of a note, one line per paragraph
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    for token in tokenize(html) {
+        match token {
+            Token::Text(part) => text.push_str(&part),
+            Token::Open { name, .. } | Token::Close(name)
+                if (is_block(&name) || name == "br")
+                    && !text.is_empty()
+                    && !text.ends_with('\n') =>
+            {
+                text.push('\n')
+            }
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Title of a note, its first line like in Zotero
+pub fn note_title(html: &str) -> String {
+    let text = html_to_text(html);
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    line.chars().take(MAX_TITLE_LENGTH).collect()
+}
+
+/// Editor for notes: `$VISUAL`, `$EDITOR` or `vi`
+fn editor_command() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Let the user edit `text` in their editor, in the foreground. The terminal must be
+/// given back to the editor before, see `suspend_terminal` in main.rs. Returns the edited
+/// text, or `None` if it is unchanged. `extension` lets the editor pick the syntax.
+pub fn edit_in_editor(text: &str, name: &str, extension: &str) -> anyhow::Result<Option<String>> {
+    let file_name = format!("rustero-{}-{}.{}", name, std::process::id(), extension);
+    let path = std::env::temp_dir().join(file_name);
+    fs::write(&path, text)?;
+    let path_str = path.to_string_lossy();
+    let ctx = OpenContext {
+        path: &path_str,
+        page: None,
+        key: name,
+    };
+    let argv = expand_template(&editor_command(), &ctx)?;
+    let status = Command::new(&argv[0])
+        .args(&argv[1..])
+        .status()
+        .map_err(|err| anyhow!("Failed to start {}: {}", argv[0], err));
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let status = status?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", argv[0], status));
+    }
+    let edited = edited?;
+    Ok((edited != text).then_some(edited))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = r#"<div data-schema-version="8"><h1>Reading notes</h1>
+<p>The <strong>main</strong> result is in <em>section 3</em>, see
+<a href="https://example.com/a?b=1&amp;c=2">the appendix</a>.</p>
+<ul><li>fast &amp; simple</li><li>uses <code>x_1</code></li></ul>
+<ol><li>first</li><li>second</li></ol>
+<blockquote><p>A quote</p><p>on two paragraphs</p></blockquote>
+<pre>let x = 1;
+let y = 2;</pre>
+<p>Line one<br>line two</p></div>"#;
+
+    #[test]
+    fn test_html_to_markdown() {
+        let markdown = html_to_markdown(NOTE);
+        assert_eq!(
+            markdown,
+            "# Reading notes
+
+The **main** result is in *section 3*, see [the appendix](https://example.com/a?b=1&c=2).
+
+- fast & simple
+- uses `x_1`
+
+1. first
+2. second
+
+> A quote
+>
+> on two paragraphs
+
+```
+let x = 1;
+let y = 2;
+```
+
+Line one\\
+line two
+"
+        );
+        // The note survives the way back, apart from the wrapper and whitespace
+        let html = markdown_to_html(&markdown);
+        assert_eq!(html_to_markdown(&html), markdown);
+        assert!(html.starts_with("<h1>Reading notes</h1>\n<p>The <strong>main</strong>"));
+        assert!(html.contains("<li>uses <code>x_1</code></li>"));
+
+        assert_eq!(
+            html_to_markdown("<p>a &lt;b&gt; &#233;&#x21;</p>"),
+            "a \\<b> é!\n"
+        );
+        assert_eq!(note_title(NOTE), "Reading notes");
+        assert_eq!(
+            note_title("<p></p><p>  Second &amp; last  </p>"),
+            "Second & last"
+        );
+        assert_eq!(
+            note_title("<p>Über “quotes” 1 < 2</p>"),
+            "Über “quotes” 1 < 2"
+        );
+        assert_eq!(
+            html_to_markdown("<p>“Zitat”</p><p>Ünïcode</p>"),
+            "“Zitat”\n\nÜnïcode\n"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+
+#[cfg(target_os = "macos")]
+const FALLBACK_OPENER: &str = "open {path}";
+#[cfg(not(target_os = "macos"))]
+const FALLBACK_OPENER: &str = "xdg-open {path}";
+
+/// Values substituted into the `{path}`, `{page}` and `{key}` placeholders of an opener
+pub struct OpenContext<'a> {
+    pub path: &'a str,
+    pub page: Option<u32>,
+    pub key: &'a str,
+}
+
+/// Maps content types (`application/pdf`, `text/*`) or file extensions (`epub`)
+/// to command templates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Openers {
+    pub templates: HashMap<String, String>,
+}
+
+impl Openers {
+    pub fn new() -> Self {
+        Self {
+            templates: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, pattern: &str, template: &str) {
+        self.templates
+            .insert(normalize_pattern(pattern), template.to_string());
+    }
+
+    /// Find the template for a content type and/or path. Exact content types win over
+    /// extensions, which win over wildcards. Falls back to `xdg-open`.
+    pub fn template_for(&self, content_type: Option<&str>, path: &str) -> &str {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        let mut candidates = Vec::new();
+        if let Some(content_type) = content_type {
+            let content_type = content_type.to_lowercase();
+            if let Some((major, _)) = content_type.split_once('/') {
+                candidates.push(content_type.clone());
+                if let Some(ext) = &extension {
+                    candidates.push(ext.clone());
+                }
+                candidates.push(format!("{}/*", major));
+            } else {
+                candidates.push(content_type);
+            }
+        } else if let Some(ext) = &extension {
+            candidates.push(ext.clone());
+        }
+        candidates.push("*".to_string());
+
+        candidates
+            .iter()
+            .find_map(|candidate| self.templates.get(candidate))
+            .map(|template| template.as_str())
+            .unwrap_or(FALLBACK_OPENER)
+    }
+
+    /// Open a web link with the `url` opener, or the system default browser.
+    pub fn open_url(&self, url: &str) -> Result<()> {
+        let template = self
+            .templates
+            .get("url")
+            .map(|template| template.as_str())
+            .unwrap_or(FALLBACK_OPENER);
+        let ctx = OpenContext {
+            path: url,
+            page: None,
+            key: "",
+        };
+        spawn_detached(&expand_template(template, &ctx)?)
+    }
+
+    pub fn open(&self, content_type: Option<&str>, ctx: &OpenContext) -> Result<()> {
+        let argv = expand_template(self.template_for(content_type, ctx.path), ctx)?;
+        spawn_detached(&argv)
+    }
+}
+
+fn normalize_pattern(pattern: &str) -> String {
+    pattern.trim().trim_start_matches('.').to_lowercase()
+}
+
+/// Split a command template into arguments (honoring single and double quotes)
+/// and substitute the placeholders in each argument.
+pub fn expand_template(template: &str, ctx: &OpenContext) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    for c in template.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(anyhow!("Unterminated quote in opener \"{}\"", template));
+    }
+    if in_arg {
+        args.push(current);
+    }
+    if args.is_empty() {
+        return Err(anyhow!("Empty opener command"));
+    }
+    // Programs given without a placeholder get the path appended, like `pdf_viewer` used to
+    if !template.contains("{path}") {
+        args.push("{path}".to_string());
+    }
+    let page = ctx.page.unwrap_or(1).to_string();
+    Ok(args
+        .into_iter()
+        .map(|arg| {
+            arg.replace("{path}", ctx.path)
+                .replace("{page}", &page)
+                .replace("{key}", ctx.key)
+        })
+        .collect())
+}
+
+/// Start `argv` without tying it to the terminal: its output would otherwise be drawn
+/// over the TUI and it would receive our signals.
+pub fn spawn_detached(argv: &[String]) -> Result<()> {
+    let mut command = Command::new(&argv[0]);
+    command
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|err| anyhow!("Failed to start {}: {}", argv[0], err))?;
+    // Reap the child once it exits so it does not linger as a zombie
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx() -> OpenContext<'static> {
+        OpenContext {
+            path: "/tmp/my paper.pdf",
+            page: Some(3),
+            key: "ABCD1234",
+        }
+    }
+
+    #[test]
+    fn test_expand_template() {
+        assert_eq!(
+            expand_template("zathura --page={page} {path}", &ctx()).unwrap(),
+            vec!["zathura", "--page=3", "/tmp/my paper.pdf"]
+        );
+        assert_eq!(
+            expand_template("zathura", &ctx()).unwrap(),
+            vec!["zathura", "/tmp/my paper.pdf"]
+        );
+        assert_eq!(
+            expand_template("sh -c 'echo {key}' {path}", &ctx()).unwrap(),
+            vec!["sh", "-c", "echo ABCD1234", "/tmp/my paper.pdf"]
+        );
+        assert!(expand_template("sh -c 'echo", &ctx()).is_err());
+    }
+
+    #[test]
+    fn test_template_for() {
+        let mut openers = Openers::new();
+        openers.insert("application/pdf", "zathura {path}");
+        openers.insert(".epub", "foliate {path}");
+        openers.insert("text/*", "firefox {path}");
+        assert_eq!(
+            openers.template_for(Some("application/pdf"), "a.pdf"),
+            "zathura {path}"
+        );
+        assert_eq!(
+            openers.template_for(Some("application/epub+zip"), "a.EPUB"),
+            "foliate {path}"
+        );
+        assert_eq!(
+            openers.template_for(Some("text/html"), "index.html"),
+            "firefox {path}"
+        );
+        assert_eq!(openers.template_for(None, "a.epub"), "foliate {path}");
+        assert_eq!(openers.template_for(None, "a.djvu"), FALLBACK_OPENER);
+    }
+}
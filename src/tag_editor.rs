@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use crate::data_structures::{RcDoc, StatefulList, Tag};
+
+/// Number of completions offered at once
+const MAX_COMPLETIONS: usize = 8;
+
+/// Tags of the documents and how many of them have each, sorted by name
+pub fn count_tags(docs: &[RcDoc]) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for doc in docs {
+        for tag in &doc.borrow().tags {
+            *counts
+                .entry((tag.name.to_lowercase(), tag.name.clone()))
+                .or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|((_, name), count)| (name, count))
+        .collect()
+}
+
+/// Give the document the tag, keeping its tags sorted by name
+pub fn push_tag(doc: &RcDoc, tag: &Tag) {
+    let mut doc = doc.borrow_mut();
+    if !doc.tags.iter().any(|existing| existing.name == tag.name) {
+        doc.tags.push(tag.clone());
+        doc.tags.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+}
+
+/// Adds tags to and removes tags from the marked documents. New tags are completed from
+/// the tags of the library.
+pub struct TagEditor {
+    pub docs: Vec<RcDoc>,
+    /// Tag to add
+    pub input: String,
+    /// Tags of the documents and how many of them have each
+    pub tags: StatefulList<(String, usize)>,
+    library_tags: Vec<String>,
+    /// What was typed before completing with Tab, and the completion shown
+    completing: Option<(String, usize)>,
+}
+
+impl TagEditor {
+    pub fn new(docs: Vec<RcDoc>, library: &[RcDoc]) -> Self {
+        let mut editor = Self {
+            docs,
+            input: String::new(),
+            tags: StatefulList::with_items(Vec::new()),
+            library_tags: count_tags(library)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect(),
+            completing: None,
+        };
+        editor.refresh();
+        editor
+    }
+    /// Count the tags of the documents again after they changed
+    pub fn refresh(&mut self) {
+        let selected = self.selected_tag();
+        self.tags.items = count_tags(&self.docs);
+        let idx = selected
+            .and_then(|name| self.tags.items.iter().position(|(tag, _)| *tag == name))
+            .or(self.tags.state.selected())
+            .map(|idx| idx.min(self.tags.items.len().saturating_sub(1)));
+        self.tags.state.select(if self.tags.items.is_empty() {
+            None
+        } else {
+            idx.or(Some(0))
+        });
+    }
+    pub fn selected_tag(&self) -> Option<String> {
+        let idx = self.tags.state.selected()?;
+        self.tags.items.get(idx).map(|(name, _)| name.clone())
+    }
+    /// Remember a tag added to the library, to offer it for completion
+    pub fn add_library_tag(&mut self, name: &str) {
+        if !self.library_tags.iter().any(|tag| tag == name) {
+            self.library_tags.push(name.to_string());
+        }
+    }
+    pub fn push_char(&mut self, c: char) {
+        self.completing = None;
+        self.input.push(c);
+    }
+    pub fn pop_char(&mut self) {
+        self.completing = None;
+        self.input.pop();
+    }
+    pub fn clear_input(&mut self) {
+        self.completing = None;
+        self.input.clear();
+    }
+    /// Library tags for what was typed, those starting with it first. Tags every document
+    /// has already are left out.
+    pub fn completions(&self) -> Vec<&str> {
+        let typed = match &self.completing {
+            Some((typed, _)) => typed,
+            None => &self.input,
+        };
+        let typed = typed.trim().to_lowercase();
+        if typed.is_empty() {
+            return Vec::new();
+        }
+        let everywhere = |name: &str| {
+            self.tags
+                .items
+                .iter()
+                .any(|(tag, count)| tag == name && *count == self.docs.len())
+        };
+        let mut matches: Vec<&str> = self
+            .library_tags
+            .iter()
+            .map(String::as_str)
+            .filter(|name| name.to_lowercase().contains(&typed) && !everywhere(name))
+            .collect();
+        matches.sort_by_key(|name| !name.to_lowercase().starts_with(&typed));
+        matches.truncate(MAX_COMPLETIONS);
+        matches
+    }
+    /// Replace the input with the next completion of what was typed
+    pub fn complete(&mut self) {
+        let next = match &self.completing {
+            Some((_, idx)) => idx + 1,
+            None => 0,
+        };
+        let typed = match self.completing.take() {
+            Some((typed, _)) => typed,
+            None => self.input.clone(),
+        };
+        self.completing = Some((typed, next));
+        let completions = self.completions();
+        if completions.is_empty() {
+            self.completing = None;
+            return;
+        }
+        self.input = completions[next % completions.len()].to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::new_test_document;
+
+    fn tagged(names: &[&str]) -> RcDoc {
+        let doc = new_test_document("book", &[("title", "T")], Vec::new());
+        for name in names {
+            push_tag(
+                &doc,
+                &Tag {
+                    tagId: 0,
+                    name: name.to_string(),
+                },
+            );
+        }
+        doc
+    }
+
+    #[test]
+    fn test_tag_editor() {
+        let a = tagged(&["reading", "ml"]);
+        let b = tagged(&["ml"]);
+        let other = tagged(&["Machine learning", "math", "reviewed"]);
+        let library = vec![a.clone(), b.clone(), other];
+        assert_eq!(
+            count_tags(&library),
+            vec![
+                ("Machine learning".to_string(), 1),
+                ("math".to_string(), 1),
+                ("ml".to_string(), 2),
+                ("reading".to_string(), 1),
+                ("reviewed".to_string(), 1),
+            ]
+        );
+
+        let mut editor = TagEditor::new(vec![a, b], &library);
+        assert_eq!(
+            editor.tags.items,
+            vec![("ml".to_string(), 2), ("reading".to_string(), 1)]
+        );
+        // `ml` is on both documents already, tags starting with the input come first
+        editor.push_char('m');
+        assert_eq!(editor.completions(), vec!["Machine learning", "math"]);
+        editor.complete();
+        assert_eq!(editor.input, "Machine learning");
+        editor.complete();
+        assert_eq!(editor.input, "math");
+        editor.complete();
+        assert_eq!(editor.input, "Machine learning");
+        editor.clear_input();
+        editor.push_char('e');
+        assert_eq!(
+            editor.completions(),
+            vec!["Machine learning", "reading", "reviewed"]
+        );
+
+        editor.tags.state.select(Some(1));
+        for doc in &editor.docs {
+            doc.borrow_mut().tags.retain(|tag| tag.name != "ml");
+        }
+        editor.refresh();
+        assert_eq!(editor.tags.items, vec![("reading".to_string(), 1)]);
+        assert_eq!(editor.selected_tag().as_deref(), Some("reading"));
+    }
+}
@@ -0,0 +1,34 @@
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A directory of its own for one test, removed when dropped even if the test fails
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// `rustero-<name>-<pid>` in the system's temporary directory, emptied first
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rustero-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
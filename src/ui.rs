@@ -6,14 +6,18 @@ use tui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table},
+    widgets::{
+        Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState,
+        Wrap,
+    },
     Frame,
 };
 use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    app::App,
+    app::{App, CollectionPick},
     collection_tree::{CollectionNodeValue, RcCollectionNode},
+    user_config::{ColumnAlignment, ColumnConfig},
 };
 
 impl fmt::Display for UIBlockType {
@@ -28,6 +32,21 @@ impl fmt::Display for UIBlockType {
         }
     }
 }
+impl fmt::Display for ColumnKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnKind::Title => write!(f, "Title"),
+            ColumnKind::Creator => write!(f, "Creator"),
+            ColumnKind::Year => write!(f, "Year"),
+            ColumnKind::DateAdded => write!(f, "Date Added"),
+            ColumnKind::Attachments => write!(f, "Files"),
+            ColumnKind::Tags => write!(f, "Tags"),
+            ColumnKind::CitationKey => write!(f, "Citation Key"),
+            ColumnKind::ItemType => write!(f, "Item Type"),
+            ColumnKind::Field(name) => write!(f, "{}", name),
+        }
+    }
+}
 pub type RcUIBlock = Rc<RefCell<UIBlock>>;
 pub struct UIBlock {
     pub ratio: usize,
@@ -43,6 +62,54 @@ pub enum UIBlockType {
     Year,
     Collections,
 }
+/// What a column of the document table displays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnKind {
+    Title,
+    Creator,
+    Year,
+    DateAdded,
+    Attachments,
+    Tags,
+    CitationKey,
+    ItemType,
+    /// Any raw Zotero item field, e.g. `DOI` or `publicationTitle`
+    Field(String),
+}
+impl ColumnKind {
+    /// Columns offered by the column chooser even if they are not configured.
+    pub fn builtin() -> Vec<ColumnKind> {
+        vec![
+            ColumnKind::Title,
+            ColumnKind::Creator,
+            ColumnKind::Year,
+            ColumnKind::DateAdded,
+            ColumnKind::Attachments,
+            ColumnKind::Tags,
+            ColumnKind::CitationKey,
+            ColumnKind::ItemType,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopupType {
+    ColumnChooser,
+    AttachmentPicker,
+    CopyMenu,
+    ExportMenu,
+    Prompt,
+    StyleMenu,
+    Preview,
+    EditForm,
+    CollectionMenu,
+    CollectionPicker,
+    TagEditor,
+    TagManager,
+    NotePicker,
+    PdfImport,
+}
+
 impl UIBlockType {
     pub fn is_searchable(&self) -> bool {
         !matches!(self, Self::Menu | Self::Input | Self::Collections)
@@ -105,45 +172,87 @@ fn build_constraints(blocks: &Vec<RcUIBlock>) -> Vec<Constraint> {
         .map(|block| Constraint::Percentage(block.borrow().ratio as _))
         .collect()
 }
+/// Pad `content` so it is aligned inside a cell of `width` columns.
+fn align_cell_content(content: String, width: usize, alignment: ColumnAlignment) -> String {
+    let content_width = content.width();
+    if content_width >= width {
+        return content;
+    }
+    let padding = width - content_width;
+    match alignment {
+        ColumnAlignment::Left => content,
+        ColumnAlignment::Right => format!("{}{}", " ".repeat(padding), content),
+        ColumnAlignment::Center => format!("{}{}", " ".repeat(padding / 2), content),
+    }
+}
+
 fn draw_document_items<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App) {
     let mut rows = Vec::new();
-    let mut header = Vec::new();
-    header.push(Cell::from(UIBlockType::Title.to_string()));
-    header.push(Cell::from(UIBlockType::Year.to_string()));
-    header.push(Cell::from(UIBlockType::Creator.to_string()));
-    for (idx, doc) in app.filtered_documents.items.iter().enumerate() {
+    // The row mappings are rebuilt on every draw since filtering and toggling change them
+    app.map_rows();
+    let columns: Vec<ColumnConfig> = app
+        .columns
+        .items
+        .iter()
+        .filter(|col| col.visible)
+        .cloned()
+        .collect();
+    // Scale the configured widths so that the visible columns fill the table
+    let total_width: u16 = columns.iter().map(|col| col.width).sum::<u16>().max(1);
+    let widths: Vec<Constraint> = columns
+        .iter()
+        .map(|col| Constraint::Percentage(col.width * 100 / total_width))
+        .collect();
+    // Borders and column spacing are not available to the cells
+    let inner_width = rect
+        .width
+        .saturating_sub(2 + columns.len().saturating_sub(1) as u16) as usize;
+    let cell_widths: Vec<usize> = columns
+        .iter()
+        .map(|col| inner_width * col.width as usize / total_width as usize)
+        .collect();
+    let header: Vec<Cell> = columns
+        .iter()
+        .zip(&cell_widths)
+        .map(|(col, width)| {
+            Cell::from(align_cell_content(
+                col.kind.to_string(),
+                *width,
+                col.alignment,
+            ))
+        })
+        .collect();
+    for doc in app.filtered_documents.items.iter() {
         let mut cells = Vec::new();
         let doc = doc.borrow();
-        let mut row_height: u16 = 1;
-        // cells.push(Cell::from(first_cell_content));
-        cells.push(Cell::from(doc.get_title().to_owned()));
-        cells.push(Cell::from(
-            doc.get_cmp_str_for_block_type(UIBlockType::Creator)
-                .to_owned(),
-        ));
-        cells.push(Cell::from(doc.get_year().to_owned()));
-        let new_row = Row::new(cells).height(row_height);
+        let row_height: u16 = 1;
+        for (col, width) in columns.iter().zip(&cell_widths) {
+            cells.push(Cell::from(align_cell_content(
+                doc.build_cell_for_column(&col.kind),
+                *width,
+                col.alignment,
+            )));
+        }
+        let mut new_row = Row::new(cells).height(row_height);
+        if doc.marked.get() {
+            new_row = new_row.style(Style::default().fg(Color::Yellow));
+        }
         rows.push(new_row);
-        app.row_num_to_doc.insert(rows.len() - 1, idx);
 
         if doc.toggled.get() {
             // TODO: different icon based on file style
             if let Some(attachments) = &doc.attachments {
-                for att in &attachments.items {
-                    if let Some(path) = &att.path {
-                        if (row_height as usize) < attachments.items.len() {
-                            rows.push(Row::new(vec![Cell::from(
-                                format!("   ├── {}", path).replace("storage:", ""),
-                            )]));
-                        } else {
-                            rows.push(Row::new(vec![Cell::from(
-                                format!("   └── {}", path).replace("storage:", ""),
-                            )]));
-                        }
-                        row_height += 1;
-                        // Update the mapping
-                        app.row_num_to_doc.insert(rows.len() - 1, idx);
-                    }
+                for (att_idx, att) in attachments.items.iter().enumerate() {
+                    let branch = if att_idx + 1 < attachments.items.len() {
+                        "├──"
+                    } else {
+                        "└──"
+                    };
+                    rows.push(Row::new(vec![Cell::from(format!(
+                        "   {} {}",
+                        branch,
+                        att.display_name()
+                    ))]));
                 }
             }
         }
@@ -170,11 +279,7 @@ fn draw_document_items<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App)
                 .title("Documents"),
         )
         // Columns widths are constrained in the same way as Layout...
-        .widths(&[
-            Constraint::Percentage(70),
-            Constraint::Percentage(20),
-            Constraint::Percentage(10),
-        ])
+        .widths(&widths)
         // ...and they can be separated by a fixed spacing.
         .column_spacing(1)
         // If you wish to highlight a row in any specific way when it is selected...
@@ -282,11 +387,532 @@ fn draw_collection_block<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App
 
     f.render_stateful_widget(list, rect, &mut app.collections.state)
 }
+/// Helper to create a centered rect using up certain percentage of the available rect `r`
+fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+    let popup_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_y) / 2),
+                Constraint::Percentage(percent_y),
+                Constraint::Percentage((100 - percent_y) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(r);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_x) / 2),
+                Constraint::Percentage(percent_x),
+                Constraint::Percentage((100 - percent_x) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(popup_layout[1])[1]
+}
+
+fn draw_column_chooser<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App) {
+    let entries: Vec<ListItem> = app
+        .columns
+        .items
+        .iter()
+        .map(|col| {
+            ListItem::new(Span::raw(format!(
+                "[{}] {} ({}%)",
+                if col.visible { "x" } else { " " },
+                col.kind,
+                col.width
+            )))
+        })
+        .collect();
+    let list = List::new(entries)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::LightGreen),
+                )
+                .title("Columns (<Space> toggle, </> move, +/- width)"),
+        )
+        .highlight_style(
+            Style::default()
+                .bg(Color::LightGreen)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+        );
+    f.render_widget(Clear, rect);
+    f.render_stateful_widget(list, rect, &mut app.columns.state);
+}
+
+fn draw_attachment_picker<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App) {
+    let rows: Vec<Row> = app
+        .attachment_picker
+        .items
+        .iter()
+        .map(|att| {
+            Row::new(vec![
+                Cell::from(att.attachment.content_type().to_owned()),
+                Cell::from(att.attachment.display_name()),
+                Cell::from(att.human_size()),
+            ])
+        })
+        .collect();
+    let tbl = Table::new(rows)
+        .style(Style::default().fg(Color::White))
+        .header(
+            Row::new(vec!["Type", "File", "Size"]).style(Style::default().fg(Color::LightGreen)),
+        )
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::LightGreen),
+                )
+                .title("Attachments"),
+        )
+        .widths(&[
+            Constraint::Percentage(25),
+            Constraint::Percentage(60),
+            Constraint::Percentage(15),
+        ])
+        .column_spacing(1)
+        .highlight_style(
+            Style::default()
+                .bg(Color::LightGreen)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+        );
+    let mut state = TableState::default();
+    state.select(app.attachment_picker.state.selected());
+    f.render_widget(Clear, rect);
+    f.render_stateful_widget(tbl, rect, &mut state);
+}
+
+fn draw_pdf_import<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App) {
+    let rows: Vec<Row> = app
+        .pdf_import
+        .items
+        .iter()
+        .map(|candidate| {
+            let doc = candidate.doc.borrow();
+            let source = match candidate.duplicate {
+                true => format!("{}, in library", candidate.source.name()),
+                false => candidate.source.name().to_owned(),
+            };
+            let file = candidate
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            Row::new(vec![
+                Cell::from(if candidate.accepted { "[x]" } else { "[ ]" }),
+                Cell::from(doc.get_title().to_owned()),
+                Cell::from(doc.build_header_for_block_type(UIBlockType::Creator)),
+                Cell::from(source),
+                Cell::from(file),
+            ])
+        })
+        .collect();
+    let tbl = Table::new(rows)
+        .style(Style::default().fg(Color::White))
+        .header(
+            Row::new(vec!["", "Title", "Creators", "Found by", "File"])
+                .style(Style::default().fg(Color::LightGreen)),
+        )
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::LightGreen),
+                )
+                .title("Import PDFs (<Space> toggle, a: toggle all, <Enter> import)"),
+        )
+        .widths(&[
+            Constraint::Length(3),
+            Constraint::Percentage(40),
+            Constraint::Percentage(20),
+            Constraint::Percentage(15),
+            Constraint::Percentage(20),
+        ])
+        .column_spacing(1)
+        .highlight_style(
+            Style::default()
+                .bg(Color::LightGreen)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+        );
+    let mut state = TableState::default();
+    state.select(app.pdf_import.state.selected());
+    f.render_widget(Clear, rect);
+    f.render_stateful_widget(tbl, rect, &mut state);
+}
+
+fn draw_menu<B: Backend>(
+    f: &mut Frame<B>,
+    rect: Rect,
+    title: &str,
+    entries: Vec<&str>,
+    state: &mut ListState,
+) {
+    let entries: Vec<ListItem> = entries
+        .into_iter()
+        .map(|entry| ListItem::new(Span::raw(entry.to_owned())))
+        .collect();
+    let list = List::new(entries)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::LightGreen),
+                )
+                .title(title.to_owned()),
+        )
+        .highlight_style(
+            Style::default()
+                .bg(Color::LightGreen)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+        );
+    f.render_widget(Clear, rect);
+    f.render_stateful_widget(list, rect, state);
+}
+
+fn draw_prompt<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let prompt = match &app.prompt {
+        Some(prompt) => prompt,
+        None => return,
+    };
+    let size = f.size();
+    let width = size.width * 6 / 10;
+    let rect = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + size.height.saturating_sub(3) / 2,
+        width,
+        3.min(size.height),
+    );
+    let input = Paragraph::new(prompt.input.as_str()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .fg(Color::LightGreen),
+            )
+            .title(prompt.title.to_owned()),
+    );
+    f.render_widget(Clear, rect);
+    f.render_widget(input, rect);
+    f.set_cursor(rect.x + prompt.input.width() as u16 + 1, rect.y + 1);
+}
+
+fn draw_preview<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let preview = match &app.preview {
+        Some(preview) => preview,
+        None => return,
+    };
+    let mut lines = vec![
+        Spans::from(Span::styled(
+            "Citation",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Spans::from(preview.bibliography.citation.as_str()),
+        Spans::from(""),
+        Spans::from(Span::styled(
+            "Bibliography",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+    ];
+    for entry in &preview.bibliography.entries {
+        lines.push(Spans::from(entry.as_str()));
+        lines.push(Spans::from(""));
+    }
+    lines.push(Spans::from(Span::styled(
+        "f: change format, y: copy bibliography, c: copy citation, Esc: close",
+        Style::default().fg(Color::Gray),
+    )));
+    let paragraph = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::LightGreen),
+                )
+                .title(format!(
+                    "{} ({})",
+                    preview.style.title,
+                    preview.format.name()
+                )),
+        )
+        .wrap(Wrap { trim: false })
+        .scroll((preview.scroll, 0));
+    let rect = centered_rect(70, 70, f.size());
+    f.render_widget(Clear, rect);
+    f.render_widget(paragraph, rect);
+}
+
+fn draw_edit_form<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let form = match app.edit_form.as_mut() {
+        Some(form) => form,
+        None => return,
+    };
+    let label_width = form
+        .rows
+        .items
+        .iter()
+        .map(|row| row.label().width())
+        .max()
+        .unwrap_or(0);
+    let entries: Vec<ListItem> = form
+        .rows
+        .items
+        .iter()
+        .map(|row| {
+            ListItem::new(Spans::from(vec![
+                Span::styled(
+                    format!("{:width$}  ", row.label(), width = label_width),
+                    Style::default().fg(Color::Gray),
+                ),
+                Span::raw(row.value()),
+            ]))
+        })
+        .collect();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::LightGreen),
+        )
+        .title(format!("Edit {}", form.doc.borrow().get_title()));
+    let rect = centered_rect(70, 70, f.size());
+    let inner = block.inner(rect);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+        .split(inner);
+    let list = List::new(entries).highlight_style(
+        Style::default()
+            .bg(Color::LightGreen)
+            .fg(Color::Black)
+            .add_modifier(Modifier::BOLD),
+    );
+    let help = Paragraph::new(Span::styled(
+        "Enter: edit, n: new field, a: add creator, t: creator type, J/K: move creator, \
+         d: delete, s: save, Esc: discard",
+        Style::default().fg(Color::Gray),
+    ));
+    f.render_widget(Clear, rect);
+    f.render_widget(block, rect);
+    f.render_stateful_widget(list, chunks[0], &mut form.rows.state);
+    f.render_widget(help, chunks[1]);
+}
+
+fn draw_tag_editor<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let editor = match app.tag_editor.as_mut() {
+        Some(editor) => editor,
+        None => return,
+    };
+    let total = editor.docs.len();
+    let entries: Vec<ListItem> = editor
+        .tags
+        .items
+        .iter()
+        .map(|(name, count)| {
+            let count = match *count == total {
+                true => String::new(),
+                false => format!(" ({}/{})", count, total),
+            };
+            ListItem::new(Spans::from(vec![
+                Span::raw(name.to_owned()),
+                Span::styled(count, Style::default().fg(Color::Gray)),
+            ]))
+        })
+        .collect();
+    let completions = editor.completions().join(", ");
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::LightGreen),
+        )
+        .title(match total {
+            1 => format!("Tags of {}", editor.docs[0].borrow().get_title()),
+            _ => format!("Tags of {} items", total),
+        });
+    let rect = centered_rect(50, 60, f.size());
+    let inner = block.inner(rect);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Length(1),
+                Constraint::Length(1),
+                Constraint::Min(1),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
+        .split(inner);
+    let input = Paragraph::new(Spans::from(vec![
+        Span::styled("Add: ", Style::default().fg(Color::Gray)),
+        Span::raw(editor.input.as_str()),
+    ]));
+    let completions = Paragraph::new(Span::styled(completions, Style::default().fg(Color::Gray)));
+    let list = List::new(entries).highlight_style(
+        Style::default()
+            .bg(Color::LightGreen)
+            .fg(Color::Black)
+            .add_modifier(Modifier::BOLD),
+    );
+    let help = Paragraph::new(Span::styled(
+        "Enter: add, Tab: complete, Del: remove selected, Esc: close",
+        Style::default().fg(Color::Gray),
+    ));
+    f.render_widget(Clear, rect);
+    f.render_widget(block, rect);
+    f.render_widget(input, chunks[0]);
+    f.render_widget(completions, chunks[1]);
+    f.render_stateful_widget(list, chunks[2], &mut editor.tags.state);
+    f.render_widget(help, chunks[3]);
+    f.set_cursor(
+        chunks[0].x + "Add: ".len() as u16 + editor.input.width() as u16,
+        chunks[0].y,
+    );
+}
+
+fn draw_popup<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let rect = centered_rect(50, 50, f.size());
+    match app.popup_type {
+        PopupType::ColumnChooser => draw_column_chooser(f, rect, app),
+        PopupType::AttachmentPicker => draw_attachment_picker(f, rect, app),
+        PopupType::CopyMenu => draw_menu(
+            f,
+            centered_rect(30, 30, f.size()),
+            "Copy as",
+            app.copy_menu
+                .items
+                .iter()
+                .map(|format| format.name())
+                .collect(),
+            &mut app.copy_menu.state,
+        ),
+        PopupType::ExportMenu => draw_menu(
+            f,
+            centered_rect(40, 30, f.size()),
+            "Export",
+            app.export_menu
+                .items
+                .iter()
+                .map(|scope| scope.name())
+                .collect(),
+            &mut app.export_menu.state,
+        ),
+        PopupType::Prompt => draw_prompt(f, app),
+        PopupType::StyleMenu => draw_menu(
+            f,
+            centered_rect(50, 60, f.size()),
+            "Citation style",
+            app.style_menu
+                .items
+                .iter()
+                .map(|style| style.title.as_str())
+                .collect(),
+            &mut app.style_menu.state,
+        ),
+        PopupType::Preview => draw_preview(f, app),
+        PopupType::EditForm => draw_edit_form(f, app),
+        PopupType::CollectionMenu => draw_menu(
+            f,
+            centered_rect(30, 30, f.size()),
+            "Collection",
+            app.collection_menu
+                .items
+                .iter()
+                .map(|action| action.name())
+                .collect(),
+            &mut app.collection_menu.state,
+        ),
+        PopupType::TagEditor => draw_tag_editor(f, app),
+        PopupType::TagManager => {
+            let entries: Vec<String> = app
+                .tag_manager
+                .items
+                .iter()
+                .map(|(name, count)| format!("{} ({})", name, count))
+                .collect();
+            draw_menu(
+                f,
+                centered_rect(40, 60, f.size()),
+                "Tags (r: rename or merge, d: delete)",
+                entries.iter().map(String::as_str).collect(),
+                &mut app.tag_manager.state,
+            )
+        }
+        PopupType::PdfImport => draw_pdf_import(f, centered_rect(90, 70, f.size()), app),
+        PopupType::NotePicker => draw_menu(
+            f,
+            centered_rect(50, 40, f.size()),
+            "Notes",
+            app.note_picker
+                .items
+                .iter()
+                .map(|note| match note {
+                    Some(note) if note.title.is_empty() => "Untitled",
+                    Some(note) => note.title.as_str(),
+                    None => "New note",
+                })
+                .collect(),
+            &mut app.note_picker.state,
+        ),
+        PopupType::CollectionPicker => {
+            if let Some(picker) = app.collection_picker.as_mut() {
+                let title = match picker.action {
+                    CollectionPick::AddDocuments => "Add to collection",
+                    CollectionPick::Move(_) => "Move into",
+                };
+                draw_menu(
+                    f,
+                    centered_rect(50, 60, f.size()),
+                    title,
+                    picker
+                        .targets
+                        .items
+                        .iter()
+                        .map(|(path, _)| path.as_str())
+                        .collect(),
+                    &mut picker.targets.state,
+                )
+            }
+        }
+    }
+}
+
 pub fn draw_main_layout<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     let main_layout = Layout::default()
         .direction(Direction::Vertical)
         .margin(1)
-        .constraints([Constraint::Length(3), Constraint::Min(1)].as_ref())
+        .constraints(
+            [
+                Constraint::Length(3),
+                Constraint::Min(1),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
         .split(f.size());
 
     f.set_cursor(
@@ -321,6 +947,14 @@ pub fn draw_main_layout<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         .split(main_layout[1]);
     draw_collection_block(f, vert_split[0], app);
     draw_document_items(f, vert_split[1], app);
+    f.render_widget(
+        Paragraph::new(app.status_message.as_str()).style(Style::default().fg(Color::Gray)),
+        main_layout[2],
+    );
+
+    if app.show_popup.get() {
+        draw_popup(f, app);
+    }
 
     // Old code
     if false {
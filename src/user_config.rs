@@ -1,17 +1,29 @@
-use crate::event::Key;
+use crate::{
+    citation_server,
+    event::Key,
+    opener::Openers,
+    ui::ColumnKind,
+    web_sync::{self, WebApiConfig},
+};
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs,
     path::{Path, PathBuf},
 };
 use tui::style::Color;
 
 const FILE_NAME: &str = "config.yml";
+const SYNC_STATE_FILE_NAME: &str = "sync.json";
+const STAGING_DIR_NAME: &str = "staging";
+const WEB_CACHE_FILE_NAME: &str = "web-library.sqlite";
 const CONFIG_DIR: &str = ".config";
 const ZOTERO_DIR: &str = "Zotero";
 const ZOTERO_STORAGE_DIR: &str = "storage";
 const ZOTERO_DB: &str = "zotero.sqlite";
+const BETTER_BIBTEX_DB: &str = "better-bibtex.sqlite";
+const ZOTERO_STYLES_DIR: &str = "styles";
 const APP_CONFIG_DIR: &str = "rustero";
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -75,6 +87,63 @@ impl Default for Theme {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ColumnConfigString {
+    pub field: String,
+    pub width: Option<u16>,
+    pub alignment: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColumnAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnConfig {
+    pub kind: ColumnKind,
+    /// Width in percent, relative to the other visible columns
+    pub width: u16,
+    pub alignment: ColumnAlignment,
+    pub visible: bool,
+}
+
+impl ColumnConfig {
+    pub fn new(kind: ColumnKind, width: u16) -> Self {
+        Self {
+            kind,
+            width,
+            alignment: ColumnAlignment::Left,
+            visible: true,
+        }
+    }
+}
+
+fn parse_column_kind(field: &str) -> ColumnKind {
+    match field {
+        "title" => ColumnKind::Title,
+        "creator" | "creators" | "author" => ColumnKind::Creator,
+        "year" => ColumnKind::Year,
+        "date_added" | "dateAdded" => ColumnKind::DateAdded,
+        "attachments" => ColumnKind::Attachments,
+        "tags" => ColumnKind::Tags,
+        "citation_key" | "citationKey" => ColumnKind::CitationKey,
+        "item_type" | "itemType" => ColumnKind::ItemType,
+        _ => ColumnKind::Field(field.to_string()),
+    }
+}
+
+fn parse_column_alignment(alignment: &str) -> Result<ColumnAlignment> {
+    match alignment.to_lowercase().as_str() {
+        "left" => Ok(ColumnAlignment::Left),
+        "center" | "centre" => Ok(ColumnAlignment::Center),
+        "right" => Ok(ColumnAlignment::Right),
+        _ => Err(anyhow!("Unknown column alignment \"{}\"", alignment)),
+    }
+}
+
 fn parse_key(key: String) -> Result<Key> {
     fn get_single_char(string: &str) -> char {
         match string.chars().next() {
@@ -146,6 +215,12 @@ fn check_reserved_keys(key: Key) -> Result<()> {
 #[derive(Clone)]
 pub struct UserConfigPaths {
     pub config_file_path: PathBuf,
+    /// Collections bound to bibliography files, see `bib_sync::SyncState`
+    pub sync_state_path: PathBuf,
+    /// Items saved from the browser, see `connector::StagingStore`
+    pub staging_dir: PathBuf,
+    /// Library synced from the Zotero Web API, see `web_sync::pull`
+    pub web_cache_path: PathBuf,
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -176,6 +251,22 @@ pub struct KeyBindingsString {
     audio_analysis: Option<String>,
     basic_view: Option<String>,
     add_item_to_queue: Option<String>,
+    column_chooser: Option<String>,
+    open_url: Option<String>,
+    copy: Option<String>,
+    mark: Option<String>,
+    export: Option<String>,
+    import: Option<String>,
+    bibliography: Option<String>,
+    sync: Option<String>,
+    edit: Option<String>,
+    file: Option<String>,
+    unfile: Option<String>,
+    collection_menu: Option<String>,
+    tags: Option<String>,
+    tag_manager: Option<String>,
+    note: Option<String>,
+    add_item: Option<String>,
 }
 
 #[derive(Clone)]
@@ -206,6 +297,22 @@ pub struct KeyBindings {
     pub audio_analysis: Key,
     pub basic_view: Key,
     pub add_item_to_queue: Key,
+    pub column_chooser: Key,
+    pub open_url: Key,
+    pub copy: Key,
+    pub mark: Key,
+    pub export: Key,
+    pub import: Key,
+    pub bibliography: Key,
+    pub sync: Key,
+    pub edit: Key,
+    pub file: Key,
+    pub unfile: Key,
+    pub collection_menu: Key,
+    pub tags: Key,
+    pub tag_manager: Key,
+    pub note: Key,
+    pub add_item: Key,
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -224,6 +331,21 @@ pub struct BehaviorConfigString {
     pub paused_icon: Option<String>,
     pub set_window_title: Option<bool>,
     pub pdf_viewer: Option<String>,
+    pub base_attachment_dir: Option<String>,
+    pub browser: Option<String>,
+    pub export_path: Option<String>,
+    pub bibtex_dialect: Option<String>,
+    pub better_bibtex_db_path: Option<String>,
+    pub csl_styles_dir: Option<String>,
+    pub csl_style: Option<String>,
+    pub pick_template: Option<String>,
+    pub citation_server: Option<bool>,
+    pub citation_server_port: Option<u16>,
+    pub connector: Option<bool>,
+    pub zotero_api_key: Option<String>,
+    pub zotero_library: Option<String>,
+    pub zotero_api_url: Option<String>,
+    pub library_file: Option<String>,
 }
 
 #[derive(Clone)]
@@ -244,6 +366,36 @@ pub struct BehaviorConfig {
     pub zotero_storage_dir: PathBuf,
     pub zotero_db_path: PathBuf,
     pub pdf_viewer: String,
+    /// Zotero's "Linked Attachment Base Directory", `attachments:` paths are relative to it
+    pub base_attachment_dir: Option<PathBuf>,
+    /// Default file offered by the export prompt
+    pub export_path: PathBuf,
+    /// Write BibLaTeX instead of BibTeX to `.bib` files
+    pub prefer_biblatex: bool,
+    pub better_bibtex_db_path: PathBuf,
+    /// Directory with the `.csl` files offered by the bibliography preview
+    pub csl_styles_dir: PathBuf,
+    /// Style used for formatted references, a file name in `csl_styles_dir`, a style id or a path
+    pub csl_style: String,
+    /// Line printed per document by `rustero pick`, with `{key}`, `{item_key}`, `{title}`,
+    /// `{authors}`, `{year}` and `{type}` placeholders
+    pub pick_template: String,
+    /// Serve Better BibTeX's citation endpoints for editor plugins while the UI runs
+    pub citation_server: bool,
+    pub citation_server_port: u16,
+    /// Save items sent by the Zotero Connector browser extension to the staging store
+    pub connector: bool,
+    /// Sync the library from zotero.org into a cache read instead of Zotero's database
+    pub web_api: Option<WebApiConfig>,
+    /// Read the library from this CSL-JSON, BibTeX or RIS file instead of Zotero's database
+    pub library_file: Option<PathBuf>,
+}
+
+impl BehaviorConfig {
+    /// File the library is read from, watched to sync bibliography files
+    pub fn library_path(&self) -> &Path {
+        self.library_file.as_deref().unwrap_or(&self.zotero_db_path)
+    }
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -251,6 +403,8 @@ pub struct UserConfigString {
     keybindings: Option<KeyBindingsString>,
     behavior: Option<BehaviorConfigString>,
     theme: Option<UserTheme>,
+    columns: Option<Vec<ColumnConfigString>>,
+    openers: Option<HashMap<String, String>>,
 }
 
 #[derive(Clone)]
@@ -258,6 +412,9 @@ pub struct UserConfig {
     pub keys: KeyBindings,
     pub theme: Theme,
     pub behavior: BehaviorConfig,
+    pub columns: Vec<ColumnConfig>,
+    /// Command templates used to open attachments, keyed by content type or extension
+    pub openers: Openers,
     pub path_to_config: Option<UserConfigPaths>,
 }
 
@@ -292,6 +449,22 @@ impl UserConfig {
                 audio_analysis: Key::Char('v'),
                 basic_view: Key::Char('B'),
                 add_item_to_queue: Key::Char('z'),
+                column_chooser: Key::Char('V'),
+                open_url: Key::Char('w'),
+                copy: Key::Char('y'),
+                mark: Key::Char('m'),
+                export: Key::Char('E'),
+                import: Key::Char('I'),
+                bibliography: Key::Char('b'),
+                sync: Key::Char('S'),
+                edit: Key::Char('e'),
+                file: Key::Char('f'),
+                unfile: Key::Char('F'),
+                collection_menu: Key::Char('c'),
+                tags: Key::Char('t'),
+                tag_manager: Key::Char('T'),
+                note: Key::Char('n'),
+                add_item: Key::Char('a'),
             },
             behavior: BehaviorConfig {
                 seek_milliseconds: 5 * 1000,
@@ -317,7 +490,31 @@ impl UserConfig {
                     dirs::home_dir().unwrap().join(ZOTERO_DIR).join(ZOTERO_DB),
                 ),
                 pdf_viewer: "zathura".to_string(),
+                base_attachment_dir: find_base_attachment_dir(),
+                export_path: PathBuf::from("references.bib"),
+                prefer_biblatex: false,
+                better_bibtex_db_path: dirs::home_dir()
+                    .unwrap()
+                    .join(ZOTERO_DIR)
+                    .join(BETTER_BIBTEX_DB),
+                csl_styles_dir: dirs::home_dir()
+                    .unwrap()
+                    .join(ZOTERO_DIR)
+                    .join(ZOTERO_STYLES_DIR),
+                csl_style: "apa".to_string(),
+                pick_template: "{key}\t{year}\t{authors}\t{title}".to_string(),
+                citation_server: false,
+                citation_server_port: citation_server::DEFAULT_PORT,
+                connector: false,
+                web_api: None,
+                library_file: None,
             },
+            columns: vec![
+                ColumnConfig::new(ColumnKind::Title, 70),
+                ColumnConfig::new(ColumnKind::Creator, 20),
+                ColumnConfig::new(ColumnKind::Year, 10),
+            ],
+            openers: Openers::new(),
             path_to_config: None,
         }
     }
@@ -341,6 +538,9 @@ impl UserConfig {
 
                 let paths = UserConfigPaths {
                     config_file_path: config_file_path.to_path_buf(),
+                    sync_state_path: app_config_dir.join(SYNC_STATE_FILE_NAME),
+                    staging_dir: app_config_dir.join(STAGING_DIR_NAME),
+                    web_cache_path: app_config_dir.join(WEB_CACHE_FILE_NAME),
                 };
                 self.path_to_config = Some(paths);
                 Ok(())
@@ -385,6 +585,22 @@ impl UserConfig {
         to_keys!(audio_analysis);
         to_keys!(basic_view);
         to_keys!(add_item_to_queue);
+        to_keys!(column_chooser);
+        to_keys!(open_url);
+        to_keys!(copy);
+        to_keys!(mark);
+        to_keys!(export);
+        to_keys!(import);
+        to_keys!(bibliography);
+        to_keys!(sync);
+        to_keys!(edit);
+        to_keys!(file);
+        to_keys!(unfile);
+        to_keys!(collection_menu);
+        to_keys!(tags);
+        to_keys!(tag_manager);
+        to_keys!(note);
+        to_keys!(add_item);
 
         Ok(())
     }
@@ -479,11 +695,133 @@ impl UserConfig {
         }
 
         if let Some(pdf_viewer) = behavior_config.pdf_viewer {
+            // Shorthand for an `application/pdf` opener, `openers` takes precedence
+            self.openers.insert("application/pdf", &pdf_viewer);
             self.behavior.pdf_viewer = pdf_viewer;
         }
+
+        if let Some(base_attachment_dir) = behavior_config.base_attachment_dir {
+            self.behavior.base_attachment_dir = Some(PathBuf::from(base_attachment_dir));
+        }
+
+        if let Some(export_path) = behavior_config.export_path {
+            self.behavior.export_path = PathBuf::from(export_path);
+        }
+
+        if let Some(dialect) = behavior_config.bibtex_dialect {
+            self.behavior.prefer_biblatex = match dialect.to_lowercase().as_str() {
+                "bibtex" => false,
+                "biblatex" => true,
+                _ => return Err(anyhow!("Unknown BibTeX dialect \"{}\"", dialect)),
+            };
+        }
+
+        if let Some(better_bibtex_db_path) = behavior_config.better_bibtex_db_path {
+            self.behavior.better_bibtex_db_path = PathBuf::from(better_bibtex_db_path);
+        }
+
+        if let Some(csl_styles_dir) = behavior_config.csl_styles_dir {
+            self.behavior.csl_styles_dir = PathBuf::from(csl_styles_dir);
+        }
+
+        if let Some(csl_style) = behavior_config.csl_style {
+            self.behavior.csl_style = csl_style;
+        }
+
+        if let Some(pick_template) = behavior_config.pick_template {
+            self.behavior.pick_template = pick_template;
+        }
+
+        if let Some(citation_server) = behavior_config.citation_server {
+            self.behavior.citation_server = citation_server;
+        }
+
+        if let Some(port) = behavior_config.citation_server_port {
+            self.behavior.citation_server_port = port;
+        }
+
+        if let Some(connector) = behavior_config.connector {
+            self.behavior.connector = connector;
+        }
+
+        if let Some(api_key) = behavior_config.zotero_api_key {
+            let library = behavior_config.zotero_library.ok_or_else(|| {
+                anyhow!("zotero_library must be set with zotero_api_key, e.g. your user id")
+            })?;
+            let api_url = behavior_config
+                .zotero_api_url
+                .unwrap_or_else(|| web_sync::DEFAULT_API_URL.to_string());
+            self.behavior.web_api = Some(WebApiConfig::new(&api_url, &library, &api_key)?);
+            // Never Zotero's own database, the sync would overwrite it
+            self.behavior.zotero_db_path = match (&self.path_to_config, dirs::data_dir()) {
+                (Some(paths), _) => paths.web_cache_path.clone(),
+                (None, Some(dir)) => dir.join(APP_CONFIG_DIR).join(WEB_CACHE_FILE_NAME),
+                (None, None) => {
+                    return Err(anyhow!(
+                        "No directory to keep the copy of the zotero.org library in"
+                    ))
+                }
+            };
+        }
+
+        if let Some(library_file) = behavior_config.library_file {
+            self.behavior.library_file = Some(PathBuf::from(library_file));
+        }
+
+        if let Some(browser) = behavior_config.browser {
+            // Shorthand for the `url` opener
+            self.openers.insert("url", &browser);
+        }
         Ok(())
     }
 
+    pub fn load_columns(&mut self, columns: Vec<ColumnConfigString>) -> Result<()> {
+        if columns.is_empty() {
+            return Ok(());
+        }
+        self.columns.clear();
+        for column in columns {
+            let width = column.width.unwrap_or(10);
+            if width == 0 || width > 100 {
+                return Err(anyhow!(
+                    "Column width must be between 1 and 100, \"{}\" is {}",
+                    column.field,
+                    width
+                ));
+            }
+            let mut config = ColumnConfig::new(parse_column_kind(&column.field), width);
+            if let Some(alignment) = column.alignment {
+                config.alignment = parse_column_alignment(&alignment)?;
+            }
+            self.columns.push(config);
+        }
+        Ok(())
+    }
+
+    pub fn load_openers(&mut self, openers: HashMap<String, String>) -> Result<()> {
+        for (pattern, template) in openers {
+            if template.trim().is_empty() {
+                return Err(anyhow!("Opener for \"{}\" is empty", pattern));
+            }
+            self.openers.insert(&pattern, &template);
+        }
+        Ok(())
+    }
+
+    /// The configured columns followed by every other built-in column, hidden,
+    /// so that the column chooser can offer them.
+    pub fn build_columns(&self) -> Vec<ColumnConfig> {
+        let mut columns = self.columns.clone();
+        for kind in ColumnKind::builtin() {
+            if !columns.iter().any(|col| col.kind == kind) {
+                let mut hidden = ColumnConfig::new(kind, 10);
+                hidden.visible = false;
+                columns.push(hidden);
+            }
+        }
+        columns
+    }
+
     pub fn load_config(&mut self) -> Result<()> {
         let paths = match &self.path_to_config {
             Some(path) => path,
@@ -511,6 +849,12 @@ impl UserConfig {
             if let Some(theme) = config_yml.theme {
                 self.load_theme(theme)?;
             }
+            if let Some(columns) = config_yml.columns {
+                self.load_columns(columns)?;
+            }
+            if let Some(openers) = config_yml.openers {
+                self.load_openers(openers)?;
+            }
 
             Ok(())
         } else {
@@ -523,6 +867,44 @@ impl UserConfig {
     }
 }
 
+const BASE_ATTACHMENT_PREF: &str = "extensions.zotero.baseAttachmentPath";
+
+/// Extract the base attachment directory from the content of a Zotero `prefs.js`.
+fn parse_base_attachment_pref(prefs: &str) -> Option<PathBuf> {
+    let line = prefs
+        .lines()
+        .find(|line| line.contains(&format!("\"{}\"", BASE_ATTACHMENT_PREF)))?;
+    // user_pref("extensions.zotero.baseAttachmentPath", "/home/me/papers");
+    let value = line.split('"').nth(3)?;
+    if value.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(value.replace("\\\\", "\\")))
+}
+
+/// Look for the base attachment directory in the prefs of every Zotero profile.
+fn find_base_attachment_dir() -> Option<PathBuf> {
+    let mut profile_roots = Vec::new();
+    if let Some(home) = dirs::home_dir() {
+        profile_roots.push(home.join(".zotero").join("zotero"));
+        profile_roots.push(
+            home.join("Library")
+                .join("Application Support")
+                .join("Zotero")
+                .join("Profiles"),
+        );
+    }
+    if let Some(config) = dirs::config_dir() {
+        profile_roots.push(config.join("Zotero").join("Zotero").join("Profiles"));
+    }
+    profile_roots
+        .iter()
+        .filter_map(|root| fs::read_dir(root).ok())
+        .flat_map(|profiles| profiles.filter_map(|profile| profile.ok()))
+        .filter_map(|profile| fs::read_to_string(profile.path().join("prefs.js")).ok())
+        .find_map(|prefs| parse_base_attachment_pref(&prefs))
+}
+
 fn parse_theme_item(theme_item: &str) -> Result<Color> {
     let color = match theme_item {
         "Reset" => Color::Reset,
@@ -605,6 +987,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_load_columns() {
+        use super::{ColumnAlignment, ColumnConfigString, UserConfig};
+        use crate::ui::ColumnKind;
+        let mut config = UserConfig::new();
+        config
+            .load_columns(vec![
+                ColumnConfigString {
+                    field: "year".to_string(),
+                    width: Some(10),
+                    alignment: Some("right".to_string()),
+                },
+                ColumnConfigString {
+                    field: "DOI".to_string(),
+                    width: None,
+                    alignment: None,
+                },
+            ])
+            .unwrap();
+        assert_eq!(config.columns[0].kind, ColumnKind::Year);
+        assert_eq!(config.columns[0].alignment, ColumnAlignment::Right);
+        assert_eq!(config.columns[1].kind, ColumnKind::Field("DOI".to_string()));
+        let columns = config.build_columns();
+        assert!(columns
+            .iter()
+            .any(|col| col.kind == ColumnKind::Title && !col.visible));
+        assert!(config
+            .load_columns(vec![ColumnConfigString {
+                field: "title".to_string(),
+                width: Some(0),
+                alignment: None,
+            }])
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_base_attachment_pref() {
+        use super::parse_base_attachment_pref;
+        use std::path::PathBuf;
+        let prefs = r#"
+user_pref("extensions.zotero.automaticScraperUpdates", false);
+user_pref("extensions.zotero.baseAttachmentPath", "/home/me/papers");
+"#;
+        assert_eq!(
+            parse_base_attachment_pref(prefs),
+            Some(PathBuf::from("/home/me/papers"))
+        );
+        assert_eq!(
+            parse_base_attachment_pref(
+                r#"user_pref("extensions.zotero.baseAttachmentPath", "C:\\Papers");"#
+            ),
+            Some(PathBuf::from("C:\\Papers"))
+        );
+        assert_eq!(parse_base_attachment_pref("user_pref(\"a\", 1);"), None);
+    }
+
+    #[test]
+    fn test_web_api_cache() {
+        use super::{BehaviorConfigString, UserConfig, WEB_CACHE_FILE_NAME};
+
+        let mut config = UserConfig::new();
+        let zotero_db_path = config.behavior.zotero_db_path.clone();
+        config
+            .load_behaviorconfig(BehaviorConfigString {
+                zotero_api_key: Some("secret".to_string()),
+                zotero_library: Some("groups/42".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        // Without a config directory the copy still doesn't replace Zotero's database
+        assert_ne!(config.behavior.zotero_db_path, zotero_db_path);
+        assert!(config
+            .behavior
+            .zotero_db_path
+            .ends_with(WEB_CACHE_FILE_NAME));
+    }
+
+    #[test]
+    fn test_default_openers() {
+        use super::{BehaviorConfigString, UserConfig};
+
+        let mut config = UserConfig::new();
+        // Without a configured opener PDFs open with the system default, like other files
+        let fallback = config.openers.template_for(None, "a.unknown").to_string();
+        assert_eq!(
+            config
+                .openers
+                .template_for(Some("application/pdf"), "a.pdf"),
+            fallback
+        );
+        config
+            .load_behaviorconfig(BehaviorConfigString {
+                pdf_viewer: Some("zathura".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            config
+                .openers
+                .template_for(Some("application/pdf"), "a.pdf"),
+            "zathura"
+        );
+    }
+
     #[test]
     fn test_reserved_key() {
         use super::check_reserved_keys;
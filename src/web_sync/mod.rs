@@ -0,0 +1,1161 @@
+//! Synchronization with the Zotero Web API (v3) for libraries without a local Zotero.
+//!
+//! `pull` downloads what changed on zotero.org since the last sync into a cache that uses
+//! the tables of Zotero's own database, so the rest of Rustero reads it like the desktop
+//! database. Changes are fetched by library version: objects modified since the version
+//! of the cache, then the keys of objects deleted since. Nothing is uploaded, edits made
+//! in Rustero stay in the cache.
+
+use std::{fmt, path::Path, time::Duration};
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{
+    query, query_scalar,
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+    ConnectOptions, Connection,
+};
+
+use crate::{
+    data_structures::{
+        Creator, DateParts, LINK_MODE_IMPORTED_FILE, LINK_MODE_IMPORTED_URL, LINK_MODE_LINKED_FILE,
+        LINK_MODE_LINKED_URL,
+    },
+    db_connector::{begin_write, end_write, find_tag, write_item_changes_locked, ItemChanges},
+    note::note_title,
+};
+
+pub const DEFAULT_API_URL: &str = "https://api.zotero.org";
+const API_VERSION: &str = "3";
+/// Most objects the API returns for one request by key
+const BATCH_SIZE: usize = 50;
+/// Fetches restarted because the library changed in the middle of one
+const MAX_ATTEMPTS: usize = 3;
+/// The cache holds a single library
+pub const LIBRARY_ID: i64 = 1;
+
+/// Tables of Zotero's schema read by `db_connector`, with the field ids its queries use
+const CACHE_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS libraries (libraryID INTEGER PRIMARY KEY, type TEXT NOT NULL,
+    editable INT NOT NULL, filesEditable INT NOT NULL, version INT NOT NULL DEFAULT 0,
+    storageVersion INT NOT NULL DEFAULT 0, lastSync INT NOT NULL DEFAULT 0,
+    archived INT NOT NULL DEFAULT 0);
+CREATE TABLE IF NOT EXISTS itemTypes (itemTypeID INTEGER PRIMARY KEY, typeName TEXT,
+    templateItemTypeID INT, display INT DEFAULT 1);
+CREATE TABLE IF NOT EXISTS fields (fieldID INTEGER PRIMARY KEY, fieldName TEXT,
+    fieldFormatID INT);
+CREATE TABLE IF NOT EXISTS creatorTypes (creatorTypeID INTEGER PRIMARY KEY, creatorType TEXT);
+CREATE TABLE IF NOT EXISTS items (itemID INTEGER PRIMARY KEY, itemTypeID INT NOT NULL,
+    dateAdded TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    dateModified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    clientDateModified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, libraryID INT NOT NULL,
+    key TEXT NOT NULL, version INT NOT NULL DEFAULT 0, synced INT NOT NULL DEFAULT 0,
+    UNIQUE (libraryID, key));
+CREATE TABLE IF NOT EXISTS itemDataValues (valueID INTEGER PRIMARY KEY, value TEXT UNIQUE);
+CREATE TABLE IF NOT EXISTS itemData (itemID INT, fieldID INT, valueID,
+    PRIMARY KEY (itemID, fieldID));
+CREATE TABLE IF NOT EXISTS creators (creatorID INTEGER PRIMARY KEY, firstName TEXT,
+    lastName TEXT, fieldMode INT, UNIQUE (lastName, firstName, fieldMode));
+CREATE TABLE IF NOT EXISTS itemCreators (itemID INT NOT NULL, creatorID INT NOT NULL,
+    creatorTypeID INT NOT NULL DEFAULT 1, orderIndex INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (itemID, creatorID, creatorTypeID, orderIndex));
+CREATE TABLE IF NOT EXISTS collections (collectionID INTEGER PRIMARY KEY,
+    collectionName TEXT NOT NULL, parentCollectionID INT DEFAULT NULL,
+    clientDateModified TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, libraryID INT NOT NULL,
+    key TEXT NOT NULL, version INT NOT NULL DEFAULT 0, synced INT NOT NULL DEFAULT 0,
+    UNIQUE (libraryID, key));
+CREATE TABLE IF NOT EXISTS collectionItems (collectionID INT NOT NULL, itemID INT NOT NULL,
+    orderIndex INT NOT NULL DEFAULT 0, PRIMARY KEY (collectionID, itemID));
+CREATE TABLE IF NOT EXISTS tags (tagID INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+CREATE TABLE IF NOT EXISTS itemTags (itemID INT NOT NULL, tagID INT NOT NULL,
+    type INT NOT NULL, PRIMARY KEY (itemID, tagID));
+CREATE TABLE IF NOT EXISTS itemNotes (itemID INTEGER PRIMARY KEY, parentItemID INT,
+    note TEXT, title TEXT);
+CREATE TABLE IF NOT EXISTS itemAttachments (itemID INTEGER PRIMARY KEY, parentItemID INT,
+    linkMode INT, contentType TEXT, charsetID INT, path TEXT, syncState INT DEFAULT 0,
+    storageModTime INT, storageHash TEXT, lastProcessedModificationTime INT);
+INSERT OR IGNORE INTO fields (fieldID, fieldName) VALUES (1, 'title'), (2, 'abstractNote'),
+    (6, 'date');
+";
+
+/// Properties of an item's `data` that aren't fields in `itemData`
+const NON_FIELDS: [&str; 20] = [
+    "key",
+    "version",
+    "itemType",
+    "creators",
+    "tags",
+    "collections",
+    "relations",
+    "dateAdded",
+    "dateModified",
+    "parentItem",
+    "deleted",
+    "inPublications",
+    "note",
+    "linkMode",
+    "contentType",
+    "charset",
+    "filename",
+    "md5",
+    "mtime",
+    "path",
+];
+
+/// Where and as whom to reach the Web API
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebApiConfig {
+    pub api_url: String,
+    /// Library path of the API, `users/<id>` or `groups/<id>`
+    pub library: String,
+    pub api_key: String,
+}
+
+impl WebApiConfig {
+    /// `library` is a user id, as shown on zotero.org/settings/keys, or `users/<id>` or
+    /// `groups/<id>`
+    pub fn new(api_url: &str, library: &str, api_key: &str) -> anyhow::Result<Self> {
+        let library = library.trim().trim_matches('/');
+        let library = match library.split_once('/') {
+            None => format!("users/{}", library),
+            Some(("users" | "groups", _)) => library.to_string(),
+            Some(_) => bail!(
+                "zotero_library must be a user id, users/<id> or groups/<id>, not \"{}\"",
+                library
+            ),
+        };
+        let id = library.split_once('/').map_or("", |(_, id)| id);
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+            bail!("\"{}\" isn't a Zotero library id", id);
+        }
+        if api_key.trim().is_empty() {
+            bail!("zotero_api_key is empty");
+        }
+        Ok(Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            library,
+            api_key: api_key.trim().to_string(),
+        })
+    }
+
+    /// `libraries.type` of the library
+    fn library_type(&self) -> &'static str {
+        match self.library.starts_with("groups/") {
+            true => "group",
+            false => "user",
+        }
+    }
+}
+
+/// What a `pull` changed in the cache
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncReport {
+    /// Library version the cache is at
+    pub version: i64,
+    pub collections: usize,
+    pub items: usize,
+    pub deleted: usize,
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.collections + self.items + self.deleted == 0 {
+            return write!(f, "The library is up to date (version {})", self.version);
+        }
+        write!(
+            f,
+            "Synced the library to version {}: {} items and {} collections updated, {} deleted",
+            self.version, self.items, self.collections, self.deleted
+        )
+    }
+}
+
+/// Keys of objects deleted since a version, the answer of `/deleted`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+struct Deleted {
+    #[serde(default)]
+    collections: Vec<String>,
+    #[serde(default)]
+    items: Vec<String>,
+    /// Tag names
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Everything that changed since a version
+#[derive(Debug, Clone, Default)]
+struct Changes {
+    version: i64,
+    collections: Vec<Value>,
+    items: Vec<Value>,
+    deleted: Deleted,
+}
+
+/// A successful answer of the API
+struct Response {
+    body: Value,
+    /// `Last-Modified-Version`, the library version the answer is from
+    version: i64,
+}
+
+pub struct WebApiClient {
+    agent: ureq::Agent,
+    config: WebApiConfig,
+}
+
+impl WebApiClient {
+    pub fn new(config: &WebApiConfig) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(60))
+            .user_agent(concat!("rustero/", env!("CARGO_PKG_VERSION")))
+            .build();
+        Self {
+            agent,
+            config: config.clone(),
+        }
+    }
+
+    /// GET `path` of the library, `None` if the library didn't change since
+    /// `if_modified_since`
+    fn get(
+        &self,
+        path: &str,
+        params: &[(&str, &str)],
+        if_modified_since: Option<i64>,
+    ) -> anyhow::Result<Option<Response>> {
+        let url = format!("{}/{}/{}", self.config.api_url, self.config.library, path);
+        let mut request = self
+            .agent
+            .get(&url)
+            .set("Zotero-API-Version", API_VERSION)
+            .set("Zotero-API-Key", &self.config.api_key);
+        for (name, value) in params {
+            request = request.query(name, value);
+        }
+        if let Some(version) = if_modified_since {
+            request = request.set("If-Modified-Since-Version", &version.to_string());
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(403, _)) => bail!(
+                "The API key has no access to {}, check zotero_api_key and zotero_library",
+                self.config.library
+            ),
+            Err(ureq::Error::Status(status @ (429 | 503), response)) => bail!(
+                "zotero.org is busy ({}), try again in {} seconds",
+                status,
+                response
+                    .header("Retry-After")
+                    .or_else(|| response.header("Backoff"))
+                    .unwrap_or("a few")
+            ),
+            Err(err) => bail!("Cannot get {}: {}", url, err),
+        };
+        if response.status() == 304 {
+            return Ok(None);
+        }
+        let version = response
+            .header("Last-Modified-Version")
+            .and_then(|version| version.parse().ok())
+            .ok_or_else(|| anyhow!("{} didn't send a library version", url))?;
+        let body = serde_json::from_str(&response.into_string()?)
+            .map_err(|err| anyhow!("Unexpected answer from {}: {}", url, err))?;
+        Ok(Some(Response { body, version }))
+    }
+
+    /// Versions by key of the `kind` objects modified since `since`
+    fn versions(
+        &self,
+        kind: &str,
+        since: i64,
+        if_modified_since: Option<i64>,
+    ) -> anyhow::Result<Option<(Vec<String>, i64)>> {
+        let since = since.to_string();
+        let Some(response) = self.get(
+            kind,
+            &[
+                ("since", &since),
+                ("format", "versions"),
+                ("includeTrashed", "1"),
+            ],
+            if_modified_since,
+        )?
+        else {
+            return Ok(None);
+        };
+        let mut keys: Vec<String> = response
+            .body
+            .as_object()
+            .ok_or_else(|| anyhow!("Unexpected versions of {}", kind))?
+            .keys()
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(Some((keys, response.version)))
+    }
+
+    /// The `kind` objects with `keys`, and the library versions of the answers
+    fn objects(
+        &self,
+        kind: &str,
+        key_param: &str,
+        keys: &[String],
+    ) -> anyhow::Result<(Vec<Value>, Vec<i64>)> {
+        let mut objects = Vec::new();
+        let mut versions = Vec::new();
+        let limit = BATCH_SIZE.to_string();
+        for batch in keys.chunks(BATCH_SIZE) {
+            let keys = batch.join(",");
+            let response = self
+                .get(
+                    kind,
+                    &[
+                        (key_param, &keys),
+                        ("includeTrashed", "1"),
+                        ("limit", &limit),
+                    ],
+                    None,
+                )?
+                .ok_or_else(|| anyhow!("Unexpected answer for {}", kind))?;
+            match response.body {
+                Value::Array(batch) => objects.extend(batch),
+                _ => bail!("Unexpected answer for {}", kind),
+            }
+            versions.push(response.version);
+        }
+        Ok((objects, versions))
+    }
+
+    /// What changed since the library version `since`, `None` if nothing did
+    fn fetch_changes(&self, since: i64) -> anyhow::Result<Option<Changes>> {
+        for _ in 0..MAX_ATTEMPTS {
+            let if_modified_since = (since > 0).then_some(since);
+            let Some((collection_keys, version)) =
+                self.versions("collections", since, if_modified_since)?
+            else {
+                return Ok(None);
+            };
+            let (item_keys, items_version) = self
+                .versions("items", since, None)?
+                .ok_or_else(|| anyhow!("Unexpected answer for items"))?;
+            let (collections, collection_versions) =
+                self.objects("collections", "collectionKey", &collection_keys)?;
+            let (items, item_versions) = self.objects("items", "itemKey", &item_keys)?;
+            let since = since.to_string();
+            let deleted = self
+                .get("deleted", &[("since", &since)], None)?
+                .ok_or_else(|| anyhow!("Unexpected answer for deleted"))?;
+
+            // Another client wrote in between, the answers may not fit together
+            let consistent = [items_version, deleted.version]
+                .iter()
+                .chain(&collection_versions)
+                .chain(&item_versions)
+                .all(|other| *other == version);
+            if consistent {
+                return Ok(Some(Changes {
+                    version,
+                    collections,
+                    items,
+                    deleted: serde_json::from_value(deleted.body)?,
+                }));
+            }
+        }
+        bail!("The library kept changing during the sync, try again later")
+    }
+}
+
+/// Create the cache if needed, returns the library version it is at
+async fn open_cache(cache_path: &Path, library_type: &str) -> anyhow::Result<i64> {
+    if let Some(dir) = cache_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut conn = SqliteConnectOptions::new()
+        .filename(cache_path)
+        .create_if_missing(true)
+        .connect()
+        .await?;
+    sqlx::query(CACHE_SCHEMA).execute(&mut conn).await?;
+    query!(
+        "INSERT OR IGNORE INTO libraries (libraryID, type, editable, filesEditable)
+        VALUES (?, ?, 1, 1)",
+        LIBRARY_ID,
+        library_type
+    )
+    .execute(&mut conn)
+    .await?;
+    let version = query_scalar!(
+        r#"SELECT version as "version!: i64" FROM libraries WHERE libraryID = ?"#,
+        LIBRARY_ID
+    )
+    .fetch_one(&mut conn)
+    .await?;
+    conn.close().await?;
+    Ok(version)
+}
+
+/// Bring the cache at `cache_path` up to date with the library on zotero.org, creating it
+/// on the first run
+pub async fn pull(config: &WebApiConfig, cache_path: &Path) -> anyhow::Result<SyncReport> {
+    let since = open_cache(cache_path, config.library_type()).await?;
+    let client = WebApiClient::new(config);
+    let changes = match client.fetch_changes(since)? {
+        Some(changes) => changes,
+        None => {
+            return Ok(SyncReport {
+                version: since,
+                ..Default::default()
+            })
+        }
+    };
+    let mut conn = begin_write(cache_path).await?;
+    let result = apply_changes(&mut conn, &changes).await;
+    end_write(conn, result).await
+}
+
+async fn apply_changes(
+    conn: &mut SqliteConnection,
+    changes: &Changes,
+) -> anyhow::Result<SyncReport> {
+    store_collections(conn, &changes.collections).await?;
+    // Parents first, so that children find them
+    let mut items: Vec<&Value> = changes.items.iter().collect();
+    items.sort_by_key(|item| item["data"]["parentItem"].is_string());
+    for item in items {
+        store_item(conn, item).await?;
+    }
+    for key in &changes.deleted.collections {
+        delete_collection(conn, key).await?;
+    }
+    for key in &changes.deleted.items {
+        delete_item(conn, key).await?;
+    }
+    for name in &changes.deleted.tags {
+        if let Some(tag_id) = find_tag(conn, name).await? {
+            query!("DELETE FROM itemTags WHERE tagID = ?", tag_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    remove_unused_rows(conn).await?;
+    query!(
+        "UPDATE libraries SET version = ?, lastSync = CAST(strftime('%s', 'now') AS INT)
+        WHERE libraryID = ?",
+        changes.version,
+        LIBRARY_ID
+    )
+    .execute(&mut *conn)
+    .await?;
+    let deleted = &changes.deleted;
+    Ok(SyncReport {
+        version: changes.version,
+        collections: changes.collections.len(),
+        items: changes.items.len(),
+        deleted: deleted.collections.len() + deleted.items.len() + deleted.tags.len(),
+    })
+}
+
+/// `2020-03-01T10:00:00Z` as Zotero stores timestamps, `2020-03-01 10:00:00`
+fn sql_timestamp(timestamp: &str) -> String {
+    timestamp.trim_end_matches('Z').replacen('T', " ", 1)
+}
+
+fn is_deleted(data: &Value) -> bool {
+    match &data["deleted"] {
+        Value::Bool(deleted) => *deleted,
+        Value::Number(deleted) => deleted.as_i64() != Some(0),
+        _ => false,
+    }
+}
+
+#[allow(non_snake_case)]
+async fn collection_id(conn: &mut SqliteConnection, key: &str) -> anyhow::Result<Option<i64>> {
+    Ok(query_scalar!(
+        r#"SELECT collectionID as "collectionId!" FROM collections
+        WHERE libraryID = ? AND key = ?"#,
+        LIBRARY_ID,
+        key
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+#[allow(non_snake_case)]
+async fn item_id(conn: &mut SqliteConnection, key: &str) -> anyhow::Result<Option<i64>> {
+    Ok(query_scalar!(
+        r#"SELECT itemID as "itemId!" FROM items WHERE libraryID = ? AND key = ?"#,
+        LIBRARY_ID,
+        key
+    )
+    .fetch_optional(conn)
+    .await?)
+}
+
+#[allow(non_snake_case)]
+async fn store_collections(
+    conn: &mut SqliteConnection,
+    collections: &[Value],
+) -> anyhow::Result<()> {
+    let mut stored = Vec::new();
+    for collection in collections {
+        let data = &collection["data"];
+        let key = data["key"]
+            .as_str()
+            .ok_or_else(|| anyhow!("A collection without key"))?;
+        if is_deleted(data) {
+            delete_collection(conn, key).await?;
+            continue;
+        }
+        let name = data["name"].as_str().unwrap_or_default();
+        let version = data["version"].as_i64().unwrap_or_default();
+        query!(
+            "INSERT INTO collections (collectionName, libraryID, key, version, synced)
+            VALUES (?, ?, ?, ?, 1)
+            ON CONFLICT (libraryID, key) DO UPDATE SET collectionName = excluded.collectionName,
+                version = excluded.version, synced = 1",
+            name,
+            LIBRARY_ID,
+            key,
+            version
+        )
+        .execute(&mut *conn)
+        .await?;
+        stored.push((key, data["parentCollection"].as_str()));
+    }
+    // Parents may come after their children
+    for (key, parent) in stored {
+        let parentCollectionId = match parent {
+            Some(parent) => collection_id(conn, parent).await?,
+            None => None,
+        };
+        query!(
+            "UPDATE collections SET parentCollectionID = ? WHERE libraryID = ? AND key = ?",
+            parentCollectionId,
+            LIBRARY_ID,
+            key
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+async fn delete_collection(conn: &mut SqliteConnection, key: &str) -> anyhow::Result<()> {
+    if let Some(collectionId) = collection_id(conn, key).await? {
+        query!(
+            "DELETE FROM collectionItems WHERE collectionID = ?",
+            collectionId
+        )
+        .execute(&mut *conn)
+        .await?;
+        query!(
+            "UPDATE collections SET parentCollectionID = NULL WHERE parentCollectionID = ?",
+            collectionId
+        )
+        .execute(&mut *conn)
+        .await?;
+        query!(
+            "DELETE FROM collections WHERE collectionID = ?",
+            collectionId
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+async fn delete_item(conn: &mut SqliteConnection, key: &str) -> anyhow::Result<()> {
+    if let Some(itemId) = item_id(conn, key).await? {
+        for sql in [
+            "DELETE FROM itemData WHERE itemID = ?",
+            "DELETE FROM itemCreators WHERE itemID = ?",
+            "DELETE FROM itemTags WHERE itemID = ?",
+            "DELETE FROM collectionItems WHERE itemID = ?",
+            "DELETE FROM itemNotes WHERE itemID = ?",
+            "DELETE FROM itemAttachments WHERE itemID = ?",
+            "DELETE FROM items WHERE itemID = ?",
+        ] {
+            sqlx::query(sql).bind(itemId).execute(&mut *conn).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Values, creators and tags no item uses anymore
+async fn remove_unused_rows(conn: &mut SqliteConnection) -> anyhow::Result<()> {
+    query!("DELETE FROM itemDataValues WHERE valueID NOT IN (SELECT valueID FROM itemData)")
+        .execute(&mut *conn)
+        .await?;
+    query!("DELETE FROM creators WHERE creatorID NOT IN (SELECT creatorID FROM itemCreators)")
+        .execute(&mut *conn)
+        .await?;
+    query!("DELETE FROM tags WHERE tagID NOT IN (SELECT tagID FROM itemTags)")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+async fn item_type_id(conn: &mut SqliteConnection, typeName: &str) -> anyhow::Result<i64> {
+    let itemTypeId = query_scalar!(
+        r#"SELECT itemTypeID as "itemTypeId!" FROM itemTypes WHERE typeName = ?"#,
+        typeName
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    match itemTypeId {
+        Some(itemTypeId) => Ok(itemTypeId),
+        None => Ok(
+            query!("INSERT INTO itemTypes (typeName) VALUES (?)", typeName)
+                .execute(&mut *conn)
+                .await?
+                .last_insert_rowid(),
+        ),
+    }
+}
+
+/// Add the fields and creator types of `changes` the cache doesn't know yet, the cache
+/// only has those of the items synced so far
+async fn add_names(conn: &mut SqliteConnection, changes: &ItemChanges) -> anyhow::Result<()> {
+    for (name, _) in &changes.fields {
+        query!(
+            "INSERT INTO fields (fieldName)
+            SELECT ?1 WHERE NOT EXISTS (SELECT 1 FROM fields WHERE fieldName = ?1)",
+            name
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    for creator in changes.creators.iter().flatten() {
+        let creator_type = creator.role();
+        query!(
+            "INSERT INTO creatorTypes (creatorType)
+            SELECT ?1 WHERE NOT EXISTS (SELECT 1 FROM creatorTypes WHERE creatorType = ?1)",
+            creator_type
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The fields and creators of an item's `data`, `current_fields` are removed unless
+/// `data` has them
+fn item_changes(data: &Value, current_fields: Vec<String>) -> ItemChanges {
+    let mut fields: Vec<(String, String)> = data
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| !NON_FIELDS.contains(&name.as_str()))
+        .filter_map(|(name, value)| {
+            let value = value.as_str()?.trim();
+            let value = match name.as_str() {
+                _ if value.is_empty() => return None,
+                "date" => DateParts::zotero_date_from_text(value)
+                    .unwrap_or_else(|| format!("0000-00-00 {}", value)),
+                "accessDate" => sql_timestamp(value),
+                _ => value.to_string(),
+            };
+            Some((name.clone(), value))
+        })
+        .collect();
+    for name in current_fields {
+        if !fields.iter().any(|(field, _)| *field == name) {
+            fields.push((name, String::new()));
+        }
+    }
+    fields.sort();
+    let creators = data["creators"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|creator| {
+            let text = |name: &str| creator[name].as_str().map(str::to_string);
+            let creator_type = text("creatorType");
+            match text("name") {
+                Some(name) => Creator {
+                    firstName: None,
+                    lastName: Some(name),
+                    creatorType: creator_type,
+                    fieldMode: Some(1),
+                },
+                None => Creator {
+                    firstName: text("firstName"),
+                    lastName: text("lastName"),
+                    creatorType: creator_type,
+                    fieldMode: Some(0),
+                },
+            }
+        })
+        .collect();
+    ItemChanges {
+        fields,
+        creators: Some(creators),
+    }
+}
+
+fn link_mode(name: &str) -> Option<i64> {
+    match name {
+        "imported_file" => Some(LINK_MODE_IMPORTED_FILE),
+        "imported_url" => Some(LINK_MODE_IMPORTED_URL),
+        "linked_file" => Some(LINK_MODE_LINKED_FILE),
+        "linked_url" => Some(LINK_MODE_LINKED_URL),
+        _ => None,
+    }
+}
+
+#[allow(non_snake_case)]
+async fn store_item(conn: &mut SqliteConnection, item: &Value) -> anyhow::Result<()> {
+    let data = &item["data"];
+    let key = data["key"]
+        .as_str()
+        .ok_or_else(|| anyhow!("An item without key"))?;
+    let typeName = data["itemType"].as_str().unwrap_or_default();
+    // Trashed items are left out, like annotations, which have no place in these tables
+    if is_deleted(data) || typeName == "annotation" || typeName.is_empty() {
+        return delete_item(conn, key).await;
+    }
+    let itemTypeId = item_type_id(conn, typeName).await?;
+    let version = data["version"].as_i64().unwrap_or_default();
+    let itemId = match item_id(conn, key).await? {
+        Some(itemId) => {
+            query!(
+                "UPDATE items SET itemTypeID = ? WHERE itemID = ?",
+                itemTypeId,
+                itemId
+            )
+            .execute(&mut *conn)
+            .await?;
+            itemId
+        }
+        None => query!(
+            "INSERT INTO items (itemTypeID, libraryID, key) VALUES (?, ?, ?)",
+            itemTypeId,
+            LIBRARY_ID,
+            key
+        )
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid(),
+    };
+
+    let currentFields = query_scalar!(
+        r#"SELECT fieldName as "fieldName!" FROM itemData
+            JOIN fields ON fields.fieldID = itemData.fieldID
+        WHERE itemID = ?"#,
+        itemId
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let changes = item_changes(data, currentFields);
+    add_names(conn, &changes).await?;
+    write_item_changes_locked(conn, itemId, &changes).await?;
+    // Undo `synced = 0` of the write, these are the server's values
+    let dateAdded = data["dateAdded"].as_str().map(sql_timestamp);
+    let dateModified = data["dateModified"].as_str().map(sql_timestamp);
+    query!(
+        "UPDATE items SET dateAdded = COALESCE(?1, dateAdded),
+            dateModified = COALESCE(?2, dateModified),
+            clientDateModified = COALESCE(?2, clientDateModified), version = ?3, synced = 1
+        WHERE itemID = ?4",
+        dateAdded,
+        dateModified,
+        version,
+        itemId
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let parentItemId = match data["parentItem"].as_str() {
+        Some(parent) => item_id(conn, parent).await?,
+        None => None,
+    };
+    match typeName {
+        "note" => {
+            let note = data["note"].as_str().unwrap_or_default();
+            let title = note_title(note);
+            query!(
+                "INSERT OR REPLACE INTO itemNotes (itemID, parentItemID, note, title)
+                VALUES (?, ?, ?, ?)",
+                itemId,
+                parentItemId,
+                note,
+                title
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        "attachment" => {
+            let linkMode = data["linkMode"].as_str().and_then(link_mode);
+            let contentType = data["contentType"]
+                .as_str()
+                .filter(|value| !value.is_empty());
+            let path = match linkMode {
+                Some(LINK_MODE_IMPORTED_FILE | LINK_MODE_IMPORTED_URL) => data["filename"]
+                    .as_str()
+                    .map(|name| format!("storage:{}", name)),
+                Some(LINK_MODE_LINKED_FILE) => data["path"].as_str().map(str::to_string),
+                _ => None,
+            };
+            query!(
+                "INSERT OR REPLACE INTO itemAttachments (itemID, parentItemID, linkMode,
+                    contentType, path)
+                VALUES (?, ?, ?, ?, ?)",
+                itemId,
+                parentItemId,
+                linkMode,
+                contentType,
+                path
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        _ => {}
+    }
+
+    query!("DELETE FROM itemTags WHERE itemID = ?", itemId)
+        .execute(&mut *conn)
+        .await?;
+    for tag in data["tags"].as_array().into_iter().flatten() {
+        let Some(name) = tag["tag"].as_str() else {
+            continue;
+        };
+        let tagType = tag["type"].as_i64().unwrap_or_default();
+        query!("INSERT OR IGNORE INTO tags (name) VALUES (?)", name)
+            .execute(&mut *conn)
+            .await?;
+        let tagId = find_tag(conn, name)
+            .await?
+            .ok_or_else(|| anyhow!("Cannot create the tag \"{}\"", name))?;
+        query!(
+            "INSERT OR IGNORE INTO itemTags (itemID, tagID, type) VALUES (?, ?, ?)",
+            itemId,
+            tagId,
+            tagType
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    query!("DELETE FROM collectionItems WHERE itemID = ?", itemId)
+        .execute(&mut *conn)
+        .await?;
+    for key in data["collections"].as_array().into_iter().flatten() {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        if let Some(collectionId) = collection_id(conn, key).await? {
+            query!(
+                "INSERT OR IGNORE INTO collectionItems (collectionID, itemID) VALUES (?, ?)",
+                collectionId,
+                itemId
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app::App, data_structures::RcDoc, db_connector::*, test_dir::TestDir};
+    use std::{
+        collections::HashMap,
+        rc::Rc,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    const COLLECTIONS: &str = include_str!("fixtures/collections.json");
+    const ITEMS: &str = include_str!("fixtures/items.json");
+    const ITEMS_MODIFIED: &str = include_str!("fixtures/items_modified.json");
+
+    /// Replays recorded answers of the API by path and sorted query, returns the base URL
+    /// and the requests it got
+    fn mock_api(responses: Vec<(&str, u16, i64, &str)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let responses: HashMap<String, (u16, i64, String)> = responses
+            .into_iter()
+            .map(|(request, status, version, body)| {
+                (request.to_string(), (status, version, body.to_string()))
+            })
+            .collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+                let mut params: Vec<String> = form_urlencoded::parse(query.as_bytes())
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                params.sort();
+                let url = format!("{}?{}", path, params.join("&"));
+                let authorized = request.headers().iter().any(|header| {
+                    header.field.equiv("Zotero-API-Key") && header.value.as_str() == "secret"
+                });
+                log.lock().unwrap().push(url.clone());
+                let (status, version, body) = match responses.get(&url) {
+                    _ if !authorized => (403, 0, "Forbidden".to_string()),
+                    Some(response) => response.clone(),
+                    None => (404, 0, "Not found".to_string()),
+                };
+                let header =
+                    tiny_http::Header::from_bytes("Last-Modified-Version", version.to_string())
+                        .unwrap();
+                let response = tiny_http::Response::from_string(body)
+                    .with_status_code(status)
+                    .with_header(header);
+                let _ = request.respond(response);
+            }
+        });
+        (format!("http://127.0.0.1:{}", port), requests)
+    }
+
+    /// The documents and collections of the cache, read as from Zotero's database
+    fn load(path: &Path) -> App {
+        let mut app = App::default();
+        tokio_test::block_on(async {
+            let db = ZoteroDb::open(path, path, Some(LIBRARY_ID)).await.unwrap();
+            app.load_from(Rc::new(db)).await.unwrap();
+        });
+        app
+    }
+
+    fn find_doc(app: &App, key: &str) -> RcDoc {
+        app.documents
+            .iter()
+            .find(|doc| doc.borrow().item_data.key == key)
+            .unwrap()
+            .clone()
+    }
+
+    fn tag_names(app: &App, key: &str) -> Vec<String> {
+        let doc = find_doc(app, key);
+        let doc = doc.borrow();
+        let mut tags: Vec<String> = doc.tags.iter().map(|tag| tag.name.clone()).collect();
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn test_web_api_config() {
+        let config = WebApiConfig::new("https://api.zotero.org/", "1234", "secret").unwrap();
+        assert_eq!(config.library, "users/1234");
+        assert_eq!(config.api_url, "https://api.zotero.org");
+        assert_eq!(config.library_type(), "user");
+        let group = WebApiConfig::new(DEFAULT_API_URL, "groups/56/", "secret").unwrap();
+        assert_eq!(group.library, "groups/56");
+        assert_eq!(group.library_type(), "group");
+        assert!(WebApiConfig::new(DEFAULT_API_URL, "users/jdoe", "secret").is_err());
+        assert!(WebApiConfig::new(DEFAULT_API_URL, "teams/56", "secret").is_err());
+        assert!(WebApiConfig::new(DEFAULT_API_URL, "1234", " ").is_err());
+    }
+
+    #[test]
+    fn test_pull() {
+        let (url, requests) = mock_api(vec![
+            (
+                "/users/1234/collections?format=versions&includeTrashed=1&since=0",
+                200,
+                10,
+                r#"{"COLLAAAA": 3, "COLLBBBB": 4}"#,
+            ),
+            (
+                "/users/1234/items?format=versions&includeTrashed=1&since=0",
+                200,
+                10,
+                r#"{"ABCD2345": 9, "ATTC2345": 9, "EFGH2345": 7, "NOTE2345": 9, "TRSH2345": 8}"#,
+            ),
+            (
+                "/users/1234/collections?collectionKey=COLLAAAA,COLLBBBB&includeTrashed=1&limit=50",
+                200,
+                10,
+                COLLECTIONS,
+            ),
+            (
+                "/users/1234/items?includeTrashed=1&itemKey=ABCD2345,ATTC2345,EFGH2345,NOTE2345,TRSH2345&limit=50",
+                200,
+                10,
+                ITEMS,
+            ),
+            (
+                "/users/1234/deleted?since=0",
+                200,
+                10,
+                r#"{"collections": [], "searches": [], "items": [], "tags": [], "settings": []}"#,
+            ),
+            (
+                "/users/1234/collections?format=versions&includeTrashed=1&since=10",
+                200,
+                12,
+                "{}",
+            ),
+            (
+                "/users/1234/items?format=versions&includeTrashed=1&since=10",
+                200,
+                12,
+                r#"{"ABCD2345": 12}"#,
+            ),
+            (
+                "/users/1234/items?includeTrashed=1&itemKey=ABCD2345&limit=50",
+                200,
+                12,
+                ITEMS_MODIFIED,
+            ),
+            (
+                "/users/1234/deleted?since=10",
+                200,
+                12,
+                r#"{"collections": ["COLLBBBB"], "searches": [], "items": ["NOTE2345"],
+                    "tags": ["ml"], "settings": []}"#,
+            ),
+            (
+                "/users/1234/collections?format=versions&includeTrashed=1&since=12",
+                304,
+                12,
+                "",
+            ),
+        ]);
+        let config = WebApiConfig::new(&url, "1234", "secret").unwrap();
+        let dir = TestDir::new("web-sync-test");
+        let path = dir.join("cache.sqlite");
+        let report = tokio_test::block_on(pull(&config, &path)).unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                version: 10,
+                collections: 2,
+                items: 5,
+                deleted: 0
+            }
+        );
+
+        // The cache reads like Zotero's database, without the trashed item
+        let app = load(&path);
+        let mut titles: Vec<String> = app
+            .documents
+            .iter()
+            .map(|doc| doc.borrow().item_data.title.clone())
+            .collect();
+        titles.sort();
+        assert_eq!(
+            titles,
+            vec!["A handbook of examples", "Sparse attention in practice"]
+        );
+        let article = find_doc(&app, "ABCD2345");
+        let article = article.borrow();
+        assert_eq!(article.item_data.typeName, "journalArticle");
+        assert_eq!(article.item_data.pubdate, "2020-00-00 March 2020");
+        assert_eq!(article.item_data.dateAdded, "2020-03-01 10:00:00");
+        assert_eq!(article.fields.get("DOI").unwrap(), "10.1000/xyz123");
+        assert_eq!(article.fields.get("volume").unwrap(), "12");
+        assert!(!article.fields.contains_key("issue"));
+        let authors: Vec<_> = article
+            .creators
+            .iter()
+            .map(|creator| creator.lastName.clone().unwrap())
+            .collect();
+        assert_eq!(authors, vec!["Doe", "Roe"]);
+        let attachments = &article.attachments.as_ref().unwrap().items;
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(
+            attachments[0].path.as_deref(),
+            Some("storage:Doe - 2020 - Sparse attention.pdf")
+        );
+        assert_eq!(attachments[0].key.as_deref(), Some("ATTC2345"));
+        assert_eq!(article.notes.len(), 1);
+        assert_eq!(article.notes[0].title, "Reading notes");
+        assert_eq!(tag_names(&app, "ABCD2345"), vec!["ml", "reading"]);
+        let book = find_doc(&app, "EFGH2345");
+        let book = book.borrow();
+        assert_eq!(book.creators[0].fieldMode, Some(1));
+        assert_eq!(book.creators[0].lastName.as_deref(), Some("Acme Research"));
+        assert_eq!(book.creators[1].creatorType.as_deref(), Some("editor"));
+        // Neither an abstract nor a date, like most items
+        assert_eq!(book.item_data.abstracttext, "");
+        assert_eq!(book.item_data.pubdate, "");
+
+        let collections: Vec<(String, Option<i64>)> = app
+            .collections
+            .items
+            .iter()
+            .map(|collection| {
+                let collection = collection.borrow();
+                (
+                    collection.collectionName.clone(),
+                    collection.parentCollectionId,
+                )
+            })
+            .collect();
+        let thesis = app
+            .collections
+            .items
+            .iter()
+            .find(|collection| collection.borrow().collectionName == "Thesis")
+            .unwrap()
+            .borrow()
+            .collectionId;
+        assert!(collections.contains(&("Chapter 2".to_string(), Some(thesis))));
+        tokio_test::block_on(app.backend.close()).unwrap();
+
+        // Only what changed since version 10 is fetched
+        let report = tokio_test::block_on(pull(&config, &path)).unwrap();
+        assert_eq!(
+            report,
+            SyncReport {
+                version: 12,
+                collections: 0,
+                items: 1,
+                deleted: 3
+            }
+        );
+        let app = load(&path);
+        let article = find_doc(&app, "ABCD2345");
+        let article = article.borrow();
+        assert_eq!(
+            article.item_data.title,
+            "Sparse attention in practice, revisited"
+        );
+        assert_eq!(article.creators.len(), 1);
+        assert!(!article.fields.contains_key("volume"));
+        assert!(article.notes.is_empty());
+        assert_eq!(tag_names(&app, "ABCD2345"), vec!["reading", "to-cite"]);
+        assert!(tag_names(&app, "EFGH2345").is_empty());
+        let names: Vec<String> = app
+            .collections
+            .items
+            .iter()
+            .map(|collection| collection.borrow().collectionName.clone())
+            .collect();
+        assert_eq!(names, vec!["Thesis"]);
+        tokio_test::block_on(app.backend.close()).unwrap();
+
+        // An unchanged library answers 304 to the first request
+        let report = tokio_test::block_on(pull(&config, &path)).unwrap();
+        assert_eq!(report.to_string(), "The library is up to date (version 12)");
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 10);
+        assert_eq!(
+            requests.last().unwrap(),
+            "/users/1234/collections?format=versions&includeTrashed=1&since=12"
+        );
+    }
+
+    #[test]
+    fn test_pull_errors() {
+        let (url, _) = mock_api(Vec::new());
+        let dir = TestDir::new("web-sync-errors");
+        let path = dir.join("cache.sqlite");
+        let config = WebApiConfig::new(&url, "1234", "wrong").unwrap();
+        let err = tokio_test::block_on(pull(&config, &path)).unwrap_err();
+        assert!(err.to_string().contains("no access to users/1234"));
+        // The cache is there even though the sync failed
+        assert!(path.exists());
+    }
+}
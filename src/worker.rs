@@ -0,0 +1,88 @@
+//! Work that would block the event loop, like looking items up on the web, run on its
+//! own thread. The event loop polls the worker on each tick and shows its progress in the
+//! status line.
+
+use std::{
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use anyhow::anyhow;
+
+enum Message<T> {
+    Progress(String),
+    Done(anyhow::Result<T>),
+}
+
+/// Where a worker is at
+pub enum WorkerPoll<T> {
+    /// Still working, with the progress reported since the last poll
+    Running(Option<String>),
+    Done(anyhow::Result<T>),
+}
+
+pub struct Worker<T> {
+    rx: Receiver<Message<T>>,
+}
+
+impl<T: Send + 'static> Worker<T> {
+    /// Run `work` on a new thread. It reports its progress with the function it is given.
+    pub fn spawn<F>(work: F) -> Self
+    where
+        F: FnOnce(&dyn Fn(String)) -> anyhow::Result<T> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let progress = |message: String| {
+                let _ = tx.send(Message::Progress(message));
+            };
+            let result = work(&progress);
+            let _ = tx.send(Message::Done(result));
+        });
+        Self { rx }
+    }
+
+    /// Never blocks. Only the latest progress is kept, the status line shows no more.
+    pub fn poll(&self) -> WorkerPoll<T> {
+        let mut progress = None;
+        loop {
+            match self.rx.try_recv() {
+                Ok(Message::Progress(message)) => progress = Some(message),
+                Ok(Message::Done(result)) => return WorkerPoll::Done(result),
+                Err(TryRecvError::Empty) => return WorkerPoll::Running(progress),
+                Err(TryRecvError::Disconnected) => {
+                    return WorkerPoll::Done(Err(anyhow!("The worker stopped unexpectedly")))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker() {
+        let worker = Worker::spawn(|progress| {
+            progress("Halfway".to_string());
+            Ok(42)
+        });
+        let result = loop {
+            match worker.poll() {
+                WorkerPoll::Running(message) => {
+                    assert!(message.is_none() || message.as_deref() == Some("Halfway"))
+                }
+                WorkerPoll::Done(result) => break result,
+            }
+            thread::yield_now();
+        };
+        assert_eq!(result.unwrap(), 42);
+
+        let worker: Worker<()> = Worker::spawn(|_| panic!("lost"));
+        while let WorkerPoll::Running(_) = worker.poll() {
+            thread::yield_now();
+        }
+        assert!(matches!(worker.poll(), WorkerPoll::Done(Err(_))));
+    }
+}